@@ -1,8 +1,10 @@
+use mini_redis::client::TlsOptions;
 use mini_redis::{client, DEFAULT_PORT};
 
 use bytes::Bytes;
 use clap::{Parser, Subcommand};
 use std::num::ParseIntError;
+use std::path::PathBuf;
 use std::str;
 use std::time::Duration;
 
@@ -22,6 +24,28 @@ struct Cli {
 
     #[clap(long, default_value_t = DEFAULT_PORT)]
     port: u16,
+
+    /// Connect with TLS
+    #[clap(long, requires = "cacert")]
+    tls: bool,
+
+    /// CA certificates, in the PEM format, the certificate of the server must
+    /// be signed by
+    #[clap(long, requires = "tls")]
+    cacert: Option<PathBuf>,
+
+    /// Client certificate, in the PEM format, for servers requiring one
+    #[clap(long, requires_all = &["key", "tls"])]
+    cert: Option<PathBuf>,
+
+    /// Private key of `--cert`, in the PEM format
+    #[clap(long, requires = "cert")]
+    key: Option<PathBuf>,
+
+    /// Name the certificate of the server must be valid for, the hostname by
+    /// default
+    #[clap(long, requires = "tls")]
+    sni: Option<String>,
 }
 
 #[derive(Subcommand, Debug)]
@@ -83,7 +107,19 @@ async fn main() -> mini_redis::Result<()> {
     let addr = format!("{}:{}", cli.host, cli.port);
 
     // Establish a connection
-    let mut client = client::connect(&addr).await?;
+    let mut client = match cli.cacert {
+        Some(ca_cert_file) => {
+            let options = TlsOptions {
+                ca_cert_file,
+                cert_file: cli.cert,
+                key_file: cli.key,
+            };
+            let server_name = cli.sni.as_deref().unwrap_or(&cli.host);
+
+            client::connect_tls(&addr, server_name, &options).await?
+        }
+        None => client::connect(&addr).await?,
+    };
 
     // Process the requested command
     match cli.command {
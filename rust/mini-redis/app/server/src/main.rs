@@ -6,9 +6,12 @@
 //!
 //! The `clap` crate is used for parsing arguments.
 
+use mini_redis::config::{AppendFsync, Config, KeyspaceEvents, MaxmemoryPolicy, SlowSubscriberPolicy};
 use mini_redis::{server, DEFAULT_PORT};
 
 use clap::Parser;
+use std::path::PathBuf;
+use std::time::Duration;
 use tokio::net::TcpListener;
 use tokio::signal;
 
@@ -19,18 +22,270 @@ pub async fn main() -> mini_redis::Result<()> {
     let cli = Cli::parse();
     let port = cli.port.unwrap_or(DEFAULT_PORT);
 
+    // Build the server configuration, starting from the defaults.
+    let mut config = Config::default();
+
+    if let Some(capacity) = cli.pubsub_capacity {
+        if capacity == 0 {
+            return Err("--pubsub-capacity must be at least 1".into());
+        }
+
+        config.pubsub_capacity = capacity;
+    }
+
+    if let Some(policy) = cli.slow_subscriber {
+        config.slow_subscriber = policy;
+    }
+
+    if let Some(events) = cli.notify_keyspace_events {
+        config.notify_keyspace_events = events;
+    }
+
+    config.snapshot_path = cli.dbfilename;
+    config.aof_path = cli.appendfilename;
+    config.replicaof = cli.replicaof.map(|primary| primary.join(":"));
+    config.metrics_addr = cli.metrics_addr;
+    config.requirepass = cli.requirepass;
+
+    if let Some(tls_port) = cli.tls_port {
+        if cli.tls_cert_file.is_none() || cli.tls_key_file.is_none() {
+            return Err("--tls-port requires --tls-cert-file and --tls-key-file".into());
+        }
+
+        config.tls_addr = Some(format!("{}:{}", cli.bind, tls_port));
+    }
+
+    // Like Redis, 0 disables both the idle timeout and TCP keepalive.
+    if let Some(secs) = cli.timeout {
+        config.timeout = (secs > 0).then(|| Duration::from_secs(secs));
+    }
+
+    if let Some(secs) = cli.tcp_keepalive {
+        config.tcp_keepalive = (secs > 0).then(|| Duration::from_secs(secs));
+    }
+
+    if let Some(maxclients) = cli.maxclients {
+        if maxclients == 0 {
+            return Err("--maxclients must be at least 1".into());
+        }
+
+        config.maxclients = maxclients;
+    }
+
+    if let Some(secs) = cli.shutdown_timeout {
+        config.shutdown_timeout = Duration::from_secs(secs);
+    }
+
+    config.tls_cert_file = cli.tls_cert_file;
+    config.tls_key_file = cli.tls_key_file;
+    config.tls_ca_cert_file = cli.tls_ca_cert_file;
+
+    if let Some(policy) = cli.appendfsync {
+        config.appendfsync = policy;
+    }
+
+    if let Some(maxmemory) = cli.maxmemory {
+        config.maxmemory = maxmemory;
+    }
+
+    if let Some(policy) = cli.maxmemory_policy {
+        config.maxmemory_policy = policy;
+    }
+
+    if let Some(samples) = cli.maxmemory_samples {
+        if samples == 0 {
+            return Err("--maxmemory-samples must be at least 1".into());
+        }
+
+        config.maxmemory_samples = samples;
+    }
+
+    // Like Redis, a negative threshold disables the slow log.
+    if let Some(usec) = cli.slowlog_log_slower_than {
+        config.slowlog_log_slower_than = u64::try_from(usec).ok().map(Duration::from_micros);
+    }
+
+    if let Some(len) = cli.slowlog_max_len {
+        config.slowlog_max_len = len;
+    }
+
+    // Like Redis, a threshold of 0 disables latency monitoring.
+    if let Some(ms) = cli.latency_monitor_threshold {
+        config.latency_monitor_threshold = (ms > 0).then(|| Duration::from_millis(ms));
+    }
+
+    if let Some(databases) = cli.databases {
+        if databases == 0 {
+            return Err("--databases must be at least 1".into());
+        }
+
+        config.databases = databases;
+    }
+
+    if let Some(shards) = cli.shards {
+        if shards == 0 {
+            return Err("--shards must be at least 1".into());
+        }
+
+        config.shards = shards;
+    }
+
     // Bind a TCP listener
-    let listener = TcpListener::bind(&format!("127.0.0.1:{}", port)).await?;
+    let listener = TcpListener::bind(&format!("{}:{}", cli.bind, port)).await?;
 
-    server::run(listener, signal::ctrl_c()).await;
+    server::run_with_config(listener, config, shutdown_signal()).await;
 
     Ok(())
 }
 
+/// Completes on `ctrl-c` or, on Unix, on SIGTERM, which is what service
+/// managers and container runtimes send to stop the server.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                tokio::select! {
+                    _ = signal::ctrl_c() => {}
+                    _ = sigterm.recv() => {}
+                }
+                return;
+            }
+            Err(err) => eprintln!("failed to listen for SIGTERM: {}", err),
+        }
+    }
+
+    let _ = signal::ctrl_c().await;
+}
+
 #[derive(Parser, Debug)]
 #[clap(name = "mini-redis-server", version, author, about = "A Redis server")]
 struct Cli {
     #[clap(long)]
     port: Option<u16>,
+
+    /// Address the plain and TLS listeners are bound to, such as `0.0.0.0`
+    /// to accept connections on every interface
+    #[clap(long, default_value = "127.0.0.1")]
+    bind: String,
+
+    /// Number of messages buffered per pub/sub channel
+    #[clap(long)]
+    pubsub_capacity: Option<usize>,
+
+    /// What to do with slow subscribers: `drop`, `disconnect` or
+    /// `grow:<max_bytes>`
+    #[clap(long)]
+    slow_subscriber: Option<SlowSubscriberPolicy>,
+
+    /// Keyspace events to publish, using the Redis flags (for example `KEA`)
+    #[clap(long)]
+    notify_keyspace_events: Option<KeyspaceEvents>,
+
+    /// Snapshot file saved by `SAVE` and `BGSAVE` and loaded on startup.
+    /// Snapshot persistence is disabled if not given
+    #[clap(long)]
+    dbfilename: Option<PathBuf>,
+
+    /// Append only file every write is logged to and replayed from on
+    /// startup. The AOF is disabled if not given
+    #[clap(long)]
+    appendfilename: Option<PathBuf>,
+
+    /// When the AOF is synced to disk: `always`, `everysec` or `no`
+    #[clap(long)]
+    appendfsync: Option<AppendFsync>,
+
+    /// Replicate the primary at the given host and port, as a read only
+    /// replica
+    #[clap(long, number_of_values = 2, value_names = &["HOST", "PORT"])]
+    replicaof: Option<Vec<String>>,
+
+    /// Number of databases, selected with `SELECT`
+    #[clap(long)]
+    databases: Option<usize>,
+
+    /// Number of shards the keys are partitioned into, each with its own lock
+    #[clap(long)]
+    shards: Option<usize>,
+
+    /// Approximate memory limit for the dataset, in bytes. No limit if not
+    /// given
+    #[clap(long)]
+    maxmemory: Option<usize>,
+
+    /// How memory is freed once `--maxmemory` is exceeded: `noeviction`,
+    /// `allkeys-lru`, `allkeys-lfu`, `allkeys-random`, `volatile-lru`,
+    /// `volatile-lfu`, `volatile-random` or `volatile-ttl`
+    #[clap(long)]
+    maxmemory_policy: Option<MaxmemoryPolicy>,
+
+    /// Number of keys sampled for each eviction by the LRU and LFU policies
+    #[clap(long)]
+    maxmemory_samples: Option<usize>,
+
+    /// Log commands that take at least this many microseconds in the slow
+    /// log. A negative value disables the slow log
+    #[clap(long, allow_hyphen_values = true)]
+    slowlog_log_slower_than: Option<i64>,
+
+    /// Maximum number of commands kept in the slow log
+    #[clap(long)]
+    slowlog_max_len: Option<usize>,
+
+    /// Sample operations that take at least this many milliseconds as
+    /// latency events. 0 disables latency monitoring
+    #[clap(long)]
+    latency_monitor_threshold: Option<u64>,
+
+    /// Serve Prometheus metrics over HTTP at `/metrics` on this address, such
+    /// as `127.0.0.1:9121`
+    #[clap(long)]
+    metrics_addr: Option<String>,
+
+    /// Require clients to authenticate as the `default` user with this
+    /// password
+    #[clap(long)]
+    requirepass: Option<String>,
+
+    /// Also accept TLS connections on this port
+    #[clap(long)]
+    tls_port: Option<u16>,
+
+    /// Certificate of the server for TLS connections, in the PEM format
+    #[clap(long)]
+    tls_cert_file: Option<PathBuf>,
+
+    /// Private key of `--tls-cert-file`, in the PEM format
+    #[clap(long)]
+    tls_key_file: Option<PathBuf>,
+
+    /// CA certificates, in the PEM format, that must have signed the
+    /// certificates of TLS clients. Clients need no certificate if not given
+    #[clap(long)]
+    tls_ca_cert_file: Option<PathBuf>,
+
+    /// Close connections that send no command for this many seconds. 0, the
+    /// default, keeps them open
+    #[clap(long)]
+    timeout: Option<u64>,
+
+    /// Seconds of silence after which TCP keepalive probes a connection. 0
+    /// disables keepalive, the default is 300
+    #[clap(long)]
+    tcp_keepalive: Option<u64>,
+
+    /// Maximum number of clients connected at once. Clients beyond it are
+    /// rejected with an error
+    #[clap(long)]
+    maxclients: Option<usize>,
+
+    /// Seconds to wait, when shutting down, for the replicas and then for the
+    /// open connections. Connections still open are then closed. The default
+    /// is 10
+    #[clap(long)]
+    shutdown_timeout: Option<u64>,
 }
 
@@ -0,0 +1,133 @@
+//! Load generator.
+//!
+//! Starts a mini-redis server in this process and measures the throughput of
+//! a growing number of concurrent clients running a mix of `GET` and `SET`
+//! on random keys. The measurement is repeated with a single shard and with
+//! the default number of shards, showing how sharding the keyspace lets the
+//! throughput scale with the number of cores.
+//!
+//! You can test this out by running:
+//!
+//!     cargo run --release --example load
+//!
+//! The number of seconds each measurement runs for may be given as an
+//! argument, 2 by default.
+
+#![warn(rust_2018_idioms)]
+
+use mini_redis::config::Config;
+use mini_redis::{client, server, Result};
+
+use rand::Rng;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::oneshot;
+
+/// Number of distinct keys the clients read and write.
+const KEYS: usize = 100_000;
+
+/// One in this many commands is a `SET`, the rest are `GET`s.
+const SET_RATIO: u32 = 4;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let secs = match std::env::args().nth(1) {
+        Some(secs) => secs.parse().map_err(|_| "invalid number of seconds")?,
+        None => 2,
+    };
+    let duration = Duration::from_secs(secs);
+
+    let cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+
+    // 1, 2, 4, ... clients, up to twice the number of cores.
+    let clients: Vec<usize> = std::iter::successors(Some(1), |n| Some(n * 2))
+        .take_while(|n| *n <= 2 * cores)
+        .collect();
+
+    println!("{} cores, {}s per measurement", cores, secs);
+    println!("{:>8} {:>8} {:>14}", "shards", "clients", "ops/sec");
+
+    for shards in [1, Config::default().shards] {
+        let (addr, stop) = start_server(Config {
+            shards,
+            ..Config::default()
+        })
+        .await?;
+
+        for &n in &clients {
+            let ops = run(addr, n, duration).await?;
+            println!("{:>8} {:>8} {:>14.0}", shards, n, ops as f64 / duration.as_secs_f64());
+        }
+
+        let _ = stop.send(());
+    }
+
+    Ok(())
+}
+
+/// Starts a server on a random port, returning its address and a sender
+/// that stops it.
+async fn start_server(config: Config) -> Result<(SocketAddr, oneshot::Sender<()>)> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    let (stop, stopped) = oneshot::channel();
+
+    tokio::spawn(server::run_with_config(listener, config, stopped));
+
+    Ok((addr, stop))
+}
+
+/// Runs `clients` concurrent clients against the server for `duration`,
+/// returning the number of commands they completed.
+async fn run(addr: SocketAddr, clients: usize, duration: Duration) -> Result<u64> {
+    let done = Arc::new(AtomicBool::new(false));
+    let ops = Arc::new(AtomicU64::new(0));
+
+    let mut tasks = Vec::with_capacity(clients);
+
+    for _ in 0..clients {
+        let mut client = client::connect(addr).await?;
+        let done = done.clone();
+        let ops = ops.clone();
+
+        tasks.push(tokio::spawn(async move {
+            let value = bytes::Bytes::from_static(b"value");
+            let mut count = 0;
+
+            while !done.load(Ordering::Relaxed) {
+                // `ThreadRng` is not `Send`, so it can not be held across the
+                // `.await`s below.
+                let (key, set) = {
+                    let mut rng = rand::thread_rng();
+                    let key = format!("key-{}", rng.gen_range(0..KEYS));
+                    (key, rng.gen_range(0..SET_RATIO) == 0)
+                };
+
+                if set {
+                    client.set(&key, value.clone()).await?;
+                } else {
+                    client.get(&key).await?;
+                }
+
+                count += 1;
+            }
+
+            ops.fetch_add(count, Ordering::Relaxed);
+            Ok::<_, mini_redis::Error>(())
+        }));
+    }
+
+    tokio::time::sleep(duration).await;
+    done.store(true, Ordering::Relaxed);
+
+    // Each client finishes the command it was running, which is negligible
+    // for the measurement.
+    for task in tasks {
+        task.await??;
+    }
+
+    Ok(ops.load(Ordering::Relaxed))
+}
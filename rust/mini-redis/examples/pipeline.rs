@@ -0,0 +1,104 @@
+//! Pipelining benchmark.
+//!
+//! Starts a mini-redis server in this process and measures the throughput of
+//! a single connection pipelining batches of `SET` commands of growing size:
+//! each batch is written at once, then all the responses are read. The
+//! server writes the responses to a batch with a single flush, so the
+//! throughput grows with the batch size instead of being bound by one
+//! syscall per response.
+//!
+//! You can test this out by running:
+//!
+//!     cargo run --release --example pipeline
+//!
+//! The number of seconds each measurement runs for may be given as an
+//! argument, 2 by default.
+
+#![warn(rust_2018_idioms)]
+
+use mini_redis::{server, Result};
+
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::oneshot;
+
+/// Numbers of commands sent in each batch.
+const BATCHES: [usize; 5] = [1, 10, 100, 1_000, 10_000];
+
+/// Response to each `SET`.
+const OK: &[u8] = b"+OK\r\n";
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let secs = match std::env::args().nth(1) {
+        Some(secs) => secs.parse().map_err(|_| "invalid number of seconds")?,
+        None => 2,
+    };
+    let duration = Duration::from_secs(secs);
+
+    let (addr, stop) = start_server().await?;
+
+    println!("{}s per measurement", secs);
+    println!("{:>8} {:>14}", "batch", "ops/sec");
+
+    for batch in BATCHES {
+        let ops = run(addr, batch, duration).await?;
+        println!("{:>8} {:>14.0}", batch, ops as f64 / duration.as_secs_f64());
+    }
+
+    let _ = stop.send(());
+
+    Ok(())
+}
+
+/// Starts a server on a random port, returning its address and a sender
+/// that stops it.
+async fn start_server() -> Result<(SocketAddr, oneshot::Sender<()>)> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    let (stop, stopped) = oneshot::channel();
+
+    tokio::spawn(server::run(listener, stopped));
+
+    Ok((addr, stop))
+}
+
+/// Sends batches of `batch` pipelined commands for `duration`, returning the
+/// number of commands completed.
+async fn run(addr: SocketAddr, batch: usize, duration: Duration) -> Result<u64> {
+    let mut stream = TcpStream::connect(addr).await?;
+    stream.set_nodelay(true)?;
+
+    // The batch is encoded once, each command setting its own key.
+    let mut request = Vec::new();
+    for i in 0..batch {
+        let key = format!("key-{}", i);
+        request.extend_from_slice(
+            format!("*3\r\n$3\r\nSET\r\n${}\r\n{}\r\n$5\r\nvalue\r\n", key.len(), key).as_bytes(),
+        );
+    }
+
+    let mut response = vec![0; batch * OK.len()];
+    let mut ops = 0;
+    let start = Instant::now();
+
+    while start.elapsed() < duration {
+        // Writing and reading concurrently keeps a large batch from filling
+        // both socket buffers, with neither peer reading.
+        let (mut reader, mut writer) = stream.split();
+        let (written, read) =
+            tokio::join!(writer.write_all(&request), reader.read_exact(&mut response));
+        written?;
+        read?;
+
+        if !response.chunks(OK.len()).all(|chunk| chunk == OK) {
+            return Err("unexpected response".into());
+        }
+
+        ops += batch as u64;
+    }
+
+    Ok(ops)
+}
@@ -0,0 +1,473 @@
+//! Access control lists, like Redis' ACL.
+//!
+//! Clients authenticate as a user with `AUTH`. Each user has passwords, and
+//! rules deciding which commands it can run, which keys those commands can
+//! access and which channels it can publish and subscribe to. Clients start
+//! as the `default` user, which can run every command, as long as it needs no
+//! password, that is unless `Config::requirepass` is set.
+//!
+//! Passwords are only kept as their SHA-256 hash, which is what `ACL LIST`
+//! and `ACL GETUSER` show.
+
+use crate::cmd::{Command, COMMAND_NAMES};
+use crate::pattern;
+
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Name of the user clients start as.
+pub(crate) const DEFAULT_USER: &str = "default";
+
+/// Command categories, for the `+@category` and `-@category` rules, with the
+/// commands in each one. `@all` is every command.
+pub(crate) const CATEGORIES: &[(&str, &[&str])] = &[
+    (
+        "admin",
+        &[
+            "acl",
+            "bgrewriteaof",
+            "bgsave",
+            "client",
+            "latency",
+            "monitor",
+            "psync",
+            "save",
+            "shutdown",
+            "slowlog",
+        ],
+    ),
+    ("connection", &["auth", "client", "ping", "quit", "reset", "select"]),
+    (
+        "dangerous",
+        &[
+            "acl",
+            "bgrewriteaof",
+            "bgsave",
+            "client",
+            "flushall",
+            "flushdb",
+            "info",
+            "latency",
+            "monitor",
+            "psync",
+            "save",
+            "shutdown",
+            "slowlog",
+            "swapdb",
+        ],
+    ),
+    ("keyspace", &["del", "flushall", "flushdb", "move", "object", "swapdb"]),
+    ("pubsub", &["publish", "pubsub", "subscribe", "unsubscribe"]),
+    ("read", &["get", "object"]),
+    ("string", &["get", "set"]),
+    ("write", &["del", "flushall", "flushdb", "move", "set", "swapdb"]),
+];
+
+/// The users, by name.
+#[derive(Debug)]
+pub(crate) struct Users {
+    users: BTreeMap<String, User>,
+}
+
+/// A user clients can authenticate as.
+#[derive(Debug, Clone)]
+pub(crate) struct User {
+    name: String,
+
+    /// Disabled users can not be authenticated as.
+    enabled: bool,
+
+    /// Any password authenticates as the user.
+    nopass: bool,
+
+    /// SHA-256 of the passwords, in hexadecimal.
+    passwords: Vec<String>,
+
+    /// Commands the user can run.
+    commands: BTreeSet<&'static str>,
+
+    /// The `+` and `-` rules applied to get `commands`, reported as they
+    /// were given. They start over with `+@all` or `-@all`.
+    command_rules: Vec<String>,
+
+    /// Patterns of the keys the user can access.
+    keys: Vec<String>,
+
+    /// Patterns of the channels the user can access.
+    channels: Vec<String>,
+}
+
+impl Users {
+    /// Creates the `default` user, which needs the password `requirepass`,
+    /// if any.
+    pub(crate) fn new(requirepass: Option<&str>) -> Users {
+        let mut default = User::new(DEFAULT_USER);
+
+        for rule in ["on", "allkeys", "allchannels", "allcommands"] {
+            default.apply_rule(rule);
+        }
+
+        match requirepass {
+            Some(password) => default.passwords.push(sha256_hex(password.as_bytes())),
+            None => default.nopass = true,
+        }
+
+        let mut users = BTreeMap::new();
+        users.insert(DEFAULT_USER.to_string(), default);
+
+        Users { users }
+    }
+
+    /// Returns the user `name`.
+    pub(crate) fn get(&self, name: &str) -> Option<&User> {
+        self.users.get(name)
+    }
+
+    /// Returns `true` if `password` authenticates as the user `name`.
+    pub(crate) fn authenticate(&self, name: &str, password: &str) -> bool {
+        match self.users.get(name) {
+            Some(user) if user.enabled => {
+                user.nopass || user.passwords.contains(&sha256_hex(password.as_bytes()))
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns `true` if clients are authenticated as the `default` user
+    /// without running `AUTH`.
+    pub(crate) fn default_login(&self) -> bool {
+        let default = &self.users[DEFAULT_USER];
+        default.enabled && default.nopass
+    }
+
+    /// Applies `rules` to the user `name`, creating it if needed. Either
+    /// every rule is applied or, if one is invalid, none is and the error to
+    /// reply with is returned.
+    pub(crate) fn set_user(&mut self, name: &str, rules: &[String]) -> Result<(), String> {
+        let mut user = match self.users.get(name) {
+            Some(user) => user.clone(),
+            None => User::new(name),
+        };
+
+        for rule in rules {
+            if !user.apply_rule(rule) {
+                return Err(format!(
+                    "ERR Error in ACL SETUSER modifier '{}': Syntax error",
+                    rule
+                ));
+            }
+        }
+
+        self.users.insert(name.to_string(), user);
+        Ok(())
+    }
+
+    /// Removes the user `name`, returning `true` if it existed. The `default`
+    /// user can not be removed.
+    pub(crate) fn del_user(&mut self, name: &str) -> bool {
+        name != DEFAULT_USER && self.users.remove(name).is_some()
+    }
+
+    /// Describes every user with the rules that would create it, for `ACL
+    /// LIST`.
+    pub(crate) fn list(&self) -> Vec<String> {
+        self.users.values().map(User::describe).collect()
+    }
+}
+
+impl User {
+    /// Creates a disabled user that can not run any command.
+    fn new(name: &str) -> User {
+        User {
+            name: name.to_string(),
+            enabled: false,
+            nopass: false,
+            passwords: vec![],
+            commands: BTreeSet::new(),
+            command_rules: vec![],
+            keys: vec![],
+            channels: vec![],
+        }
+    }
+
+    /// Applies a rule of `ACL SETUSER`. Returns `false` if it is not valid.
+    fn apply_rule(&mut self, rule: &str) -> bool {
+        match &rule.to_lowercase()[..] {
+            "on" => self.enabled = true,
+            "off" => self.enabled = false,
+            "nopass" => {
+                self.nopass = true;
+                self.passwords.clear();
+            }
+            "resetpass" => {
+                self.nopass = false;
+                self.passwords.clear();
+            }
+            "allkeys" => return self.apply_rule("~*"),
+            "resetkeys" => self.keys.clear(),
+            "allchannels" => return self.apply_rule("&*"),
+            "resetchannels" => self.channels.clear(),
+            "allcommands" => return self.apply_rule("+@all"),
+            "nocommands" => return self.apply_rule("-@all"),
+            "reset" => {
+                for rule in ["resetpass", "resetkeys", "resetchannels", "off", "-@all"] {
+                    self.apply_rule(rule);
+                }
+            }
+            _ => {
+                if let Some(password) = rule.strip_prefix('>') {
+                    self.add_password(sha256_hex(password.as_bytes()));
+                } else if let Some(password) = rule.strip_prefix('<') {
+                    let hash = sha256_hex(password.as_bytes());
+                    self.passwords.retain(|p| *p != hash);
+                } else if let Some(hash) = rule.strip_prefix('#') {
+                    if hash.len() != 64 || !hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+                        return false;
+                    }
+
+                    self.add_password(hash.to_lowercase());
+                } else if let Some(hash) = rule.strip_prefix('!') {
+                    let hash = hash.to_lowercase();
+                    self.passwords.retain(|p| *p != hash);
+                } else if let Some(pattern) = rule.strip_prefix('~') {
+                    add_pattern(&mut self.keys, pattern);
+                } else if let Some(pattern) = rule.strip_prefix('&') {
+                    add_pattern(&mut self.channels, pattern);
+                } else if let Some(name) = rule.strip_prefix('+') {
+                    return self.set_commands(name, true);
+                } else if let Some(name) = rule.strip_prefix('-') {
+                    return self.set_commands(name, false);
+                } else {
+                    return false;
+                }
+            }
+        }
+
+        true
+    }
+
+    /// Adds a password, by its hash. A user with passwords needs one of them.
+    fn add_password(&mut self, hash: String) {
+        self.nopass = false;
+
+        if !self.passwords.contains(&hash) {
+            self.passwords.push(hash);
+        }
+    }
+
+    /// Allows or denies the command `name`, or the commands of the category
+    /// `@name`. Returns `false` if there is no such command or category.
+    fn set_commands(&mut self, name: &str, allowed: bool) -> bool {
+        let name = name.to_lowercase();
+
+        let commands: &[&'static str] = match name.strip_prefix('@') {
+            Some("all") => COMMAND_NAMES,
+            Some(category) => match CATEGORIES.iter().find(|(c, _)| *c == category) {
+                Some((_, commands)) => commands,
+                None => return false,
+            },
+            None => match COMMAND_NAMES.iter().position(|c| *c == name) {
+                Some(i) => &COMMAND_NAMES[i..=i],
+                None => return false,
+            },
+        };
+
+        if allowed {
+            self.commands.extend(commands);
+        } else {
+            for command in commands {
+                self.commands.remove(command);
+            }
+        }
+
+        // Earlier rules no longer have any effect.
+        if name == "@all" {
+            self.command_rules.clear();
+        }
+
+        let sign = if allowed { '+' } else { '-' };
+        self.command_rules.push(format!("{}{}", sign, name));
+
+        true
+    }
+
+    /// Checks that the user can run `cmd`, on the keys and channels it
+    /// accesses. Returns the error to reply with otherwise.
+    pub(crate) fn check(&self, cmd: &Command) -> Result<(), String> {
+        let name = cmd.get_name();
+
+        // Unknown commands fail anyway, for every user.
+        if !COMMAND_NAMES.contains(&name) {
+            return Ok(());
+        }
+
+        if !self.commands.contains(name) {
+            return Err(format!(
+                "NOPERM User {} has no permissions to run the '{}' command",
+                self.name, name
+            ));
+        }
+
+        if !cmd.keys().iter().all(|key| matches_any(&self.keys, key)) {
+            return Err("NOPERM No permissions to access a key".to_string());
+        }
+
+        if !cmd.channels().iter().all(|channel| matches_any(&self.channels, channel)) {
+            return Err("NOPERM No permissions to access a channel".to_string());
+        }
+
+        Ok(())
+    }
+
+    /// Flags of the user, as `ACL GETUSER` reports them.
+    pub(crate) fn flags(&self) -> Vec<&'static str> {
+        let mut flags = vec![if self.enabled { "on" } else { "off" }];
+
+        if self.nopass {
+            flags.push("nopass");
+        }
+
+        flags
+    }
+
+    /// SHA-256 of the passwords, in hexadecimal.
+    pub(crate) fn passwords(&self) -> &[String] {
+        &self.passwords
+    }
+
+    /// Rules giving the commands the user can run, like `+@all -flushall`.
+    pub(crate) fn command_rules(&self) -> String {
+        match self.command_rules.first().map(String::as_str) {
+            Some("+@all" | "-@all") => self.command_rules.join(" "),
+            _ => {
+                let mut rules = vec!["-@all".to_string()];
+                rules.extend(self.command_rules.iter().cloned());
+                rules.join(" ")
+            }
+        }
+    }
+
+    /// Rules giving the keys the user can access, like `~cache:* ~tmp:*`.
+    pub(crate) fn key_rules(&self) -> String {
+        pattern_rules('~', &self.keys)
+    }
+
+    /// Rules giving the channels the user can access, like `&news.*`.
+    pub(crate) fn channel_rules(&self) -> String {
+        pattern_rules('&', &self.channels)
+    }
+
+    /// Describes the user with the rules that would create it, for `ACL
+    /// LIST`.
+    fn describe(&self) -> String {
+        let mut rules = vec![format!("user {}", self.name)];
+        rules.extend(self.flags().iter().map(|flag| flag.to_string()));
+        rules.extend(self.passwords.iter().map(|hash| format!("#{}", hash)));
+
+        for patterns in [self.key_rules(), self.channel_rules()] {
+            if !patterns.is_empty() {
+                rules.push(patterns);
+            }
+        }
+
+        rules.push(self.command_rules());
+        rules.join(" ")
+    }
+}
+
+fn add_pattern(patterns: &mut Vec<String>, pattern: &str) {
+    if !patterns.iter().any(|p| p == pattern) {
+        patterns.push(pattern.to_string());
+    }
+}
+
+fn matches_any(patterns: &[String], name: &str) -> bool {
+    patterns
+        .iter()
+        .any(|pattern| pattern::matches(pattern.as_bytes(), name.as_bytes()))
+}
+
+fn pattern_rules(prefix: char, patterns: &[String]) -> String {
+    patterns
+        .iter()
+        .map(|pattern| format!("{}{}", prefix, pattern))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Computes the SHA-256 digest of `data`, in hexadecimal, as specified in
+/// FIPS 180-4.
+fn sha256_hex(data: &[u8]) -> String {
+    const K: [u32; 64] = [
+        0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4,
+        0xab1c5ed5, 0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe,
+        0x9bdc06a7, 0xc19bf174, 0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f,
+        0x4a7484aa, 0x5cb0a9dc, 0x76f988da, 0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7,
+        0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967, 0x27b70a85, 0x2e1b2138, 0x4d2c6dfc,
+        0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85, 0xa2bfe8a1, 0xa81a664b,
+        0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070, 0x19a4c116,
+        0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+        0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7,
+        0xc67178f2,
+    ];
+
+    let mut state: [u32; 8] = [
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+        0x5be0cd19,
+    ];
+
+    // The message is padded with a 1 bit, zeros and its length in bits, to a
+    // multiple of 64 bytes.
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+
+    for block in message.chunks(64) {
+        let mut w = [0u32; 64];
+
+        for (word, bytes) in w.iter_mut().zip(block.chunks(4)) {
+            *word = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = state;
+
+        for (k, w) in K.iter().zip(w.iter()) {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(*k)
+                .wrapping_add(*w);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+
+        for (word, value) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *word = word.wrapping_add(value);
+        }
+    }
+
+    state.iter().map(|word| format!("{:08x}", word)).collect()
+}
@@ -0,0 +1,533 @@
+//! Append only file persistence.
+//!
+//! When enabled, every write applied to the `Db` is appended to the AOF as the
+//! command that reproduces it, encoded in RESP just like a client would send
+//! it. On startup the AOF is replayed to rebuild the dataset.
+//!
+//! Writes are handed to the AOF by the `Db` while it holds its lock, so the
+//! order of the commands in the file is the order in which they were
+//! applied. The file itself is written by a dedicated thread, so the lock is
+//! never held while waiting on the disk. Keys
+//! expiring are logged as `DEL`, and expirations are logged as absolute
+//! times (`SET key value PXAT <unix-ms>`), so replaying the file later does
+//! not extend the life of a key.
+//!
+//! Writes are logged against the database they were applied to. A `SELECT`
+//! is logged before a write whenever the database differs from the one of
+//! the previous write, so replaying the file always starts from database 0.
+//!
+//! As the AOF only grows, `BGREWRITEAOF` rewrites it from the current
+//! dataset: one `SET` per key. Writes applied while the new file is written
+//! are buffered and appended to it before it replaces the old one.
+
+use crate::cmd::{Del, FlushAll, FlushDb, FlushMode, Move, Select, Set, SwapDb};
+use crate::config::AppendFsync;
+use crate::frame::{self, Frame};
+use crate::snapshot::{self, Record};
+use crate::{Command, Db};
+
+use bytes::Bytes;
+use std::fs::{self, File, OpenOptions};
+use std::future::Future;
+use std::io::{self, Cursor, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::SystemTime;
+use tokio::sync::{oneshot, watch};
+use tokio::time::{self, Duration};
+use tracing::{error, info, warn};
+
+/// An open append only file.
+///
+/// The file is owned by a writer thread, which performs the operations sent
+/// to it in order. The `Db` sends them while it holds its lock, but never
+/// waits on the disk.
+#[derive(Debug)]
+pub(crate) struct Aof {
+    path: PathBuf,
+
+    /// Sends operations to the writer thread. The thread exits once it is
+    /// dropped.
+    tx: mpsc::Sender<Op>,
+
+    /// `true` from the start of a rewrite until the writer thread is done
+    /// with it.
+    rewriting: Arc<AtomicBool>,
+
+    /// Number of writes sent to the writer thread.
+    appended: u64,
+
+    /// Number of writes the writer thread has written to the file, and
+    /// synced if the policy is `Always`.
+    written: watch::Receiver<u64>,
+}
+
+/// An operation performed by the writer thread.
+#[derive(Debug)]
+enum Op {
+    /// Appends an encoded write to a database.
+    Append { index: usize, buf: Vec<u8> },
+
+    /// Syncs the appended writes to disk, then notifies the receiver.
+    Sync(mpsc::Sender<()>),
+
+    /// Starts buffering appended writes for a rewrite.
+    BeginRewrite,
+
+    /// Completes or abandons a rewrite, see `Aof::finish_rewrite`.
+    FinishRewrite {
+        tmp: PathBuf,
+        written: io::Result<()>,
+        done: oneshot::Sender<io::Result<()>>,
+    },
+}
+
+/// State of the writer thread.
+#[derive(Debug)]
+struct Writer {
+    path: PathBuf,
+
+    /// Opened in append mode.
+    file: File,
+
+    fsync: AppendFsync,
+
+    /// Database selected by the last `SELECT` in the file, `None` if unknown.
+    selected: Option<usize>,
+
+    /// Writes appended while a rewrite is in progress. `None` when there is no
+    /// rewrite in progress.
+    rewrite: Option<Rewrite>,
+
+    rewriting: Arc<AtomicBool>,
+    written: watch::Sender<u64>,
+}
+
+/// Writes buffered during a rewrite, to be appended to the rewritten file.
+#[derive(Debug)]
+struct Rewrite {
+    buf: Vec<u8>,
+
+    /// Database selected by the last `SELECT` in `buf`. It starts unknown, as
+    /// it is the last one of the rewritten dataset.
+    selected: Option<usize>,
+}
+
+impl Aof {
+    /// Opens the AOF at `path` for appending, creating it if needed, and
+    /// starts its writer thread.
+    fn open(path: PathBuf, fsync: AppendFsync) -> io::Result<Aof> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let rewriting = Arc::new(AtomicBool::new(false));
+        let (tx, rx) = mpsc::channel();
+        let (written_tx, written) = watch::channel(0);
+
+        let writer = Writer {
+            path: path.clone(),
+            file,
+            fsync,
+            selected: None,
+            rewrite: None,
+            rewriting: rewriting.clone(),
+            written: written_tx,
+        };
+
+        thread::Builder::new()
+            .name("aof-writer".to_string())
+            .spawn(move || writer.run(rx))?;
+
+        Ok(Aof {
+            path,
+            tx,
+            rewriting,
+            appended: 0,
+            written,
+        })
+    }
+
+    /// Appends an encoded write to database `index` to the file, syncing it
+    /// if the policy is `Always`.
+    ///
+    /// The write is only queued. Errors are logged by the writer thread and
+    /// otherwise ignored: the write has already been applied to the `Db` and
+    /// cannot be rejected anymore.
+    pub(crate) fn append(&mut self, index: usize, buf: Vec<u8>) {
+        self.appended += 1;
+        self.send(Op::Append { index, buf });
+    }
+
+    /// Returns a future that completes once the writes appended so far are
+    /// written to the file, and synced if the policy is `Always`.
+    pub(crate) fn written(&self) -> impl Future<Output = ()> {
+        let appended = self.appended;
+        let mut written = self.written.clone();
+
+        async move {
+            while *written.borrow_and_update() < appended {
+                // The writer thread exited, as the server shut down.
+                if written.changed().await.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Syncs the writes appended so far to disk. The returned receiver is
+    /// notified once they are synced.
+    pub(crate) fn sync(&self) -> mpsc::Receiver<()> {
+        let (tx, rx) = mpsc::channel();
+        self.send(Op::Sync(tx));
+        rx
+    }
+
+    /// Returns `true` if a rewrite is in progress.
+    pub(crate) fn is_rewriting(&self) -> bool {
+        self.rewriting.load(Ordering::Acquire)
+    }
+
+    /// Starts buffering appended writes for a rewrite. Returns the path the
+    /// rewritten file is written to.
+    pub(crate) fn begin_rewrite(&mut self) -> crate::Result<PathBuf> {
+        if self
+            .rewriting
+            .compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
+            .is_err()
+        {
+            return Err("background AOF rewrite already in progress".into());
+        }
+
+        self.send(Op::BeginRewrite);
+        Ok(rewrite_path(&self.path))
+    }
+
+    /// Completes a rewrite once the current dataset has been written to
+    /// `tmp`, or abandons it if `written` is an error.
+    ///
+    /// The writes buffered since the rewrite started are appended to `tmp`,
+    /// which then replaces the AOF. Must be called with the `Db` lock held,
+    /// so the writes that follow are appended to the new file. The returned
+    /// receiver gets the outcome.
+    pub(crate) fn finish_rewrite(
+        &mut self,
+        tmp: PathBuf,
+        written: io::Result<()>,
+    ) -> oneshot::Receiver<io::Result<()>> {
+        let (done, rx) = oneshot::channel();
+        self.send(Op::FinishRewrite { tmp, written, done });
+        rx
+    }
+
+    fn send(&self, op: Op) {
+        // The writer thread only exits once the sender is dropped.
+        let _ = self.tx.send(op);
+    }
+}
+
+impl Writer {
+    /// Performs the operations received on `rx` until the `Aof` is dropped.
+    fn run(mut self, rx: mpsc::Receiver<Op>) {
+        for op in rx {
+            match op {
+                Op::Append { index, buf } => self.append(index, &buf),
+                Op::Sync(done) => {
+                    if let Err(err) = self.file.sync_data() {
+                        error!(cause = %err, "failed to sync the AOF");
+                    }
+                    let _ = done.send(());
+                }
+                Op::BeginRewrite => {
+                    self.rewrite = Some(Rewrite {
+                        buf: Vec::new(),
+                        selected: None,
+                    });
+                }
+                Op::FinishRewrite { tmp, written, done } => {
+                    let res = self.finish_rewrite(&tmp, written);
+                    self.rewriting.store(false, Ordering::Release);
+                    let _ = done.send(res);
+                }
+            }
+        }
+    }
+
+    fn append(&mut self, index: usize, buf: &[u8]) {
+        if let Some(rewrite) = &mut self.rewrite {
+            select(&mut rewrite.selected, index, &mut rewrite.buf);
+            rewrite.buf.extend_from_slice(buf);
+        }
+
+        let mut select_buf = Vec::new();
+        select(&mut self.selected, index, &mut select_buf);
+
+        let res = self
+            .file
+            .write_all(&select_buf)
+            .and_then(|()| self.file.write_all(buf))
+            .and_then(|()| match self.fsync {
+            AppendFsync::Always => self.file.sync_data(),
+            _ => Ok(()),
+        });
+
+        if let Err(err) = res {
+            error!(cause = %err, "failed to append to the AOF");
+        }
+
+        self.written.send_modify(|written| *written += 1);
+    }
+
+    fn finish_rewrite(&mut self, tmp: &Path, written: io::Result<()>) -> io::Result<()> {
+        let rewrite = match self.rewrite.take() {
+            Some(rewrite) => rewrite,
+            None => return Err(io::Error::other("no AOF rewrite in progress")),
+        };
+
+        let res = written.and_then(|()| {
+            let mut file = OpenOptions::new().append(true).open(tmp)?;
+            file.write_all(&rewrite.buf)?;
+            file.sync_all()?;
+            fs::rename(tmp, &self.path)?;
+
+            // The handle follows the renamed file.
+            self.file = file;
+            self.selected = rewrite.selected;
+            Ok(())
+        });
+
+        if res.is_err() {
+            let _ = fs::remove_file(tmp);
+        }
+
+        res
+    }
+}
+
+/// Returns the frame of the `SET` command that sets `key` to `value`, expiring
+/// at `expires_at`.
+pub(crate) fn set_frame(key: &str, value: Bytes, expires_at: Option<SystemTime>) -> Frame {
+    let mut frame = Set::new(key, value, None).into_frame();
+
+    if let Some(expires_at) = expires_at {
+        frame.push_bulk(Bytes::from("pxat".as_bytes()));
+        frame.push_int(snapshot::unix_millis(expires_at));
+    }
+
+    frame
+}
+
+/// Returns the frame of the `DEL` command that removes `key`.
+pub(crate) fn del_frame(key: &str) -> Frame {
+    Del::new(&[key.to_string()]).into_frame()
+}
+
+/// Returns the frame of the `SELECT` command that selects database `index`.
+pub(crate) fn select_frame(index: usize) -> Frame {
+    Select::new(index).into_frame()
+}
+
+/// Returns the frame of the `MOVE` command that moves `key` to database `db`.
+pub(crate) fn move_frame(key: &str, db: usize) -> Frame {
+    Move::new(key, db).into_frame()
+}
+
+/// Returns the frame of the `SWAPDB` command that swaps databases `first` and
+/// `second`.
+pub(crate) fn swapdb_frame(first: usize, second: usize) -> Frame {
+    SwapDb::new(first, second).into_frame()
+}
+
+/// Returns the frame of the `FLUSHDB` command. How the keys are released does
+/// not matter when replaying it.
+pub(crate) fn flushdb_frame() -> Frame {
+    FlushDb::new(FlushMode::Sync).into_frame()
+}
+
+/// Returns the frame of the `FLUSHALL` command.
+pub(crate) fn flushall_frame() -> Frame {
+    FlushAll::new(FlushMode::Sync).into_frame()
+}
+
+/// Encodes a `SELECT` of database `index` to `dst` if `selected` is another
+/// one, and records it as selected.
+fn select(selected: &mut Option<usize>, index: usize, dst: &mut Vec<u8>) {
+    if *selected != Some(index) {
+        select_frame(index).encode(dst);
+        *selected = Some(index);
+    }
+}
+
+/// Replays the AOF at the configured path into `db`, then opens it to log
+/// the writes that follow.
+///
+/// A missing AOF is created empty. If the server stopped while a command was
+/// being appended, the truncated command at the end of the file is discarded.
+/// Any other invalid content is an error.
+pub(crate) async fn open(db: &Db) -> crate::Result<()> {
+    let path = match db.aof_path() {
+        Some(path) => path.to_path_buf(),
+        None => return Ok(()),
+    };
+
+    let data = match tokio::fs::read(&path).await {
+        Ok(data) => data,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => vec![],
+        Err(err) => return Err(err.into()),
+    };
+
+    let (num_commands, len) = replay(db, &data)?;
+
+    if len < data.len() {
+        warn!(
+            path = %path.display(),
+            discarded = data.len() - len,
+            "AOF ends with a truncated command, discarding it"
+        );
+
+        // Truncate the file, so the writes that follow are not appended
+        // after the partial command.
+        OpenOptions::new().write(true).open(&path)?.set_len(len as u64)?;
+    }
+
+    let fsync = db.appendfsync();
+    db.attach_aof(Aof::open(path.clone(), fsync)?);
+
+    if fsync == AppendFsync::EverySec {
+        tokio::spawn(sync_every_second(db.clone()));
+    }
+
+    info!(path = %path.display(), num_commands, "AOF loaded");
+    Ok(())
+}
+
+/// Starts rewriting the AOF from the current dataset in the background.
+///
+/// Used by `BGREWRITEAOF`. Returns once the dataset has been copied, the
+/// outcome of the rewrite is only logged.
+pub(crate) fn bgrewrite(db: &Db) -> crate::Result<()> {
+    let (records, tmp) = db.begin_aof_rewrite()?;
+    let db = db.clone();
+
+    tokio::spawn(async move {
+        let path = tmp.clone();
+        let res = tokio::task::spawn_blocking(move || write_records(&path, &records)).await;
+
+        let written = match res {
+            Ok(res) => res,
+            Err(err) => Err(io::Error::other(err)),
+        };
+
+        let done = match db.with_aof(|aof| aof.finish_rewrite(tmp.clone(), written)) {
+            Some(done) => done,
+            // The server shut down while rewriting.
+            None => {
+                let _ = fs::remove_file(&tmp);
+                return;
+            }
+        };
+
+        match done.await {
+            Ok(Ok(())) => info!("background AOF rewrite completed"),
+            Ok(Err(err)) => error!(cause = %err, "background AOF rewrite failed"),
+            // The writer thread exited, as the server shut down.
+            Err(_) => {}
+        }
+    });
+
+    Ok(())
+}
+
+/// Applies the commands in `data` to `db`.
+///
+/// Returns the number of commands applied and the length of `data` they
+/// span. It is shorter than `data` if the last command is truncated.
+fn replay(db: &Db, data: &[u8]) -> crate::Result<(usize, usize)> {
+    // The commands are applied through a handle of their own, as they select
+    // databases.
+    let mut db = db.clone();
+    let mut src = Cursor::new(data);
+    let mut num_commands = 0;
+
+    loop {
+        let start = src.position() as usize;
+
+        if start == data.len() {
+            return Ok((num_commands, start));
+        }
+
+        match Frame::check(&mut src) {
+            Ok(()) => {}
+            Err(frame::Error::Incomplete) => return Ok((num_commands, start)),
+            Err(frame::Error::Other(err)) => {
+                return Err(format!("invalid AOF at byte {}; {}", start, err).into())
+            }
+        }
+
+        src.set_position(start as u64);
+        apply(&mut db, Frame::parse(&mut src)?)?;
+
+        num_commands += 1;
+    }
+}
+
+/// Applies a write logged by `Db` to `db`.
+///
+/// Used to replay the AOF and to apply the replication stream on replicas,
+/// which carry the same commands. A `SELECT` changes the database `db`
+/// applies the commands that follow to.
+pub(crate) fn apply(db: &mut Db, frame: Frame) -> crate::Result<()> {
+    match Command::from_frame(frame)? {
+        Command::Set(cmd) => db.set(cmd.key().to_string(), cmd.value().clone(), cmd.expire()),
+        Command::Del(cmd) => {
+            db.del(cmd.keys());
+        }
+        Command::Select(cmd) => db.select(cmd.index())?,
+        Command::Move(cmd) => {
+            db.move_key(cmd.key(), cmd.db())?;
+        }
+        Command::SwapDb(cmd) => db.swapdb(cmd.first(), cmd.second())?,
+        Command::FlushDb(cmd) => db.flushdb(cmd.mode()),
+        Command::FlushAll(cmd) => db.flushall(cmd.mode()),
+        cmd => return Err(format!("unexpected `{}` write", cmd.get_name()).into()),
+    }
+
+    Ok(())
+}
+
+/// Writes `records` to a new file at `path` as `SET` commands, selecting the
+/// database of each group of records.
+fn write_records(path: &Path, records: &[Record]) -> io::Result<()> {
+    let mut buf = Vec::new();
+    let mut selected = None;
+
+    for record in records {
+        select(&mut selected, record.db, &mut buf);
+        set_frame(&record.key, record.value.clone(), record.expires_at).encode(&mut buf);
+    }
+
+    let mut file = File::create(path)?;
+    file.write_all(&buf)?;
+    file.sync_all()
+}
+
+/// Syncs the AOF once per second, for the `EverySec` policy. Runs until the
+/// `Db` shuts down.
+async fn sync_every_second(db: Db) {
+    let mut interval = time::interval(Duration::from_secs(1));
+
+    loop {
+        interval.tick().await;
+
+        // The sync is only queued, the writer thread performs it.
+        if db.with_aof(|aof| aof.sync()).is_none() {
+            return;
+        }
+    }
+}
+
+/// Path the AOF at `path` is rewritten to, before replacing it.
+fn rewrite_path(path: &Path) -> PathBuf {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(format!(".rewrite-{}", std::process::id()));
+    tmp.into()
+}
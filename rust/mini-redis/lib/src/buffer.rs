@@ -1,5 +1,5 @@
 use crate::client::Client;
-use crate::Result;
+use crate::{Error, Result};
 
 use bytes::Bytes;
 use tokio::sync::mpsc::{channel, Receiver, Sender};
@@ -88,12 +88,12 @@ impl Buffer {
         let (tx, rx) = oneshot::channel();
 
         // Send the request
-        self.tx.send((get, tx)).await?;
+        self.tx.send((get, tx)).await.map_err(|_| Error::ConnectionClosed)?;
 
         // Await the response
         match rx.await {
             Ok(res) => res,
-            Err(err) => Err(err.into()),
+            Err(_) => Err(Error::ConnectionClosed),
         }
     }
 
@@ -109,12 +109,12 @@ impl Buffer {
         let (tx, rx) = oneshot::channel();
 
         // Send the request
-        self.tx.send((set, tx)).await?;
+        self.tx.send((set, tx)).await.map_err(|_| Error::ConnectionClosed)?;
 
         // Await the response
         match rx.await {
             Ok(res) => res.map(|_| ()),
-            Err(err) => Err(err.into()),
+            Err(_) => Err(Error::ConnectionClosed),
         }
     }
 }
@@ -2,14 +2,20 @@
 //!
 //! Provides an async connect and methods for issuing the supported commands.
 
-use crate::cmd::{Get, Ping, Publish, Set, Subscribe, Unsubscribe};
-use crate::{Connection, Frame};
+use crate::cmd::{
+    Acl, Auth, BgRewriteAof, BgSave, Del, FlushAll, FlushDb, FlushMode, Get, Info, Latency, Move, Object,
+    PauseMode, Ping, Psync, Publish, Pubsub, Reset, Save, SaveMode, Select, Set, Shutdown, Slowlog,
+    Subscribe, SwapDb, Unsubscribe,
+};
+use crate::{tls, Connection, Error, Frame, FromFrame};
 
 use async_stream::try_stream;
 use bytes::Bytes;
-use std::io::{Error, ErrorKind};
+use std::collections::VecDeque;
+use std::path::PathBuf;
 use std::time::Duration;
 use tokio::net::{TcpStream, ToSocketAddrs};
+use tokio_rustls::rustls::pki_types::ServerName;
 use tokio_stream::Stream;
 use tracing::{debug, instrument};
 
@@ -42,6 +48,47 @@ pub struct Subscriber {
 
     /// The set of channels to which the `Subscriber` is currently subscribed.
     subscribed_channels: Vec<String>,
+
+    /// Number of messages the server reported as dropped because this
+    /// subscriber was not reading fast enough.
+    lagged: u64,
+
+    /// Messages received while waiting for the response to another command,
+    /// such as `PING`. They are returned by `next_message` before reading
+    /// from the connection again.
+    pending: VecDeque<Message>,
+}
+
+/// A client that has entered the monitor state.
+///
+/// Once in the monitor state, the client receives a line per command executed
+/// by the server. The `Client` type is transitioned to a `Monitor` type, which
+/// can only read the lines or leave the monitor state.
+pub struct Monitor {
+    /// The monitoring client.
+    client: Client,
+}
+
+/// A command built with [`Client::cmd`], for commands `Client` has no method
+/// for.
+///
+/// Arguments are appended with `arg`, then the command is sent with `send`,
+/// returning the raw response, or `query`, decoding it.
+pub struct Cmd<'a> {
+    /// The client the command is sent on.
+    client: &'a mut Client,
+
+    /// The command name and the arguments, as an array of bulk strings.
+    frame: Frame,
+}
+
+/// A frame pushed by the server to a client in the subscribed state.
+enum Push {
+    /// A message published on a subscribed channel.
+    Message(Message),
+
+    /// The response to a `PING` issued in the subscribed state.
+    Pong(Bytes),
 }
 
 /// A message received on a subscribed channel.
@@ -51,6 +98,80 @@ pub struct Message {
     pub content: Bytes,
 }
 
+/// A command logged in the slow log of the server.
+#[derive(Debug, Clone)]
+pub struct SlowlogEntry {
+    /// Unique identifier, increasing with each entry logged.
+    pub id: u64,
+
+    /// Unix time the command was logged at, in seconds.
+    pub time: u64,
+
+    /// Time spent executing the command.
+    pub duration: Duration,
+
+    /// The command and its arguments, possibly shortened.
+    pub args: Vec<Bytes>,
+
+    /// Address of the client that sent the command.
+    pub addr: String,
+
+    /// Name of the client that sent the command, empty if it has none.
+    pub name: String,
+}
+
+/// The latest sample of a latency event of the server.
+#[derive(Debug, Clone)]
+pub struct LatencyEvent {
+    /// Name of the event, such as `command`.
+    pub name: String,
+
+    /// Unix time of the latest sample, in seconds.
+    pub time: u64,
+
+    /// Latency of the latest sample, in milliseconds.
+    pub latency: u64,
+
+    /// Highest latency ever sampled, in milliseconds.
+    pub max: u64,
+}
+
+/// A user of the server, as returned by `ACL GETUSER`.
+#[derive(Debug, Clone)]
+pub struct AclUser {
+    /// `on` or `off`, and `nopass` if any password authenticates as the
+    /// user.
+    pub flags: Vec<String>,
+
+    /// SHA-256 of the passwords, in hexadecimal.
+    pub passwords: Vec<String>,
+
+    /// Rules giving the commands the user can run, like `+@all -flushall`.
+    pub commands: String,
+
+    /// Patterns of the keys the user can access, like `~cache:*`.
+    pub keys: String,
+
+    /// Patterns of the channels the user can access, like `&news.*`.
+    pub channels: String,
+}
+
+/// How [`connect_tls`] establishes the TLS session. Files are in the PEM
+/// format.
+#[derive(Debug, Clone)]
+pub struct TlsOptions {
+    /// Certificates of the CAs the certificate of the server must be signed
+    /// by.
+    pub ca_cert_file: PathBuf,
+
+    /// Certificate presented to servers requiring one, along with
+    /// `key_file`.
+    pub cert_file: Option<PathBuf>,
+
+    /// Private key of `cert_file`.
+    pub key_file: Option<PathBuf>,
+}
+
 /// Establish a connection with the Redis server located at `addr`.
 ///
 /// `addr` may be any type that can be asynchronously converted to a
@@ -86,6 +207,61 @@ pub async fn connect<T: ToSocketAddrs>(addr: T) -> crate::Result<Client> {
     Ok(Client { connection })
 }
 
+/// Establish a connection with the Redis server located at `addr` and
+/// authenticate as `username`, or as the `default` user if `None`.
+///
+/// Fails if the credentials are refused.
+pub async fn connect_with_auth<T: ToSocketAddrs>(
+    addr: T,
+    username: Option<&str>,
+    password: &str,
+) -> crate::Result<Client> {
+    let mut client = connect(addr).await?;
+    client.auth(username, password).await?;
+
+    Ok(client)
+}
+
+/// Establish a TLS connection with the Redis server located at `addr`.
+///
+/// The certificate of the server must be valid for `server_name`, usually
+/// the host name in `addr`, and be signed by a CA of `options`.
+///
+/// # Examples
+///
+/// ```no_run
+/// use mini_redis::client::{self, TlsOptions};
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let options = TlsOptions {
+///         ca_cert_file: "ca.crt".into(),
+///         cert_file: None,
+///         key_file: None,
+///     };
+///
+///     let client = match client::connect_tls("localhost:6380", "localhost", &options).await {
+///         Ok(client) => client,
+///         Err(_) => panic!("failed to establish connection"),
+///     };
+/// # drop(client);
+/// }
+/// ```
+pub async fn connect_tls<T: ToSocketAddrs>(
+    addr: T,
+    server_name: &str,
+    options: &TlsOptions,
+) -> crate::Result<Client> {
+    let connector = tls::connector(options)?;
+    let server_name = ServerName::try_from(server_name.to_string())?;
+
+    let socket = TcpStream::connect(addr).await?;
+    let stream = connector.connect(server_name, socket).await?;
+    let connection = Connection::new_tls(stream.into());
+
+    Ok(Client { connection })
+}
+
 impl Client {
     /// Ping to the server.
     ///
@@ -252,30 +428,44 @@ impl Client {
         self.set_cmd(Set::new(key, value, Some(expiration))).await
     }
 
-    /// The core `SET` logic, used by both `set` and `set_expires.
-    async fn set_cmd(&mut self, cmd: Set) -> crate::Result<()> {
-        // Convert the `Set` command into a frame
-        let frame = cmd.into_frame();
+    /// Removes the specified `keys`.
+    ///
+    /// Returns the number of keys that were removed. Keys that do not exist
+    /// are ignored.
+    ///
+    /// # Examples
+    ///
+    /// Demonstrates basic usage.
+    ///
+    /// ```no_run
+    /// use mini_redis::client;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let mut client = client::connect("localhost:6379").await.unwrap();
+    ///
+    ///     client.set("foo", "bar".into()).await.unwrap();
+    ///
+    ///     let removed = client.del(&["foo".into()]).await.unwrap();
+    ///     assert_eq!(removed, 1);
+    /// }
+    /// ```
+    #[instrument(skip(self))]
+    pub async fn del(&mut self, keys: &[String]) -> crate::Result<u64> {
+        let frame = Del::new(keys).into_frame();
 
         debug!(request = ?frame);
 
-        // Write the frame to the socket. This writes the full frame to the
-        // socket, waiting if necessary.
         self.connection.write_frame(&frame).await?;
 
-        // Wait for the response from the server. On success, the server
-        // responds simply with `OK`. Any other response indicates an error.
         match self.read_response().await? {
-            Frame::Simple(response) if response == "OK" => Ok(()),
+            Frame::Integer(response) => Ok(response),
             frame => Err(frame.to_error()),
         }
     }
 
-    /// Posts `message` to the given `channel`.
-    ///
-    /// Returns the number of subscribers currently listening on the channel.
-    /// There is no guarantee that these subscribers receive the message as they
-    /// may disconnect at any time.
+    /// Selects the database the following commands of this connection operate
+    /// on. Every connection starts on database 0.
     ///
     /// # Examples
     ///
@@ -288,138 +478,1073 @@ impl Client {
     /// async fn main() {
     ///     let mut client = client::connect("localhost:6379").await.unwrap();
     ///
-    ///     let val = client.publish("foo", "bar".into()).await.unwrap();
-    ///     println!("Got = {:?}", val);
+    ///     client.select(1).await.unwrap();
+    ///     client.set("foo", "bar".into()).await.unwrap();
     /// }
     /// ```
     #[instrument(skip(self))]
-    pub async fn publish(&mut self, channel: &str, message: Bytes) -> crate::Result<u64> {
-        // Convert the `Publish` command into a frame
-        let frame = Publish::new(channel, message).into_frame();
+    pub async fn select(&mut self, index: usize) -> crate::Result<()> {
+        let frame = Select::new(index).into_frame();
 
         debug!(request = ?frame);
 
-        // Write the frame to the socket
         self.connection.write_frame(&frame).await?;
 
-        // Read the response
         match self.read_response().await? {
-            Frame::Integer(response) => Ok(response),
+            Frame::Simple(response) if response == "OK" => Ok(()),
             frame => Err(frame.to_error()),
         }
     }
 
-    /// Subscribes the client to the specified channels.
-    ///
-    /// Once a client issues a subscribe command, it may no longer issue any
-    /// non-pub/sub commands. The function consumes `self` and returns a `Subscriber`.
+    /// Moves `key` from the selected database to database `db`.
     ///
-    /// The `Subscriber` value is used to receive messages as well as manage the
-    /// list of channels the client is subscribed to.
+    /// Returns `false` if the key does not exist, or already exists in `db`.
     #[instrument(skip(self))]
-    pub async fn subscribe(mut self, channels: Vec<String>) -> crate::Result<Subscriber> {
-        // Issue the subscribe command to the server and wait for confirmation.
-        // The client will then have been transitioned into the "subscriber"
-        // state and may only issue pub/sub commands from that point on.
-        self.subscribe_cmd(&channels).await?;
+    pub async fn move_key(&mut self, key: &str, db: usize) -> crate::Result<bool> {
+        let frame = Move::new(key, db).into_frame();
 
-        // Return the `Subscriber` type
-        Ok(Subscriber {
-            client: self,
-            subscribed_channels: channels,
-        })
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Integer(moved) => Ok(moved == 1),
+            frame => Err(frame.to_error()),
+        }
     }
 
-    /// The core `SUBSCRIBE` logic, used by misc subscribe fns
-    async fn subscribe_cmd(&mut self, channels: &[String]) -> crate::Result<()> {
-        // Convert the `Subscribe` command into a frame
-        let frame = Subscribe::new(&channels).into_frame();
+    /// Swaps the contents of databases `first` and `second`.
+    #[instrument(skip(self))]
+    pub async fn swapdb(&mut self, first: usize, second: usize) -> crate::Result<()> {
+        let frame = SwapDb::new(first, second).into_frame();
 
         debug!(request = ?frame);
 
-        // Write the frame to the socket
         self.connection.write_frame(&frame).await?;
 
-        // For each channel being subscribed to, the server responds with a
-        // message confirming subscription to that channel.
-        for channel in channels {
-            // Read the response
-            let response = self.read_response().await?;
-
-            // Verify it is confirmation of subscription.
-            match response {
-                Frame::Array(ref frame) => match frame.as_slice() {
-                    // The server responds with an array frame in the form of:
-                    //
-                    // ```
-                    // [ "subscribe", channel, num-subscribed ]
-                    // ```
-                    //
-                    // where channel is the name of the channel and
-                    // num-subscribed is the number of channels that the client
-                    // is currently subscribed to.
-                    [subscribe, schannel, ..]
-                        if *subscribe == "subscribe" && *schannel == channel => {}
-                    _ => return Err(response.to_error()),
-                },
-                frame => return Err(frame.to_error()),
-            };
+        match self.read_response().await? {
+            Frame::Simple(response) if response == "OK" => Ok(()),
+            frame => Err(frame.to_error()),
         }
+    }
 
-        Ok(())
+    /// Removes every key of the selected database.
+    #[instrument(skip(self))]
+    pub async fn flushdb(&mut self, mode: FlushMode) -> crate::Result<()> {
+        let frame = FlushDb::new(mode).into_frame();
+
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Simple(response) if response == "OK" => Ok(()),
+            frame => Err(frame.to_error()),
+        }
     }
 
-    /// Reads a response frame from the socket.
-    ///
-    /// If an `Error` frame is received, it is converted to `Err`.
-    async fn read_response(&mut self) -> crate::Result<Frame> {
-        let response = self.connection.read_frame().await?;
+    /// Removes every key of every database.
+    #[instrument(skip(self))]
+    pub async fn flushall(&mut self, mode: FlushMode) -> crate::Result<()> {
+        let frame = FlushAll::new(mode).into_frame();
 
-        debug!(?response);
+        debug!(request = ?frame);
 
-        match response {
-            // Error frames are converted to `Err`
-            Some(Frame::Error(msg)) => Err(msg.into()),
-            Some(frame) => Ok(frame),
-            None => {
-                // Receiving `None` here indicates the server has closed the
-                // connection without sending a frame. This is unexpected and is
-                // represented as a "connection reset by peer" error.
-                let err = Error::new(ErrorKind::ConnectionReset, "connection reset by server");
+        self.connection.write_frame(&frame).await?;
 
-                Err(err.into())
-            }
+        match self.read_response().await? {
+            Frame::Simple(response) if response == "OK" => Ok(()),
+            frame => Err(frame.to_error()),
         }
     }
-}
 
-impl Subscriber {
-    /// Returns the set of channels currently subscribed to.
-    pub fn get_subscribed(&self) -> &[String] {
-        &self.subscribed_channels
+    /// Returns the number of seconds since `key` was last read or written, or
+    /// `None` if it does not exist.
+    #[instrument(skip(self))]
+    pub async fn object_idletime(&mut self, key: &str) -> crate::Result<Option<u64>> {
+        self.object_cmd(Object::IdleTime(key.to_string())).await
     }
 
-    /// Receive the next message published on a subscribed channel, waiting if
-    /// necessary.
-    ///
-    /// `None` indicates the subscription has been terminated.
-    pub async fn next_message(&mut self) -> crate::Result<Option<Message>> {
-        match self.client.connection.read_frame().await? {
-            Some(mframe) => {
-                debug!(?mframe);
+    /// Returns the access frequency counter of `key`, used by the LFU eviction
+    /// policies, or `None` if it does not exist.
+    #[instrument(skip(self))]
+    pub async fn object_freq(&mut self, key: &str) -> crate::Result<Option<u64>> {
+        self.object_cmd(Object::Freq(key.to_string())).await
+    }
 
-                match mframe {
-                    Frame::Array(ref frame) => match frame.as_slice() {
-                        [message, channel, content] if *message == "message" => Ok(Some(Message {
-                            channel: channel.to_string(),
-                            content: Bytes::from(content.to_string()),
-                        })),
-                        _ => Err(mframe.to_error()),
+    /// Returns the server information report for `section`, or for the
+    /// default sections if `None`, in the text format of Redis' `INFO`.
+    #[instrument(skip(self))]
+    pub async fn info(&mut self, section: Option<&str>) -> crate::Result<String> {
+        let frame = Info::new(section.map(str::to_string)).into_frame();
+
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Bulk(report) => Ok(String::from_utf8(report.to_vec())?),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Returns up to `count` entries of the slow log of the server, newest
+    /// first, or all of them if `None`.
+    #[instrument(skip(self))]
+    pub async fn slowlog_get(&mut self, count: Option<usize>) -> crate::Result<Vec<SlowlogEntry>> {
+        let frame = Slowlog::Get(count).into_frame();
+
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Array(entries) => entries
+                .into_iter()
+                .map(|entry| match entry {
+                    Frame::Array(ref fields) => match &fields[..] {
+                        [Frame::Integer(id), Frame::Integer(time), Frame::Integer(usec), Frame::Array(args), addr, name] => {
+                            let args = args
+                                .iter()
+                                .map(|arg| match arg {
+                                    Frame::Bulk(arg) => Ok(arg.clone()),
+                                    frame => Err(frame.to_error()),
+                                })
+                                .collect::<crate::Result<_>>()?;
+
+                            Ok(SlowlogEntry {
+                                id: *id,
+                                time: *time,
+                                duration: Duration::from_micros(*usec),
+                                args,
+                                addr: addr.to_string(),
+                                name: name.to_string(),
+                            })
+                        }
+                        _ => Err(entry.to_error()),
                     },
                     frame => Err(frame.to_error()),
-                }
-            }
-            None => Ok(None),
+                })
+                .collect(),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Returns the number of entries in the slow log of the server.
+    #[instrument(skip(self))]
+    pub async fn slowlog_len(&mut self) -> crate::Result<u64> {
+        let frame = Slowlog::Len.into_frame();
+
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Integer(len) => Ok(len),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Removes every entry of the slow log of the server.
+    #[instrument(skip(self))]
+    pub async fn slowlog_reset(&mut self) -> crate::Result<()> {
+        let frame = Slowlog::Reset.into_frame();
+
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Simple(response) if response == "OK" => Ok(()),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Returns the latest sample of each latency event of the server.
+    #[instrument(skip(self))]
+    pub async fn latency_latest(&mut self) -> crate::Result<Vec<LatencyEvent>> {
+        let frame = Latency::Latest.into_frame();
+
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Array(events) => events
+                .into_iter()
+                .map(|event| match event {
+                    Frame::Array(ref fields) => match &fields[..] {
+                        [name, Frame::Integer(time), Frame::Integer(latency), Frame::Integer(max)] => {
+                            Ok(LatencyEvent {
+                                name: name.to_string(),
+                                time: *time,
+                                latency: *latency,
+                                max: *max,
+                            })
+                        }
+                        _ => Err(event.to_error()),
+                    },
+                    frame => Err(frame.to_error()),
+                })
+                .collect(),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Returns the samples of the latency event `event` of the server, oldest
+    /// first, as pairs of their Unix time in seconds and their latency in
+    /// milliseconds.
+    #[instrument(skip(self))]
+    pub async fn latency_history(&mut self, event: &str) -> crate::Result<Vec<(u64, u64)>> {
+        let frame = Latency::History(event.to_string()).into_frame();
+
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Array(samples) => samples
+                .into_iter()
+                .map(|sample| match sample {
+                    Frame::Array(ref fields) => match &fields[..] {
+                        [Frame::Integer(time), Frame::Integer(latency)] => Ok((*time, *latency)),
+                        _ => Err(sample.to_error()),
+                    },
+                    frame => Err(frame.to_error()),
+                })
+                .collect(),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Authenticates the connection as `username`, or as the `default` user
+    /// if `None`. The following commands run with the permissions of the
+    /// user.
+    #[instrument(skip(self, password))]
+    pub async fn auth(&mut self, username: Option<&str>, password: &str) -> crate::Result<()> {
+        let frame = Auth::new(username, password).into_frame();
+
+        // The request is not logged, it carries the password.
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Simple(response) if response == "OK" => Ok(()),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Applies the ACL `rules` to the user `username`, creating it if it
+    /// does not exist. No rule is applied if one is invalid.
+    #[instrument(skip(self, rules))]
+    pub async fn acl_setuser(&mut self, username: &str, rules: &[&str]) -> crate::Result<()> {
+        let rules = rules.iter().map(|rule| rule.to_string()).collect();
+        let frame = Acl::SetUser(username.to_string(), rules).into_frame();
+
+        // The request is not logged, the rules may carry passwords.
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Simple(response) if response == "OK" => Ok(()),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Returns the user `username`, `None` if it does not exist.
+    #[instrument(skip(self))]
+    pub async fn acl_getuser(&mut self, username: &str) -> crate::Result<Option<AclUser>> {
+        let frame = Acl::GetUser(username.to_string()).into_frame();
+
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        let fields = match self.read_response().await? {
+            Frame::Array(fields) => fields,
+            Frame::Null => return Ok(None),
+            frame => return Err(frame.to_error()),
+        };
+
+        let strings = |frame: &Frame| match frame {
+            Frame::Array(values) => Ok(values.iter().map(|value| value.to_string()).collect()),
+            frame => Err(frame.to_error()),
+        };
+
+        match &fields[..] {
+            [_, flags, _, passwords, _, commands, _, keys, _, channels] => Ok(Some(AclUser {
+                flags: strings(flags)?,
+                passwords: strings(passwords)?,
+                commands: commands.to_string(),
+                keys: keys.to_string(),
+                channels: channels.to_string(),
+            })),
+            _ => Err(Frame::Array(fields).to_error()),
+        }
+    }
+
+    /// Removes the users `usernames` and terminates the clients authenticated
+    /// as them. Returns how many users existed.
+    #[instrument(skip(self))]
+    pub async fn acl_deluser(&mut self, usernames: &[&str]) -> crate::Result<u64> {
+        let usernames = usernames.iter().map(|username| username.to_string()).collect();
+        let frame = Acl::DelUser(usernames).into_frame();
+
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Integer(deleted) => Ok(deleted),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Returns every user of the server, as the ACL rules that would create
+    /// it.
+    #[instrument(skip(self))]
+    pub async fn acl_list(&mut self) -> crate::Result<Vec<String>> {
+        let frame = Acl::List.into_frame();
+
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Array(users) => Ok(users.iter().map(|user| user.to_string()).collect()),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Returns the user this connection is authenticated as.
+    #[instrument(skip(self))]
+    pub async fn acl_whoami(&mut self) -> crate::Result<String> {
+        let frame = Acl::WhoAmI.into_frame();
+
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Bulk(username) => Ok(String::from_utf8(username.to_vec())?),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Returns the ACL command categories, or the commands in `category`.
+    #[instrument(skip(self))]
+    pub async fn acl_cat(&mut self, category: Option<&str>) -> crate::Result<Vec<String>> {
+        let frame = Acl::Cat(category.map(str::to_string)).into_frame();
+
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Array(names) => Ok(names.iter().map(|name| name.to_string()).collect()),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Returns the id the server gave to this connection.
+    #[instrument(skip(self))]
+    pub async fn client_id(&mut self) -> crate::Result<u64> {
+        let frame = crate::cmd::Client::Id.into_frame();
+
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Integer(id) => Ok(id),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Names this connection, as shown by `CLIENT LIST` and the slow log. An
+    /// empty name removes it.
+    #[instrument(skip(self))]
+    pub async fn client_setname(&mut self, name: &str) -> crate::Result<()> {
+        let frame = crate::cmd::Client::SetName(name.to_string()).into_frame();
+
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Simple(response) if response == "OK" => Ok(()),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Returns the name of this connection, `None` if it has none.
+    #[instrument(skip(self))]
+    pub async fn client_getname(&mut self) -> crate::Result<Option<String>> {
+        let frame = crate::cmd::Client::GetName.into_frame();
+
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Bulk(name) => Ok(Some(String::from_utf8(name.to_vec())?)),
+            Frame::Null => Ok(None),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Returns the description of every client connected to the server, one
+    /// per line, as `field=value` pairs.
+    #[instrument(skip(self))]
+    pub async fn client_list(&mut self) -> crate::Result<String> {
+        let frame = crate::cmd::Client::List.into_frame();
+
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Bulk(list) => Ok(String::from_utf8(list.to_vec())?),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Terminates the client with id `id`, returning whether it was
+    /// connected.
+    #[instrument(skip(self))]
+    pub async fn client_kill_id(&mut self, id: u64) -> crate::Result<bool> {
+        let frame = crate::cmd::Client::Kill {
+            id: Some(id),
+            addr: None,
+            user: None,
+            skipme: false,
+        }
+        .into_frame();
+
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Integer(killed) => Ok(killed > 0),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Terminates the client connected from `addr`, as `ip:port`. Fails if
+    /// there is none.
+    #[instrument(skip(self))]
+    pub async fn client_kill_addr(&mut self, addr: &str) -> crate::Result<()> {
+        let frame = crate::cmd::Client::KillAddr(addr.to_string()).into_frame();
+
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Simple(response) if response == "OK" => Ok(()),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Holds the commands of every client for `timeout`, or only those
+    /// writing keys with `PauseMode::Write`.
+    #[instrument(skip(self))]
+    pub async fn client_pause(&mut self, timeout: Duration, mode: PauseMode) -> crate::Result<()> {
+        let frame = crate::cmd::Client::Pause(timeout, mode).into_frame();
+
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Simple(response) if response == "OK" => Ok(()),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Ends a pause started with `client_pause`.
+    #[instrument(skip(self))]
+    pub async fn client_unpause(&mut self) -> crate::Result<()> {
+        let frame = crate::cmd::Client::Unpause.into_frame();
+
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Simple(response) if response == "OK" => Ok(()),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Saves a snapshot of the dataset on the server, returning once it has
+    /// been written to disk.
+    ///
+    /// Fails if the server has snapshot persistence disabled or is already
+    /// saving a snapshot.
+    #[instrument(skip(self))]
+    pub async fn save(&mut self) -> crate::Result<()> {
+        let frame = Save::new().into_frame();
+
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Simple(response) if response == "OK" => Ok(()),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Stops the server, saving a final snapshot according to `save`.
+    ///
+    /// Unless `now`, the server first waits for its replicas to receive every
+    /// write. Returns once the server has closed the connection. Fails if the
+    /// server cannot save as asked, or if `shutdown_abort` cancelled the
+    /// shutdown while it waited for the replicas.
+    #[instrument(skip(self))]
+    pub async fn shutdown(&mut self, save: SaveMode, now: bool) -> crate::Result<()> {
+        let frame = Shutdown::new(save, now).into_frame();
+
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        // Unlike other commands, success closes the connection without a
+        // response.
+        let response = self.connection.read_frame().await?;
+
+        debug!(?response);
+
+        match response {
+            None => Ok(()),
+            Some(Frame::Error(msg)) => Err(Error::server(msg)),
+            Some(frame) => Err(frame.to_error()),
+        }
+    }
+
+    /// Cancels a `shutdown` that is waiting for the replicas, from another
+    /// connection. Fails if there is none.
+    #[instrument(skip(self))]
+    pub async fn shutdown_abort(&mut self) -> crate::Result<()> {
+        let frame = Shutdown::abort().into_frame();
+
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Simple(response) if response == "OK" => Ok(()),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Starts saving a snapshot of the dataset on the server in the
+    /// background. Returns as soon as the save has started.
+    #[instrument(skip(self))]
+    pub async fn bgsave(&mut self) -> crate::Result<()> {
+        let frame = BgSave::new().into_frame();
+
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Simple(_) => Ok(()),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Starts rewriting the append only file on the server in the background.
+    /// Returns as soon as the rewrite has started.
+    #[instrument(skip(self))]
+    pub async fn bgrewriteaof(&mut self) -> crate::Result<()> {
+        let frame = BgRewriteAof::new().into_frame();
+
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Simple(_) => Ok(()),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Requests the replication stream of the server, returning its reply.
+    ///
+    /// Used by replicas. The stream is then read with `read_replicated`.
+    pub(crate) async fn psync(&mut self, cmd: Psync) -> crate::Result<String> {
+        let frame = cmd.into_frame();
+
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Simple(response) => Ok(response),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Reads the next frame of the replication stream.
+    pub(crate) async fn read_replicated(&mut self) -> crate::Result<Frame> {
+        self.read_response().await
+    }
+
+    /// The core `OBJECT` logic, used by `object_idletime` and `object_freq`.
+    async fn object_cmd(&mut self, cmd: Object) -> crate::Result<Option<u64>> {
+        let frame = cmd.into_frame();
+
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Integer(value) => Ok(Some(value)),
+            Frame::Null => Ok(None),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// The core `SET` logic, used by both `set` and `set_expires.
+    async fn set_cmd(&mut self, cmd: Set) -> crate::Result<()> {
+        // Convert the `Set` command into a frame
+        let frame = cmd.into_frame();
+
+        debug!(request = ?frame);
+
+        // Write the frame to the socket. This writes the full frame to the
+        // socket, waiting if necessary.
+        self.connection.write_frame(&frame).await?;
+
+        // Wait for the response from the server. On success, the server
+        // responds simply with `OK`. Any other response indicates an error.
+        match self.read_response().await? {
+            Frame::Simple(response) if response == "OK" => Ok(()),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Posts `message` to the given `channel`.
+    ///
+    /// Returns the number of subscribers currently listening on the channel.
+    /// There is no guarantee that these subscribers receive the message as they
+    /// may disconnect at any time.
+    ///
+    /// # Examples
+    ///
+    /// Demonstrates basic usage.
+    ///
+    /// ```no_run
+    /// use mini_redis::client;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let mut client = client::connect("localhost:6379").await.unwrap();
+    ///
+    ///     let val = client.publish("foo", "bar".into()).await.unwrap();
+    ///     println!("Got = {:?}", val);
+    /// }
+    /// ```
+    #[instrument(skip(self))]
+    pub async fn publish(&mut self, channel: &str, message: Bytes) -> crate::Result<u64> {
+        // Convert the `Publish` command into a frame
+        let frame = Publish::new(channel, message).into_frame();
+
+        debug!(request = ?frame);
+
+        // Write the frame to the socket
+        self.connection.write_frame(&frame).await?;
+
+        // Read the response
+        match self.read_response().await? {
+            Frame::Integer(response) => Ok(response),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Lists the currently active channels.
+    ///
+    /// A channel is active when it has at least one subscriber. If `pattern`
+    /// is given, only channels matching the glob-style pattern are returned.
+    ///
+    /// # Examples
+    ///
+    /// Demonstrates basic usage.
+    ///
+    /// ```no_run
+    /// use mini_redis::client;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let mut client = client::connect("localhost:6379").await.unwrap();
+    ///
+    ///     let channels = client.pubsub_channels(Some("news.*")).await.unwrap();
+    ///     println!("Got = {:?}", channels);
+    /// }
+    /// ```
+    #[instrument(skip(self))]
+    pub async fn pubsub_channels(&mut self, pattern: Option<&str>) -> crate::Result<Vec<String>> {
+        let frame = Pubsub::Channels(pattern.map(str::to_string)).into_frame();
+
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Array(channels) => Ok(channels.iter().map(|c| c.to_string()).collect()),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Returns the number of subscribers of each of the given `channels`.
+    ///
+    /// The result has one `(channel, subscribers)` entry per requested channel,
+    /// in the same order.
+    #[instrument(skip(self))]
+    pub async fn pubsub_numsub(&mut self, channels: &[String]) -> crate::Result<Vec<(String, u64)>> {
+        let frame = Pubsub::NumSub(channels.to_vec()).into_frame();
+
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        // The server responds with a flat array alternating channel names and
+        // subscriber counts.
+        match self.read_response().await? {
+            Frame::Array(ref parts) if parts.len() % 2 == 0 => parts
+                .chunks(2)
+                .map(|pair| match pair {
+                    [channel, Frame::Integer(count)] => Ok((channel.to_string(), *count)),
+                    _ => Err(Frame::Array(pair.to_vec()).to_error()),
+                })
+                .collect(),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Returns the number of pattern subscriptions on the server.
+    #[instrument(skip(self))]
+    pub async fn pubsub_numpat(&mut self) -> crate::Result<u64> {
+        let frame = Pubsub::NumPat.into_frame();
+
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Integer(response) => Ok(response),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Starts building an arbitrary command, such as
+    /// `client.cmd("HSET").arg(key).arg(field).arg(value)`.
+    ///
+    /// Commands changing the state of the connection, like `SUBSCRIBE` or
+    /// `MONITOR`, must be issued with their own methods instead.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use mini_redis::client;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let mut client = client::connect("localhost:6379").await.unwrap();
+    ///
+    ///     let deleted: i64 = client.cmd("DEL").arg("foo").arg("bar").query().await.unwrap();
+    ///     println!("deleted {} keys", deleted);
+    /// }
+    /// ```
+    pub fn cmd(&mut self, name: &str) -> Cmd<'_> {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::copy_from_slice(name.as_bytes()));
+
+        Cmd {
+            client: self,
+            frame,
+        }
+    }
+
+    /// Subscribes the client to the specified channels.
+    ///
+    /// Once a client issues a subscribe command, it may no longer issue any
+    /// non-pub/sub commands. The function consumes `self` and returns a `Subscriber`.
+    ///
+    /// The `Subscriber` value is used to receive messages as well as manage the
+    /// list of channels the client is subscribed to.
+    #[instrument(skip(self))]
+    pub async fn subscribe(mut self, channels: Vec<String>) -> crate::Result<Subscriber> {
+        // Issue the subscribe command to the server and wait for confirmation.
+        // The client will then have been transitioned into the "subscriber"
+        // state and may only issue pub/sub commands from that point on.
+        self.subscribe_cmd(&channels).await?;
+
+        // Return the `Subscriber` type
+        Ok(Subscriber {
+            client: self,
+            subscribed_channels: channels,
+            lagged: 0,
+            pending: VecDeque::new(),
+        })
+    }
+
+    /// Enters the monitor state, receiving a line per command executed by the
+    /// server from now on.
+    ///
+    /// The function consumes `self` and returns a `Monitor`.
+    #[instrument(skip(self))]
+    pub async fn monitor(mut self) -> crate::Result<Monitor> {
+        let frame = crate::cmd::Monitor::new().into_frame();
+
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Simple(response) if response == "OK" => Ok(Monitor { client: self }),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// The core `SUBSCRIBE` logic, used by misc subscribe fns
+    async fn subscribe_cmd(&mut self, channels: &[String]) -> crate::Result<()> {
+        // Convert the `Subscribe` command into a frame
+        let frame = Subscribe::new(&channels).into_frame();
+
+        debug!(request = ?frame);
+
+        // Write the frame to the socket
+        self.connection.write_frame(&frame).await?;
+
+        // For each channel being subscribed to, the server responds with a
+        // message confirming subscription to that channel.
+        for channel in channels {
+            // Read the response
+            let response = self.read_response().await?;
+
+            // Verify it is confirmation of subscription.
+            match response {
+                Frame::Array(ref frame) => match frame.as_slice() {
+                    // The server responds with an array frame in the form of:
+                    //
+                    // ```
+                    // [ "subscribe", channel, num-subscribed ]
+                    // ```
+                    //
+                    // where channel is the name of the channel and
+                    // num-subscribed is the number of channels that the client
+                    // is currently subscribed to.
+                    [subscribe, schannel, ..]
+                        if *subscribe == "subscribe" && *schannel == channel => {}
+                    _ => return Err(response.to_error()),
+                },
+                frame => return Err(frame.to_error()),
+            };
+        }
+
+        Ok(())
+    }
+
+    /// Reads a response frame from the socket.
+    ///
+    /// If an `Error` frame is received, it is converted to `Err`.
+    async fn read_response(&mut self) -> crate::Result<Frame> {
+        let response = self.connection.read_frame().await?;
+
+        debug!(?response);
+
+        match response {
+            // Error frames are converted to `Err`
+            Some(Frame::Error(msg)) => Err(Error::server(msg)),
+            Some(frame) => Ok(frame),
+            // Receiving `None` here indicates the server has closed the
+            // connection without sending a frame.
+            None => Err(Error::ConnectionClosed),
+        }
+    }
+}
+
+impl Cmd<'_> {
+    /// Appends an argument to the command.
+    pub fn arg(mut self, arg: impl AsRef<[u8]>) -> Self {
+        self.frame.push_bulk(Bytes::copy_from_slice(arg.as_ref()));
+        self
+    }
+
+    /// Sends the command and returns the response frame.
+    ///
+    /// An error reply is returned as `Error::Server`.
+    #[instrument(skip(self))]
+    pub async fn send(self) -> crate::Result<Frame> {
+        debug!(request = ?self.frame);
+
+        self.client.connection.write_frame(&self.frame).await?;
+
+        self.client.read_response().await
+    }
+
+    /// Sends the command and decodes the response as a `T`.
+    pub async fn query<T: FromFrame>(self) -> crate::Result<T> {
+        T::from_frame(self.send().await?)
+    }
+}
+
+impl Monitor {
+    /// Receive the line of the next command executed by the server, waiting if
+    /// necessary, such as `1339518083.107412 [0 127.0.0.1:60866] "get" "foo"`.
+    ///
+    /// `None` indicates the server closed the connection.
+    pub async fn next_line(&mut self) -> crate::Result<Option<String>> {
+        match self.client.connection.read_frame().await? {
+            Some(Frame::Simple(line)) => Ok(Some(line)),
+            Some(frame) => Err(frame.to_error()),
+            None => Ok(None),
+        }
+    }
+
+    /// Leaves the monitor state.
+    ///
+    /// Returns the `Client`, which can issue any command again. Lines not
+    /// received yet are discarded.
+    #[instrument(skip(self))]
+    pub async fn reset(mut self) -> crate::Result<Client> {
+        let frame = Reset::new().into_frame();
+        debug!(request = ?frame);
+
+        self.client.connection.write_frame(&frame).await?;
+
+        // Lines of commands executed before the server processed the `RESET`
+        // may still be received first. They are skipped.
+        loop {
+            match self.client.read_response().await? {
+                Frame::Simple(response) if response == "RESET" => return Ok(self.client),
+                Frame::Simple(_) => {}
+                frame => return Err(frame.to_error()),
+            }
+        }
+    }
+}
+
+impl Subscriber {
+    /// Returns the set of channels currently subscribed to.
+    pub fn get_subscribed(&self) -> &[String] {
+        &self.subscribed_channels
+    }
+
+    /// Returns the number of messages the server dropped so far because this
+    /// subscriber was not receiving them fast enough.
+    ///
+    /// Whether messages are dropped or the subscriber is disconnected instead
+    /// depends on the server configuration.
+    pub fn lagged_messages(&self) -> u64 {
+        self.lagged
+    }
+
+    /// Receive the next message published on a subscribed channel, waiting if
+    /// necessary.
+    ///
+    /// `None` indicates the subscription has been terminated.
+    pub async fn next_message(&mut self) -> crate::Result<Option<Message>> {
+        // Messages received while waiting for a `PING` response come first.
+        if let Some(message) = self.pending.pop_front() {
+            return Ok(Some(message));
+        }
+
+        match self.read_push().await? {
+            Some(Push::Message(message)) => Ok(Some(message)),
+            // No `PING` is in flight, a `pong` is unexpected here.
+            Some(Push::Pong(_)) => Err("unexpected `pong` received".into()),
+            None => Ok(None),
+        }
+    }
+
+    /// Ping the server through the subscribed connection.
+    ///
+    /// Returns PONG if no argument is provided, otherwise return a copy of the
+    /// argument. Long lived subscriber connections can use this to check the
+    /// connection is still alive, for example when they are idle behind a
+    /// load balancer that drops inactive connections.
+    ///
+    /// Messages published while waiting for the response are not lost, they
+    /// are returned by the next calls to `next_message`.
+    #[instrument(skip(self))]
+    pub async fn ping(&mut self, msg: Option<String>) -> crate::Result<Bytes> {
+        let no_msg = msg.is_none();
+        let frame = Ping::new(msg).into_frame();
+        debug!(request = ?frame);
+
+        self.client.connection.write_frame(&frame).await?;
+
+        loop {
+            match self.read_push().await? {
+                Some(Push::Message(message)) => self.pending.push_back(message),
+                // In the subscribed state the server echoes an empty message
+                // when none was sent. `PONG` is returned instead, like
+                // `Client::ping` does.
+                Some(Push::Pong(_)) if no_msg => return Ok(Bytes::from_static(b"PONG")),
+                Some(Push::Pong(value)) => return Ok(value),
+                None => return Err(Error::ConnectionClosed),
+            }
+        }
+    }
+
+    /// Leaves the subscribed state, unsubscribing from all channels.
+    ///
+    /// Returns the `Client`, which can issue any command again. Messages not
+    /// received yet are discarded.
+    #[instrument(skip(self))]
+    pub async fn reset(mut self) -> crate::Result<Client> {
+        let frame = Reset::new().into_frame();
+        debug!(request = ?frame);
+
+        self.client.connection.write_frame(&frame).await?;
+
+        // Messages published before the server processed the `RESET` may
+        // still be received first. They are skipped.
+        loop {
+            match self.client.read_response().await? {
+                Frame::Simple(response) if response == "RESET" => return Ok(self.client),
+                Frame::Array(_) => {}
+                frame => return Err(frame.to_error()),
+            }
+        }
+    }
+
+    /// Reads the next frame pushed by the server.
+    ///
+    /// `lagged` notifications are recorded and skipped. `None` indicates the
+    /// subscription has been terminated.
+    async fn read_push(&mut self) -> crate::Result<Option<Push>> {
+        loop {
+            let mframe = match self.client.connection.read_frame().await? {
+                Some(mframe) => mframe,
+                None => return Ok(None),
+            };
+
+            debug!(?mframe);
+
+            match mframe {
+                Frame::Array(ref frame) => match frame.as_slice() {
+                    [message, channel, content] if *message == "message" => {
+                        return Ok(Some(Push::Message(Message {
+                            channel: channel.to_string(),
+                            content: Bytes::from(content.to_string()),
+                        })))
+                    }
+                    [pong, Frame::Bulk(value)] if *pong == "pong" => {
+                        return Ok(Some(Push::Pong(value.clone())))
+                    }
+                    // The server dropped messages because we fell behind.
+                    // Record it and keep waiting for the next message.
+                    [lagged, _, Frame::Integer(num_dropped)] if *lagged == "lagged" => {
+                        self.lagged += num_dropped;
+                    }
+                    _ => return Err(mframe.to_error()),
+                },
+                frame => return Err(frame.to_error()),
+            }
         }
     }
 
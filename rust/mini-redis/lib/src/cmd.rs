@@ -1,22 +1,116 @@
+mod acl;
+pub use acl::Acl;
+
+mod auth;
+pub use auth::Auth;
+
+mod bgrewriteaof;
+pub use bgrewriteaof::BgRewriteAof;
+
+mod client;
+pub use client::{Client, PauseMode};
+
+mod del;
+pub use del::Del;
+
+mod flush;
+pub use flush::{FlushAll, FlushDb, FlushMode};
+
 mod get;
 pub use get::Get;
 
+mod info;
+pub use info::Info;
+
+mod latency;
+pub use latency::Latency;
+
+mod monitor;
+pub use monitor::Monitor;
+pub(crate) use monitor::monitor_line;
+
+mod object;
+pub use object::Object;
+
+mod r#move;
+pub use r#move::Move;
+
+mod psync;
+pub use psync::Psync;
+
 mod publish;
 pub use publish::Publish;
 
+mod pubsub;
+pub use pubsub::Pubsub;
+
+mod save;
+pub use save::{BgSave, Save};
+
+mod select;
+pub use select::Select;
+
 mod set;
 pub use set::Set;
 
+mod shutdown;
+pub use shutdown::{SaveMode, Shutdown};
+
+mod slowlog;
+pub use slowlog::Slowlog;
+
 mod subscribe;
 pub use subscribe::{Subscribe, Unsubscribe};
 
+mod swapdb;
+pub use swapdb::SwapDb;
+
 mod ping;
 pub use ping::Ping;
 
+mod quit;
+pub use quit::Quit;
+
+mod reset;
+pub use reset::Reset;
+
 mod unknown;
 pub use unknown::Unknown;
 
-use crate::{Connection, Db, Frame, Parse, ParseError, Shutdown};
+use crate::{Connection, Db, Frame, Parse, ParseError};
+
+/// Nombres de todos los comandos soportados, tal y como los retorna
+/// 'Command::get_name'.
+pub(crate) const COMMAND_NAMES: &[&str] = &[
+    "acl",
+    "auth",
+    "bgrewriteaof",
+    "client",
+    "del",
+    "flushall",
+    "flushdb",
+    "get",
+    "info",
+    "latency",
+    "monitor",
+    "move",
+    "object",
+    "psync",
+    "publish",
+    "pubsub",
+    "save",
+    "bgsave",
+    "select",
+    "set",
+    "shutdown",
+    "slowlog",
+    "subscribe",
+    "unsubscribe",
+    "swapdb",
+    "ping",
+    "quit",
+    "reset",
+];
 
 /// Enumeraciopn de los comandos Redis que son soportados.
 ///
@@ -24,12 +118,34 @@ use crate::{Connection, Db, Frame, Parse, ParseError, Shutdown};
 /// del comandos.
 #[derive(Debug)]
 pub enum Command {
+    Acl(Acl),
+    Auth(Auth),
+    BgRewriteAof(BgRewriteAof),
+    Client(Client),
+    Del(Del),
+    FlushAll(FlushAll),
+    FlushDb(FlushDb),
     Get(Get),
+    Info(Info),
+    Latency(Latency),
+    Monitor(Monitor),
+    Move(Move),
+    Object(Object),
+    Psync(Psync),
     Publish(Publish),
+    Pubsub(Pubsub),
+    Save(Save),
+    BgSave(BgSave),
+    Select(Select),
     Set(Set),
+    Shutdown(Shutdown),
+    Slowlog(Slowlog),
     Subscribe(Subscribe),
     Unsubscribe(Unsubscribe),
+    SwapDb(SwapDb),
     Ping(Ping),
+    Quit(Quit),
+    Reset(Reset),
     Unknown(Unknown),
 }
 
@@ -41,8 +157,10 @@ impl Command {
     /// 
     /// # Retorno
     /// Si el resultado es satisfactorio, se retorna un 'Command' y
-    /// en caso de error se retorna un 'Err'.
-    pub fn from_frame(frame: Frame) -> crate::Result<Command> {
+    /// en caso de error se retorna un 'Err'. Un 'ParseError::Syntax' indica
+    /// un argumento invalido o un numero de argumentos incorrecto, y el
+    /// servidor lo responde sin cerrar la conexion.
+    pub fn from_frame(frame: Frame) -> Result<Command, ParseError> {
         // El valor de la trama es decorado con un `Parse`el cual
         // proporciona una API tipo "cursor" el cual permite un 
         // parseado mas sencillo.
@@ -58,50 +176,128 @@ impl Command {
 
         // Se busca la coincidencia del comando para delegar el resto del comando
         // especificamente a cada comando.
-        let command = match &command_name[..] {
-            "get" => Command::Get(Get::parse_frames(&mut parse)?),
-            "publish" => Command::Publish(Publish::parse_frames(&mut parse)?),
-            "set" => Command::Set(Set::parse_frames(&mut parse)?),
-            "subscribe" => Command::Subscribe(Subscribe::parse_frames(&mut parse)?),
-            "unsubscribe" => Command::Unsubscribe(Unsubscribe::parse_frames(&mut parse)?),
-            "ping" => Command::Ping(Ping::parse_frames(&mut parse)?),
-            _ => {
-                // Si el comando no es reconocido se retornara un
-                // comando Unknown ya que 
-                //
-                // Ademas se retorna ya sin esperar a que se ejecute
-                // el parse.finish().
-                return Ok(Command::Unknown(Unknown::new(command_name)));
-            }
+        let command = Command::parse_frames(&command_name, &mut parse);
+
+        // Si faltan argumentos obligatorios, el comando ha consumido toda la
+        // trama. Como Redis, se indica el numero de argumentos incorrecto.
+        let command = match command {
+            Ok(command) => command,
+            Err(ParseError::EndOfStream) => return Err(wrong_arguments(&command_name)),
+            Err(err) => return Err(err),
         };
 
         // Verifica si quedan frame spendientes de consumer despues de invocar
         // al parseado de cada comando. Si hay campos pendientes aunque el 
         // parseado de comando haya resultado satisfactorio indicara que hay 
         // mas campos de los permitidos y un error sera retornado.
-        parse.finish()?;
+        //
+        // Los comandos desconocidos no consumen sus argumentos.
+        if !matches!(command, Command::Unknown(_)) && parse.finish().is_err() {
+            return Err(wrong_arguments(&command_name));
+        }
 
         // El comando ha sido parseado satisfactoriamente.
         Ok(command)
     }
 
+    /// Parsea los argumentos del comando 'command_name' con el 'parse'
+    /// posicionado tras el nombre.
+    fn parse_frames(command_name: &str, parse: &mut Parse) -> Result<Command, ParseError> {
+        let command = match command_name {
+            "acl" => Command::Acl(Acl::parse_frames(parse)?),
+            "auth" => Command::Auth(Auth::parse_frames(parse)?),
+            "bgrewriteaof" => Command::BgRewriteAof(BgRewriteAof::new()),
+            "client" => Command::Client(Client::parse_frames(parse)?),
+            "del" => Command::Del(Del::parse_frames(parse)?),
+            "flushall" => Command::FlushAll(FlushAll::parse_frames(parse)?),
+            "flushdb" => Command::FlushDb(FlushDb::parse_frames(parse)?),
+            "get" => Command::Get(Get::parse_frames(parse)?),
+            "info" => Command::Info(Info::parse_frames(parse)?),
+            "latency" => Command::Latency(Latency::parse_frames(parse)?),
+            "monitor" => Command::Monitor(Monitor::new()),
+            "move" => Command::Move(Move::parse_frames(parse)?),
+            "object" => Command::Object(Object::parse_frames(parse)?),
+            "psync" => Command::Psync(Psync::parse_frames(parse)?),
+            "publish" => Command::Publish(Publish::parse_frames(parse)?),
+            "pubsub" => Command::Pubsub(Pubsub::parse_frames(parse)?),
+            "save" => Command::Save(Save::new()),
+            "bgsave" => Command::BgSave(BgSave::new()),
+            "select" => Command::Select(Select::parse_frames(parse)?),
+            "set" => Command::Set(Set::parse_frames(parse)?),
+            "shutdown" => Command::Shutdown(Shutdown::parse_frames(parse)?),
+            "slowlog" => Command::Slowlog(Slowlog::parse_frames(parse)?),
+            "subscribe" => Command::Subscribe(Subscribe::parse_frames(parse)?),
+            "unsubscribe" => Command::Unsubscribe(Unsubscribe::parse_frames(parse)?),
+            "swapdb" => Command::SwapDb(SwapDb::parse_frames(parse)?),
+            "ping" => Command::Ping(Ping::parse_frames(parse)?),
+            "quit" => Command::Quit(Quit::new()),
+            "reset" => Command::Reset(Reset::new()),
+            // Si el comando no es reconocido se retornara un comando
+            // Unknown, que responde con un error.
+            _ => Command::Unknown(Unknown::new(command_name)),
+        };
+
+        Ok(command)
+    }
+
     /// Aplica el comando a la base de datos.
     /// 
-    /// La respuesta es escrita en `dst'. 
+    /// La respuesta es escrita en `dst'. 'db' es el de la conexion, ya que
+    /// 'SELECT' cambia la base de datos seleccionada.
     pub(crate) async fn apply(
         self,
-        db: &Db,
+        db: &mut Db,
         dst: &mut Connection,
-        shutdown: &mut Shutdown,
+        shutdown: &mut crate::Shutdown,
     ) -> crate::Result<()> {
         use Command::*;
 
+        // Las replicas unicamente reciben escrituras de su primario.
+        if self.is_write() && db.is_replica() {
+            let response =
+                Frame::Error("READONLY You can't write against a read only replica.".to_string());
+            dst.write_frame(&response).await?;
+            return Ok(());
+        }
+
+        // Los comandos que pueden añadir datos primero liberan memoria, si
+        // se ha superado 'maxmemory', y se rechazan si no es posible.
+        if self.is_denyoom() && !db.free_memory() {
+            let response = Frame::Error(
+                "OOM command not allowed when used memory > 'maxmemory'.".to_string(),
+            );
+            dst.write_frame(&response).await?;
+            return Ok(());
+        }
+
         match self {
+            Acl(cmd) => cmd.apply(db, dst).await,
+            Auth(cmd) => cmd.apply(db, dst).await,
+            BgRewriteAof(cmd) => cmd.apply(db, dst).await,
+            Client(cmd) => cmd.apply(db, dst).await,
+            Del(cmd) => cmd.apply(db, dst).await,
+            FlushAll(cmd) => cmd.apply(db, dst).await,
+            FlushDb(cmd) => cmd.apply(db, dst).await,
             Get(cmd) => cmd.apply(db, dst).await,
+            Info(cmd) => cmd.apply(db, dst).await,
+            Latency(cmd) => cmd.apply(db, dst).await,
+            Monitor(cmd) => cmd.apply(db, dst, shutdown).await,
+            Move(cmd) => cmd.apply(db, dst).await,
+            Object(cmd) => cmd.apply(db, dst).await,
+            Psync(cmd) => cmd.apply(db, dst, shutdown).await,
             Publish(cmd) => cmd.apply(db, dst).await,
+            Pubsub(cmd) => cmd.apply(db, dst).await,
+            Save(cmd) => cmd.apply(db, dst).await,
+            BgSave(cmd) => cmd.apply(db, dst).await,
+            Select(cmd) => cmd.apply(db, dst).await,
             Set(cmd) => cmd.apply(db, dst).await,
+            Shutdown(cmd) => cmd.apply(db, dst).await,
+            Slowlog(cmd) => cmd.apply(db, dst).await,
             Subscribe(cmd) => cmd.apply(db, dst, shutdown).await,
+            SwapDb(cmd) => cmd.apply(db, dst).await,
             Ping(cmd) => cmd.apply(dst).await,
+            Quit(cmd) => cmd.apply(dst, shutdown).await,
+            Reset(cmd) => cmd.apply(db, dst).await,
             Unknown(cmd) => cmd.apply(dst).await,
             // `Unsubscribe` no puede ser aplicado, el es solo recibiso 
             // desde el contexto de un comando 'Subscribe'.
@@ -109,16 +305,108 @@ impl Command {
         }
     }
 
+    /// Retorna 'true' si el comando modifica las claves.
+    pub(crate) fn is_write(&self) -> bool {
+        matches!(
+            self,
+            Command::Del(_)
+                | Command::FlushAll(_)
+                | Command::FlushDb(_)
+                | Command::Move(_)
+                | Command::Set(_)
+                | Command::SwapDb(_)
+        )
+    }
+
+    /// Retorna las claves a las que accede el comando, para comprobar los
+    /// permisos del usuario.
+    pub(crate) fn keys(&self) -> Vec<&str> {
+        match self {
+            Command::Del(cmd) => cmd.keys().iter().map(String::as_str).collect(),
+            Command::Get(cmd) => vec![cmd.key()],
+            Command::Move(cmd) => vec![cmd.key()],
+            Command::Object(cmd) => vec![cmd.key()],
+            Command::Set(cmd) => vec![cmd.key()],
+            _ => vec![],
+        }
+    }
+
+    /// Retorna los canales a los que accede el comando, para comprobar los
+    /// permisos del usuario.
+    pub(crate) fn channels(&self) -> Vec<&str> {
+        match self {
+            Command::Publish(cmd) => vec![cmd.channel()],
+            Command::Subscribe(cmd) => cmd.channels().iter().map(String::as_str).collect(),
+            _ => vec![],
+        }
+    }
+
+    /// Retorna 'true' si el comando puede contener contraseñas. No se envia
+    /// a las conexiones en 'MONITOR' ni se registra en el 'slow log'.
+    pub(crate) fn has_secrets(&self) -> bool {
+        matches!(self, Command::Acl(_) | Command::Auth(_))
+    }
+
+    /// Retorna 'true' si el comando debe esperar mientras los clientes estan
+    /// pausados en el modo 'mode' con 'CLIENT PAUSE'.
+    pub(crate) fn is_paused_by(&self, mode: PauseMode) -> bool {
+        match mode {
+            // 'CLIENT' nunca espera, para poder terminar la pausa.
+            PauseMode::All => !matches!(self, Command::Client(_)),
+            PauseMode::Write => self.is_write(),
+        }
+    }
+
+    /// Retorna 'true' si el comando puede aumentar la memoria utilizada.
+    pub(crate) fn is_denyoom(&self) -> bool {
+        matches!(self, Command::Set(_))
+    }
+
+    /// Retorna 'true' si el comando mantiene la conexion ocupada mientras
+    /// recibe mensajes, escrituras o comandos, como 'SUBSCRIBE'. Su duracion no se
+    /// registra en el 'slow log' ni como latencia.
+    pub(crate) fn is_streaming(&self) -> bool {
+        matches!(self, Command::Monitor(_) | Command::Psync(_) | Command::Subscribe(_))
+    }
+
     /// Retorna el nombre del comando
     pub(crate) fn get_name(&self) -> &str {
         match self {
+            Command::Acl(_) => "acl",
+            Command::Auth(_) => "auth",
+            Command::BgRewriteAof(_) => "bgrewriteaof",
+            Command::Client(_) => "client",
+            Command::Del(_) => "del",
+            Command::FlushAll(_) => "flushall",
+            Command::FlushDb(_) => "flushdb",
             Command::Get(_) => "get",
-            Command::Publish(_) => "pub",
+            Command::Info(_) => "info",
+            Command::Latency(_) => "latency",
+            Command::Monitor(_) => "monitor",
+            Command::Move(_) => "move",
+            Command::Object(_) => "object",
+            Command::Psync(_) => "psync",
+            Command::Publish(_) => "publish",
+            Command::Pubsub(_) => "pubsub",
+            Command::Save(_) => "save",
+            Command::BgSave(_) => "bgsave",
+            Command::Select(_) => "select",
             Command::Set(_) => "set",
+            Command::Shutdown(_) => "shutdown",
+            Command::Slowlog(_) => "slowlog",
             Command::Subscribe(_) => "subscribe",
             Command::Unsubscribe(_) => "unsubscribe",
+            Command::SwapDb(_) => "swapdb",
             Command::Ping(_) => "ping",
+            Command::Quit(_) => "quit",
+            Command::Reset(_) => "reset",
             Command::Unknown(cmd) => cmd.get_name(),
         }
     }
 }
+
+/// Error de un comando 'command_name' con un numero de argumentos
+/// incorrecto, con el mismo mensaje que Redis.
+fn wrong_arguments(command_name: &str) -> ParseError {
+    ParseError::Syntax(format!("wrong number of arguments for '{}' command", command_name))
+}
@@ -0,0 +1,227 @@
+use crate::acl::{CATEGORIES, DEFAULT_USER};
+use crate::{Connection, Db, Frame, Parse, ParseError};
+
+use bytes::Bytes;
+use tracing::{debug, instrument};
+
+/// Manages the users clients authenticate as with `AUTH`.
+///
+/// The command is composed of several subcommands:
+///
+/// * `SETUSER username [rule ...]` -- Creates the user, disabled and unable
+///   to run any command, if it does not exist, then applies the rules in
+///   order. Every rule is applied, or none if one is invalid.
+/// * `GETUSER username` -- Returns the flags, password hashes, command rules,
+///   key patterns and channel patterns of the user, or nil if it does not
+///   exist.
+/// * `DELUSER username [username ...]` -- Removes the users and terminates
+///   the clients authenticated as them. Returns how many users existed. The
+///   `default` user can not be removed.
+/// * `LIST` -- Returns every user, as the rules that would create it.
+/// * `WHOAMI` -- Returns the user of the connection.
+/// * `CAT [category]` -- Returns the command categories, or the commands in
+///   `category`.
+///
+/// The rules are those of Redis:
+///
+/// * `on`, `off` -- Enables or disables the user. Disabled users can not be
+///   authenticated as, clients already authenticated keep running.
+/// * `>password`, `<password` -- Adds or removes a password.
+/// * `#hash`, `!hash` -- Adds or removes a password by its SHA-256 hash.
+/// * `nopass` -- Any password authenticates as the user. `resetpass` removes
+///   every password and `nopass`.
+/// * `~pattern`, `allkeys`, `resetkeys` -- Adds a pattern of the keys the
+///   user can access, `~*` for every key, or removes every pattern.
+/// * `&pattern`, `allchannels`, `resetchannels` -- The same for the channels.
+/// * `+command`, `-command`, `+@category`, `-@category` -- Allows or denies a
+///   command, or every command in a category. `allcommands` and
+///   `nocommands` stand for `+@all` and `-@all`.
+/// * `reset` -- Makes the user disabled, without passwords, keys, channels or
+///   commands.
+///
+/// Permissions apply to whole commands: a user allowed to run `CLIENT` can
+/// run every `CLIENT` subcommand, and one allowed to run `ACL` every `ACL`
+/// subcommand.
+#[derive(Debug)]
+pub enum Acl {
+    /// `ACL SETUSER username [rule ...]`
+    SetUser(String, Vec<String>),
+
+    /// `ACL GETUSER username`
+    GetUser(String),
+
+    /// `ACL DELUSER username [username ...]`
+    DelUser(Vec<String>),
+
+    /// `ACL LIST`
+    List,
+
+    /// `ACL WHOAMI`
+    WhoAmI,
+
+    /// `ACL CAT [category]`
+    Cat(Option<String>),
+}
+
+impl Acl {
+    /// Parse an `Acl` instance from a received frame.
+    ///
+    /// The `ACL` string has already been consumed.
+    ///
+    /// # Format
+    ///
+    /// ```text
+    /// ACL SETUSER username [rule ...]
+    /// ACL GETUSER username
+    /// ACL DELUSER username [username ...]
+    /// ACL LIST
+    /// ACL WHOAMI
+    /// ACL CAT [category]
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<Acl, ParseError> {
+        let subcommand = parse.next_string()?.to_lowercase();
+
+        match &subcommand[..] {
+            "setuser" => {
+                let username = parse.next_string()?;
+                Ok(Acl::SetUser(username, remaining(parse)?))
+            }
+            "getuser" => Ok(Acl::GetUser(parse.next_string()?)),
+            "deluser" => {
+                let mut usernames = vec![parse.next_string()?];
+                usernames.extend(remaining(parse)?);
+                Ok(Acl::DelUser(usernames))
+            }
+            "list" => Ok(Acl::List),
+            "whoami" => Ok(Acl::WhoAmI),
+            "cat" => match parse.next_string() {
+                Ok(category) => Ok(Acl::Cat(Some(category))),
+                Err(ParseError::EndOfStream) => Ok(Acl::Cat(None)),
+                Err(err) => Err(err),
+            },
+            _ => Err(format!("unknown `ACL` subcommand '{}'", subcommand).into()),
+        }
+    }
+
+    /// Apply the `Acl` command to the specified `Db` instance.
+    ///
+    /// The response is written to `dst`. This is called by the server in order
+    /// to execute a received command.
+    #[instrument(skip(self, db, dst))]
+    pub(crate) async fn apply(self, db: &Db, dst: &mut Connection) -> crate::Result<()> {
+        let response = match self {
+            Acl::SetUser(username, rules) => match db.acl_setuser(&username, &rules) {
+                Ok(()) => Frame::Simple("OK".to_string()),
+                Err(err) => Frame::Error(err),
+            },
+            Acl::GetUser(username) => match db.acl_getuser(&username) {
+                Some(user) => {
+                    let flags = user.flags().into_iter().map(bulk).collect();
+                    let passwords = user.passwords().iter().map(|hash| bulk(hash)).collect();
+
+                    Frame::Array(vec![
+                        bulk("flags"),
+                        Frame::Array(flags),
+                        bulk("passwords"),
+                        Frame::Array(passwords),
+                        bulk("commands"),
+                        bulk(&user.command_rules()),
+                        bulk("keys"),
+                        bulk(&user.key_rules()),
+                        bulk("channels"),
+                        bulk(&user.channel_rules()),
+                    ])
+                }
+                None => Frame::Null,
+            },
+            Acl::DelUser(usernames) => {
+                if usernames.iter().any(|username| username == DEFAULT_USER) {
+                    Frame::Error("ERR The 'default' user cannot be removed".to_string())
+                } else {
+                    Frame::Integer(db.acl_deluser(&usernames) as u64)
+                }
+            }
+            Acl::List => Frame::Array(db.acl_list().iter().map(|user| bulk(user)).collect()),
+            Acl::WhoAmI => match db.client_user() {
+                Some(username) => bulk(&username),
+                None => Frame::Null,
+            },
+            Acl::Cat(None) => {
+                Frame::Array(CATEGORIES.iter().map(|(category, _)| bulk(category)).collect())
+            }
+            Acl::Cat(Some(category)) => {
+                let lowercase = category.to_lowercase();
+
+                match CATEGORIES.iter().find(|(c, _)| *c == lowercase) {
+                    Some((_, commands)) => Frame::Array(commands.iter().map(|c| bulk(c)).collect()),
+                    None => Frame::Error(format!("ERR Unknown category '{}'", category)),
+                }
+            }
+        };
+
+        debug!(?response);
+
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    ///
+    /// This is called by the client when encoding an `Acl` command to send
+    /// to the server.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("acl".as_bytes()));
+
+        let args = match self {
+            Acl::SetUser(username, rules) => {
+                frame.push_bulk(Bytes::from("setuser".as_bytes()));
+                std::iter::once(username).chain(rules).collect()
+            }
+            Acl::GetUser(username) => {
+                frame.push_bulk(Bytes::from("getuser".as_bytes()));
+                vec![username]
+            }
+            Acl::DelUser(usernames) => {
+                frame.push_bulk(Bytes::from("deluser".as_bytes()));
+                usernames
+            }
+            Acl::List => {
+                frame.push_bulk(Bytes::from("list".as_bytes()));
+                vec![]
+            }
+            Acl::WhoAmI => {
+                frame.push_bulk(Bytes::from("whoami".as_bytes()));
+                vec![]
+            }
+            Acl::Cat(category) => {
+                frame.push_bulk(Bytes::from("cat".as_bytes()));
+                category.into_iter().collect()
+            }
+        };
+
+        for arg in args {
+            frame.push_bulk(Bytes::from(arg.into_bytes()));
+        }
+
+        frame
+    }
+}
+
+/// Reads the remaining arguments.
+fn remaining(parse: &mut Parse) -> Result<Vec<String>, ParseError> {
+    let mut args = vec![];
+
+    loop {
+        match parse.next_string() {
+            Ok(arg) => args.push(arg),
+            Err(ParseError::EndOfStream) => return Ok(args),
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+fn bulk(s: &str) -> Frame {
+    Frame::Bulk(Bytes::from(s.to_string()))
+}
@@ -0,0 +1,107 @@
+use crate::acl::DEFAULT_USER;
+use crate::{Connection, Db, Frame, Parse, ParseError};
+
+use bytes::Bytes;
+use tracing::{debug, instrument};
+
+/// Authenticates the connection as a user.
+///
+/// With a single argument, the password is checked against the `default`
+/// user, the one `Config::requirepass` sets the password of. On success the
+/// server replies `OK` and the following commands run as the user, with its
+/// permissions. Otherwise it replies with a `WRONGPASS` error and the
+/// connection keeps its current user.
+#[derive(Debug)]
+pub struct Auth {
+    /// The user, `default` if `None`.
+    username: Option<String>,
+
+    password: String,
+}
+
+impl Auth {
+    /// Create a new `Auth` command authenticating as `username`, or as the
+    /// `default` user if `None`.
+    pub fn new(username: Option<&str>, password: &str) -> Auth {
+        Auth {
+            username: username.map(str::to_string),
+            password: password.to_string(),
+        }
+    }
+
+    /// Parse an `Auth` instance from a received frame.
+    ///
+    /// The `AUTH` string has already been consumed.
+    ///
+    /// # Format
+    ///
+    /// ```text
+    /// AUTH [username] password
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<Auth, ParseError> {
+        let first = parse.next_string()?;
+
+        match parse.next_string() {
+            Ok(password) => Ok(Auth {
+                username: Some(first),
+                password,
+            }),
+            Err(ParseError::EndOfStream) => Ok(Auth {
+                username: None,
+                password: first,
+            }),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Apply the `Auth` command to the specified `Db` instance.
+    ///
+    /// The response is written to `dst`. This is called by the server in order
+    /// to execute a received command.
+    #[instrument(skip(self, db, dst))]
+    pub(crate) async fn apply(self, db: &Db, dst: &mut Connection) -> crate::Result<()> {
+        let response = match self.username {
+            // Like Redis, a password for a `default` user that needs none is
+            // most likely a configuration mistake.
+            None if db.default_login() => Frame::Error(
+                "ERR AUTH <password> called without any password configured for the default \
+                 user. Are you sure your configuration is correct?"
+                    .to_string(),
+            ),
+            username => {
+                let username = username.as_deref().unwrap_or(DEFAULT_USER);
+
+                if db.authenticate(username, &self.password) {
+                    Frame::Simple("OK".to_string())
+                } else {
+                    Frame::Error(
+                        "WRONGPASS invalid username-password pair or user is disabled."
+                            .to_string(),
+                    )
+                }
+            }
+        };
+
+        debug!(?response);
+
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    ///
+    /// This is called by the client when encoding an `Auth` command to send
+    /// to the server.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("auth".as_bytes()));
+
+        if let Some(username) = self.username {
+            frame.push_bulk(Bytes::from(username.into_bytes()));
+        }
+
+        frame.push_bulk(Bytes::from(self.password.into_bytes()));
+        frame
+    }
+}
@@ -0,0 +1,48 @@
+use crate::{aof, Connection, Db, Frame};
+
+use bytes::Bytes;
+use tracing::{debug, instrument};
+
+/// Rewrites the append only file from the current dataset, in the background.
+///
+/// The rewritten file contains a single `SET` per key, dropping the history
+/// of overwritten and removed keys. The server replies as soon as the rewrite
+/// has started. An error is returned if the AOF is disabled or a rewrite is
+/// already in progress.
+#[derive(Debug, Default)]
+pub struct BgRewriteAof;
+
+impl BgRewriteAof {
+    /// Create a new `BgRewriteAof` command.
+    pub fn new() -> BgRewriteAof {
+        BgRewriteAof
+    }
+
+    /// Apply the `BgRewriteAof` command to the specified `Db` instance.
+    ///
+    /// The response is written to `dst`. This is called by the server in order
+    /// to execute a received command.
+    #[instrument(skip(self, db, dst))]
+    pub(crate) async fn apply(self, db: &Db, dst: &mut Connection) -> crate::Result<()> {
+        let response = match aof::bgrewrite(db) {
+            Ok(()) => Frame::Simple("Background append only file rewriting started".to_string()),
+            Err(err) => Frame::Error(format!("ERR {}", err)),
+        };
+
+        debug!(?response);
+
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    ///
+    /// This is called by the client when encoding a `BgRewriteAof` command to
+    /// send to the server.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("bgrewriteaof".as_bytes()));
+        frame
+    }
+}
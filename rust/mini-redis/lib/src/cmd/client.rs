@@ -0,0 +1,316 @@
+use crate::registry::KillFilter;
+use crate::{Connection, Db, Frame, Parse, ParseError};
+
+use bytes::Bytes;
+use std::time::Duration;
+use tracing::{debug, instrument};
+
+/// Inspects and manages the connected clients.
+///
+/// The command is composed of several subcommands:
+///
+/// * `LIST` -- Returns a bulk string describing every client, one per line,
+///   with its id, address, name, age and idle time in seconds, flags (`P` for
+///   a client subscribed to channels, `N` otherwise), selected database,
+///   number of subscriptions, last command and user.
+/// * `INFO` -- Returns the line of `LIST` describing the client issuing it.
+/// * `ID` -- Returns the id of the client.
+/// * `GETNAME` -- Returns the name of the client, or nil if it has none.
+/// * `SETNAME name` -- Names the client, an empty name removes it.
+/// * `KILL addr` -- Terminates the client connected from `addr`, replying
+///   with an error if there is none.
+/// * `KILL [ID id] [ADDR addr] [USER username] [SKIPME yes|no]` --
+///   Terminates the clients matching every filter given, except the client
+///   issuing the command unless `SKIPME no` is given, and returns how many
+///   there were.
+/// * `PAUSE timeout [WRITE|ALL]` -- Holds the commands of every client for
+///   `timeout` milliseconds, only those writing keys with `WRITE`. Commands
+///   are held before running, so a failover can wait for the replicas to
+///   catch up while no write happens. `CLIENT` commands are never held.
+/// * `UNPAUSE` -- Ends the pause early.
+#[derive(Debug)]
+pub enum Client {
+    /// `CLIENT LIST`
+    List,
+
+    /// `CLIENT INFO`
+    Info,
+
+    /// `CLIENT ID`
+    Id,
+
+    /// `CLIENT GETNAME`
+    GetName,
+
+    /// `CLIENT SETNAME name`
+    SetName(String),
+
+    /// `CLIENT KILL addr`
+    KillAddr(String),
+
+    /// `CLIENT KILL [ID id] [ADDR addr] [USER username] [SKIPME yes|no]`
+    Kill {
+        id: Option<u64>,
+        addr: Option<String>,
+        user: Option<String>,
+        skipme: bool,
+    },
+
+    /// `CLIENT PAUSE timeout [WRITE|ALL]`
+    Pause(Duration, PauseMode),
+
+    /// `CLIENT UNPAUSE`
+    Unpause,
+}
+
+/// Which commands `CLIENT PAUSE` holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PauseMode {
+    /// Only the commands writing keys.
+    Write,
+
+    /// Every command but `CLIENT`.
+    All,
+}
+
+impl Client {
+    /// Parse a `Client` instance from a received frame.
+    ///
+    /// The `CLIENT` string has already been consumed.
+    ///
+    /// # Format
+    ///
+    /// ```text
+    /// CLIENT LIST
+    /// CLIENT INFO
+    /// CLIENT ID
+    /// CLIENT GETNAME
+    /// CLIENT SETNAME name
+    /// CLIENT KILL addr
+    /// CLIENT KILL [ID id] [ADDR addr] [USER username] [SKIPME yes|no]
+    /// CLIENT PAUSE timeout [WRITE|ALL]
+    /// CLIENT UNPAUSE
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<Client, ParseError> {
+        let subcommand = parse.next_string()?.to_lowercase();
+
+        match &subcommand[..] {
+            "list" => Ok(Client::List),
+            "info" => Ok(Client::Info),
+            "id" => Ok(Client::Id),
+            "getname" => Ok(Client::GetName),
+            "setname" => Ok(Client::SetName(parse.next_string()?)),
+            "kill" => parse_kill(parse),
+            "pause" => {
+                let timeout = Duration::from_millis(parse.next_int()?);
+
+                let mode = match parse.next_string() {
+                    Ok(s) if s.to_uppercase() == "WRITE" => PauseMode::Write,
+                    Ok(s) if s.to_uppercase() == "ALL" => PauseMode::All,
+                    Ok(_) => return Err("syntax error".into()),
+                    Err(ParseError::EndOfStream) => PauseMode::All,
+                    Err(err) => return Err(err),
+                };
+
+                Ok(Client::Pause(timeout, mode))
+            }
+            "unpause" => Ok(Client::Unpause),
+            _ => Err(format!("unknown `CLIENT` subcommand '{}'", subcommand).into()),
+        }
+    }
+
+    /// Apply the `Client` command to the specified `Db` instance.
+    ///
+    /// The response is written to `dst`. This is called by the server in order
+    /// to execute a received command.
+    #[instrument(skip(self, db, dst))]
+    pub(crate) async fn apply(self, db: &Db, dst: &mut Connection) -> crate::Result<()> {
+        let response = match self {
+            Client::List => Frame::Bulk(Bytes::from(db.client_list())),
+            Client::Info => match db.client() {
+                Some(client) => Frame::Bulk(Bytes::from(client.describe() + "\n")),
+                None => Frame::Null,
+            },
+            Client::Id => match db.client() {
+                Some(client) => Frame::Integer(client.id),
+                None => Frame::Null,
+            },
+            Client::GetName => match db.client_name() {
+                name if name.is_empty() => Frame::Null,
+                name => Frame::Bulk(Bytes::from(name)),
+            },
+            Client::SetName(name) => {
+                // Names show up in the space separated `CLIENT LIST`.
+                if name.bytes().any(|b| !b.is_ascii_graphic()) {
+                    Frame::Error(
+                        "ERR Client names cannot contain spaces, newlines or special characters."
+                            .to_string(),
+                    )
+                } else {
+                    if let Some(client) = db.client() {
+                        client.set_name(name);
+                    }
+
+                    Frame::Simple("OK".to_string())
+                }
+            }
+            Client::KillAddr(addr) => {
+                let filter = KillFilter {
+                    addr: Some(addr),
+                    ..KillFilter::default()
+                };
+
+                match db.client_kill(&filter) {
+                    0 => Frame::Error("ERR No such client".to_string()),
+                    _ => Frame::Simple("OK".to_string()),
+                }
+            }
+            Client::Kill {
+                id,
+                addr,
+                user,
+                skipme,
+            } => {
+                let skip = match db.client() {
+                    Some(client) if skipme => Some(client.id),
+                    _ => None,
+                };
+
+                let filter = KillFilter {
+                    id,
+                    addr,
+                    user,
+                    skip,
+                };
+                Frame::Integer(db.client_kill(&filter) as u64)
+            }
+            Client::Pause(timeout, mode) => {
+                db.client_pause(timeout, mode);
+                Frame::Simple("OK".to_string())
+            }
+            Client::Unpause => {
+                db.client_unpause();
+                Frame::Simple("OK".to_string())
+            }
+        };
+
+        debug!(?response);
+
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    ///
+    /// This is called by the client when encoding a `Client` command to send
+    /// to the server.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("client".as_bytes()));
+
+        match self {
+            Client::List => frame.push_bulk(Bytes::from("list".as_bytes())),
+            Client::Info => frame.push_bulk(Bytes::from("info".as_bytes())),
+            Client::Id => frame.push_bulk(Bytes::from("id".as_bytes())),
+            Client::GetName => frame.push_bulk(Bytes::from("getname".as_bytes())),
+            Client::SetName(name) => {
+                frame.push_bulk(Bytes::from("setname".as_bytes()));
+                frame.push_bulk(Bytes::from(name.into_bytes()));
+            }
+            Client::KillAddr(addr) => {
+                frame.push_bulk(Bytes::from("kill".as_bytes()));
+                frame.push_bulk(Bytes::from(addr.into_bytes()));
+            }
+            Client::Kill {
+                id,
+                addr,
+                user,
+                skipme,
+            } => {
+                frame.push_bulk(Bytes::from("kill".as_bytes()));
+
+                if let Some(id) = id {
+                    frame.push_bulk(Bytes::from("id".as_bytes()));
+                    frame.push_bulk(Bytes::from(id.to_string().into_bytes()));
+                }
+
+                if let Some(addr) = addr {
+                    frame.push_bulk(Bytes::from("addr".as_bytes()));
+                    frame.push_bulk(Bytes::from(addr.into_bytes()));
+                }
+
+                if let Some(user) = user {
+                    frame.push_bulk(Bytes::from("user".as_bytes()));
+                    frame.push_bulk(Bytes::from(user.into_bytes()));
+                }
+
+                let skipme = if skipme { "yes" } else { "no" };
+                frame.push_bulk(Bytes::from("skipme".as_bytes()));
+                frame.push_bulk(Bytes::from(skipme.as_bytes()));
+            }
+            Client::Pause(timeout, mode) => {
+                frame.push_bulk(Bytes::from("pause".as_bytes()));
+                frame.push_int(timeout.as_millis() as u64);
+
+                let mode = match mode {
+                    PauseMode::Write => "write",
+                    PauseMode::All => "all",
+                };
+                frame.push_bulk(Bytes::from(mode.as_bytes()));
+            }
+            Client::Unpause => frame.push_bulk(Bytes::from("unpause".as_bytes())),
+        }
+
+        frame
+    }
+}
+
+/// Parses the arguments of `CLIENT KILL`, in either form.
+fn parse_kill(parse: &mut Parse) -> Result<Client, ParseError> {
+    let first = parse.next_string()?;
+
+    // A single argument is the address of the client, as in Redis.
+    let mut value = match parse.next_string() {
+        Ok(value) => value,
+        Err(ParseError::EndOfStream) => return Ok(Client::KillAddr(first)),
+        Err(err) => return Err(err),
+    };
+
+    let mut id = None;
+    let mut addr = None;
+    let mut user = None;
+    let mut skipme = true;
+    let mut filter = first;
+
+    loop {
+        match &filter.to_lowercase()[..] {
+            "id" => match value.parse() {
+                Ok(value) => id = Some(value),
+                Err(_) => return Err("client-id should be greater than 0".into()),
+            },
+            "addr" => addr = Some(value),
+            "user" => user = Some(value),
+            "skipme" => match &value.to_lowercase()[..] {
+                "yes" => skipme = true,
+                "no" => skipme = false,
+                _ => return Err("syntax error".into()),
+            },
+            _ => return Err("syntax error".into()),
+        }
+
+        filter = match parse.next_string() {
+            Ok(filter) => filter,
+            Err(ParseError::EndOfStream) => break,
+            Err(err) => return Err(err),
+        };
+        value = parse.next_string()?;
+    }
+
+    Ok(Client::Kill {
+        id,
+        addr,
+        user,
+        skipme,
+    })
+}
@@ -0,0 +1,152 @@
+use crate::{Connection, Db, Frame, Parse, ParseError};
+
+use bytes::Bytes;
+use tracing::{debug, instrument};
+
+/// How `FLUSHDB` and `FLUSHALL` release the removed keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlushMode {
+    /// The keys are released before replying.
+    Sync,
+
+    /// The keys are removed right away, but released on a background thread,
+    /// so flushing a large database does not delay other commands.
+    Async,
+}
+
+/// Removes every key of the selected database.
+#[derive(Debug)]
+pub struct FlushDb {
+    mode: FlushMode,
+}
+
+/// Removes every key of every database.
+#[derive(Debug)]
+pub struct FlushAll {
+    mode: FlushMode,
+}
+
+impl FlushDb {
+    /// Create a new `FlushDb` command.
+    pub fn new(mode: FlushMode) -> FlushDb {
+        FlushDb { mode }
+    }
+
+    /// Get the flush mode
+    pub fn mode(&self) -> FlushMode {
+        self.mode
+    }
+
+    /// Parse a `FlushDb` instance from a received frame.
+    ///
+    /// The `FLUSHDB` string has already been consumed.
+    ///
+    /// # Format
+    ///
+    /// ```text
+    /// FLUSHDB [ASYNC|SYNC]
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<FlushDb, ParseError> {
+        Ok(FlushDb {
+            mode: parse_mode(parse)?,
+        })
+    }
+
+    /// Apply the `FlushDb` command to the specified `Db` instance.
+    ///
+    /// The response is written to `dst`. This is called by the server in order
+    /// to execute a received command.
+    #[instrument(skip(self, db, dst))]
+    pub(crate) async fn apply(self, db: &Db, dst: &mut Connection) -> crate::Result<()> {
+        db.flushdb(self.mode);
+
+        let response = Frame::Simple("OK".to_string());
+        debug!(?response);
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    ///
+    /// This is called by the client when encoding a `FlushDb` command to send
+    /// to the server.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("flushdb".as_bytes()));
+        push_mode(&mut frame, self.mode);
+        frame
+    }
+}
+
+impl FlushAll {
+    /// Create a new `FlushAll` command.
+    pub fn new(mode: FlushMode) -> FlushAll {
+        FlushAll { mode }
+    }
+
+    /// Get the flush mode
+    pub fn mode(&self) -> FlushMode {
+        self.mode
+    }
+
+    /// Parse a `FlushAll` instance from a received frame.
+    ///
+    /// The `FLUSHALL` string has already been consumed.
+    ///
+    /// # Format
+    ///
+    /// ```text
+    /// FLUSHALL [ASYNC|SYNC]
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<FlushAll, ParseError> {
+        Ok(FlushAll {
+            mode: parse_mode(parse)?,
+        })
+    }
+
+    /// Apply the `FlushAll` command to the specified `Db` instance.
+    ///
+    /// The response is written to `dst`. This is called by the server in order
+    /// to execute a received command.
+    #[instrument(skip(self, db, dst))]
+    pub(crate) async fn apply(self, db: &Db, dst: &mut Connection) -> crate::Result<()> {
+        db.flushall(self.mode);
+
+        let response = Frame::Simple("OK".to_string());
+        debug!(?response);
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    ///
+    /// This is called by the client when encoding a `FlushAll` command to send
+    /// to the server.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("flushall".as_bytes()));
+        push_mode(&mut frame, self.mode);
+        frame
+    }
+}
+
+/// Parses the optional `ASYNC` or `SYNC` argument. Flushing is synchronous
+/// by default.
+fn parse_mode(parse: &mut Parse) -> Result<FlushMode, ParseError> {
+    match parse.next_string() {
+        Ok(s) if s.to_uppercase() == "ASYNC" => Ok(FlushMode::Async),
+        Ok(s) if s.to_uppercase() == "SYNC" => Ok(FlushMode::Sync),
+        Ok(_) => Err("syntax error".into()),
+        Err(ParseError::EndOfStream) => Ok(FlushMode::Sync),
+        Err(err) => Err(err),
+    }
+}
+
+/// Pushes the mode argument, omitted for the default `Sync`.
+fn push_mode(frame: &mut Frame, mode: FlushMode) {
+    if mode == FlushMode::Async {
+        frame.push_bulk(Bytes::from("async".as_bytes()));
+    }
+}
@@ -0,0 +1,226 @@
+use crate::db::Stats;
+use crate::latency::unix_time;
+use crate::{Connection, Db, Frame, Parse, ParseError};
+
+use bytes::Bytes;
+use std::fmt::Write;
+use std::time::SystemTime;
+use tracing::{debug, instrument};
+
+/// Returns information and statistics about the server, in the text format
+/// Redis uses: a `# Section` header followed by `field:value` lines for each
+/// section.
+///
+/// Without a section, the default sections are returned: `server`,
+/// `clients`, `memory`, `persistence`, `stats` and `keyspace`. `all` also
+/// returns `commandstats`, the calls and time spent in each command. A single
+/// section may be requested by name, an unknown section returns an empty
+/// report.
+#[derive(Debug, Default)]
+pub struct Info {
+    /// Section to return, the default sections if `None`
+    section: Option<String>,
+}
+
+/// Sections returned when none is requested, in order.
+const DEFAULT_SECTIONS: &[&str] = &["server", "clients", "memory", "persistence", "stats", "keyspace"];
+
+/// Sections returned by `INFO all`, in order.
+const ALL_SECTIONS: &[&str] = &[
+    "server",
+    "clients",
+    "memory",
+    "persistence",
+    "stats",
+    "commandstats",
+    "keyspace",
+];
+
+impl Info {
+    /// Create a new `Info` command which returns `section`, or the default
+    /// sections if `None`.
+    pub fn new(section: Option<String>) -> Info {
+        Info { section }
+    }
+
+    /// Parse an `Info` instance from a received frame.
+    ///
+    /// The `INFO` string has already been consumed.
+    ///
+    /// # Format
+    ///
+    /// ```text
+    /// INFO [section]
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<Info, ParseError> {
+        match parse.next_string() {
+            Ok(section) => Ok(Info::new(Some(section))),
+            Err(ParseError::EndOfStream) => Ok(Info::default()),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Apply the `Info` command to the specified `Db` instance.
+    ///
+    /// The response is written to `dst`. This is called by the server in order
+    /// to execute a received command.
+    #[instrument(skip(self, db, dst))]
+    pub(crate) async fn apply(self, db: &Db, dst: &mut Connection) -> crate::Result<()> {
+        let section = self.section.map(|section| section.to_lowercase());
+
+        let sections: Vec<&str> = match section.as_deref() {
+            None | Some("default") => DEFAULT_SECTIONS.to_vec(),
+            Some("all") | Some("everything") => ALL_SECTIONS.to_vec(),
+            Some(section) => vec![section],
+        };
+
+        let stats = db.stats();
+        let mut report = String::new();
+
+        for section in sections {
+            write_section(&mut report, section, &stats);
+        }
+
+        let response = Frame::Bulk(Bytes::from(report));
+        debug!(?response);
+
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    ///
+    /// This is called by the client when encoding an `Info` command to send
+    /// to the server.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("info".as_bytes()));
+
+        if let Some(section) = self.section {
+            frame.push_bulk(Bytes::from(section.into_bytes()));
+        }
+
+        frame
+    }
+}
+
+/// Appends `section` to `report`, separated from the previous one by an
+/// empty line. Unknown sections are skipped.
+fn write_section(report: &mut String, section: &str, stats: &Stats) {
+    let mut lines = String::new();
+
+    // Writing to a `String` never fails.
+    let _ = match section {
+        "server" => write_server(&mut lines, stats),
+        "clients" => write_clients(&mut lines, stats),
+        "memory" => write_memory(&mut lines, stats),
+        "persistence" => write_persistence(&mut lines, stats),
+        "stats" => write_stats(&mut lines, stats),
+        "commandstats" => write_commandstats(&mut lines, stats),
+        "keyspace" => write_keyspace(&mut lines, stats),
+        _ => return,
+    };
+
+    if !report.is_empty() {
+        report.push_str("\r\n");
+    }
+
+    // The header capitalizes the name, as Redis does.
+    let mut name = section.chars();
+    let first = name.next().map(|c| c.to_ascii_uppercase()).unwrap_or_default();
+    let _ = write!(report, "# {}{}\r\n{}", first, name.as_str(), lines);
+}
+
+fn write_server(out: &mut String, stats: &Stats) -> std::fmt::Result {
+    let uptime = stats.uptime.as_secs();
+
+    write!(out, "process_id:{}\r\n", std::process::id())?;
+    write!(out, "uptime_in_seconds:{}\r\n", uptime)?;
+    write!(out, "uptime_in_days:{}\r\n", uptime / 86400)
+}
+
+fn write_clients(out: &mut String, stats: &Stats) -> std::fmt::Result {
+    write!(out, "connected_clients:{}\r\n", stats.connected_clients)?;
+    write!(out, "maxclients:{}\r\n", stats.maxclients)
+}
+
+fn write_memory(out: &mut String, stats: &Stats) -> std::fmt::Result {
+    write!(out, "used_memory:{}\r\n", stats.used_memory)?;
+    write!(out, "used_memory_human:{}\r\n", human_bytes(stats.used_memory))?;
+    write!(out, "maxmemory:{}\r\n", stats.maxmemory)?;
+    write!(out, "maxmemory_human:{}\r\n", human_bytes(stats.maxmemory))?;
+    write!(out, "maxmemory_policy:{}\r\n", stats.maxmemory_policy)
+}
+
+fn write_persistence(out: &mut String, stats: &Stats) -> std::fmt::Result {
+    // Like Redis, the server start counts as the last save until a snapshot
+    // is saved.
+    let last_save = stats
+        .last_save
+        .unwrap_or_else(|| SystemTime::now() - stats.uptime);
+
+    write!(out, "rdb_changes_since_last_save:{}\r\n", stats.dirty)?;
+    write!(out, "rdb_bgsave_in_progress:{}\r\n", stats.saving as u8)?;
+    write!(out, "rdb_last_save_time:{}\r\n", unix_time(last_save))?;
+    write!(out, "aof_enabled:{}\r\n", stats.aof_enabled as u8)?;
+    write!(out, "aof_rewrite_in_progress:{}\r\n", stats.aof_rewriting as u8)
+}
+
+fn write_stats(out: &mut String, stats: &Stats) -> std::fmt::Result {
+    let total_commands: u64 = stats.commands.iter().map(|command| command.calls).sum();
+
+    write!(out, "total_connections_received:{}\r\n", stats.total_connections)?;
+    write!(out, "total_commands_processed:{}\r\n", total_commands)?;
+    write!(out, "total_net_input_bytes:{}\r\n", stats.net_input_bytes)?;
+    write!(out, "total_net_output_bytes:{}\r\n", stats.net_output_bytes)?;
+    write!(out, "rejected_connections:{}\r\n", stats.rejected_connections)?;
+    write!(out, "expired_keys:{}\r\n", stats.expired_keys)?;
+    write!(out, "evicted_keys:{}\r\n", stats.evicted_keys)?;
+    write!(out, "pubsub_channels:{}\r\n", stats.pubsub_channels)
+}
+
+fn write_commandstats(out: &mut String, stats: &Stats) -> std::fmt::Result {
+    for command in &stats.commands {
+        write!(
+            out,
+            "cmdstat_{}:calls={},usec={},usec_per_call={:.2}\r\n",
+            command.name,
+            command.calls,
+            command.usec,
+            command.usec as f64 / command.calls as f64,
+        )?;
+    }
+
+    Ok(())
+}
+
+fn write_keyspace(out: &mut String, stats: &Stats) -> std::fmt::Result {
+    // Empty databases are left out.
+    for (index, (keys, expires)) in stats.keyspaces.iter().enumerate() {
+        if *keys > 0 {
+            write!(out, "db{}:keys={},expires={}\r\n", index, keys, expires)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Formats a number of bytes like Redis does, such as `1.50K` or `12B`.
+fn human_bytes(bytes: usize) -> String {
+    const UNITS: &[&str] = &["K", "M", "G", "T"];
+
+    if bytes < 1024 {
+        return format!("{}B", bytes);
+    }
+
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    format!("{:.2}{}", value, UNITS[unit])
+}
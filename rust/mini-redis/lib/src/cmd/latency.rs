@@ -0,0 +1,110 @@
+use crate::{Connection, Db, Frame, Parse, ParseError};
+
+use bytes::Bytes;
+use tracing::{debug, instrument};
+
+/// Reports the latency events sampled by the server, the operations that
+/// took at least `Config::latency_monitor_threshold`.
+///
+/// The events are:
+///
+/// * `command` -- The execution of a command.
+/// * `expire-cycle` -- A cycle of the task removing expired keys.
+/// * `snapshot-copy` -- Copying the dataset for `SAVE` or `BGSAVE`, while no
+///   other command can run. It takes the place of Redis' `fork` event, as
+///   mini-redis does not fork to save snapshots.
+///
+/// The command is composed of several subcommands:
+///
+/// * `LATEST` -- Returns, for each event, an array of its name, the Unix time
+///   of its latest sample, the latency of that sample and the highest latency
+///   ever sampled, in milliseconds.
+/// * `HISTORY event` -- Returns the samples of `event`, oldest first, as
+///   arrays of their Unix time and latency in milliseconds.
+#[derive(Debug)]
+pub enum Latency {
+    /// `LATENCY LATEST`
+    Latest,
+
+    /// `LATENCY HISTORY event`
+    History(String),
+}
+
+impl Latency {
+    /// Parse a `Latency` instance from a received frame.
+    ///
+    /// The `LATENCY` string has already been consumed.
+    ///
+    /// # Format
+    ///
+    /// ```text
+    /// LATENCY LATEST
+    /// LATENCY HISTORY event
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<Latency, ParseError> {
+        let subcommand = parse.next_string()?.to_lowercase();
+
+        match &subcommand[..] {
+            "latest" => Ok(Latency::Latest),
+            "history" => Ok(Latency::History(parse.next_string()?)),
+            _ => Err(format!("unknown `LATENCY` subcommand '{}'", subcommand).into()),
+        }
+    }
+
+    /// Apply the `Latency` command to the specified `Db` instance.
+    ///
+    /// The response is written to `dst`. This is called by the server in order
+    /// to execute a received command.
+    #[instrument(skip(self, db, dst))]
+    pub(crate) async fn apply(self, db: &Db, dst: &mut Connection) -> crate::Result<()> {
+        let response = match self {
+            Latency::Latest => {
+                let events = db.latency_latest().into_iter().map(|(name, latest, max)| {
+                    Frame::Array(vec![
+                        Frame::Bulk(Bytes::from(name.as_bytes())),
+                        Frame::Integer(latest.time),
+                        Frame::Integer(latest.latency),
+                        Frame::Integer(max),
+                    ])
+                });
+
+                Frame::Array(events.collect())
+            }
+            Latency::History(event) => {
+                let samples = db.latency_history(&event).into_iter().map(|sample| {
+                    Frame::Array(vec![
+                        Frame::Integer(sample.time),
+                        Frame::Integer(sample.latency),
+                    ])
+                });
+
+                Frame::Array(samples.collect())
+            }
+        };
+
+        debug!(?response);
+
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    ///
+    /// This is called by the client when encoding a `Latency` command to send
+    /// to the server.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("latency".as_bytes()));
+
+        match self {
+            Latency::Latest => frame.push_bulk(Bytes::from("latest".as_bytes())),
+            Latency::History(event) => {
+                frame.push_bulk(Bytes::from("history".as_bytes()));
+                frame.push_bulk(Bytes::from(event.into_bytes()));
+            }
+        }
+
+        frame
+    }
+}
@@ -0,0 +1,161 @@
+use crate::cmd::Unknown;
+use crate::{Command, Connection, Db, Frame, Shutdown};
+
+use bytes::Bytes;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::select;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{debug, instrument};
+
+/// Streams every command executed by the server, for debugging.
+///
+/// After replying `OK`, the connection enters the monitor state and receives
+/// a simple string per command executed by any client, in the format Redis
+/// uses:
+///
+/// ```text
+/// 1339518083.107412 [0 127.0.0.1:60866] "set" "foo" "bar"
+/// ```
+///
+/// That is, the Unix time with microseconds, the database selected and the
+/// address of the client, and the quoted command and arguments. Only `QUIT`
+/// and `RESET` are accepted in the monitor state, `RESET` returns the
+/// connection to the regular state.
+///
+/// Lines are dropped if the client reads them slower than commands are
+/// executed.
+#[derive(Debug, Default)]
+pub struct Monitor;
+
+impl Monitor {
+    /// Create a new `Monitor` command.
+    pub fn new() -> Monitor {
+        Monitor
+    }
+
+    /// Apply the `Monitor` command to the specified `Db` instance.
+    ///
+    /// Lines are written to `dst` until the client leaves the monitor state
+    /// or the server shuts down.
+    #[instrument(skip(self, db, dst, shutdown))]
+    pub(crate) async fn apply(
+        self,
+        db: &mut Db,
+        dst: &mut Connection,
+        shutdown: &mut Shutdown,
+    ) -> crate::Result<()> {
+        // Commands are fed once the receiver exists, so the first line the
+        // client gets is the `OK`.
+        let mut lines = db.monitor();
+
+        let response = Frame::Simple("OK".to_string());
+        debug!(?response);
+        dst.write_frame(&response).await?;
+
+        loop {
+            select! {
+                res = lines.recv() => match res {
+                    Ok(line) => dst.write_frame(&Frame::Simple(line)).await?,
+                    Err(RecvError::Lagged(num_dropped)) => {
+                        debug!(num_dropped, "monitor fell behind");
+                    }
+                    Err(RecvError::Closed) => return Ok(()),
+                },
+                res = dst.read_frame() => {
+                    let frame = match res? {
+                        Some(frame) => frame,
+                        // This happens if the remote client has disconnected.
+                        None => return Ok(()),
+                    };
+
+                    let command = match Command::from_frame(frame) {
+                        Ok(command) => command,
+                        Err(err) if err.is_recoverable() => {
+                            dst.write_frame(&err.into_frame()).await?;
+                            continue;
+                        }
+                        Err(err) => return Err(err.into()),
+                    };
+
+                    match command {
+                        Command::Quit(quit) => {
+                            quit.apply(dst, shutdown).await?;
+                            return Ok(());
+                        }
+                        Command::Reset(reset) => {
+                            reset.apply(db, dst).await?;
+                            return Ok(());
+                        }
+                        command => {
+                            let cmd = Unknown::new(command.get_name());
+                            cmd.apply(dst).await?;
+                        }
+                    }
+                }
+                _ = shutdown.recv() => return Ok(()),
+            }
+        }
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    ///
+    /// This is called by the client when encoding a `Monitor` command to send
+    /// to the server.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("monitor".as_bytes()));
+        frame
+    }
+}
+
+/// Formats the line monitors receive for the command in `frame`, executed at
+/// `time` on database `db` by the client at `addr`.
+pub(crate) fn monitor_line(time: SystemTime, db: usize, addr: SocketAddr, frame: &Frame) -> String {
+    let time = time.duration_since(UNIX_EPOCH).unwrap_or(Duration::ZERO);
+    let mut line = format!("{}.{:06} [{} {}]", time.as_secs(), time.subsec_micros(), db, addr);
+
+    let args = match frame {
+        Frame::Array(args) => &args[..],
+        frame => std::slice::from_ref(frame),
+    };
+
+    for arg in args {
+        line.push(' ');
+
+        match arg {
+            Frame::Simple(s) | Frame::Error(s) => push_quoted(&mut line, s.as_bytes()),
+            Frame::Bulk(data) => push_quoted(&mut line, data),
+            Frame::Integer(n) => push_quoted(&mut line, n.to_string().as_bytes()),
+            Frame::Null | Frame::Array(_) => push_quoted(&mut line, b""),
+        }
+    }
+
+    line
+}
+
+/// Appends `data` to `line` between double quotes, escaping quotes,
+/// backslashes and non printable bytes like Redis does.
+fn push_quoted(line: &mut String, data: &[u8]) {
+    line.push('"');
+
+    for &byte in data {
+        match byte {
+            b'\\' => line.push_str("\\\\"),
+            b'"' => line.push_str("\\\""),
+            b'\n' => line.push_str("\\n"),
+            b'\r' => line.push_str("\\r"),
+            b'\t' => line.push_str("\\t"),
+            0x07 => line.push_str("\\a"),
+            0x08 => line.push_str("\\b"),
+            byte if byte.is_ascii_graphic() || byte == b' ' => line.push(byte as char),
+            // Writing to a `String` never fails.
+            byte => {
+                let _ = write!(line, "\\x{:02x}", byte);
+            }
+        }
+    }
+
+    line.push('"');
+}
@@ -0,0 +1,84 @@
+use crate::{Connection, Db, Frame, Parse, ParseError};
+
+use bytes::Bytes;
+use tracing::{debug, instrument};
+
+/// Moves `key` from the selected database to another one.
+///
+/// Nothing is moved if the key does not exist in the selected database or
+/// already exists in the destination. Replies with 1 if the key was moved and
+/// 0 otherwise.
+#[derive(Debug)]
+pub struct Move {
+    /// Name of the key to move
+    key: String,
+
+    /// Index of the destination database
+    db: usize,
+}
+
+impl Move {
+    /// Create a new `Move` command which moves `key` to database `db`.
+    pub fn new(key: impl ToString, db: usize) -> Move {
+        Move {
+            key: key.to_string(),
+            db,
+        }
+    }
+
+    /// Get the key
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Get the destination database index
+    pub fn db(&self) -> usize {
+        self.db
+    }
+
+    /// Parse a `Move` instance from a received frame.
+    ///
+    /// The `MOVE` string has already been consumed.
+    ///
+    /// # Format
+    ///
+    /// ```text
+    /// MOVE key db
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<Move, ParseError> {
+        let key = parse.next_string()?;
+        let db = parse.next_int()? as usize;
+
+        Ok(Move { key, db })
+    }
+
+    /// Apply the `Move` command to the specified `Db` instance.
+    ///
+    /// The response is written to `dst`. This is called by the server in order
+    /// to execute a received command.
+    #[instrument(skip(self, db, dst))]
+    pub(crate) async fn apply(self, db: &Db, dst: &mut Connection) -> crate::Result<()> {
+        let response = match db.move_key(&self.key, self.db) {
+            Ok(moved) => Frame::Integer(moved as u64),
+            Err(err) => Frame::Error(format!("ERR {}", err)),
+        };
+
+        debug!(?response);
+
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    ///
+    /// This is called by the client when encoding a `Move` command to send to
+    /// the server.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("move".as_bytes()));
+        frame.push_bulk(Bytes::from(self.key.into_bytes()));
+        frame.push_int(self.db as u64);
+        frame
+    }
+}
@@ -0,0 +1,94 @@
+use crate::{Connection, Db, Frame, Parse, ParseError};
+
+use bytes::Bytes;
+use tracing::{debug, instrument};
+
+/// Inspects the internal state kept for a key.
+///
+/// The command is composed of several subcommands:
+///
+/// * `IDLETIME key` -- Returns the number of seconds since the key was last
+///   read or written.
+/// * `FREQ key` -- Returns the logarithmic access frequency counter of the key,
+///   used by the LFU eviction policies.
+///
+/// Neither subcommand counts as an access to the key. Both reply with a null
+/// if the key does not exist.
+#[derive(Debug)]
+pub enum Object {
+    /// `OBJECT IDLETIME key`
+    IdleTime(String),
+
+    /// `OBJECT FREQ key`
+    Freq(String),
+}
+
+impl Object {
+    /// Get the key
+    pub fn key(&self) -> &str {
+        match self {
+            Object::IdleTime(key) | Object::Freq(key) => key,
+        }
+    }
+
+    /// Parse an `Object` instance from a received frame.
+    ///
+    /// The `OBJECT` string has already been consumed.
+    ///
+    /// # Format
+    ///
+    /// ```text
+    /// OBJECT IDLETIME key
+    /// OBJECT FREQ key
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<Object, ParseError> {
+        let subcommand = parse.next_string()?.to_lowercase();
+
+        match &subcommand[..] {
+            "idletime" => Ok(Object::IdleTime(parse.next_string()?)),
+            "freq" => Ok(Object::Freq(parse.next_string()?)),
+            _ => Err(format!("unknown `OBJECT` subcommand '{}'", subcommand).into()),
+        }
+    }
+
+    /// Apply the `Object` command to the specified `Db` instance.
+    ///
+    /// The response is written to `dst`. This is called by the server in order
+    /// to execute a received command.
+    #[instrument(skip(self, db, dst))]
+    pub(crate) async fn apply(self, db: &Db, dst: &mut Connection) -> crate::Result<()> {
+        let value = match self {
+            Object::IdleTime(key) => db.idletime(&key),
+            Object::Freq(key) => db.freq(&key).map(u64::from),
+        };
+
+        let response = match value {
+            Some(value) => Frame::Integer(value),
+            None => Frame::Null,
+        };
+
+        debug!(?response);
+
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    ///
+    /// This is called by the client when encoding an `Object` command to send
+    /// to the server.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("object".as_bytes()));
+
+        let (subcommand, key) = match self {
+            Object::IdleTime(key) => ("idletime", key),
+            Object::Freq(key) => ("freq", key),
+        };
+
+        frame.push_bulk(Bytes::from(subcommand.as_bytes()));
+        frame.push_bulk(Bytes::from(key.into_bytes()));
+        frame
+    }
+}
@@ -39,11 +39,11 @@ impl Ping {
     /// ```text
     /// PING [message]
     /// ```
-    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<Ping> {
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<Ping, ParseError> {
         match parse.next_string() {
             Ok(msg) => Ok(Ping::new(Some(msg))),
             Err(ParseError::EndOfStream) => Ok(Ping::default()),
-            Err(e) => Err(e.into()),
+            Err(e) => Err(e),
         }
     }
 
@@ -64,6 +64,22 @@ impl Ping {
         Ok(())
     }
 
+    /// Apply the `Ping` command in the subscribed state.
+    ///
+    /// In this state replies are arrays, like published messages are, so the
+    /// response is `["pong", message]`. The message is empty if none was
+    /// provided.
+    #[instrument(skip(self, dst))]
+    pub(crate) async fn apply_subscribed(self, dst: &mut Connection) -> crate::Result<()> {
+        let mut response = Frame::array();
+        response.push_bulk(Bytes::from_static(b"pong"));
+        response.push_bulk(self.msg.map(Bytes::from).unwrap_or_default());
+
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
     /// Converts the command into an equivalent `Frame`.
     ///
     /// This is called by the client when encoding a `Ping` command to send
@@ -0,0 +1,140 @@
+use crate::replication::Resync;
+use crate::{snapshot, Connection, Db, Frame, Parse, ParseError, Shutdown};
+
+use bytes::Bytes;
+use tracing::{debug, instrument};
+
+/// Starts replicating the server.
+///
+/// Sent by replicas to their primary. The connection then carries the
+/// replication stream until it is closed. See the `replication` module for a
+/// description of the protocol.
+#[derive(Debug)]
+pub struct Psync {
+    /// Replication id of the stream the replica followed, if any.
+    replid: Option<String>,
+
+    /// Offset in that stream the replica has applied, if any.
+    offset: Option<u64>,
+}
+
+impl Psync {
+    /// Create a new `Psync` command resuming the stream `replid` at `offset`.
+    /// A full resync is requested if either is `None`.
+    pub(crate) fn new(replid: Option<String>, offset: Option<u64>) -> Psync {
+        Psync { replid, offset }
+    }
+
+    /// Parse a `Psync` instance from a received frame.
+    ///
+    /// The `PSYNC` string has already been consumed.
+    ///
+    /// # Format
+    ///
+    /// ```text
+    /// PSYNC replid offset
+    /// ```
+    ///
+    /// where `replid` is `?` and `offset` is `-1` when they are not known.
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<Psync, ParseError> {
+        let replid = parse.next_string()?;
+        let offset = parse.next_string()?;
+
+        let replid = if replid == "?" { None } else { Some(replid) };
+        let offset = if offset == "-1" {
+            None
+        } else {
+            Some(offset.parse().map_err(|_| "value is not an integer or out of range")?)
+        };
+
+        Ok(Psync { replid, offset })
+    }
+
+    /// Apply the `Psync` command to the specified `Db` instance.
+    ///
+    /// Writes the start of the replication stream to `dst` and keeps streaming
+    /// writes until the connection or the server shuts down. Returns `Err` if
+    /// the replica falls so far behind that the writes it needs are no longer
+    /// in the backlog, closing the connection. The replica then reconnects and
+    /// gets a full resync.
+    #[instrument(skip(self, db, dst, shutdown))]
+    pub(crate) async fn apply(
+        self,
+        db: &Db,
+        dst: &mut Connection,
+        shutdown: &mut Shutdown,
+    ) -> crate::Result<()> {
+        if db.is_replica() {
+            let response = Frame::Error("ERR chained replication is not supported".to_string());
+            debug!(?response);
+            dst.write_frame(&response).await?;
+            return Ok(());
+        }
+
+        let (mut offset, mut changes) = match db.psync(self.replid.as_deref(), self.offset) {
+            Resync::Full {
+                replid,
+                offset,
+                records,
+                changes,
+            } => {
+                let response = Frame::Simple(format!("FULLRESYNC {} {}", replid, offset));
+                debug!(?response);
+                dst.write_frame(&response).await?;
+
+                let data = snapshot::encode(&records);
+                dst.write_frame(&Frame::Bulk(Bytes::from(data))).await?;
+
+                (offset, changes)
+            }
+            Resync::Partial { replid, changes } => {
+                let response = Frame::Simple(format!("CONTINUE {}", replid));
+                debug!(?response);
+                dst.write_frame(&response).await?;
+
+                // A partial resync is only accepted for a known offset.
+                (self.offset.unwrap(), changes)
+            }
+        };
+
+        // Tracks what the replica received, for `SHUTDOWN`, until the stream
+        // ends.
+        let replica = db.add_replica(offset);
+
+        loop {
+            let (frames, next) = db
+                .backlog_since(offset)
+                .ok_or("replica fell behind the replication backlog")?;
+
+            for frame in &frames {
+                dst.write_frame(frame).await?;
+            }
+
+            offset = next;
+            replica.sent(offset);
+
+            tokio::select! {
+                res = changes.changed() => {
+                    if res.is_err() {
+                        return Ok(());
+                    }
+                }
+                _ = shutdown.recv() => return Ok(()),
+            }
+        }
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    ///
+    /// This is called by the replica when encoding a `Psync` command to send
+    /// to the primary.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("psync".as_bytes()));
+        frame.push_bulk(Bytes::from(self.replid.unwrap_or_else(|| "?".to_string())));
+
+        let offset = self.offset.map_or_else(|| "-1".to_string(), |offset| offset.to_string());
+        frame.push_bulk(Bytes::from(offset));
+        frame
+    }
+}
@@ -1,4 +1,4 @@
-use crate::{Connection, Db, Frame, Parse};
+use crate::{Connection, Db, Frame, Parse, ParseError};
 
 use bytes::Bytes;
 
@@ -27,6 +27,11 @@ impl Publish {
         }
     }
 
+    /// Get the channel
+    pub(crate) fn channel(&self) -> &str {
+        &self.channel
+    }
+
     /// Parse a `Publish` instance from a received frame.
     ///
     /// The `Parse` argument provides a cursor-like API to read fields from the
@@ -47,7 +52,7 @@ impl Publish {
     /// ```text
     /// PUBLISH channel message
     /// ```
-    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<Publish> {
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<Publish, ParseError> {
         // The `PUBLISH` string has already been consumed. Extract the `channel`
         // and `message` values from the frame.
         //
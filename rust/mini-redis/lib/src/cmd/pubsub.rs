@@ -0,0 +1,151 @@
+use crate::{Connection, Db, Frame, Parse, ParseError};
+
+use bytes::Bytes;
+use tracing::{debug, instrument};
+
+/// Introspects the state of the pub/sub subsystem.
+///
+/// The command is composed of several subcommands:
+///
+/// * `CHANNELS [pattern]` -- Lists the active channels, optionally filtered by
+///   a glob-style pattern. A channel is active when it has at least one
+///   subscriber.
+/// * `NUMSUB [channel ...]` -- Returns the number of subscribers of each of the
+///   specified channels.
+/// * `NUMPAT` -- Returns the number of pattern subscriptions. `mini-redis` does
+///   not support `PSUBSCRIBE`, so this is always zero.
+#[derive(Debug)]
+pub enum Pubsub {
+    /// `PUBSUB CHANNELS [pattern]`
+    Channels(Option<String>),
+
+    /// `PUBSUB NUMSUB [channel ...]`
+    NumSub(Vec<String>),
+
+    /// `PUBSUB NUMPAT`
+    NumPat,
+}
+
+impl Pubsub {
+    /// Parse a `Pubsub` instance from a received frame.
+    ///
+    /// The `Parse` argument provides a cursor-like API to read fields from the
+    /// `Frame`. At this point, the entire frame has already been received from
+    /// the socket.
+    ///
+    /// The `PUBSUB` string has already been consumed.
+    ///
+    /// # Returns
+    ///
+    /// Returns the `Pubsub` value on success. If the frame is malformed or the
+    /// subcommand is unknown, `Err` is returned.
+    ///
+    /// # Format
+    ///
+    /// Expects an array frame containing the subcommand and its arguments.
+    ///
+    /// ```text
+    /// PUBSUB CHANNELS [pattern]
+    /// PUBSUB NUMSUB [channel [channel ...]]
+    /// PUBSUB NUMPAT
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<Pubsub, ParseError> {
+        use ParseError::EndOfStream;
+
+        let subcommand = parse.next_string()?.to_lowercase();
+
+        match &subcommand[..] {
+            "channels" => match parse.next_string() {
+                Ok(pattern) => Ok(Pubsub::Channels(Some(pattern))),
+                Err(EndOfStream) => Ok(Pubsub::Channels(None)),
+                Err(err) => Err(err),
+            },
+            "numsub" => {
+                // Any number of channels, including none, may follow.
+                let mut channels = vec![];
+
+                loop {
+                    match parse.next_string() {
+                        Ok(channel) => channels.push(channel),
+                        Err(EndOfStream) => break,
+                        Err(err) => return Err(err),
+                    }
+                }
+
+                Ok(Pubsub::NumSub(channels))
+            }
+            "numpat" => Ok(Pubsub::NumPat),
+            _ => Err(format!("unknown `PUBSUB` subcommand '{}'", subcommand).into()),
+        }
+    }
+
+    /// Apply the `Pubsub` command to the specified `Db` instance.
+    ///
+    /// The response is written to `dst`. This is called by the server in order
+    /// to execute a received command.
+    #[instrument(skip(self, db, dst))]
+    pub(crate) async fn apply(self, db: &Db, dst: &mut Connection) -> crate::Result<()> {
+        let response = match self {
+            Pubsub::Channels(pattern) => {
+                // The active channels are returned as an array of bulk strings.
+                let mut response = Frame::array();
+
+                for channel in db.pubsub_channels(pattern.as_deref()) {
+                    response.push_bulk(Bytes::from(channel));
+                }
+
+                response
+            }
+            Pubsub::NumSub(channels) => {
+                // The response is a flat array alternating each channel name
+                // with its number of subscribers.
+                let counts = db.pubsub_numsub(&channels);
+                let mut response = Frame::array();
+
+                for (channel, count) in channels.into_iter().zip(counts) {
+                    response.push_bulk(Bytes::from(channel));
+                    response.push_int(count as u64);
+                }
+
+                response
+            }
+            // There are no pattern subscriptions in `mini-redis`.
+            Pubsub::NumPat => Frame::Integer(0),
+        };
+
+        debug!(?response);
+
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    ///
+    /// This is called by the client when encoding a `Pubsub` command to send
+    /// to the server.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("pubsub".as_bytes()));
+
+        match self {
+            Pubsub::Channels(pattern) => {
+                frame.push_bulk(Bytes::from("channels".as_bytes()));
+                if let Some(pattern) = pattern {
+                    frame.push_bulk(Bytes::from(pattern.into_bytes()));
+                }
+            }
+            Pubsub::NumSub(channels) => {
+                frame.push_bulk(Bytes::from("numsub".as_bytes()));
+                for channel in channels {
+                    frame.push_bulk(Bytes::from(channel.into_bytes()));
+                }
+            }
+            Pubsub::NumPat => {
+                frame.push_bulk(Bytes::from("numpat".as_bytes()));
+            }
+        }
+
+        frame
+    }
+}
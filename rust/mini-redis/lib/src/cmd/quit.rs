@@ -0,0 +1,32 @@
+use crate::{Connection, Frame, Shutdown};
+
+use tracing::{debug, instrument};
+
+/// Asks the server to close the connection.
+///
+/// The server replies with `OK` and closes the connection once the reply has
+/// been written. `QUIT` is accepted in the subscribed state as well.
+#[derive(Debug, Default)]
+pub struct Quit;
+
+impl Quit {
+    /// Create a new `Quit` command.
+    pub fn new() -> Quit {
+        Quit
+    }
+
+    /// Apply the `Quit` command.
+    ///
+    /// The response is written to `dst` and the connection's `shutdown` is
+    /// triggered, terminating the connection once the command returns.
+    #[instrument(skip(self, dst, shutdown))]
+    pub(crate) async fn apply(self, dst: &mut Connection, shutdown: &mut Shutdown) -> crate::Result<()> {
+        let response = Frame::Simple("OK".to_string());
+        debug!(?response);
+        dst.write_frame(&response).await?;
+
+        shutdown.trigger();
+
+        Ok(())
+    }
+}
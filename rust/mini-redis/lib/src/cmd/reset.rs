@@ -0,0 +1,48 @@
+use crate::{Connection, Db, Frame};
+
+use bytes::Bytes;
+use tracing::{debug, instrument};
+
+/// Resets the connection to its initial state.
+///
+/// The connection switches back to database 0 and loses the name set with
+/// `CLIENT SETNAME`. The client is authenticated as the `default` user again
+/// if it needs no password, and is left unauthenticated otherwise.
+///
+/// When issued in the subscribed state, the client is also unsubscribed from
+/// every channel and leaves the subscribed state. The server replies with
+/// `RESET`.
+#[derive(Debug, Default)]
+pub struct Reset;
+
+impl Reset {
+    /// Create a new `Reset` command.
+    pub fn new() -> Reset {
+        Reset
+    }
+
+    /// Apply the `Reset` command.
+    ///
+    /// The connection state kept by `db` is reset and the response is written
+    /// to `dst`. Leaving the subscribed or monitor state is up to the caller.
+    #[instrument(skip(self, db, dst))]
+    pub(crate) async fn apply(self, db: &mut Db, dst: &mut Connection) -> crate::Result<()> {
+        db.reset_client();
+
+        let response = Frame::Simple("RESET".to_string());
+        debug!(?response);
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    ///
+    /// This is called by the client when encoding a `Reset` command to send to
+    /// the server.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("reset".as_bytes()));
+        frame
+    }
+}
@@ -0,0 +1,91 @@
+use crate::{snapshot, Connection, Db, Frame};
+
+use bytes::Bytes;
+use tracing::{debug, instrument};
+
+/// Saves a snapshot of the dataset to disk, replying once it is written.
+///
+/// The snapshot is written to the path configured in
+/// [`Config::snapshot_path`](crate::config::Config::snapshot_path). An error is
+/// returned if snapshot persistence is disabled or another save is in
+/// progress.
+#[derive(Debug, Default)]
+pub struct Save;
+
+/// Saves a snapshot of the dataset to disk in the background.
+///
+/// Replies as soon as the save has started. The snapshot contains the dataset
+/// as it was when the command was received.
+#[derive(Debug, Default)]
+pub struct BgSave;
+
+impl Save {
+    /// Create a new `Save` command.
+    pub fn new() -> Save {
+        Save
+    }
+
+    /// Apply the `Save` command to the specified `Db` instance.
+    ///
+    /// The response is written to `dst`. This is called by the server in order
+    /// to execute a received command.
+    #[instrument(skip(self, db, dst))]
+    pub(crate) async fn apply(self, db: &Db, dst: &mut Connection) -> crate::Result<()> {
+        // Failing to save is reported to the client, the connection is kept.
+        let response = match snapshot::save(db).await {
+            Ok(()) => Frame::Simple("OK".to_string()),
+            Err(err) => Frame::Error(format!("ERR {}", err)),
+        };
+
+        debug!(?response);
+
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    ///
+    /// This is called by the client when encoding a `Save` command to send to
+    /// the server.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("save".as_bytes()));
+        frame
+    }
+}
+
+impl BgSave {
+    /// Create a new `BgSave` command.
+    pub fn new() -> BgSave {
+        BgSave
+    }
+
+    /// Apply the `BgSave` command to the specified `Db` instance.
+    ///
+    /// The response is written to `dst`. This is called by the server in order
+    /// to execute a received command.
+    #[instrument(skip(self, db, dst))]
+    pub(crate) async fn apply(self, db: &Db, dst: &mut Connection) -> crate::Result<()> {
+        let response = match snapshot::bgsave(db) {
+            Ok(()) => Frame::Simple("Background saving started".to_string()),
+            Err(err) => Frame::Error(format!("ERR {}", err)),
+        };
+
+        debug!(?response);
+
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    ///
+    /// This is called by the client when encoding a `BgSave` command to send
+    /// to the server.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("bgsave".as_bytes()));
+        frame
+    }
+}
@@ -0,0 +1,70 @@
+use crate::{Connection, Db, Frame, Parse, ParseError};
+
+use bytes::Bytes;
+use tracing::{debug, instrument};
+
+/// Selects the logical database the connection operates on.
+///
+/// Every connection starts on database 0. The number of databases is set by
+/// `Config::databases`, 16 by default.
+#[derive(Debug)]
+pub struct Select {
+    /// Index of the database to select
+    index: usize,
+}
+
+impl Select {
+    /// Create a new `Select` command which selects database `index`.
+    pub fn new(index: usize) -> Select {
+        Select { index }
+    }
+
+    /// Get the database index
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Parse a `Select` instance from a received frame.
+    ///
+    /// The `SELECT` string has already been consumed.
+    ///
+    /// # Format
+    ///
+    /// ```text
+    /// SELECT index
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<Select, ParseError> {
+        let index = parse.next_int()? as usize;
+
+        Ok(Select { index })
+    }
+
+    /// Apply the `Select` command, switching `db` to the requested database.
+    ///
+    /// `db` is the handle of the connection, so only the database of this
+    /// connection changes. The response is written to `dst`.
+    #[instrument(skip(self, db, dst))]
+    pub(crate) async fn apply(self, db: &mut Db, dst: &mut Connection) -> crate::Result<()> {
+        let response = match db.select(self.index) {
+            Ok(()) => Frame::Simple("OK".to_string()),
+            Err(err) => Frame::Error(format!("ERR {}", err)),
+        };
+
+        debug!(?response);
+
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    ///
+    /// This is called by the client when encoding a `Select` command to send
+    /// to the server.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("select".as_bytes()));
+        frame.push_int(self.index as u64);
+        frame
+    }
+}
@@ -2,7 +2,7 @@ use crate::cmd::{Parse, ParseError};
 use crate::{Connection, Db, Frame};
 
 use bytes::Bytes;
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tracing::{debug, instrument};
 
 /// Set `key` to hold the string `value`.
@@ -17,6 +17,9 @@ use tracing::{debug, instrument};
 ///
 /// * EX `seconds` -- Set the specified expire time, in seconds.
 /// * PX `milliseconds` -- Set the specified expire time, in milliseconds.
+/// * EXAT `timestamp` -- Set the Unix time, in seconds, the key expires at.
+/// * PXAT `timestamp` -- Set the Unix time, in milliseconds, the key expires
+///   at.
 #[derive(Debug)]
 pub struct Set {
     /// the lookup key
@@ -75,9 +78,9 @@ impl Set {
     /// Expects an array frame containing at least 3 entries.
     ///
     /// ```text
-    /// SET key value [EX seconds|PX milliseconds]
+    /// SET key value [EX seconds|PX milliseconds|EXAT timestamp|PXAT timestamp]
     /// ```
-    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<Set> {
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<Set, ParseError> {
         use ParseError::EndOfStream;
 
         // Read the key to set. This is a required field
@@ -104,17 +107,28 @@ impl Set {
                 let ms = parse.next_int()?;
                 expire = Some(Duration::from_millis(ms));
             }
+            Ok(s) if s.to_uppercase() == "EXAT" || s.to_uppercase() == "PXAT" => {
+                // An absolute expiration. It is converted to the time left
+                // until then, a time in the past expires the key right away.
+                let timestamp = parse.next_int()?;
+                let at = if s.to_uppercase() == "EXAT" {
+                    UNIX_EPOCH + Duration::from_secs(timestamp)
+                } else {
+                    UNIX_EPOCH + Duration::from_millis(timestamp)
+                };
+                expire = Some(at.duration_since(SystemTime::now()).unwrap_or_default());
+            }
             // Currently, mini-redis does not support any of the other SET
-            // options. An error here results in the connection being
-            // terminated. Other connections will continue to operate normally.
+            // options. The error is answered to the client, and the
+            // connection keeps going.
             Ok(_) => return Err("currently `SET` only supports the expiration option".into()),
             // The `EndOfStream` error indicates there is no further data to
             // parse. In this case, it is a normal run time situation and
             // indicates there are no specified `SET` options.
             Err(EndOfStream) => {}
-            // All other errors are bubbled up, resulting in the connection
-            // being terminated.
-            Err(err) => return Err(err.into()),
+            // All other errors are bubbled up. Only protocol errors result
+            // in the connection being terminated.
+            Err(err) => return Err(err),
         }
 
         Ok(Set { key, value, expire })
@@ -0,0 +1,190 @@
+use crate::cmd::PauseMode;
+use crate::{Connection, Db, Frame, Parse, ParseError};
+
+use bytes::Bytes;
+use tokio::time;
+use tracing::{debug, info, instrument, warn};
+
+/// Whether `SHUTDOWN` saves a final snapshot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SaveMode {
+    /// Save if snapshot persistence is enabled.
+    Default,
+
+    /// Save, failing if snapshot persistence is disabled.
+    Save,
+
+    /// Do not save, even if snapshot persistence is enabled.
+    NoSave,
+}
+
+/// Stops the server.
+///
+/// Unless `NOW` is given, the server first waits for its replicas to receive
+/// every write, with writes paused, for up to `Config::shutdown_timeout`.
+/// `SHUTDOWN ABORT` cancels that wait from another connection, and the
+/// `SHUTDOWN` waiting fails.
+///
+/// The server then stops accepting connections and closes the open ones, as
+/// on `ctrl-c`, and saves a final snapshot according to the `SaveMode`. The
+/// connection that sent the command is closed without a reply.
+#[derive(Debug)]
+pub struct Shutdown {
+    save: SaveMode,
+
+    /// Do not wait for the replicas.
+    now: bool,
+
+    /// `SHUTDOWN ABORT`: cancels a `SHUTDOWN` waiting for the replicas
+    /// instead.
+    abort: bool,
+}
+
+impl Shutdown {
+    /// Create a new `Shutdown` command stopping the server, without waiting
+    /// for the replicas if `now`.
+    pub fn new(save: SaveMode, now: bool) -> Shutdown {
+        Shutdown {
+            save,
+            now,
+            abort: false,
+        }
+    }
+
+    /// Create a new `Shutdown` command cancelling a `SHUTDOWN` waiting for
+    /// the replicas.
+    pub fn abort() -> Shutdown {
+        Shutdown {
+            save: SaveMode::Default,
+            now: false,
+            abort: true,
+        }
+    }
+
+    /// Parse a `Shutdown` instance from a received frame.
+    ///
+    /// The `SHUTDOWN` string has already been consumed.
+    ///
+    /// # Format
+    ///
+    /// ```text
+    /// SHUTDOWN [NOSAVE|SAVE] [NOW]
+    /// SHUTDOWN ABORT
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<Shutdown, ParseError> {
+        let mut save = SaveMode::Default;
+        let mut now = false;
+        let mut abort = false;
+
+        loop {
+            let option = match parse.next_string() {
+                Ok(option) => option,
+                Err(ParseError::EndOfStream) => break,
+                Err(err) => return Err(err),
+            };
+
+            match &option.to_uppercase()[..] {
+                "SAVE" if save == SaveMode::Default => save = SaveMode::Save,
+                "NOSAVE" if save == SaveMode::Default => save = SaveMode::NoSave,
+                "NOW" => now = true,
+                "ABORT" => abort = true,
+                _ => return Err("syntax error".into()),
+            }
+        }
+
+        if abort && (save != SaveMode::Default || now) {
+            return Err("syntax error".into());
+        }
+
+        Ok(Shutdown { save, now, abort })
+    }
+
+    /// Apply the `Shutdown` command to the specified `Db` instance.
+    ///
+    /// The response, if any, is written to `dst`. This is called by the
+    /// server in order to execute a received command.
+    #[instrument(skip(self, db, dst))]
+    pub(crate) async fn apply(self, db: &Db, dst: &mut Connection) -> crate::Result<()> {
+        let response = if self.abort {
+            if db.abort_shutdown() {
+                Frame::Simple("OK".to_string())
+            } else {
+                Frame::Error("ERR No shutdown in progress.".to_string())
+            }
+        } else if self.save == SaveMode::Save && db.snapshot_path().is_none() {
+            Frame::Error("ERR snapshot persistence is disabled".to_string())
+        } else if !self.now && !wait_replicas(db).await {
+            Frame::Error("ERR Errors trying to SHUTDOWN. Check logs.".to_string())
+        } else {
+            // The server closes the connection once it shuts down.
+            db.request_shutdown(self.save);
+            return Ok(());
+        };
+
+        debug!(?response);
+
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    ///
+    /// This is called by the client when encoding a `Shutdown` command to
+    /// send to the server.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("shutdown".as_bytes()));
+
+        match self.save {
+            SaveMode::Default => {}
+            SaveMode::Save => frame.push_bulk(Bytes::from("save".as_bytes())),
+            SaveMode::NoSave => frame.push_bulk(Bytes::from("nosave".as_bytes())),
+        }
+
+        if self.now {
+            frame.push_bulk(Bytes::from("now".as_bytes()));
+        }
+
+        if self.abort {
+            frame.push_bulk(Bytes::from("abort".as_bytes()));
+        }
+
+        frame
+    }
+}
+
+/// Waits, with writes paused, for the replicas to receive every write, up to
+/// the shutdown timeout. Returns `false` if `SHUTDOWN ABORT` cancelled the
+/// wait.
+async fn wait_replicas(db: &Db) -> bool {
+    if !db.has_replicas() {
+        return true;
+    }
+
+    let timeout = db.shutdown_timeout();
+    let abort = db.begin_shutdown();
+
+    // No new write can leave the replicas behind.
+    db.client_pause(timeout, PauseMode::Write);
+    info!("waiting for the replicas before shutting down");
+
+    let aborted = tokio::select! {
+        _ = db.wait_replicas() => false,
+        _ = time::sleep(timeout) => {
+            // Like Redis, the server shuts down anyway.
+            warn!("replicas still behind after the shutdown timeout");
+            false
+        }
+        _ = abort.notified() => true,
+    };
+
+    db.end_shutdown();
+
+    if aborted {
+        info!("shutdown aborted");
+        db.client_unpause();
+    }
+
+    !aborted
+}
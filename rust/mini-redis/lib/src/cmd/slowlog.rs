@@ -0,0 +1,120 @@
+use crate::latency::unix_time;
+use crate::{Connection, Db, Frame, Parse, ParseError};
+
+use bytes::Bytes;
+use tracing::{debug, instrument};
+
+/// Reads or resets the slow log, the most recent commands that took longer
+/// than `Config::slowlog_log_slower_than` to execute.
+///
+/// The command is composed of several subcommands:
+///
+/// * `GET [count]` -- Returns the `count` newest entries, 10 by default, or
+///   all of them if `count` is -1. Each entry is an array of its unique id,
+///   the Unix time it was logged at, the execution time in microseconds, the
+///   command and its arguments, the client address and the client name.
+/// * `LEN` -- Returns the number of entries.
+/// * `RESET` -- Removes every entry.
+#[derive(Debug)]
+pub enum Slowlog {
+    /// `SLOWLOG GET [count]`, `None` returns every entry.
+    Get(Option<usize>),
+
+    /// `SLOWLOG LEN`
+    Len,
+
+    /// `SLOWLOG RESET`
+    Reset,
+}
+
+/// Number of entries `SLOWLOG GET` returns by default.
+const DEFAULT_COUNT: usize = 10;
+
+impl Slowlog {
+    /// Parse a `Slowlog` instance from a received frame.
+    ///
+    /// The `SLOWLOG` string has already been consumed.
+    ///
+    /// # Format
+    ///
+    /// ```text
+    /// SLOWLOG GET [count]
+    /// SLOWLOG LEN
+    /// SLOWLOG RESET
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<Slowlog, ParseError> {
+        let subcommand = parse.next_string()?.to_lowercase();
+
+        match &subcommand[..] {
+            "get" => match parse.next_string() {
+                Ok(count) if count == "-1" => Ok(Slowlog::Get(None)),
+                Ok(count) => match count.parse() {
+                    Ok(count) => Ok(Slowlog::Get(Some(count))),
+                    Err(_) => Err("value is not an integer or out of range".into()),
+                },
+                Err(ParseError::EndOfStream) => Ok(Slowlog::Get(Some(DEFAULT_COUNT))),
+                Err(err) => Err(err),
+            },
+            "len" => Ok(Slowlog::Len),
+            "reset" => Ok(Slowlog::Reset),
+            _ => Err(format!("unknown `SLOWLOG` subcommand '{}'", subcommand).into()),
+        }
+    }
+
+    /// Apply the `Slowlog` command to the specified `Db` instance.
+    ///
+    /// The response is written to `dst`. This is called by the server in order
+    /// to execute a received command.
+    #[instrument(skip(self, db, dst))]
+    pub(crate) async fn apply(self, db: &Db, dst: &mut Connection) -> crate::Result<()> {
+        let response = match self {
+            Slowlog::Get(count) => {
+                let entries = db.slowlog_get(count).into_iter().map(|entry| {
+                    Frame::Array(vec![
+                        Frame::Integer(entry.id),
+                        Frame::Integer(unix_time(entry.time)),
+                        Frame::Integer(entry.duration.as_micros() as u64),
+                        Frame::Array(entry.args.into_iter().map(Frame::Bulk).collect()),
+                        Frame::Bulk(Bytes::from(entry.addr.to_string())),
+                        Frame::Bulk(Bytes::from(entry.name)),
+                    ])
+                });
+
+                Frame::Array(entries.collect())
+            }
+            Slowlog::Len => Frame::Integer(db.slowlog_len() as u64),
+            Slowlog::Reset => {
+                db.slowlog_reset();
+                Frame::Simple("OK".to_string())
+            }
+        };
+
+        debug!(?response);
+
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    ///
+    /// This is called by the client when encoding a `Slowlog` command to send
+    /// to the server.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("slowlog".as_bytes()));
+
+        match self {
+            Slowlog::Get(count) => {
+                frame.push_bulk(Bytes::from("get".as_bytes()));
+
+                let count = count.map_or("-1".to_string(), |count| count.to_string());
+                frame.push_bulk(Bytes::from(count.into_bytes()));
+            }
+            Slowlog::Len => frame.push_bulk(Bytes::from("len".as_bytes())),
+            Slowlog::Reset => frame.push_bulk(Bytes::from("reset".as_bytes())),
+        }
+
+        frame
+    }
+}
@@ -1,17 +1,21 @@
 use crate::cmd::{Parse, ParseError, Unknown};
+use crate::config::SlowSubscriberPolicy;
 use crate::{Command, Connection, Db, Frame, Shutdown};
 
 use bytes::Bytes;
 use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 use tokio::select;
-use tokio::sync::broadcast;
+use tokio::sync::{broadcast, mpsc};
 use tokio_stream::{Stream, StreamExt, StreamMap};
+use tracing::warn;
 
 /// Subscribes the client to one or more channels.
 ///
 /// Once the client enters the subscribed state, it is not supposed to issue any
 /// other commands, except for additional SUBSCRIBE, PSUBSCRIBE, UNSUBSCRIBE,
-/// PUNSUBSCRIBE, PING and QUIT commands.
+/// PUNSUBSCRIBE, PING, QUIT and RESET commands.
 #[derive(Debug)]
 pub struct Subscribe {
     channels: Vec<String>,
@@ -30,7 +34,25 @@ pub struct Unsubscribe {
 /// `broadcast::Receiver`. We use `stream!` to create a `Stream` that consumes
 /// messages. Because `stream!` values cannot be named, we box the stream using
 /// a trait object.
-type Messages = Pin<Box<dyn Stream<Item = Bytes> + Send>>;
+type Messages = Pin<Box<dyn Stream<Item = Delivery> + Send>>;
+
+/// An item yielded by a subscription stream.
+///
+/// Besides the published messages, the stream reports how the subscriber is
+/// keeping up with the publishers so `Subscribe::apply` can enforce the
+/// configured `SlowSubscriberPolicy`.
+#[derive(Debug)]
+enum Delivery {
+    /// A message published on the channel.
+    Message(Bytes),
+
+    /// The subscriber fell behind and this many messages were dropped.
+    Lagged(u64),
+
+    /// The subscriber fell behind more than the policy allows. The client must
+    /// be disconnected.
+    Overflow,
+}
 
 impl Subscribe {
     /// Creates a new `Subscribe` command to listen on the specified channels.
@@ -40,6 +62,11 @@ impl Subscribe {
         }
     }
 
+    /// Get the channels to subscribe to
+    pub(crate) fn channels(&self) -> &[String] {
+        &self.channels
+    }
+
     /// Parse a `Subscribe` instance from a received frame.
     ///
     /// The `Parse` argument provides a cursor-like API to read fields from the
@@ -60,7 +87,7 @@ impl Subscribe {
     /// ```text
     /// SUBSCRIBE channel [channel ...]
     /// ```
-    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<Subscribe> {
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<Subscribe, ParseError> {
         use ParseError::EndOfStream;
 
         // The `SUBSCRIBE` string has already been consumed. At this point,
@@ -82,9 +109,9 @@ impl Subscribe {
                 // The `EndOfStream` error indicates there is no further data to
                 // parse.
                 Err(EndOfStream) => break,
-                // All other errors are bubbled up, resulting in the connection
-                // being terminated.
-                Err(err) => return Err(err.into()),
+                // All other errors are bubbled up. Only protocol errors result
+                // in the connection being terminated.
+                Err(err) => return Err(err),
             }
         }
 
@@ -101,7 +128,7 @@ impl Subscribe {
     /// [here]: https://redis.io/topics/pubsub
     pub(crate) async fn apply(
         mut self,
-        db: &Db,
+        db: &mut Db,
         dst: &mut Connection,
         shutdown: &mut Shutdown,
     ) -> crate::Result<()> {
@@ -116,43 +143,86 @@ impl Subscribe {
         // they are received.
         let mut subscriptions = StreamMap::new();
 
-        loop {
-            // `self.channels` is used to track additional channels to subscribe
-            // to. When new `SUBSCRIBE` commands are received during the
-            // execution of `apply`, the new channels are pushed onto this vec.
-            for channel_name in self.channels.drain(..) {
-                subscribe_to_channel(channel_name, &mut subscriptions, db, dst).await?;
+        let res = async {
+            loop {
+                // `self.channels` is used to track additional channels to
+                // subscribe to. When new `SUBSCRIBE` commands are received
+                // during the execution of `apply`, the new channels are pushed
+                // onto this vec.
+                for channel_name in self.channels.drain(..) {
+                    subscribe_to_channel(channel_name, &mut subscriptions, db, dst).await?;
+                }
+
+                // Wait for one of the following to happen:
+                //
+                // - Receive a message from one of the subscribed channels.
+                // - Receive a command from the client.
+                // - A server shutdown signal.
+                select! {
+                    // Receive messages from subscribed channels
+                    Some((channel_name, delivery)) = subscriptions.next() => {
+                        match delivery {
+                            Delivery::Message(msg) => {
+                                dst.write_frame(&make_message_frame(channel_name, msg)).await?;
+                            }
+                            Delivery::Lagged(num_dropped) => {
+                                db.messages_dropped(num_dropped);
+                                let frame = make_lagged_frame(channel_name, num_dropped);
+                                dst.write_frame(&frame).await?;
+                            }
+                            Delivery::Overflow => {
+                                // Returning an error terminates the connection.
+                                warn!(channel = %channel_name, "disconnecting slow subscriber");
+                                return Err(format!(
+                                    "subscriber fell behind on channel '{}'",
+                                    channel_name
+                                ).into());
+                            }
+                        }
+                    }
+                    res = dst.read_frame() => {
+                        let frame = match res? {
+                            Some(frame) => frame,
+                            // This happens if the remote client has disconnected.
+                            None => return Ok(())
+                        };
+
+                        let subscribed = handle_command(
+                            frame,
+                            &mut self.channels,
+                            &mut subscriptions,
+                            db,
+                            dst,
+                            shutdown,
+                        ).await?;
+
+                        if !subscribed {
+                            // The client left the subscribed state, `QUIT` or
+                            // `RESET` was received.
+                            return Ok(());
+                        }
+                    }
+                    _ = shutdown.recv() => {
+                        return Ok(());
+                    }
+                };
             }
+        }
+        .await;
 
-            // Wait for one of the following to happen:
-            //
-            // - Receive a message from one of the subscribed channels.
-            // - Receive a subscribe or unsubscribe command from the client.
-            // - A server shutdown signal.
-            select! {
-                // Receive messages from subscribed channels
-                Some((channel_name, msg)) = subscriptions.next() => {
-                    dst.write_frame(&make_message_frame(channel_name, msg)).await?;
-                }
-                res = dst.read_frame() => {
-                    let frame = match res? {
-                        Some(frame) => frame,
-                        // This happens if the remote client has disconnected.
-                        None => return Ok(())
-                    };
-
-                    handle_command(
-                        frame,
-                        &mut self.channels,
-                        &mut subscriptions,
-                        dst,
-                    ).await?;
-                }
-                _ = shutdown.recv() => {
-                    return Ok(());
-                }
-            };
+        // However the subscription ended, the receivers still held by this
+        // client are dropped here and the channels are released, so that
+        // channels left without subscribers are removed from the `Db`.
+        let channel_names: Vec<String> = subscriptions.keys().cloned().collect();
+        drop(subscriptions);
+
+        for channel_name in &channel_names {
+            db.unsubscribe(channel_name);
         }
+
+        db.client_subscriptions(0);
+
+        res
     }
 
     /// Converts the command into an equivalent `Frame`.
@@ -175,22 +245,22 @@ async fn subscribe_to_channel(
     db: &Db,
     dst: &mut Connection,
 ) -> crate::Result<()> {
-    let mut rx = db.subscribe(channel_name.clone());
-
-    // Subscribe to the channel.
-    let rx = Box::pin(async_stream::stream! {
-        loop {
-            match rx.recv().await {
-                Ok(msg) => yield msg,
-                // If we lagged in consuming messages, just resume.
-                Err(broadcast::error::RecvError::Lagged(_)) => {}
-                Err(_) => break,
-            }
+    let rx = db.subscribe(channel_name.clone());
+
+    // Subscribe to the channel. How the stream reacts to the subscriber
+    // falling behind depends on the configured policy.
+    let rx = match db.slow_subscriber_policy() {
+        SlowSubscriberPolicy::DropAndNotify => lagging_stream(rx, false),
+        SlowSubscriberPolicy::Disconnect => lagging_stream(rx, true),
+        SlowSubscriberPolicy::Grow { max_bytes } => {
+            buffered_stream(rx, channel_name.clone(), db.clone(), max_bytes)
         }
-    });
+    };
 
-    // Track subscription in this client's subscription set.
+    // Track subscription in this client's subscription set, which `CLIENT
+    // LIST` reports.
     subscriptions.insert(channel_name.clone(), rx);
+    db.client_subscriptions(subscriptions.len());
 
     // Respond with the successful subscription
     let response = make_subscribe_frame(channel_name, subscriptions.len());
@@ -199,26 +269,145 @@ async fn subscribe_to_channel(
     Ok(())
 }
 
-/// Handle a command received while inside `Subscribe::apply`. Only subscribe
-/// and unsubscribe commands are permitted in this context.
+/// Creates a stream reading messages straight from the broadcast channel.
+///
+/// Messages are only received while the stream is polled, which
+/// `Subscribe::apply` does not do while it is writing to a slow client. If
+/// more messages than the channel capacity are published in the meantime, the
+/// oldest ones are dropped. When this happens the stream yields either a
+/// `Lagged` notification and resumes, or `Overflow` if `disconnect` is set.
+fn lagging_stream(mut rx: broadcast::Receiver<Bytes>, disconnect: bool) -> Messages {
+    Box::pin(async_stream::stream! {
+        loop {
+            match rx.recv().await {
+                Ok(msg) => yield Delivery::Message(msg),
+                Err(broadcast::error::RecvError::Lagged(_)) if disconnect => {
+                    yield Delivery::Overflow;
+                    break;
+                }
+                Err(broadcast::error::RecvError::Lagged(num_dropped)) => {
+                    yield Delivery::Lagged(num_dropped);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    })
+}
+
+/// Creates a stream backed by a buffer that grows as needed.
+///
+/// A task is spawned to move messages from the broadcast channel into an
+/// unbounded `mpsc` channel as soon as they are published, independently of
+/// how fast the client reads them. The total size of the messages waiting in
+/// the `mpsc` channel is tracked and, once it exceeds `max_bytes`, the stream
+/// yields `Overflow` and the task stops.
+///
+/// The task holds the `broadcast::Receiver`, so it also takes care of
+/// releasing the channel once the stream is dropped.
+fn buffered_stream(
+    mut rx: broadcast::Receiver<Bytes>,
+    channel_name: String,
+    db: Db,
+    max_bytes: usize,
+) -> Messages {
+    let (tx, mut buffer) = mpsc::unbounded_channel();
+
+    // Number of bytes pushed into the `mpsc` channel and not yet yielded by
+    // the stream. Shared by the forwarding task and the stream.
+    let buffered = Arc::new(AtomicUsize::new(0));
+    let pending = buffered.clone();
+
+    tokio::spawn(async move {
+        loop {
+            let delivery = select! {
+                res = rx.recv() => match res {
+                    Ok(msg) => {
+                        let len = msg.len();
+
+                        if pending.fetch_add(len, Ordering::Relaxed) + len > max_bytes {
+                            let _ = tx.send(Delivery::Overflow);
+                            break;
+                        }
+
+                        Delivery::Message(msg)
+                    }
+                    // Only possible if more than the channel capacity is
+                    // published before this task gets to run.
+                    Err(broadcast::error::RecvError::Lagged(num_dropped)) => {
+                        Delivery::Lagged(num_dropped)
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+                // The stream has been dropped, the client unsubscribed or
+                // disconnected.
+                _ = tx.closed() => break,
+            };
+
+            if tx.send(delivery).is_err() {
+                break;
+            }
+        }
+
+        // The receiver must be dropped before releasing the channel, otherwise
+        // it still counts as a subscriber.
+        drop(rx);
+        db.unsubscribe(&channel_name);
+    });
+
+    Box::pin(async_stream::stream! {
+        while let Some(delivery) = buffer.recv().await {
+            if let Delivery::Message(msg) = &delivery {
+                buffered.fetch_sub(msg.len(), Ordering::Relaxed);
+            }
+
+            yield delivery;
+        }
+    })
+}
+
+/// Handle a command received while inside `Subscribe::apply`. Only subscribe,
+/// unsubscribe, ping, quit and reset commands are permitted in this context.
 ///
 /// Any new subscriptions are appended to `subscribe_to` instead of modifying
 /// `subscriptions`.
+///
+/// Returns `false` if the client is leaving the subscribed state. The caller
+/// is then responsible for releasing the remaining subscriptions.
 async fn handle_command(
     frame: Frame,
     subscribe_to: &mut Vec<String>,
     subscriptions: &mut StreamMap<String, Messages>,
+    db: &mut Db,
     dst: &mut Connection,
-) -> crate::Result<()> {
+    shutdown: &mut Shutdown,
+) -> crate::Result<bool> {
     // A command has been received from the client.
     //
-    // Only `SUBSCRIBE` and `UNSUBSCRIBE` commands are permitted
-    // in this context.
-    match Command::from_frame(frame)? {
-        Command::Subscribe(subscribe) => {
+    // Only `SUBSCRIBE`, `UNSUBSCRIBE`, `PING`, `QUIT` and `RESET` commands
+    // are permitted in this context. Invalid commands are answered with an
+    // error, as outside of it.
+    let command = match Command::from_frame(frame) {
+        Ok(command) => command,
+        Err(err) if err.is_recoverable() => {
+            dst.write_frame(&err.into_frame()).await?;
+            return Ok(true);
+        }
+        Err(err) => return Err(err.into()),
+    };
+
+    match command {
+        command @ Command::Subscribe(_) => {
+            // The user may not be allowed to access the new channels.
+            if let Err(err) = db.authorize(&command) {
+                dst.write_frame(&Frame::Error(err)).await?;
+                return Ok(true);
+            }
+
             // The `apply` method will subscribe to the channels we add to this
             // vector.
-            subscribe_to.extend(subscribe.channels.into_iter());
+            if let Command::Subscribe(subscribe) = command {
+                subscribe_to.extend(subscribe.channels.into_iter());
+            }
         }
         Command::Unsubscribe(mut unsubscribe) => {
             // If no channels are specified, this requests unsubscribing from
@@ -233,18 +422,38 @@ async fn handle_command(
             }
 
             for channel_name in unsubscribe.channels {
+                // Dropping the stream drops the `broadcast::Receiver`, the
+                // channel can then be released if it was the last one.
                 subscriptions.remove(&channel_name);
+                db.unsubscribe(&channel_name);
+                db.client_subscriptions(subscriptions.len());
 
                 let response = make_unsubscribe_frame(channel_name, subscriptions.len());
                 dst.write_frame(&response).await?;
             }
         }
+        Command::Ping(ping) => {
+            // Replies have a different shape in the subscribed state.
+            ping.apply_subscribed(dst).await?;
+        }
+        Command::Quit(quit) => {
+            // Triggers `shutdown`, which terminates the connection once
+            // `Subscribe::apply` returns.
+            quit.apply(dst, shutdown).await?;
+            return Ok(false);
+        }
+        Command::Reset(reset) => {
+            // Returning to the regular state unsubscribes from all channels,
+            // the rest of the connection state is reset by `apply`.
+            reset.apply(db, dst).await?;
+            return Ok(false);
+        }
         command => {
             let cmd = Unknown::new(command.get_name());
             cmd.apply(dst).await?;
         }
     }
-    Ok(())
+    Ok(true)
 }
 
 /// Creates the response to a subcribe request.
@@ -270,6 +479,19 @@ fn make_unsubscribe_frame(channel_name: String, num_subs: usize) -> Frame {
     response
 }
 
+/// Creates a message informing the client that it fell behind on a channel and
+/// `num_dropped` messages published there were not delivered.
+///
+/// This is not part of the Redis protocol, Redis disconnects the client
+/// instead.
+fn make_lagged_frame(channel_name: String, num_dropped: u64) -> Frame {
+    let mut response = Frame::array();
+    response.push_bulk(Bytes::from_static(b"lagged"));
+    response.push_bulk(Bytes::from(channel_name));
+    response.push_int(num_dropped);
+    response
+}
+
 /// Creates a message informing the client about a new message on a channel that
 /// the client subscribes to.
 fn make_message_frame(channel_name: String, msg: Bytes) -> Frame {
@@ -325,8 +547,8 @@ impl Unsubscribe {
                 // The `EndOfStream` error indicates there is no further data to
                 // parse.
                 Err(EndOfStream) => break,
-                // All other errors are bubbled up, resulting in the connection
-                // being terminated.
+                // All other errors are bubbled up. Only protocol errors result
+                // in the connection being terminated.
                 Err(err) => return Err(err),
             }
         }
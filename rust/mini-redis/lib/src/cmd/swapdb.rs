@@ -0,0 +1,81 @@
+use crate::{Connection, Db, Frame, Parse, ParseError};
+
+use bytes::Bytes;
+use tracing::{debug, instrument};
+
+/// Swaps the contents of two databases.
+///
+/// Connections that selected one of the databases see the contents of the
+/// other one right away.
+#[derive(Debug)]
+pub struct SwapDb {
+    /// Index of the first database
+    first: usize,
+
+    /// Index of the second database
+    second: usize,
+}
+
+impl SwapDb {
+    /// Create a new `SwapDb` command which swaps databases `first` and
+    /// `second`.
+    pub fn new(first: usize, second: usize) -> SwapDb {
+        SwapDb { first, second }
+    }
+
+    /// Get the index of the first database
+    pub fn first(&self) -> usize {
+        self.first
+    }
+
+    /// Get the index of the second database
+    pub fn second(&self) -> usize {
+        self.second
+    }
+
+    /// Parse a `SwapDb` instance from a received frame.
+    ///
+    /// The `SWAPDB` string has already been consumed.
+    ///
+    /// # Format
+    ///
+    /// ```text
+    /// SWAPDB index1 index2
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<SwapDb, ParseError> {
+        let first = parse.next_int()? as usize;
+        let second = parse.next_int()? as usize;
+
+        Ok(SwapDb { first, second })
+    }
+
+    /// Apply the `SwapDb` command to the specified `Db` instance.
+    ///
+    /// The response is written to `dst`. This is called by the server in order
+    /// to execute a received command.
+    #[instrument(skip(self, db, dst))]
+    pub(crate) async fn apply(self, db: &Db, dst: &mut Connection) -> crate::Result<()> {
+        let response = match db.swapdb(self.first, self.second) {
+            Ok(()) => Frame::Simple("OK".to_string()),
+            Err(err) => Frame::Error(format!("ERR {}", err)),
+        };
+
+        debug!(?response);
+
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    ///
+    /// This is called by the client when encoding a `SwapDb` command to send
+    /// to the server.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("swapdb".as_bytes()));
+        frame.push_int(self.first as u64);
+        frame.push_int(self.second as u64);
+        frame
+    }
+}
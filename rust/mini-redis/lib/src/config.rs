@@ -0,0 +1,452 @@
+//! Server configuration.
+//!
+//! A `Config` is handed to [`server::run_with_config`] and shared with every
+//! component of the server that needs it. [`server::run`] uses the defaults.
+//!
+//! [`server::run_with_config`]: crate::server::run_with_config
+//! [`server::run`]: crate::server::run
+
+use std::fmt;
+use std::ops::BitOr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Settings of a mini-redis server.
+///
+/// `Config` implements `Default`, so only the fields that differ from the
+/// defaults need to be set:
+///
+/// ```
+/// use mini_redis::config::{Config, SlowSubscriberPolicy};
+///
+/// let config = Config {
+///     slow_subscriber: SlowSubscriberPolicy::Disconnect,
+///     ..Config::default()
+/// };
+/// # drop(config);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Number of messages each pub/sub channel buffers for its subscribers.
+    ///
+    /// A message stays in the buffer until every subscriber has received it.
+    /// Once the buffer is full, publishing drops the oldest message and the
+    /// subscribers that had not received it yet are handled according to
+    /// `slow_subscriber`. Must be at least 1, the default is 1024.
+    pub pubsub_capacity: usize,
+
+    /// What to do with subscribers that do not keep up with the publishers.
+    pub slow_subscriber: SlowSubscriberPolicy,
+
+    /// Keyspace events published through pub/sub, like Redis'
+    /// `notify-keyspace-events`. None by default.
+    pub notify_keyspace_events: KeyspaceEvents,
+
+    /// File the snapshot is saved to by `SAVE` and `BGSAVE`, and loaded from
+    /// when the server starts. Snapshot persistence is disabled if `None`,
+    /// which is the default.
+    pub snapshot_path: Option<PathBuf>,
+
+    /// Append only file every write is logged to, and replayed from when the
+    /// server starts. The AOF is disabled if `None`, which is the default.
+    ///
+    /// When the AOF is enabled, the snapshot is not loaded on startup: the AOF
+    /// is the more complete copy of the dataset.
+    pub aof_path: Option<PathBuf>,
+
+    /// When writes logged to the AOF are synced to disk.
+    pub appendfsync: AppendFsync,
+
+    /// Address (`host:port`) of the primary to replicate, like Redis'
+    /// `replicaof`. The server is a read only replica of that primary if set,
+    /// and a primary otherwise, which is the default.
+    pub replicaof: Option<String>,
+
+    /// Size in bytes of the replication backlog: the most recent writes kept
+    /// by a primary, so replicas that reconnect after a short disconnection
+    /// only receive the writes they missed instead of a full snapshot.
+    pub repl_backlog_size: usize,
+
+    /// Number of logical databases, selected with `SELECT`. Must be at least
+    /// 1, the default is 16.
+    pub databases: usize,
+
+    /// Number of shards the keys are partitioned into, each behind its own
+    /// lock, so connections working on different keys do not wait for each
+    /// other. Must be at least 1, the default is 16.
+    pub shards: usize,
+
+    /// Approximate number of bytes the keys and values may use, like Redis'
+    /// `maxmemory`. Once it is exceeded, commands that add data free memory
+    /// according to `maxmemory_policy`. 0, the default, means no limit.
+    pub maxmemory: usize,
+
+    /// How memory is freed once `maxmemory` is exceeded.
+    pub maxmemory_policy: MaxmemoryPolicy,
+
+    /// Number of keys sampled to pick each key to evict by the approximated
+    /// LRU and LFU policies. More samples are more accurate, but slower.
+    /// Must be at least 1, the default is 5.
+    pub maxmemory_samples: usize,
+
+    /// Commands that take at least this long to execute are logged in the
+    /// slow log, like Redis' `slowlog-log-slower-than`. The default is 10
+    /// milliseconds. `None` disables the slow log.
+    pub slowlog_log_slower_than: Option<Duration>,
+
+    /// Maximum number of commands kept in the slow log, the oldest are
+    /// dropped first. The default is 128.
+    pub slowlog_max_len: usize,
+
+    /// Operations that take at least this long are sampled as latency
+    /// events, reported by `LATENCY`. `None`, the default, disables latency
+    /// monitoring.
+    pub latency_monitor_threshold: Option<Duration>,
+
+    /// Address (`host:port`) of an HTTP listener that serves metrics in the
+    /// Prometheus text format at `/metrics`. Not started if `None`, the
+    /// default.
+    pub metrics_addr: Option<String>,
+
+    /// Password of the `default` user, like Redis' `requirepass`. Clients
+    /// must authenticate with `AUTH` before running other commands. `None`,
+    /// the default, lets clients in as `default` without a password.
+    pub requirepass: Option<String>,
+
+    /// Address (`host:port`) of a second listener, whose connections are
+    /// encrypted with TLS, like Redis' `tls-port`. Not started if `None`, the
+    /// default. Requires `tls_cert_file` and `tls_key_file`.
+    pub tls_addr: Option<String>,
+
+    /// Certificate chain of the server, in the PEM format.
+    pub tls_cert_file: Option<PathBuf>,
+
+    /// Private key of `tls_cert_file`, in the PEM format.
+    pub tls_key_file: Option<PathBuf>,
+
+    /// Certificates of the CAs, in the PEM format, that must have signed the
+    /// certificates of the clients. Clients connecting with TLS must present
+    /// a certificate if set, which is known as mutual TLS. They need none if
+    /// `None`, the default.
+    pub tls_ca_cert_file: Option<PathBuf>,
+
+    /// Connections that send no command for this long are closed, like
+    /// Redis' `timeout`, so dead clients do not hold a connection slot
+    /// forever. Subscribers and monitors only receive, and are never closed.
+    /// `None`, the default, keeps idle connections open.
+    pub timeout: Option<Duration>,
+
+    /// Enables TCP keepalive on accepted connections, like Redis'
+    /// `tcp-keepalive`: the operating system probes a connection after it
+    /// has been silent for this long, and closes it if the peer is gone.
+    /// The default is 300 seconds. `None` disables keepalive.
+    pub tcp_keepalive: Option<Duration>,
+
+    /// Maximum number of clients connected at once, like Redis'
+    /// `maxclients`. Connections beyond it are answered with an error and
+    /// closed. The default is 250, a low value to discourage using
+    /// mini-redis in production.
+    pub maxclients: usize,
+
+    /// How long the server waits when shutting down, like Redis'
+    /// `shutdown-timeout`: first for the replicas to receive every write, on
+    /// `SHUTDOWN`, then for the open connections to finish their commands.
+    /// Connections still open after it are closed. The default is 10
+    /// seconds.
+    pub shutdown_timeout: Duration,
+}
+
+/// Policy applied to pub/sub subscribers that fall behind the publishers.
+///
+/// A subscriber falls behind when the client reads messages from its socket
+/// slower than they are published.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SlowSubscriberPolicy {
+    /// Messages the subscriber missed are dropped. The subscriber is sent a
+    /// `lagged` notification with the number of dropped messages and then
+    /// resumes with the oldest message still buffered.
+    DropAndNotify,
+
+    /// The subscriber is disconnected as soon as it misses a message. This is
+    /// similar to Redis' `client-output-buffer-limit pubsub` hard limit.
+    Disconnect,
+
+    /// Messages are moved from the channel into a buffer owned by the
+    /// subscriber as soon as they are published, so the subscriber never
+    /// misses one. If the buffered messages exceed `max_bytes`, the subscriber
+    /// is disconnected.
+    Grow { max_bytes: usize },
+}
+
+/// When writes to the append only file are synced to disk, like Redis'
+/// `appendfsync`.
+///
+/// Every write is handed to the operating system right away. The policy only
+/// decides when the operating system is asked to flush it to disk, which
+/// bounds how many writes a power loss can lose.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AppendFsync {
+    /// Sync after every write. No acknowledged write is lost, but every write
+    /// waits for the disk.
+    Always,
+
+    /// Sync once per second, losing at most one second of writes.
+    EverySec,
+
+    /// Never sync explicitly, the operating system flushes when it sees fit.
+    No,
+}
+
+/// Which keys are evicted once `Config::maxmemory` is exceeded, like Redis'
+/// `maxmemory-policy`.
+///
+/// The `AllKeys` policies pick among every key, the `Volatile` ones only among
+/// keys with an expiration. The LRU and LFU policies are approximated: each
+/// evicted key is the best of `Config::maxmemory_samples` random keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MaxmemoryPolicy {
+    /// Do not evict anything. Commands that add data fail with an `OOM` error
+    /// instead.
+    NoEviction,
+
+    /// Evict the least recently used keys.
+    AllKeysLru,
+
+    /// Evict the least frequently used keys.
+    AllKeysLfu,
+
+    /// Evict random keys.
+    AllKeysRandom,
+
+    /// Evict the least recently used keys with an expiration.
+    VolatileLru,
+
+    /// Evict the least frequently used keys with an expiration.
+    VolatileLfu,
+
+    /// Evict random keys with an expiration.
+    VolatileRandom,
+
+    /// Evict the keys with an expiration closest to expiring.
+    VolatileTtl,
+}
+
+/// Set of keyspace events to publish.
+///
+/// When a key is modified, an event is published on two channels: on
+/// `__keyspace@<db>__:<key>` with the name of the event as message, and on
+/// `__keyevent@<db>__:<event>` with the name of the key as message. Events are
+/// grouped in classes that are enabled separately, and at least one of
+/// `KEYSPACE` or `KEYEVENT` must be enabled to select the channels.
+///
+/// The set is usually parsed from the string representation Redis uses, for
+/// example `"Ex"` to receive expired keys on the keyevent channel:
+///
+/// ```
+/// use mini_redis::config::KeyspaceEvents;
+///
+/// let events: KeyspaceEvents = "Ex".parse().unwrap();
+/// assert!(events.contains(KeyspaceEvents::KEYEVENT | KeyspaceEvents::EXPIRED));
+/// assert!(!events.contains(KeyspaceEvents::KEYSPACE));
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct KeyspaceEvents(u8);
+
+impl KeyspaceEvents {
+    /// Publish on `__keyspace@<db>__:<key>` channels (`K`).
+    pub const KEYSPACE: KeyspaceEvents = KeyspaceEvents(1 << 0);
+
+    /// Publish on `__keyevent@<db>__:<event>` channels (`E`).
+    pub const KEYEVENT: KeyspaceEvents = KeyspaceEvents(1 << 1);
+
+    /// Generic commands not specific to a type, such as `del` or `expire`
+    /// (`g`).
+    pub const GENERIC: KeyspaceEvents = KeyspaceEvents(1 << 2);
+
+    /// String commands, such as `set` (`$`).
+    pub const STRING: KeyspaceEvents = KeyspaceEvents(1 << 3);
+
+    /// Keys removed because their time to live elapsed (`x`).
+    pub const EXPIRED: KeyspaceEvents = KeyspaceEvents(1 << 4);
+
+    /// Keys evicted to stay under the memory limit (`e`).
+    pub const EVICTED: KeyspaceEvents = KeyspaceEvents(1 << 5);
+
+    /// Every event class, but none of the channels (`A`).
+    pub const ALL: KeyspaceEvents = KeyspaceEvents(
+        KeyspaceEvents::GENERIC.0
+            | KeyspaceEvents::STRING.0
+            | KeyspaceEvents::EXPIRED.0
+            | KeyspaceEvents::EVICTED.0,
+    );
+
+    /// Returns `true` if every event in `other` is also in `self`.
+    pub fn contains(self, other: KeyspaceEvents) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Returns `true` if no event would be published. This is the case when
+    /// either no channel or no event class is enabled.
+    pub fn is_empty(self) -> bool {
+        let channels = KeyspaceEvents::KEYSPACE.0 | KeyspaceEvents::KEYEVENT.0;
+
+        self.0 & channels == 0 || self.0 & KeyspaceEvents::ALL.0 == 0
+    }
+}
+
+impl BitOr for KeyspaceEvents {
+    type Output = KeyspaceEvents;
+
+    fn bitor(self, rhs: KeyspaceEvents) -> KeyspaceEvents {
+        KeyspaceEvents(self.0 | rhs.0)
+    }
+}
+
+impl FromStr for KeyspaceEvents {
+    type Err = String;
+
+    /// Parses the set of events from the flags Redis uses in
+    /// `notify-keyspace-events`. Flags for event classes mini-redis does not
+    /// have (lists, hashes, ...) are rejected.
+    fn from_str(src: &str) -> Result<KeyspaceEvents, String> {
+        src.chars().try_fold(KeyspaceEvents::default(), |events, flag| {
+            let event = match flag {
+                'K' => KeyspaceEvents::KEYSPACE,
+                'E' => KeyspaceEvents::KEYEVENT,
+                'g' => KeyspaceEvents::GENERIC,
+                '$' => KeyspaceEvents::STRING,
+                'x' => KeyspaceEvents::EXPIRED,
+                'e' => KeyspaceEvents::EVICTED,
+                'A' => KeyspaceEvents::ALL,
+                _ => return Err(format!("unsupported keyspace event flag '{}'", flag)),
+            };
+
+            Ok(events | event)
+        })
+    }
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            pubsub_capacity: 1024,
+            slow_subscriber: SlowSubscriberPolicy::DropAndNotify,
+            notify_keyspace_events: KeyspaceEvents::default(),
+            snapshot_path: None,
+            aof_path: None,
+            appendfsync: AppendFsync::EverySec,
+            replicaof: None,
+            repl_backlog_size: 1024 * 1024,
+            databases: 16,
+            shards: 16,
+            maxmemory: 0,
+            maxmemory_policy: MaxmemoryPolicy::NoEviction,
+            maxmemory_samples: 5,
+            slowlog_log_slower_than: Some(Duration::from_millis(10)),
+            slowlog_max_len: 128,
+            latency_monitor_threshold: None,
+            metrics_addr: None,
+            requirepass: None,
+            tls_addr: None,
+            tls_cert_file: None,
+            tls_key_file: None,
+            tls_ca_cert_file: None,
+            timeout: None,
+            tcp_keepalive: Some(Duration::from_secs(300)),
+            maxclients: 250,
+            shutdown_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+impl FromStr for SlowSubscriberPolicy {
+    type Err = String;
+
+    /// Parses a policy from its command line representation: `drop`,
+    /// `disconnect` or `grow:<max_bytes>`.
+    fn from_str(src: &str) -> Result<SlowSubscriberPolicy, String> {
+        match src.split_once(':') {
+            None if src.eq_ignore_ascii_case("drop") => Ok(SlowSubscriberPolicy::DropAndNotify),
+            None if src.eq_ignore_ascii_case("disconnect") => Ok(SlowSubscriberPolicy::Disconnect),
+            Some((policy, max_bytes)) if policy.eq_ignore_ascii_case("grow") => max_bytes
+                .parse()
+                .map(|max_bytes| SlowSubscriberPolicy::Grow { max_bytes })
+                .map_err(|_| format!("invalid byte limit '{}'", max_bytes)),
+            _ => Err(format!(
+                "invalid policy '{}', expected `drop`, `disconnect` or `grow:<max_bytes>`",
+                src
+            )),
+        }
+    }
+}
+
+impl FromStr for AppendFsync {
+    type Err = String;
+
+    /// Parses a policy from the values Redis uses: `always`, `everysec` or
+    /// `no`.
+    fn from_str(src: &str) -> Result<AppendFsync, String> {
+        match src.to_ascii_lowercase().as_str() {
+            "always" => Ok(AppendFsync::Always),
+            "everysec" => Ok(AppendFsync::EverySec),
+            "no" => Ok(AppendFsync::No),
+            _ => Err(format!(
+                "invalid policy '{}', expected `always`, `everysec` or `no`",
+                src
+            )),
+        }
+    }
+}
+
+impl MaxmemoryPolicy {
+    /// Returns `true` if the policy only evicts keys with an expiration.
+    pub fn is_volatile(self) -> bool {
+        matches!(
+            self,
+            MaxmemoryPolicy::VolatileLru
+                | MaxmemoryPolicy::VolatileLfu
+                | MaxmemoryPolicy::VolatileRandom
+                | MaxmemoryPolicy::VolatileTtl
+        )
+    }
+}
+
+impl FromStr for MaxmemoryPolicy {
+    type Err = String;
+
+    /// Parses a policy from the names Redis uses, such as `noeviction` or
+    /// `allkeys-lru`.
+    fn from_str(src: &str) -> Result<MaxmemoryPolicy, String> {
+        match src.to_ascii_lowercase().as_str() {
+            "noeviction" => Ok(MaxmemoryPolicy::NoEviction),
+            "allkeys-lru" => Ok(MaxmemoryPolicy::AllKeysLru),
+            "allkeys-lfu" => Ok(MaxmemoryPolicy::AllKeysLfu),
+            "allkeys-random" => Ok(MaxmemoryPolicy::AllKeysRandom),
+            "volatile-lru" => Ok(MaxmemoryPolicy::VolatileLru),
+            "volatile-lfu" => Ok(MaxmemoryPolicy::VolatileLfu),
+            "volatile-random" => Ok(MaxmemoryPolicy::VolatileRandom),
+            "volatile-ttl" => Ok(MaxmemoryPolicy::VolatileTtl),
+            _ => Err(format!("invalid maxmemory policy '{}'", src)),
+        }
+    }
+}
+
+impl fmt::Display for MaxmemoryPolicy {
+    /// Formats the policy with the name Redis uses, the one `from_str`
+    /// parses.
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            MaxmemoryPolicy::NoEviction => "noeviction",
+            MaxmemoryPolicy::AllKeysLru => "allkeys-lru",
+            MaxmemoryPolicy::AllKeysLfu => "allkeys-lfu",
+            MaxmemoryPolicy::AllKeysRandom => "allkeys-random",
+            MaxmemoryPolicy::VolatileLru => "volatile-lru",
+            MaxmemoryPolicy::VolatileLfu => "volatile-lfu",
+            MaxmemoryPolicy::VolatileRandom => "volatile-random",
+            MaxmemoryPolicy::VolatileTtl => "volatile-ttl",
+        };
+
+        fmt.write_str(name)
+    }
+}
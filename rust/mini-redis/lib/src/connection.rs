@@ -2,8 +2,13 @@ use crate::frame::{self, Frame};
 
 use bytes::{Buf, BytesMut};
 use std::io::{self, Cursor};
-use tokio::io::{AsyncReadExt, AsyncWriteExt, BufWriter};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufWriter, ReadBuf};
 use tokio::net::TcpStream;
+use tokio_rustls::TlsStream;
 
 /// Send and receive `Frame` values from a remote peer.
 ///
@@ -16,32 +21,83 @@ use tokio::net::TcpStream;
 /// the `Connection` creates the frame and returns it to the caller.
 ///
 /// When sending frames, the frame is first encoded into the write buffer.
-/// The contents of the write buffer are then written to the socket.
+/// The contents of the write buffer are then written to the socket, unless
+/// flushing is deferred to send several frames at once.
 #[derive(Debug)]
 pub struct Connection {
-    // The `TcpStream`. It is decorated with a `BufWriter`, which provides write
-    // level buffering. The `BufWriter` implementation provided by Tokio is
-    // sufficient for our needs.
-    stream: BufWriter<TcpStream>,
+    // The `TcpStream`, encrypted or not. It is decorated with a `BufWriter`,
+    // which provides write level buffering. The `BufWriter` implementation
+    // provided by Tokio is sufficient for our needs.
+    stream: BufWriter<Stream>,
 
     // The buffer for reading frames.
     buffer: BytesMut,
+
+    // Where the bytes read and written are counted, if anywhere.
+    traffic: Option<Arc<Traffic>>,
+
+    // Whether `write_frame` leaves the frames in the write buffer, to be
+    // written by a later `flush`.
+    defer_flush: bool,
+}
+
+/// Total bytes read and written by the connections counting into it.
+#[derive(Debug, Default)]
+pub(crate) struct Traffic {
+    /// Bytes read from the peers.
+    pub(crate) input: AtomicU64,
+
+    /// Bytes written to the peers.
+    pub(crate) output: AtomicU64,
 }
 
 impl Connection {
     /// Create a new `Connection`, backed by `socket`. Read and write buffers
     /// are initialized.
     pub fn new(socket: TcpStream) -> Connection {
+        Connection::with_stream(Stream::Tcp(socket))
+    }
+
+    /// Create a new `Connection` over the TLS session `stream`, established
+    /// by either peer.
+    pub(crate) fn new_tls(stream: TlsStream<TcpStream>) -> Connection {
+        Connection::with_stream(Stream::Tls(Box::new(stream)))
+    }
+
+    fn with_stream(stream: Stream) -> Connection {
         Connection {
-            stream: BufWriter::new(socket),
+            stream: BufWriter::new(stream),
             // Default to a 4KB read buffer. For the use case of mini redis,
             // this is fine. However, real applications will want to tune this
             // value to their specific use case. There is a high likelihood that
             // a larger read buffer will work better.
             buffer: BytesMut::with_capacity(4 * 1024),
+            traffic: None,
+            defer_flush: false,
         }
     }
 
+    /// Counts the bytes read and written from now on into `traffic`.
+    pub(crate) fn count_traffic(&mut self, traffic: Arc<Traffic>) {
+        self.traffic = Some(traffic);
+    }
+
+    /// Defers writing the frames to the socket until `flush` is called, or
+    /// stops deferring it. While deferred, `write_frame` only writes to the
+    /// socket once the write buffer is full.
+    ///
+    /// The server defers it while the client has pipelined more commands, so
+    /// their responses are written with a single syscall instead of one each.
+    pub(crate) fn defer_flush(&mut self, defer: bool) {
+        self.defer_flush = defer;
+    }
+
+    /// Returns `true` if a whole frame has already been read from the socket,
+    /// so the next `read_frame` returns without waiting.
+    pub(crate) fn has_frame(&self) -> bool {
+        Frame::check(&mut Cursor::new(&self.buffer[..])).is_ok()
+    }
+
     /// Read a single `Frame` value from the underlying stream.
     ///
     /// The function waits until it has retrieved enough data to parse a frame.
@@ -66,7 +122,9 @@ impl Connection {
             //
             // On success, the number of bytes is returned. `0` indicates "end
             // of stream".
-            if 0 == self.stream.read_buf(&mut self.buffer).await? {
+            let n = self.stream.read_buf(&mut self.buffer).await?;
+
+            if 0 == n {
                 // The remote closed the connection. For this to be a clean
                 // shutdown, there should be no data in the read buffer. If
                 // there is, this means that the peer closed the socket while
@@ -74,9 +132,13 @@ impl Connection {
                 if self.buffer.is_empty() {
                     return Ok(None);
                 } else {
-                    return Err("connection reset by peer".into());
+                    return Err(crate::Error::ConnectionClosed);
                 }
             }
+
+            if let Some(traffic) = &self.traffic {
+                traffic.input.fetch_add(n as u64, Ordering::Relaxed);
+            }
         }
     }
 
@@ -147,90 +209,83 @@ impl Connection {
 
     /// Write a single `Frame` value to the underlying stream.
     ///
-    /// The `Frame` value is written to the socket using the various `write_*`
-    /// functions provided by `AsyncWrite`. Calling these functions directly on
-    /// a `TcpStream` is **not** advised, as this will result in a large number of
-    /// syscalls. However, it is fine to call these functions on a *buffered*
-    /// write stream. The data will be written to the buffer. Once the buffer is
+    /// The frame is encoded with `Frame::encode` and written to the buffered
+    /// write stream. Writing directly to a `TcpStream` is **not** advised, as
+    /// this would result in a large number of syscalls. Once the buffer is
     /// full, it is flushed to the underlying socket.
+    ///
+    /// The frame is then flushed to the socket, unless `defer_flush` is set.
     pub async fn write_frame(&mut self, frame: &Frame) -> io::Result<()> {
-        // Arrays are encoded by encoding each entry. All other frame types are
-        // considered literals. For now, mini-redis is not able to encode
-        // recursive frame structures. See below for more details.
-        match frame {
-            Frame::Array(val) => {
-                // Encode the frame type prefix. For an array, it is `*`.
-                self.stream.write_u8(b'*').await?;
-
-                // Encode the length of the array.
-                self.write_decimal(val.len() as u64).await?;
-
-                // Iterate and encode each entry in the array.
-                for entry in &**val {
-                    self.write_value(entry).await?;
-                }
-            }
-            // The frame type is a literal. Encode the value directly.
-            _ => self.write_value(frame).await?,
+        let mut buf = Vec::new();
+        frame.encode(&mut buf);
+
+        self.stream.write_all(&buf).await?;
+
+        if let Some(traffic) = &self.traffic {
+            traffic.output.fetch_add(buf.len() as u64, Ordering::Relaxed);
+        }
+
+        if self.defer_flush {
+            return Ok(());
         }
 
-        // Ensure the encoded frame is written to the socket. The calls above
-        // are to the buffered stream and writes. Calling `flush` writes the
-        // remaining contents of the buffer to the socket.
+        // Ensure the encoded frame is written to the socket. The call above
+        // is to the buffered stream. Calling `flush` writes the remaining
+        // contents of the buffer to the socket.
         self.stream.flush().await
     }
 
-    /// Write a frame literal to the stream
-    async fn write_value(&mut self, frame: &Frame) -> io::Result<()> {
-        match frame {
-            Frame::Simple(val) => {
-                self.stream.write_u8(b'+').await?;
-                self.stream.write_all(val.as_bytes()).await?;
-                self.stream.write_all(b"\r\n").await?;
-            }
-            Frame::Error(val) => {
-                self.stream.write_u8(b'-').await?;
-                self.stream.write_all(val.as_bytes()).await?;
-                self.stream.write_all(b"\r\n").await?;
-            }
-            Frame::Integer(val) => {
-                self.stream.write_u8(b':').await?;
-                self.write_decimal(*val).await?;
-            }
-            Frame::Null => {
-                self.stream.write_all(b"$-1\r\n").await?;
-            }
-            Frame::Bulk(val) => {
-                let len = val.len();
+    /// Writes the frames left in the write buffer to the socket.
+    pub(crate) async fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush().await
+    }
+}
 
-                self.stream.write_u8(b'$').await?;
-                self.write_decimal(len as u64).await?;
-                self.stream.write_all(val).await?;
-                self.stream.write_all(b"\r\n").await?;
-            }
-            // Encoding an `Array` from within a value cannot be done using a
-            // recursive strategy. In general, async fns do not support
-            // recursion. Mini-redis has not needed to encode nested arrays yet,
-            // so for now it is skipped.
-            Frame::Array(_val) => unreachable!(),
-        }
+/// The socket under a `Connection`.
+#[derive(Debug)]
+enum Stream {
+    Tcp(TcpStream),
 
-        Ok(())
-    }
+    // Boxed, the TLS session state is much larger than a socket.
+    Tls(Box<TlsStream<TcpStream>>),
+}
 
-    /// Write a decimal frame to the stream
-    async fn write_decimal(&mut self, val: u64) -> io::Result<()> {
-        use std::io::Write;
+impl AsyncRead for Stream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+            Stream::Tls(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
 
-        // Convert the value to a string
-        let mut buf = [0u8; 20];
-        let mut buf = Cursor::new(&mut buf[..]);
-        write!(&mut buf, "{}", val)?;
+impl AsyncWrite for Stream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Stream::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+            Stream::Tls(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
 
-        let pos = buf.position() as usize;
-        self.stream.write_all(&buf.get_ref()[..pos]).await?;
-        self.stream.write_all(b"\r\n").await?;
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+            Stream::Tls(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
 
-        Ok(())
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Tcp(stream) => Pin::new(stream).poll_shutdown(cx),
+            Stream::Tls(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
     }
 }
@@ -1,11 +1,31 @@
-use tokio::sync::{broadcast, Notify};
+use tokio::sync::{broadcast, watch, Notify, Semaphore};
 use tokio::time::{self, Duration, Instant};
 
 use bytes::Bytes;
-use std::collections::{BTreeMap, HashMap};
-use std::sync::{Arc, Mutex};
+use rand::Rng;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::hash::{Hash, Hasher};
+use std::mem;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+use std::time::SystemTime;
 use tracing::debug;
 
+use crate::acl::{User, Users, DEFAULT_USER};
+use crate::aof::{self, Aof};
+use crate::cmd::{self, FlushMode, SaveMode};
+use crate::config::{AppendFsync, Config, KeyspaceEvents, MaxmemoryPolicy, SlowSubscriberPolicy};
+use crate::connection::Traffic;
+use crate::latency::{LatencyMonitor, LatencySample, Slowlog, SlowlogEntry};
+use crate::pattern;
+use crate::registry::{ClientEntry, KillFilter, Registry};
+use crate::replication::{self, Backlog, Resync};
+use crate::snapshot::Record;
+use crate::Frame;
+
 /// Un envoltorio alrededor de una instancia `Db`. 
 /// Su funcion es permitir la limpieza ordenada de `Db` al marcar que 
 /// la tarea de purga en segundo plano se cierre cuando se elimine esta estructura.
@@ -29,16 +49,39 @@ pub struct DbDropGuard {
 /// utilizada para gestionar la expiracion de los valores. La tarea funcionara 
 /// hasta que todas las instancias de 'Db' son borradas, momento en el que
 /// terminara.
+///
+/// Las claves estan repartidas en varias bases de datos numeradas. Cada
+/// 'Db' opera sobre la base de datos seleccionada con 'select', de modo que
+/// cada conexion tiene su propio 'Db' con su propia seleccion.
 #[derive(Debug, Clone)]
 pub struct Db {
     /// Gestiona el estado compartido. La tarea secundaria que gestiona 
     /// las expiraciones tambien poseera un `Arc<Shared>`.
     shared: Arc<Shared>,
+
+    /// Indice de la base de datos seleccionada.
+    index: usize,
+
+    /// Cliente de la conexion que usa este 'Db', registrado con
+    /// 'register_client'. Es 'None' en los 'Db' que no pertenecen a una
+    /// conexion.
+    client: Option<Arc<ClientEntry>>,
 }
 
+/// Las claves estan repartidas entre varios 'shards' segun un 'hash' de su
+/// nombre, cada uno con su propio mutex, de modo que las conexiones que
+/// operan sobre claves distintas no compiten por el mismo bloqueo. Una clave
+/// esta en el mismo 'shard' en todas las bases de datos.
+///
+/// Para evitar interbloqueos, cuando se necesitan varios bloqueos a la vez
+/// se adquieren siempre en el mismo orden: los 'shards' por orden de indice
+/// y 'state' el ultimo.
 #[derive(Debug)]
 struct Shared {
-    /// El estado compartido es custodiado por un mutex. Este es un `std::sync::Mutex`
+    /// Las claves, repartidas entre los 'shards'.
+    shards: Vec<Mutex<Shard>>,
+
+    /// El resto del estado compartido es custodiado por un mutex. Este es un `std::sync::Mutex`
     /// standar y no se utiliza la version del mutex de Tokio.
     /// Esto es asi porque no se estan realizando operaciones asincronas mientras 
     /// se mantiene ocupado el mutex. Ademas la seccion critica es muy pequeña.
@@ -58,17 +101,287 @@ struct Shared {
     /// La tarea en segundo plano espera a que se notifique esto, luego verifica 
     /// los valores caducados o la señal de parada.
     background_task: Notify,
+
+    /// Configuracion del servidor. No cambia una vez creada la 'Db', por lo
+    /// que no necesita estar protegida por el mutex.
+    config: Config,
+
+    /// 'true' mientras se esta guardando un 'snapshot'. Evita que 'SAVE' y
+    /// 'BGSAVE' escriban el mismo fichero a la vez.
+    saving: AtomicBool,
+
+    /// Notifica el final de un guardado a quien espera en 'wait_save'.
+    save_done: Notify,
+
+    /// Identificador del flujo de replicacion de este servidor.
+    replid: String,
+
+    /// Identificador que se utilizara para la clave compuesta de la proxima expiracion.
+    next_id: AtomicU64,
+
+    /// Numero de modificaciones desde que se guardo el ultimo 'snapshot'.
+    dirty: AtomicU64,
+
+    /// Numero total de claves que han expirado.
+    expired_keys: AtomicU64,
+
+    /// 'true' si hay un AOF o un 'backlog' a los que propagar las
+    /// escrituras. Permite que las escrituras no adquieran 'state' cuando no
+    /// hay ninguno.
+    propagating: AtomicBool,
+
+    /// Instante en el que se creo la 'Db', para calcular el tiempo que lleva
+    /// funcionando el servidor.
+    started: Instant,
+
+    /// Numero total de conexiones aceptadas.
+    total_connections: AtomicU64,
+
+    /// Contadores de cada comando, por su nombre. El mapa no cambia una vez
+    /// creado, de modo que no necesita un mutex.
+    commands: HashMap<&'static str, CommandCounters>,
+
+    /// Numero total de claves desalojadas para liberar memoria.
+    evicted_keys: AtomicU64,
+
+    /// Numero total de conexiones rechazadas porque se habia alcanzado el
+    /// maximo de conexiones.
+    rejected_connections: AtomicU64,
+
+    /// Bytes leidos y escritos por todas las conexiones.
+    traffic: Arc<Traffic>,
+
+    /// Numero total de mensajes publicados con 'PUBLISH'.
+    published_messages: AtomicU64,
+
+    /// Numero total de mensajes que los subscriptores lentos han perdido.
+    dropped_messages: AtomicU64,
+
+    /// Canal por el que se envian los comandos ejecutados a las conexiones
+    /// en 'MONITOR'.
+    monitor: broadcast::Sender<String>,
+
+    /// Numero de conexiones en 'MONITOR'. Mientras es 0 los comandos no se
+    /// formatean ni se envian.
+    monitors: AtomicUsize,
+
+    /// Clientes conectados, para el comando 'CLIENT'.
+    registry: Registry,
+
+    /// Usuarios de las ACL. Se consultan antes de cada comando, por lo que
+    /// se usa un 'RwLock': las conexiones solo compiten con 'ACL SETUSER' y
+    /// 'ACL DELUSER'. Nunca se adquiere junto con otro bloqueo.
+    users: RwLock<Users>,
+
+    /// 'offset' del flujo de replicacion enviado a cada replica conectada,
+    /// por un identificador propio de cada una. 'SHUTDOWN' espera a que
+    /// todas alcancen el 'offset' del 'backlog'.
+    replicas: watch::Sender<BTreeMap<u64, u64>>,
+
+    /// Modo de guardado del 'SHUTDOWN' ejecutado, si lo hay. El servidor lo
+    /// espera para pararse.
+    shutdown_request: watch::Sender<Option<SaveMode>>,
+
+    /// Notifica a un 'SHUTDOWN' que espera a las replicas que 'SHUTDOWN
+    /// ABORT' lo ha cancelado. Solo existe mientras espera.
+    pending_shutdown: Mutex<Option<Arc<Notify>>>,
 }
 
+/// Numero de comandos que puede tener pendientes una conexion en 'MONITOR'
+/// antes de empezar a perderlos.
+const MONITOR_CAPACITY: usize = 1024;
+
+/// Receptor de los comandos ejecutados, para una conexion en 'MONITOR'.
+/// Deja de contar como monitor cuando se elimina.
 #[derive(Debug)]
-struct State {
-    // Key/Value: Utilizamos un `std::collections::HashMap`.
-    entries: HashMap<String, Entry>,
+pub(crate) struct MonitorReceiver {
+    rx: broadcast::Receiver<String>,
+    shared: Arc<Shared>,
+}
+
+/// 'offset' enviado a una replica conectada. Deja de contar como replica
+/// cuando se elimina.
+#[derive(Debug)]
+pub(crate) struct ReplicaOffset {
+    id: u64,
+    shared: Arc<Shared>,
+}
+
+/// Guardado de un 'snapshot' en curso. Mientras exista no se puede iniciar
+/// otro. Si se elimina sin llamar a 'finish', el guardado cuenta como
+/// fallido.
+#[derive(Debug)]
+pub(crate) struct SaveGuard {
+    shared: Arc<Shared>,
+}
+
+/// Limites superiores, en microsegundos, de los intervalos en los que se
+/// cuentan las duraciones de los comandos. Hay un ultimo intervalo, sin
+/// limite, para las que superan el mayor.
+pub(crate) const LATENCY_BUCKETS: [u64; 12] =
+    [10, 25, 50, 100, 250, 500, 1_000, 2_500, 5_000, 10_000, 100_000, 1_000_000];
+
+/// Contadores de un comando.
+#[derive(Debug, Default)]
+struct CommandCounters {
+    /// Numero de veces que se ha ejecutado.
+    calls: AtomicU64,
+
+    /// Numero de ejecuciones terminadas en cada intervalo de
+    /// 'LATENCY_BUCKETS', mas el intervalo sin limite.
+    durations: [AtomicU64; LATENCY_BUCKETS.len() + 1],
 
+    /// Suma de las duraciones de las ejecuciones terminadas, en
+    /// microsegundos.
+    usec: AtomicU64,
+}
+
+/// Las claves de un 'shard'.
+#[derive(Debug)]
+struct Shard {
+    /// Bases de datos numeradas, cada una con las claves del 'shard' y sus
+    /// expiraciones.
+    dbs: Vec<Keyspace>,
+}
+
+#[derive(Debug)]
+struct State {
     /// Se utiliza un espacio separado para el key/value y el pub/sub. Tambien se
-    /// utiliza un `std::collections::HashMap`.
+    /// utiliza un `std::collections::HashMap`. Los canales son comunes a todas
+    /// las bases de datos.
     pub_sub: HashMap<String, broadcast::Sender<Bytes>>,
 
+    /// Inicio de la ventana en la que se cuentan las claves expiradas por
+    /// segundo, y numero de claves expiradas en ella.
+    expire_window: (Instant, u64),
+
+    /// Momento en el que se guardo el ultimo 'snapshot' correctamente.
+    last_save: Option<SystemTime>,
+
+    /// Fichero AOF en el que se registran las escrituras, si esta activado.
+    /// Esta dentro del estado para que las escrituras se registren en el
+    /// mismo orden en el que se aplican.
+    aof: Option<Aof>,
+
+    /// Ultimas escrituras, para enviarlas a las replicas. Se crea cuando se
+    /// conecta la primera replica.
+    backlog: Option<Backlog>,
+
+    /// 'True' si la instancia de la base de datos se esta deteniendo. Esto 
+    /// ocurre cuando todos los values de 'Db' han sido Drop. Asignando este
+    /// valor a 'true' se marca a la tarea secundaria para que se detenga.
+    shutdown: bool,
+
+    /// Semaforo que limita el numero de conexiones y numero maximo de
+    /// conexiones, para saber cuantas hay abiertas.
+    clients: Option<(Arc<Semaphore>, usize)>,
+
+    /// Ultimos comandos que han superado 'Config::slowlog_log_slower_than'.
+    slowlog: Slowlog,
+
+    /// Eventos que han superado 'Config::latency_monitor_threshold'.
+    latency: LatencyMonitor,
+}
+
+/// Cifras del servidor en un instante, para el comando 'INFO'.
+#[derive(Debug)]
+pub(crate) struct Stats {
+    /// Tiempo que lleva funcionando el servidor.
+    pub(crate) uptime: Duration,
+
+    /// Numero de conexiones abiertas.
+    pub(crate) connected_clients: usize,
+
+    /// Numero maximo de conexiones abiertas.
+    pub(crate) maxclients: usize,
+
+    /// Numero total de conexiones aceptadas.
+    pub(crate) total_connections: u64,
+
+    /// Cifras de cada comando, ordenados por nombre. Los que no se han
+    /// ejecutado nunca no aparecen.
+    pub(crate) commands: Vec<CommandStats>,
+
+    /// Numero total de conexiones rechazadas porque se habia alcanzado el
+    /// maximo de conexiones.
+    pub(crate) rejected_connections: u64,
+
+    /// Bytes leidos de todas las conexiones.
+    pub(crate) net_input_bytes: u64,
+
+    /// Bytes escritos en todas las conexiones.
+    pub(crate) net_output_bytes: u64,
+
+    /// Numero total de mensajes publicados con 'PUBLISH'.
+    pub(crate) published_messages: u64,
+
+    /// Numero total de mensajes que los subscriptores lentos han perdido.
+    pub(crate) dropped_messages: u64,
+
+    /// Numero total de claves que han expirado.
+    pub(crate) expired_keys: u64,
+
+    /// Numero total de claves desalojadas para liberar memoria.
+    pub(crate) evicted_keys: u64,
+
+    /// Numero de canales con al menos un subscriptor.
+    pub(crate) pubsub_channels: usize,
+
+    /// Memoria aproximada utilizada por las entradas, en bytes.
+    pub(crate) used_memory: usize,
+
+    /// Limite de memoria configurado, 0 si no hay limite.
+    pub(crate) maxmemory: usize,
+
+    /// Politica para liberar memoria.
+    pub(crate) maxmemory_policy: MaxmemoryPolicy,
+
+    /// Numero de claves y de claves con una expiracion de cada base de datos.
+    pub(crate) keyspaces: Vec<(usize, usize)>,
+
+    /// Numero de modificaciones desde que se guardo el ultimo 'snapshot'.
+    pub(crate) dirty: u64,
+
+    /// 'true' si se esta guardando un 'snapshot'.
+    pub(crate) saving: bool,
+
+    /// Momento en el que se guardo el ultimo 'snapshot' correctamente.
+    pub(crate) last_save: Option<SystemTime>,
+
+    /// 'true' si el AOF esta activado.
+    pub(crate) aof_enabled: bool,
+
+    /// 'true' si se esta reescribiendo el AOF.
+    pub(crate) aof_rewriting: bool,
+}
+
+/// Cifras de un comando, ver 'Stats'.
+#[derive(Debug)]
+pub(crate) struct CommandStats {
+    /// Nombre del comando.
+    pub(crate) name: &'static str,
+
+    /// Numero de veces que se ha ejecutado.
+    pub(crate) calls: u64,
+
+    /// Suma de las duraciones de las ejecuciones terminadas, en
+    /// microsegundos.
+    pub(crate) usec: u64,
+
+    /// Numero de ejecuciones terminadas en cada intervalo de
+    /// 'LATENCY_BUCKETS', mas el intervalo sin limite. No son acumulados.
+    pub(crate) durations: Vec<u64>,
+}
+
+/// Una base de datos dentro de un 'shard': sus claves y sus expiraciones.
+///
+/// Las entradas se añaden y eliminan unicamente con 'insert' y 'remove', que
+/// mantienen al dia el resto de campos.
+#[derive(Debug, Default)]
+struct Keyspace {
+    // Key/Value: Utilizamos un `std::collections::HashMap`.
+    entries: HashMap<String, Entry>,
+
     /// Seguimiento de las claves TTLs
     /// 
     /// Un 'BTreeMap' se utiliza para mantener los vencimientos ordenados por 
@@ -80,13 +393,15 @@ struct State {
     /// Un identificador unico 'u64' se utiliza para garantiza que la clave sea unica.
     expirations: BTreeMap<(Instant, u64), String>,
 
-    /// Identificador que se utilizara para la clave compuesta de la proxima expiracion.
-    next_id: u64,
+    /// Las mismas claves que 'entries', para poder elegir claves al azar al
+    /// liberar memoria. Un 'HashMap' no permite hacerlo sin recorrerlo.
+    keys: Vec<String>,
 
-    /// 'True' si la instancia de la base de datos se esta deteniendo. Esto 
-    /// ocurre cuando todos los values de 'Db' han sido Drop. Asignando este
-    /// valor a 'true' se marca a la tarea secundaria para que se detenga.
-    shutdown: bool,
+    /// Las claves que tienen una expiracion, con el mismo proposito.
+    volatile: Vec<String>,
+
+    /// Memoria aproximada utilizada por las entradas, en bytes.
+    used_memory: usize,
 }
 
 /// Entrada en el almacen Key/Value
@@ -100,15 +415,56 @@ struct Entry {
 
     /// Instante en el que la entrada expira y debe ser eliminada de la base de datos
     expires_at: Option<Instant>,
+
+    /// Posicion de la clave en 'Keyspace::keys'.
+    slot: usize,
+
+    /// Posicion de la clave en 'Keyspace::volatile', si tiene una expiracion.
+    volatile_slot: Option<usize>,
+
+    /// Ultimo acceso a la entrada, para la politica LRU y 'OBJECT IDLETIME'.
+    accessed_at: Instant,
+
+    /// Contador logaritmico de accesos, para la politica LFU y 'OBJECT FREQ'.
+    /// Se decrementa con el tiempo que pasa sin accesos.
+    freq: u8,
 }
 
+/// Memoria que se estima que ocupa una entrada ademas de la clave y el
+/// valor: la propia 'Entry', la posicion en el 'HashMap' y en los vectores
+/// de claves.
+const ENTRY_OVERHEAD: usize = 96;
+
+/// Numero maximo de claves expiradas que la tarea en segundo plano elimina
+/// en cada tanda, antes de liberar el bloqueo.
+const EXPIRE_BATCH: usize = 256;
+
+/// Tiempo maximo que la tarea en segundo plano mantiene el bloqueo en cada
+/// tanda de expiraciones.
+const EXPIRE_SLICE: Duration = Duration::from_millis(1);
+
+/// Duracion de la ventana en la que se calcula el numero de claves
+/// expiradas por segundo.
+const EXPIRE_STATS_WINDOW: Duration = Duration::from_secs(1);
+
+/// Valor inicial del contador LFU, para que las claves nuevas no se
+/// desalojen antes de tener la oportunidad de ser accedidas.
+const LFU_INIT_VAL: u8 = 5;
+
+/// Cuanto cuesta incrementar el contador LFU. Con 10, como en Redis, hacen
+/// falta alrededor de un millon de accesos para llegar a 255.
+const LFU_LOG_FACTOR: f64 = 10.0;
+
+/// Tiempo sin accesos tras el que el contador LFU se decrementa en 1.
+const LFU_DECAY_TIME: Duration = Duration::from_secs(60);
+
 impl DbDropGuard {
     /// Crea un nuevo 'DbDropGuard' que recubre a una instancia de 'Db'.
     /// Este envoltorio permite realiza la purga de la Bd cuando esta instancia
     /// es 'droped'.
-    pub(crate) fn new() -> DbDropGuard {
+    pub(crate) fn new(config: Config) -> DbDropGuard {
         DbDropGuard { 
-            db: Db::new() 
+            db: Db::new(config) 
         }
     }
 
@@ -121,6 +477,12 @@ impl DbDropGuard {
 
 impl Drop for DbDropGuard {
     fn drop(&mut self) {
+        // Las ultimas escrituras registradas en el AOF se llevan a disco. Se
+        // espera a que terminen sin mantener el bloqueo.
+        if let Some(synced) = self.db.with_aof(|aof| aof.sync()) {
+            let _ = synced.recv();
+        }
+
         // Marca la instancia de 'Db' para que se detenga la tarea que purga las 
         // claves que han expirado.
         self.db.shutdown_purge_task();
@@ -131,25 +493,67 @@ impl Db {
     /// Crea una nueva instancia de 'Db' que no contiene ninguna entrada. Tambien
     /// crea la tarea que gestiona las expiraciones proporcionandole el primero
     /// clon de la base de datos.
-    pub(crate) fn new() -> Db {
+    pub(crate) fn new(config: Config) -> Db {
+
+        let shards = (0..config.shards)
+            .map(|_| {
+                let dbs = (0..config.databases).map(|_| Keyspace::default()).collect();
+                Mutex::new(Shard { dbs })
+            })
+            .collect();
+
+        let users = Users::new(config.requirepass.as_deref());
 
         let shared = Arc::new(Shared {
+            shards,
             state: Mutex::new(State {
-                entries: HashMap::new(),
                 pub_sub: HashMap::new(),
-                expirations: BTreeMap::new(),
-                next_id: 0,
+                expire_window: (Instant::now(), 0),
+                last_save: None,
+                aof: None,
+                backlog: None,
                 shutdown: false,
+                clients: None,
+                slowlog: Slowlog::new(config.slowlog_max_len),
+                latency: LatencyMonitor::default(),
             }),
             background_task: Notify::new(),
+            config,
+            saving: AtomicBool::new(false),
+            save_done: Notify::new(),
+            replid: replication::new_replid(),
+            next_id: AtomicU64::new(0),
+            dirty: AtomicU64::new(0),
+            expired_keys: AtomicU64::new(0),
+            propagating: AtomicBool::new(false),
+            started: Instant::now(),
+            total_connections: AtomicU64::new(0),
+            commands: cmd::COMMAND_NAMES
+                .iter()
+                .map(|name| (*name, CommandCounters::default()))
+                .collect(),
+            evicted_keys: AtomicU64::new(0),
+            rejected_connections: AtomicU64::new(0),
+            traffic: Arc::new(Traffic::default()),
+            published_messages: AtomicU64::new(0),
+            dropped_messages: AtomicU64::new(0),
+            monitor: broadcast::channel(MONITOR_CAPACITY).0,
+            monitors: AtomicUsize::new(0),
+            registry: Registry::new(),
+            users: RwLock::new(users),
+            replicas: watch::channel(BTreeMap::new()).0,
+            shutdown_request: watch::channel(None).0,
+            pending_shutdown: Mutex::new(None),
         });
 
         // Inicial la tarea.
         tokio::spawn(purge_expired_tasks(shared.clone()));
 
-        // Se instancia un 'Db'
+        // Se instancia un 'Db', con la base de datos 0 seleccionada.
         Db { 
-            shared 
+            shared,
+            index: 0,
+            client: None,
         }
 
     }
@@ -160,55 +564,138 @@ impl Db {
     /// Get the value associated with a key. Esto puede a que nunca de
     /// le asigno un valor a la clave o a que el valor expiro.
     pub(crate) fn get(&self, key: &str) -> Option<Bytes> {
-        // Se adquire el bloqueo
-        let state = self.shared.state.lock().unwrap();
+        // Se adquire el bloqueo del 'shard' de la clave
+        let mut shard = self.shared.lock_shard(key);
+        let keyspace = &mut shard.dbs[self.index];
 
-        // Se lee la entrada y clona el valor.
+        // Una clave expirada que la tarea en segundo plano todavia no ha
+        // eliminado no debe retornarse.
+        if self.shared.expire_if_needed(keyspace, self.index, key) {
+            return None;
+        }
+
+        // Se lee la entrada, se registra el acceso y se clona el valor.
         //
-        // Como los datos estan almacenados utilizando 'Bytes', un clone 
+        // Como los datos estan almacenados utilizando 'Bytes', un clone
         // en este caso es un clonado superficial (los datos no se copias).
-        state.entries.get(key).map(|entry| entry.data.clone())
+        keyspace.entries.get_mut(key).map(|entry| {
+            entry.touch();
+            entry.data.clone()
+        })
+    }
+
+    /// Segundos desde el ultimo acceso a la clave, sin contar este.
+    ///
+    /// Retorna 'None' si la clave no existe.
+    pub(crate) fn idletime(&self, key: &str) -> Option<u64> {
+        let mut shard = self.shared.lock_shard(key);
+        let keyspace = &mut shard.dbs[self.index];
+
+        if self.shared.expire_if_needed(keyspace, self.index, key) {
+            return None;
+        }
+
+        keyspace
+            .entries
+            .get(key)
+            .map(|entry| entry.accessed_at.elapsed().as_secs())
+    }
+
+    /// Contador LFU de la clave, sin contar este acceso.
+    ///
+    /// Retorna 'None' si la clave no existe.
+    pub(crate) fn freq(&self, key: &str) -> Option<u8> {
+        let mut shard = self.shared.lock_shard(key);
+        let keyspace = &mut shard.dbs[self.index];
+
+        if self.shared.expire_if_needed(keyspace, self.index, key) {
+            return None;
+        }
+
+        keyspace
+            .entries
+            .get(key)
+            .map(|entry| entry.decayed_freq(Instant::now()))
+    }
+
+    /// Libera memoria segun 'Config::maxmemory_policy' hasta quedar por
+    /// debajo de 'Config::maxmemory'. Se llama antes de ejecutar los comandos
+    /// que pueden añadir datos.
+    ///
+    /// Retorna 'false' si no se ha podido liberar suficiente memoria, en cuyo
+    /// caso el comando debe rechazarse.
+    pub(crate) fn free_memory(&self) -> bool {
+        let config = &self.shared.config;
+
+        if config.maxmemory == 0 {
+            return true;
+        }
+
+        // Lo habitual es que no haya que desalojar nada, y para comprobarlo
+        // basta con adquirir los bloqueos de uno en uno.
+        if self.shared.used_memory() <= config.maxmemory {
+            return true;
+        }
+
+        // Para elegir que claves desalojar hay que ver todos los 'shards' a
+        // la vez.
+        let mut shards = self.shared.lock_all();
+
+        while used_memory(&shards) > config.maxmemory {
+            let (shard, index, key) =
+                match eviction_candidate(&shards, config.maxmemory_policy, config.maxmemory_samples) {
+                    Some(candidate) => candidate,
+                    None => return false,
+                };
+
+            shards[shard].dbs[index].remove(&key);
+            self.shared.dirty.fetch_add(1, Ordering::Relaxed);
+            self.shared.evicted_keys.fetch_add(1, Ordering::Relaxed);
+
+            // Se propaga como un 'DEL', igual que las expiraciones.
+            self.shared.propagate(index, || aof::del_frame(&key));
+            self.shared.notify_keyspace_event(index, KeyspaceEvents::EVICTED, "evicted", &key);
+        }
+
+        true
     }
 
     /// Establece un valor asociado con una clave junto con un periodo de
     /// vencimiento que es opcional.
-    /// 
-    /// Si ya hay un valor asociado con la clave, el nuevo valor substituira 
+    ///
+    /// Si ya hay un valor asociado con la clave, el nuevo valor substituira
     /// al anterior.
     pub(crate) fn set(&self, key: String, value: Bytes, expire: Option<Duration>) {
         let notify = {
-            // Se adquire el bloqueo
-            let mut state = self.shared.state.lock().unwrap();
-
-            // El Id almacenado en el estado es el que se utilizara para esta operacion.
-            let id = state.next_id;
+            // Se adquire el bloqueo del 'shard' de la clave
+            let mut shard = self.shared.lock_shard(&key);
 
-            // Se incremente el Id para proxima insercion. Gracias a la 
-            // proteccion del bloqueo cada operacion 'set' tiene garantizado un Id unico.
-            state.next_id += 1;
+            // Cada operacion 'set' obtiene un Id unico, que se utilizara para
+            // la clave compuesta de su expiracion.
+            let id = self.shared.next_id.fetch_add(1, Ordering::Relaxed);
 
-            // En caso de que se haya especificado una duracion para la expiracion 
-            // del valor, se convierte este duracion en el momento exacto de 
+            // En caso de que se haya especificado una duracion para la expiracion
+            // del valor, se convierte este duracion en el momento exacto de
             // la expiracion.
             //
             // Tambien se programa la expiracion en el mapa de expiraciones.
             //
             // En caso de que la nueva expiracion resulta ser la proxima a ejecutar
-            // se le enviara una notificacion a la tarea subyacente. 
+            // se le enviara una notificacion a la tarea subyacente.
             let (notify, expires_at) = if expire.is_some() {
                 // Se calcula cuando la clave expirara.
                 let when = Instant::now() + expire.unwrap();
 
                 // Unicamente se notificara a la tarea de gestion de las expiraciones si
                 // la expiracion del nuevo valor que se esta estableciendo resulta
-                // ser la proxima expiracion a ejecutarse.
-                let notify = state
+                // ser la proxima expiracion a ejecutarse. Si no habia ninguna
+                // expiracion programada, esta es la proxima. Basta con mirar
+                // este 'shard': si la expiracion no es la proxima en el,
+                // tampoco lo es entre todos.
+                let notify = shard
                     .next_expiration()
                     .map(|expiration| expiration > when)
-                    .unwrap();
-
-                // Track the expiration.
-                state.expirations.insert((when, id), key.clone());
+                    .unwrap_or(true);
 
                 // Resultado
                 (notify, Option::Some(when))
@@ -217,45 +704,74 @@ impl Db {
                 (false, Option::None)
             };
 
-            // Se asigna la clave el nuevo valor en el HashMap principal.
-            // Si para esta misma clave habia un valor anterior, este se
-            // obtendra como resultado de la ejecucion.
-            let prev = state.entries.insert(
-                key,
-                Entry {
-                    id,
-                    data: value,
-                    expires_at,
-                },
-            );
-
-            // Si previamente habia un valor asociado a la clave y ese valor tenia
-            // definida una expiracion entonces hay que aliminar la correpondiente
-            // entrada de mapa de expiraciones.
-            if let Some(prev) = prev {
-                if let Some(when) = prev.expires_at {
-                    // clear expiration
-                    state.expirations.remove(&(when, prev.id));
-                }
+            self.shared.dirty.fetch_add(1, Ordering::Relaxed);
+
+            // Se registra la escritura en el AOF y se envia a las replicas.
+            self.shared.propagate(self.index, || {
+                aof::set_frame(&key, value.clone(), expires_at.map(system_time))
+            });
+
+            // Se publican las notificaciones de 'keyspace', si estan activadas.
+            self.shared.notify_keyspace_event(self.index, KeyspaceEvents::STRING, "set", &key);
+
+            if expires_at.is_some() {
+                self.shared.notify_keyspace_event(self.index, KeyspaceEvents::GENERIC, "expire", &key);
             }
 
-            // Se liberta el mutex antes de notificar la tarea en segundo plano. 
-            // Esto ayuda a reducir la contención al evitar que la tarea en segundo 
-            // plano se active y no pueda adquirir el mutex debido a que esta función 
-            // aún lo retiene.
-            //drop(state);
+            // Se asigna la clave el nuevo valor. Si para esta misma clave
+            // habia un valor anterior, 'insert' lo sustituye junto con su
+            // expiracion.
+            shard.dbs[self.index].insert(key, Entry::new(id, value, expires_at));
 
+            // El mutex se libera al terminar este bloque, antes de notificar la
+            // tarea en segundo plano. Esto ayuda a reducir la contención al evitar
+            // que la tarea en segundo plano se active y no pueda adquirir el mutex
+            // debido a que esta función aún lo retiene.
             notify
         };
 
         if notify {
-            // Finalmente, solo se notifica a la tarea en segundo plano si necesita 
+            // Finalmente, solo se notifica a la tarea en segundo plano si necesita
             // actualizar su estado para reflejar un nuevo vencimiento.
             self.shared.background_task.notify_one();
         }
 
     }
 
+    /// Elimina las claves y los valores asociados a ellas.
+    ///
+    /// Se eliminan todas a la vez: se adquieren los bloqueos de todos sus
+    /// 'shards' antes de eliminar ninguna. Retorna el numero de claves que
+    /// existian.
+    pub(crate) fn del(&self, keys: &[String]) -> usize {
+        let mut shards = self.shared.lock_keys(keys);
+        let mut removed = 0;
+
+        for key in keys {
+            let keyspace = &mut shards.get_mut(&self.shared.shard_index(key)).unwrap().dbs[self.index];
+
+            // Una clave expirada ya no existe, aunque todavia no se haya
+            // eliminado.
+            if self.shared.expire_if_needed(keyspace, self.index, key) {
+                continue;
+            }
+
+            // Si el valor tenia una expiracion programada, 'remove' tambien la
+            // elimina. No es necesario notificar a la tarea en segundo plano: como
+            // mucho se despertara antes de lo necesario.
+            if keyspace.remove(key).is_some() {
+                self.shared.dirty.fetch_add(1, Ordering::Relaxed);
+
+                self.shared.propagate(self.index, || aof::del_frame(key));
+                self.shared.notify_keyspace_event(self.index, KeyspaceEvents::GENERIC, "del", key);
+
+                removed += 1;
+            }
+        }
+
+        removed
+    }
+
     /// Retorna un 'tokio::sync::broadcast::Receiver' para el canal requerido.
     /// 
     /// El 'Receiver' recibido se puede utilizar para recibir valores difundidos
@@ -279,7 +795,8 @@ impl Db {
             Entry::Vacant(e) => {
                 // No existe el canal de difusion, asi que se crea uno.
                 //
-                // El canal es creado con la capacidad de 1024 mensajes. Un
+                // El canal es creado con la capacidad configurada en
+                // 'Config::pubsub_capacity' (1024 mensajes por defecto). Un
                 // mensaje es almacenado en el canal hasta que TODOS los 
                 // subscriptores lo han recibido. Esto significa que 
                 // un subscriptor lento podria dejar mensajes almacenados
@@ -288,7 +805,9 @@ impl Db {
                 // Cuando la capacidad del canal se llene, la publicación 
                 // dará como resultado que se eliminen los mensajes antiguos. 
                 // Esto evita que los consumidores lentos bloqueen todo el sistema.
-                let (tx, rx) = broadcast::channel(1024);
+                // Lo que ocurre con esos consumidores depende de
+                // 'Config::slow_subscriber'.
+                let (tx, rx) = broadcast::channel(self.shared.config.pubsub_capacity);
 
                 // Se inserta en el mapa el 'tokio::sync::broadcast::Sender'
                 e.insert(tx);
@@ -302,90 +821,1172 @@ impl Db {
     /// Publica un mensaje en el canal y retorna el numero de subscriptores
     /// que hay en el momento del envio (no quiered decir que todos lo reciban)
     pub fn publish(&self, key: &str, value: Bytes) -> usize {
-        // Se adquiere el bloqueo
+        self.shared.published_messages.fetch_add(1, Ordering::Relaxed);
+
+        // Se adquiere el bloqueo y se publica.
+        self.shared.state.lock().unwrap().publish(key, value)
+    }
+
+    /// Politica a aplicar a los subscriptores que no consumen los mensajes
+    /// al mismo ritmo que se publican.
+    pub(crate) fn slow_subscriber_policy(&self) -> SlowSubscriberPolicy {
+        self.shared.config.slow_subscriber
+    }
+
+    /// Informa de que un subscriptor ha dejado de escuchar el canal.
+    ///
+    /// El 'Receiver' del subscriptor ya debe haber sido liberado. Si era el
+    /// ultimo, el 'Sender' del canal se elimina del mapa. De lo contrario el
+    /// mapa 'pub_sub' creceria indefinidamente con canales que nadie escucha.
+    pub(crate) fn unsubscribe(&self, key: &str) {
+        let mut state = self.shared.state.lock().unwrap();
+
+        if let Some(tx) = state.pub_sub.get(key) {
+            if tx.receiver_count() == 0 {
+                state.pub_sub.remove(key);
+            }
+        }
+    }
+
+    /// Retorna los canales activos, es decir, los que tienen al menos un
+    /// subscriptor.
+    ///
+    /// Si se especifica un 'pattern' unicamente se retornan los canales cuyo
+    /// nombre coincide con el patron (con la sintaxis 'glob' de Redis).
+    pub(crate) fn pubsub_channels(&self, pattern: Option<&str>) -> Vec<String> {
         let state = self.shared.state.lock().unwrap();
 
-        // Se buscan el 'tokio::sync::broadcast::Sender' para el canal.
         state
             .pub_sub
-            .get(key)
-            // Si se encuentra utilizamos el closure del '.map' para
-            // enviar el mensaje con el 'Sender' recuperado.
-            // Del Option resultante del envio retornamos el numero de subscriptores
-            // o un valor 0 se se produjo un error en el envio.
-            .map(|tx| tx.send(value).unwrap_or(0))
-            // Si no existia en el mapa el canal, se retornaran 0 subscriptores
-            .unwrap_or(0)
+            .iter()
+            .filter(|(_, tx)| tx.receiver_count() > 0)
+            .filter(|(channel, _)| match pattern {
+                Some(pattern) => pattern::matches(pattern.as_bytes(), channel.as_bytes()),
+                None => true,
+            })
+            .map(|(channel, _)| channel.clone())
+            .collect()
     }
 
-    /// Le envia la senyal a la tarea de shutdown. Esta funcion es llamada por la
-    /// implementacion del trait 'Drop' de 'DbDropGuard'.
-    fn shutdown_purge_task(&self) {
+    /// Retorna el numero de subscriptores de cada uno de los canales
+    /// indicados, en el mismo orden. Un canal sin 'Sender' tiene 0
+    /// subscriptores.
+    pub(crate) fn pubsub_numsub(&self, channels: &[String]) -> Vec<usize> {
+        let state = self.shared.state.lock().unwrap();
 
-        {
-            // Se adquiere el bloqueo
-            let mut state = self.shared.state.lock().unwrap();
+        channels
+            .iter()
+            .map(|channel| {
+                state
+                    .pub_sub
+                    .get(channel)
+                    .map(|tx| tx.receiver_count())
+                    .unwrap_or(0)
+            })
+            .collect()
+    }
 
-            // Se marca `State::shutdown` a `true`.
-            state.shutdown = true;
+    /// Fichero en el que se guarda el 'snapshot', si la persistencia esta
+    /// activada.
+    pub(crate) fn snapshot_path(&self) -> Option<&Path> {
+        self.shared.config.snapshot_path.as_deref()
+    }
 
-            // Se liberta el mutex antes de notificar la tarea en segundo plano. 
-            // Esto ayuda a reducir la contención al evitar que la tarea en segundo 
-            // plano se active y no pueda adquirir el mutex debido a que esta función 
-            // aún lo retiene.
-            //drop(state);
+    /// Marca el inicio del guardado de un 'snapshot'.
+    ///
+    /// Retorna 'None' si ya habia otro guardado en curso, en cuyo caso no se
+    /// debe iniciar otro. El guardado termina cuando se elimina el
+    /// 'SaveGuard' retornado, aunque la tarea que lo hacia se cancele.
+    pub(crate) fn begin_save(&self) -> Option<SaveGuard> {
+        self.shared
+            .saving
+            .compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
+            .ok()
+            .map(|_| SaveGuard {
+                shared: self.shared.clone(),
+            })
+    }
+
+    /// Espera a que termine el guardado en curso, si lo hay.
+    pub(crate) async fn wait_save(&self) {
+        loop {
+            // Se crea antes de comprobar el indicador para no perder la
+            // notificacion de un guardado que termine entre medias.
+            let done = self.shared.save_done.notified();
+
+            if !self.shared.saving.load(Ordering::Acquire) {
+                return;
+            }
+
+            done.await;
         }
+    }
 
-        // Se le envia la notificacion a la tarea
-        self.shared.background_task.notify_one();
+    /// Retorna una copia de todas las entradas para guardarlas en un
+    /// 'snapshot', junto con el numero de modificaciones pendientes de
+    /// guardar en ese momento.
+    ///
+    /// Los bloqueos de los 'shards' unicamente se mantienen mientras se
+    /// copian las entradas. Copiar un 'Bytes' unicamente incrementa un
+    /// contador de referencias, de modo que la copia es mucho mas rapida que
+    /// escribir el fichero, que se hace sin los bloqueos.
+    ///
+    /// El tiempo que se mantienen los bloqueos se registra como el evento
+    /// de latencia 'snapshot-copy', el equivalente al 'fork' de Redis.
+    pub(crate) fn records(&self) -> (Vec<Record>, u64) {
+        let start = Instant::now();
+
+        let copied = {
+            let shards = self.shared.lock_all();
+            (records(&shards), self.shared.dirty.load(Ordering::Relaxed))
+        };
+
+        self.shared.latency_sample("snapshot-copy", start.elapsed());
 
+        copied
     }
-}
 
-impl Shared {
-    /// Purga todas las claves que han expirado y retorna el `Instant` de la 
-    /// que sera la siguiente expiracion.
-    fn purge_expired_keys(&self) -> Option<Instant> {
-        // Se adquiere el bloqueo
-        let mut state = self.state.lock().unwrap();
+    /// Fichero AOF configurado, si esta activado.
+    pub(crate) fn aof_path(&self) -> Option<&Path> {
+        self.shared.config.aof_path.as_deref()
+    }
+
+    /// Politica de sincronizacion del AOF.
+    pub(crate) fn appendfsync(&self) -> AppendFsync {
+        self.shared.config.appendfsync
+    }
+
+    /// Empieza a registrar las escrituras en el AOF. Se llama despues de
+    /// reproducirlo, para que la reproduccion no se vuelva a registrar.
+    pub(crate) fn attach_aof(&self, aof: Aof) {
+        self.shared.state.lock().unwrap().aof = Some(aof);
+        self.shared.propagating.store(true, Ordering::Release);
+    }
+
+    /// Ejecuta 'f' sobre el AOF con el bloqueo adquirido, de modo que ninguna
+    /// escritura se registra mientras tanto.
+    ///
+    /// Retorna 'None' si el AOF no esta activado o la base de datos se esta
+    /// deteniendo.
+    pub(crate) fn with_aof<R>(&self, f: impl FnOnce(&mut Aof) -> R) -> Option<R> {
+        let mut state = self.shared.state.lock().unwrap();
 
         if state.shutdown {
-            // la base de datos se esta deteniendo.
-            // Todos los handlers del estado compartido seran borrados.
-            // La tarea en background se detendra.
             return None;
         }
 
-        // This is needed to make the borrow checker happy. In short, `lock()`
-        // returns a `MutexGuard` and not a `&mut State`. The borrow checker is
-        // not able to see "through" the mutex guard and determine that it is
-        // safe to access both `state.expirations` and `state.entries` mutably,
-        // so we get a "real" mutable reference to `State` outside of the loop.
-        let state = &mut *state;
+        state.aof.as_mut().map(f)
+    }
 
-        // Se buscaran todas las claves que han expirado ya.
-        let now = Instant::now();
+    /// Espera a que las escrituras registradas hasta ahora en el AOF esten
+    /// escritas en el fichero, y sincronizadas si la politica es 'Always'.
+    /// El bloqueo no se mantiene mientras se espera.
+    pub(crate) async fn wait_aof(&self) {
+        if let Some(written) = self.with_aof(|aof| aof.written()) {
+            written.await;
+        }
+    }
 
-        // Hay que tener en cuenta que el siguiente iterador entregara las entradas
-        // del hash ordenadas por su clave.
-        // Esto quiere decir que cuando la caducidad de la entrada sea posterior
-        // a la establecida, todas las restantes entradas seran posteriores y ya
-        // no es necesario continuiar avanzando la entrada.
-        while let Some((&(when, id), key)) = state.expirations.iter().next() {
-            if when > now {
-                // se ha terminado la purga, la entrada actual ya es posterior al instante
-                // definidi como limite y tambien es por tanto la proxima entrada
-                // que caducara.
-                // La tarea esperara hasta entonces.
-                return Some(when);
-            }
+    /// Inicia la reescritura del AOF.
+    ///
+    /// Retorna una copia de todas las entradas junto con el fichero en el que
+    /// se deben escribir. Desde este momento, las escrituras se acumulan para
+    /// añadirlas al nuevo fichero al terminar.
+    pub(crate) fn begin_aof_rewrite(&self) -> crate::Result<(Vec<Record>, PathBuf)> {
+        let shards = self.shared.lock_all();
+        let mut state = self.shared.state.lock().unwrap();
 
-            // La clave ha expirado, se borra.
-            state.entries.remove(key);
-            state.expirations.remove(&(when, id));
-        }
+        let tmp = match &mut state.aof {
+            Some(aof) => aof.begin_rewrite()?,
+            None => return Err("AOF is disabled".into()),
+        };
 
-        None
+        Ok((records(&shards), tmp))
+    }
+
+    /// Añade a las bases de datos las entradas leidas de un 'snapshot'.
+    ///
+    /// Las entradas que ya han expirado se descartan. No se publican
+    /// notificaciones de 'keyspace'. Falla, sin añadir ninguna entrada, si
+    /// alguna pertenece a una base de datos que no existe.
+    pub(crate) fn restore(&self, records: Vec<Record>) -> crate::Result<()> {
+        self.check_records(&records)?;
+        self.shared.insert_records(&mut self.shared.lock_all(), records);
+
+        // La tarea en segundo plano debe tener en cuenta las nuevas
+        // expiraciones.
+        self.shared.background_task.notify_one();
+
+        Ok(())
+    }
+
+    /// Sustituye todas las bases de datos por las entradas leidas de un
+    /// 'snapshot', como un 'FLUSHALL' seguido de 'restore'.
+    ///
+    /// Los bloqueos de los 'shards' se mantienen durante toda la sustitucion,
+    /// de modo que ningun comando ve las bases de datos vacias. Falla, sin
+    /// modificar nada, si alguna entrada pertenece a una base de datos que no
+    /// existe.
+    pub(crate) fn replace(&self, records: Vec<Record>) -> crate::Result<()> {
+        self.check_records(&records)?;
+
+        let dbs = {
+            let mut shards = self.shared.lock_all();
+
+            let dbs = shards
+                .iter_mut()
+                .flat_map(|shard| shard.dbs.iter_mut().map(mem::take))
+                .collect();
+            self.shared.dirty.fetch_add(1, Ordering::Relaxed);
+
+            self.shared.propagate(self.index, aof::flushall_frame);
+            self.shared.insert_records(&mut shards, records);
+
+            dbs
+        };
+
+        release(dbs, FlushMode::Sync);
+
+        self.shared.background_task.notify_one();
+
+        Ok(())
+    }
+
+    /// Comprueba que todas las entradas pertenecen a bases de datos que
+    /// existen.
+    fn check_records(&self, records: &[Record]) -> crate::Result<()> {
+        let databases = self.shared.config.databases;
+
+        match records.iter().find(|record| record.db >= databases) {
+            Some(record) => Err(format!("database index {} is out of range", record.db).into()),
+            None => Ok(()),
+        }
+    }
+
+    /// Selecciona la base de datos sobre la que opera este 'Db'. No afecta
+    /// al resto de 'Db', en particular a los de otras conexiones.
+    pub(crate) fn select(&mut self, index: usize) -> crate::Result<()> {
+        if index >= self.shared.config.databases {
+            return Err("DB index is out of range".into());
+        }
+
+        self.index = index;
+
+        if let Some(client) = &self.client {
+            client.set_db(index);
+        }
+
+        Ok(())
+    }
+
+    /// Devuelve la conexion que usa este 'Db' al estado en el que empieza,
+    /// para 'RESET': vuelve a seleccionar la base de datos 0 y borra el
+    /// nombre asignado con 'CLIENT SETNAME'.
+    ///
+    /// El cliente queda autenticado como el usuario 'default' si este no
+    /// necesita contraseña, y sin autenticar si la necesita.
+    pub(crate) fn reset_client(&mut self) {
+        self.index = 0;
+
+        if let Some(client) = &self.client {
+            client.set_db(0);
+            client.set_name(String::new());
+
+            if self.default_login() {
+                client.set_user(DEFAULT_USER);
+            } else {
+                client.clear_user();
+            }
+        }
+    }
+
+    /// Mueve la clave a la base de datos 'dst', manteniendo su expiracion.
+    ///
+    /// Retorna 'false' si la clave no existe en la base de datos
+    /// seleccionada o ya existe en 'dst'.
+    pub(crate) fn move_key(&self, key: &str, dst: usize) -> crate::Result<bool> {
+        if dst >= self.shared.config.databases {
+            return Err("DB index is out of range".into());
+        }
+
+        if dst == self.index {
+            return Err("source and destination objects are the same".into());
+        }
+
+        // La clave esta en el mismo 'shard' en todas las bases de datos,
+        // basta con un bloqueo.
+        let mut shard = self.shared.lock_shard(key);
+
+        let expired = self.shared.expire_if_needed(&mut shard.dbs[self.index], self.index, key);
+        self.shared.expire_if_needed(&mut shard.dbs[dst], dst, key);
+
+        if expired || shard.dbs[dst].entries.contains_key(key) {
+            return Ok(false);
+        }
+
+        let entry = match shard.dbs[self.index].remove(key) {
+            Some(entry) => entry,
+            None => return Ok(false),
+        };
+
+        // La expiracion se traslada con la entrada a la otra base de datos.
+        // El instante no cambia, por lo que no hay que notificar a la tarea
+        // en segundo plano.
+        shard.dbs[dst].insert(key.to_string(), entry);
+        self.shared.dirty.fetch_add(1, Ordering::Relaxed);
+
+        self.shared.propagate(self.index, || aof::move_frame(key, dst));
+
+        self.shared.notify_keyspace_event(self.index, KeyspaceEvents::GENERIC, "move_from", key);
+        self.shared.notify_keyspace_event(dst, KeyspaceEvents::GENERIC, "move_to", key);
+
+        Ok(true)
+    }
+
+    /// Intercambia el contenido de dos bases de datos.
+    pub(crate) fn swapdb(&self, first: usize, second: usize) -> crate::Result<()> {
+        let databases = self.shared.config.databases;
+
+        if first >= databases {
+            return Err("invalid first DB index".into());
+        }
+
+        if second >= databases {
+            return Err("invalid second DB index".into());
+        }
+
+        // Se intercambian en todos los 'shards' a la vez, de modo que ninguna
+        // conexion ve unicamente una parte intercambiada.
+        let mut shards = self.shared.lock_all();
+
+        for shard in shards.iter_mut() {
+            shard.dbs.swap(first, second);
+        }
+
+        self.shared.dirty.fetch_add(1, Ordering::Relaxed);
+
+        self.shared.propagate(self.index, || aof::swapdb_frame(first, second));
+
+        Ok(())
+    }
+
+    /// Elimina todas las claves de la base de datos seleccionada.
+    pub(crate) fn flushdb(&self, mode: FlushMode) {
+        let dbs = {
+            let mut shards = self.shared.lock_all();
+
+            let dbs = shards
+                .iter_mut()
+                .map(|shard| mem::take(&mut shard.dbs[self.index]))
+                .collect();
+            self.shared.dirty.fetch_add(1, Ordering::Relaxed);
+
+            self.shared.propagate(self.index, aof::flushdb_frame);
+
+            dbs
+        };
+
+        release(dbs, mode);
+    }
+
+    /// Elimina todas las claves de todas las bases de datos.
+    pub(crate) fn flushall(&self, mode: FlushMode) {
+        let dbs = {
+            let mut shards = self.shared.lock_all();
+
+            let dbs = shards
+                .iter_mut()
+                .flat_map(|shard| shard.dbs.iter_mut().map(mem::take))
+                .collect();
+            self.shared.dirty.fetch_add(1, Ordering::Relaxed);
+
+            self.shared.propagate(self.index, aof::flushall_frame);
+
+            dbs
+        };
+
+        release(dbs, mode);
+    }
+
+    /// Retorna 'true' si el servidor es una replica.
+    pub(crate) fn is_replica(&self) -> bool {
+        self.shared.config.replicaof.is_some()
+    }
+
+    /// Decide como empieza el flujo de replicacion para una replica que ya
+    /// ha aplicado el flujo 'replid' hasta 'offset'.
+    ///
+    /// Si las escrituras desde 'offset' siguen en el 'backlog' la replica
+    /// puede continuar donde lo dejo. En otro caso, recibe una copia de todas
+    /// las entradas tomada en el mismo instante que el 'offset' retornado.
+    pub(crate) fn psync(&self, replid: Option<&str>, offset: Option<u64>) -> Resync {
+        // Ninguna escritura puede quedar entre la copia y el 'offset'.
+        let shards = self.shared.lock_all();
+        let mut state = self.shared.state.lock().unwrap();
+
+        let capacity = self.shared.config.repl_backlog_size;
+        let backlog = state.backlog.get_or_insert_with(|| Backlog::new(capacity));
+        let changes = backlog.subscribe();
+
+        // Desde ahora las escrituras se deben propagar al 'backlog'.
+        self.shared.propagating.store(true, Ordering::Release);
+
+        // Una replica que se sincroniza por completo no sabe que base de
+        // datos esta seleccionada en el flujo, la siguiente escritura debe
+        // seleccionarla de nuevo.
+        let partial = replid == Some(self.shared.replid.as_str())
+            && offset.is_some_and(|offset| backlog.since(offset).is_some());
+
+        if !partial {
+            backlog.reselect();
+        }
+
+        let replid = self.shared.replid.clone();
+
+        if partial {
+            Resync::Partial { replid, changes }
+        } else {
+            let offset = backlog.offset();
+
+            Resync::Full {
+                replid,
+                offset,
+                records: records(&shards),
+                changes,
+            }
+        }
+    }
+
+    /// Retorna las escrituras del 'backlog' desde 'offset', junto con el
+    /// 'offset' que sigue a la ultima.
+    ///
+    /// Retorna 'None' si ya no estan en el 'backlog'.
+    pub(crate) fn backlog_since(&self, offset: u64) -> Option<(Vec<Frame>, u64)> {
+        let state = self.shared.state.lock().unwrap();
+        let backlog = state.backlog.as_ref()?;
+
+        backlog.since(offset).map(|frames| (frames, backlog.offset()))
+    }
+
+    /// Registra una replica, que ha recibido el flujo de replicacion hasta
+    /// 'offset'.
+    pub(crate) fn add_replica(&self, offset: u64) -> ReplicaOffset {
+        let mut id = 0;
+
+        self.shared.replicas.send_modify(|replicas| {
+            id = replicas.last_key_value().map_or(0, |(id, _)| id + 1);
+            replicas.insert(id, offset);
+        });
+
+        ReplicaOffset {
+            id,
+            shared: self.shared.clone(),
+        }
+    }
+
+    /// Retorna 'true' si hay alguna replica conectada.
+    pub(crate) fn has_replicas(&self) -> bool {
+        !self.shared.replicas.borrow().is_empty()
+    }
+
+    /// Espera a que todas las replicas conectadas hayan recibido todas las
+    /// escrituras que hay ahora en el 'backlog'.
+    pub(crate) async fn wait_replicas(&self) {
+        let target = match &self.shared.state.lock().unwrap().backlog {
+            Some(backlog) => backlog.offset(),
+            None => return,
+        };
+
+        let mut replicas = self.shared.replicas.subscribe();
+
+        while !replicas.borrow_and_update().values().all(|offset| *offset >= target) {
+            // El emisor vive tanto como 'Shared', no puede fallar.
+            if replicas.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+
+    /// Tiempo que el servidor espera a las replicas, y despues a las
+    /// conexiones, al pararse.
+    pub(crate) fn shutdown_timeout(&self) -> Duration {
+        self.shared.config.shutdown_timeout
+    }
+
+    /// Pide al servidor que se pare, guardando un 'snapshot' segun 'save'.
+    pub(crate) fn request_shutdown(&self, save: SaveMode) {
+        self.shared.shutdown_request.send_replace(Some(save));
+    }
+
+    /// Espera a que se pida parar el servidor. Retorna el modo de guardado
+    /// pedido.
+    pub(crate) async fn shutdown_requested(&self) -> SaveMode {
+        let mut request = self.shared.shutdown_request.subscribe();
+
+        loop {
+            if let Some(save) = *request.borrow_and_update() {
+                return save;
+            }
+
+            // El emisor vive tanto como 'Shared'.
+            if request.changed().await.is_err() {
+                return std::future::pending().await;
+            }
+        }
+    }
+
+    /// Empieza la espera de un 'SHUTDOWN' a las replicas. Retorna la
+    /// notificacion de 'SHUTDOWN ABORT'.
+    pub(crate) fn begin_shutdown(&self) -> Arc<Notify> {
+        let abort = Arc::new(Notify::new());
+        *self.shared.pending_shutdown.lock().unwrap() = Some(abort.clone());
+        abort
+    }
+
+    /// Termina la espera de un 'SHUTDOWN' a las replicas.
+    pub(crate) fn end_shutdown(&self) {
+        self.shared.pending_shutdown.lock().unwrap().take();
+    }
+
+    /// Cancela el 'SHUTDOWN' que espera a las replicas. Retorna 'false' si
+    /// no hay ninguno.
+    pub(crate) fn abort_shutdown(&self) -> bool {
+        match self.shared.pending_shutdown.lock().unwrap().take() {
+            Some(abort) => {
+                // 'notify_one' guarda el permiso aunque el 'SHUTDOWN' aun
+                // no este esperando.
+                abort.notify_one();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Registra el semaforo que limita el numero de conexiones, junto con el
+    /// numero maximo de conexiones, para poder informar de cuantas hay.
+    pub(crate) fn attach_clients(&self, limit_connections: Arc<Semaphore>, max_connections: usize) {
+        self.shared.state.lock().unwrap().clients = Some((limit_connections, max_connections));
+    }
+
+    /// Tiempo tras el que se cierran las conexiones inactivas, si se
+    /// cierran.
+    pub(crate) fn timeout(&self) -> Option<Duration> {
+        self.shared.config.timeout
+    }
+
+    /// Intervalo de keepalive TCP de las conexiones aceptadas, si esta
+    /// activado.
+    pub(crate) fn tcp_keepalive(&self) -> Option<Duration> {
+        self.shared.config.tcp_keepalive
+    }
+
+    /// Cuenta una conexion aceptada.
+    pub(crate) fn connection_received(&self) {
+        self.shared.total_connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Cuenta una conexion rechazada porque se habia alcanzado el maximo de
+    /// conexiones.
+    pub(crate) fn connection_rejected(&self) {
+        self.shared.rejected_connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Contadores en los que las conexiones cuentan los bytes leidos y
+    /// escritos.
+    pub(crate) fn traffic(&self) -> Arc<Traffic> {
+        self.shared.traffic.clone()
+    }
+
+    /// Cuenta los mensajes que un subscriptor lento ha perdido.
+    pub(crate) fn messages_dropped(&self, num_dropped: u64) {
+        self.shared.dropped_messages.fetch_add(num_dropped, Ordering::Relaxed);
+    }
+
+    /// Cuenta una ejecucion del comando 'name'. Los nombres que no estan en
+    /// 'cmd::COMMAND_NAMES', como los de comandos desconocidos, se ignoran.
+    ///
+    /// Retorna el nombre del comando contado, para pasarselo a
+    /// 'command_completed' cuando termine.
+    ///
+    /// Tambien se registra como el ultimo comando del cliente, para
+    /// 'CLIENT LIST'.
+    pub(crate) fn command_processed(&self, name: &str) -> Option<&'static str> {
+        let entry = self.shared.commands.get_key_value(name);
+
+        if let Some(client) = &self.client {
+            client.touch(entry.map(|(name, _)| *name));
+        }
+
+        let (name, counters) = entry?;
+        counters.calls.fetch_add(1, Ordering::Relaxed);
+
+        Some(name)
+    }
+
+    /// Registra la duracion de una ejecucion del comando 'name', contada con
+    /// 'command_processed'.
+    pub(crate) fn command_completed(&self, name: &str, elapsed: Duration) {
+        if let Some(counters) = self.shared.commands.get(name) {
+            let usec = elapsed.as_micros() as u64;
+            let bucket = LATENCY_BUCKETS
+                .iter()
+                .position(|limit| usec <= *limit)
+                .unwrap_or(LATENCY_BUCKETS.len());
+
+            counters.durations[bucket].fetch_add(1, Ordering::Relaxed);
+            counters.usec.fetch_add(usec, Ordering::Relaxed);
+        }
+    }
+
+    /// Registra un comando que ha tardado 'duration' en ejecutarse en el
+    /// 'slow log', y como el evento de latencia 'command', si supera los
+    /// limites configurados. 'frame' es el comando recibido, 'addr' y 'name'
+    /// identifican al cliente que lo ha enviado.
+    pub(crate) fn command_executed(&self, frame: &Frame, duration: Duration, addr: SocketAddr, name: &str) {
+        let config = &self.shared.config;
+
+        let slow = config
+            .slowlog_log_slower_than
+            .is_some_and(|threshold| duration >= threshold);
+
+        // Es el caso habitual, no se adquiere 'state'.
+        if !slow && !reaches_threshold(config, duration) {
+            return;
+        }
+
+        let mut state = self.shared.state.lock().unwrap();
+
+        if slow {
+            state.slowlog.push(frame, duration, addr, name);
+        }
+
+        if reaches_threshold(config, duration) {
+            state.latency.record("command", duration);
+        }
+    }
+
+    /// Registra al cliente de la conexion que usa este 'Db', conectado desde
+    /// 'addr'. Retorna la señal que se notifica si otro cliente lo termina
+    /// con 'CLIENT KILL'.
+    ///
+    /// El cliente queda autenticado como el usuario 'default' si este no
+    /// necesita contraseña.
+    pub(crate) fn register_client(&mut self, addr: SocketAddr) -> Arc<Notify> {
+        let client = self.shared.registry.register(addr);
+        let kill = client.kill_signal();
+
+        if self.default_login() {
+            client.set_user(DEFAULT_USER);
+        }
+
+        self.client = Some(client);
+        kill
+    }
+
+    /// Elimina al cliente del registro, cuando la conexion termina.
+    pub(crate) fn unregister_client(&mut self) {
+        if let Some(client) = self.client.take() {
+            self.shared.registry.unregister(client.id);
+        }
+    }
+
+    /// Cliente de la conexion que usa este 'Db', si lo hay.
+    pub(crate) fn client(&self) -> Option<&ClientEntry> {
+        self.client.as_deref()
+    }
+
+    /// Nombre del cliente asignado con 'CLIENT SETNAME', vacio si no tiene.
+    pub(crate) fn client_name(&self) -> String {
+        self.client.as_ref().map(|client| client.name()).unwrap_or_default()
+    }
+
+    /// Registra el numero de canales a los que esta subscrito el cliente.
+    pub(crate) fn client_subscriptions(&self, subscriptions: usize) {
+        if let Some(client) = &self.client {
+            client.set_subscriptions(subscriptions);
+        }
+    }
+
+    /// Usuario con el que esta autenticado el cliente, si lo esta.
+    pub(crate) fn client_user(&self) -> Option<String> {
+        self.client.as_ref().and_then(|client| client.user())
+    }
+
+    /// Retorna 'true' si los clientes quedan autenticados como el usuario
+    /// 'default' sin usar 'AUTH'.
+    pub(crate) fn default_login(&self) -> bool {
+        self.shared.users.read().unwrap().default_login()
+    }
+
+    /// Autentica al cliente como el usuario 'user' si 'password' es una de
+    /// sus contraseñas. Retorna 'false' si no lo es o el usuario no existe o
+    /// esta deshabilitado.
+    pub(crate) fn authenticate(&self, user: &str, password: &str) -> bool {
+        if !self.shared.users.read().unwrap().authenticate(user, password) {
+            return false;
+        }
+
+        if let Some(client) = &self.client {
+            client.set_user(user);
+        }
+
+        true
+    }
+
+    /// Comprueba que el cliente puede ejecutar 'cmd': que esta autenticado
+    /// y que su usuario tiene permiso para el comando y las claves y canales
+    /// a los que accede. Retorna el error con el que responder si no.
+    ///
+    /// Los 'Db' que no pertenecen a una conexion pueden ejecutar cualquier
+    /// comando.
+    pub(crate) fn authorize(&self, cmd: &cmd::Command) -> Result<(), String> {
+        let client = match &self.client {
+            Some(client) => client,
+            None => return Ok(()),
+        };
+
+        // Siempre se puede cambiar de usuario o cerrar la conexion.
+        if matches!(cmd, cmd::Command::Auth(_) | cmd::Command::Quit(_)) {
+            return Ok(());
+        }
+
+        let noauth = || "NOAUTH Authentication required.".to_string();
+        let user = client.user().ok_or_else(noauth)?;
+
+        // Los clientes de un usuario eliminado se terminan, pero pueden
+        // recibir un comando mas antes de darse cuenta.
+        match self.shared.users.read().unwrap().get(&user) {
+            Some(user) => user.check(cmd),
+            None => Err(noauth()),
+        }
+    }
+
+    /// Aplica las reglas 'rules' al usuario 'name', creandolo si no existe.
+    /// Retorna el error con el que responder si alguna no es valida.
+    pub(crate) fn acl_setuser(&self, name: &str, rules: &[String]) -> Result<(), String> {
+        self.shared.users.write().unwrap().set_user(name, rules)
+    }
+
+    /// Retorna una copia del usuario 'name'.
+    pub(crate) fn acl_getuser(&self, name: &str) -> Option<User> {
+        self.shared.users.read().unwrap().get(name).cloned()
+    }
+
+    /// Elimina los usuarios 'names' y termina los clientes autenticados
+    /// como ellos. Retorna cuantos usuarios existian.
+    pub(crate) fn acl_deluser(&self, names: &[String]) -> usize {
+        let mut deleted = 0;
+
+        for name in names {
+            // El bloqueo se libera antes de terminar los clientes.
+            let existed = self.shared.users.write().unwrap().del_user(name);
+
+            if existed {
+                deleted += 1;
+                self.shared.registry.kill(&KillFilter {
+                    user: Some(name.clone()),
+                    ..KillFilter::default()
+                });
+            }
+        }
+
+        deleted
+    }
+
+    /// Describe los usuarios, para 'ACL LIST'.
+    pub(crate) fn acl_list(&self) -> Vec<String> {
+        self.shared.users.read().unwrap().list()
+    }
+
+    /// Describe los clientes conectados, uno por linea, para 'CLIENT LIST'.
+    pub(crate) fn client_list(&self) -> String {
+        self.shared
+            .registry
+            .list()
+            .iter()
+            .map(|client| client.describe() + "\n")
+            .collect()
+    }
+
+    /// Termina los clientes que cumplen 'filter'. Retorna cuantos eran.
+    pub(crate) fn client_kill(&self, filter: &KillFilter) -> usize {
+        self.shared.registry.kill(filter)
+    }
+
+    /// Pausa a los clientes durante 'timeout', segun 'mode'.
+    pub(crate) fn client_pause(&self, timeout: Duration, mode: cmd::PauseMode) {
+        self.shared.registry.pause(timeout, mode);
+    }
+
+    /// Termina la pausa de los clientes.
+    pub(crate) fn client_unpause(&self) {
+        self.shared.registry.unpause();
+    }
+
+    /// Espera a que termine la pausa de los clientes, si la hay y 'paused'
+    /// retorna 'true' para su modo.
+    pub(crate) async fn wait_unpaused(&self, paused: impl Fn(cmd::PauseMode) -> bool) {
+        self.shared.registry.wait_unpaused(paused).await
+    }
+
+    /// Retorna un receptor de los comandos que se ejecuten a partir de este
+    /// momento, para el comando 'MONITOR'.
+    pub(crate) fn monitor(&self) -> MonitorReceiver {
+        let rx = self.shared.monitor.subscribe();
+        self.shared.monitors.fetch_add(1, Ordering::Relaxed);
+
+        MonitorReceiver {
+            rx,
+            shared: self.shared.clone(),
+        }
+    }
+
+    /// Envia el comando 'frame', que el cliente de 'addr' va a ejecutar
+    /// sobre esta base de datos, a las conexiones en 'MONITOR'.
+    pub(crate) fn feed_monitors(&self, frame: &Frame, addr: SocketAddr) {
+        // Es el caso habitual, no hay nada que formatear.
+        if self.shared.monitors.load(Ordering::Relaxed) == 0 {
+            return;
+        }
+
+        let line = cmd::monitor_line(SystemTime::now(), self.index, addr, frame);
+
+        // Solo falla si el ultimo monitor acaba de irse.
+        let _ = self.shared.monitor.send(line);
+    }
+
+    /// Retorna hasta 'count' entradas del 'slow log', empezando por la mas
+    /// reciente. Todas si es 'None'.
+    pub(crate) fn slowlog_get(&self, count: Option<usize>) -> Vec<SlowlogEntry> {
+        self.shared.state.lock().unwrap().slowlog.get(count)
+    }
+
+    /// Numero de entradas del 'slow log'.
+    pub(crate) fn slowlog_len(&self) -> usize {
+        self.shared.state.lock().unwrap().slowlog.len()
+    }
+
+    /// Vacia el 'slow log'.
+    pub(crate) fn slowlog_reset(&self) {
+        self.shared.state.lock().unwrap().slowlog.reset();
+    }
+
+    /// Retorna la ultima muestra y la latencia maxima de cada evento de
+    /// latencia, ordenados por nombre.
+    pub(crate) fn latency_latest(&self) -> Vec<(&'static str, LatencySample, u64)> {
+        self.shared.state.lock().unwrap().latency.latest()
+    }
+
+    /// Retorna las muestras del evento de latencia 'event', empezando por la
+    /// mas antigua.
+    pub(crate) fn latency_history(&self, event: &str) -> Vec<LatencySample> {
+        self.shared.state.lock().unwrap().latency.history(event)
+    }
+
+    /// Retorna las cifras del servidor en este momento.
+    ///
+    /// Los bloqueos de los 'shards' se adquieren de uno en uno, de modo que
+    /// las cifras de las claves son aproximadas si hay escrituras
+    /// simultaneas.
+    pub(crate) fn stats(&self) -> Stats {
+        let shared = &self.shared;
+        let config = &shared.config;
+
+        let mut keyspaces = vec![(0, 0); config.databases];
+        let mut used_memory = 0;
+
+        for shard in &shared.shards {
+            let shard = shard.lock().unwrap();
+
+            for (counts, keyspace) in keyspaces.iter_mut().zip(&shard.dbs) {
+                counts.0 += keyspace.entries.len();
+                counts.1 += keyspace.volatile.len();
+            }
+
+            used_memory += shard.used_memory();
+        }
+
+        let mut commands: Vec<_> = shared
+            .commands
+            .iter()
+            .map(|(name, counters)| CommandStats {
+                name,
+                calls: counters.calls.load(Ordering::Relaxed),
+                usec: counters.usec.load(Ordering::Relaxed),
+                durations: counters
+                    .durations
+                    .iter()
+                    .map(|count| count.load(Ordering::Relaxed))
+                    .collect(),
+            })
+            .filter(|command| command.calls > 0)
+            .collect();
+        commands.sort_unstable_by_key(|command| command.name);
+
+        let state = shared.state.lock().unwrap();
+
+        let (connected_clients, maxclients) = match &state.clients {
+            Some((limit, max)) => (max - limit.available_permits(), *max),
+            None => (0, 0),
+        };
+
+        Stats {
+            uptime: shared.started.elapsed(),
+            connected_clients,
+            maxclients,
+            total_connections: shared.total_connections.load(Ordering::Relaxed),
+            commands,
+            rejected_connections: shared.rejected_connections.load(Ordering::Relaxed),
+            net_input_bytes: shared.traffic.input.load(Ordering::Relaxed),
+            net_output_bytes: shared.traffic.output.load(Ordering::Relaxed),
+            published_messages: shared.published_messages.load(Ordering::Relaxed),
+            dropped_messages: shared.dropped_messages.load(Ordering::Relaxed),
+            expired_keys: shared.expired_keys.load(Ordering::Relaxed),
+            evicted_keys: shared.evicted_keys.load(Ordering::Relaxed),
+            pubsub_channels: state
+                .pub_sub
+                .values()
+                .filter(|tx| tx.receiver_count() > 0)
+                .count(),
+            used_memory,
+            maxmemory: config.maxmemory,
+            maxmemory_policy: config.maxmemory_policy,
+            keyspaces,
+            dirty: shared.dirty.load(Ordering::Relaxed),
+            saving: shared.saving.load(Ordering::Acquire),
+            last_save: state.last_save,
+            aof_enabled: state.aof.is_some(),
+            aof_rewriting: state.aof.as_ref().is_some_and(Aof::is_rewriting),
+        }
+    }
+
+    /// Le envia la senyal a la tarea de shutdown. Esta funcion es llamada por la
+    /// implementacion del trait 'Drop' de 'DbDropGuard'.
+    fn shutdown_purge_task(&self) {
+
+        {
+            // Se adquiere el bloqueo
+            let mut state = self.shared.state.lock().unwrap();
+
+            // Se marca `State::shutdown` a `true`.
+            state.shutdown = true;
+
+            // Se liberta el mutex antes de notificar la tarea en segundo plano. 
+            // Esto ayuda a reducir la contención al evitar que la tarea en segundo 
+            // plano se active y no pueda adquirir el mutex debido a que esta función 
+            // aún lo retiene.
+            //drop(state);
+        }
+
+        // Se le envia la notificacion a la tarea
+        self.shared.background_task.notify_one();
+
+    }
+}
+
+impl Shared {
+    /// Indice del 'shard' en el que esta la clave.
+    fn shard_index(&self, key: &str) -> usize {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        (hasher.finish() % self.shards.len() as u64) as usize
+    }
+
+    /// Adquiere el bloqueo del 'shard' en el que esta la clave.
+    fn lock_shard(&self, key: &str) -> MutexGuard<'_, Shard> {
+        self.shards[self.shard_index(key)].lock().unwrap()
+    }
+
+    /// Adquiere los bloqueos de los 'shards' en los que estan las claves, por
+    /// orden de indice. Se retornan indexados por el indice del 'shard'.
+    fn lock_keys(&self, keys: &[String]) -> BTreeMap<usize, MutexGuard<'_, Shard>> {
+        let indexes: BTreeSet<usize> = keys.iter().map(|key| self.shard_index(key)).collect();
+
+        indexes
+            .into_iter()
+            .map(|index| (index, self.shards[index].lock().unwrap()))
+            .collect()
+    }
+
+    /// Añade las entradas leidas de un 'snapshot' a los 'shards', que deben
+    /// estar todos bloqueados. Las entradas que ya han expirado se
+    /// descartan.
+    fn insert_records(&self, shards: &mut [MutexGuard<'_, Shard>], records: Vec<Record>) {
+        let now = Instant::now();
+        let wall_now = SystemTime::now();
+
+        for record in records {
+            let expires_at = match record.expires_at {
+                Some(expires_at) => match expires_at.duration_since(wall_now) {
+                    Ok(ttl) if !ttl.is_zero() => Some(now + ttl),
+                    // Ha expirado mientras el servidor estaba parado.
+                    _ => continue,
+                },
+                None => None,
+            };
+
+            let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+            let shard = self.shard_index(&record.key);
+
+            shards[shard].dbs[record.db].insert(record.key, Entry::new(id, record.value, expires_at));
+        }
+    }
+
+    /// Adquiere los bloqueos de todos los 'shards', por orden de indice.
+    fn lock_all(&self) -> Vec<MutexGuard<'_, Shard>> {
+        self.shards.iter().map(|shard| shard.lock().unwrap()).collect()
+    }
+
+    /// Memoria aproximada utilizada por las entradas de todas las bases de
+    /// datos, en bytes. Los bloqueos se adquieren de uno en uno, de modo que
+    /// el resultado es aproximado si hay escrituras simultaneas.
+    fn used_memory(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.lock().unwrap().used_memory())
+            .sum()
+    }
+
+    /// Elimina la clave si ha expirado, aunque la tarea en segundo plano
+    /// todavia no lo haya hecho. Retorna 'true' si la ha eliminado.
+    fn expire_if_needed(&self, keyspace: &mut Keyspace, index: usize, key: &str) -> bool {
+        let expired = keyspace
+            .entries
+            .get(key)
+            .and_then(|entry| entry.expires_at)
+            .is_some_and(|when| when <= Instant::now());
+
+        if expired {
+            self.expire(keyspace, index, key);
+        }
+
+        expired
+    }
+
+    /// Elimina una clave que ha expirado.
+    fn expire(&self, keyspace: &mut Keyspace, index: usize, key: &str) {
+        keyspace.remove(key);
+        self.dirty.fetch_add(1, Ordering::Relaxed);
+
+        let expired_keys = self.expired_keys.fetch_add(1, Ordering::Relaxed) + 1;
+        self.state.lock().unwrap().count_expired(expired_keys);
+
+        // La expiracion se propaga como un 'DEL', de modo que al
+        // reproducirla la clave desaparece en el mismo punto.
+        self.propagate(index, || aof::del_frame(key));
+
+        self.notify_keyspace_event(index, KeyspaceEvents::EXPIRED, "expired", key);
+    }
+
+    /// Registra una escritura en el AOF y la envia a las replicas.
+    ///
+    /// Se llama con el bloqueo del 'shard' de la escritura adquirido, para
+    /// que las escrituras sobre una misma clave se registren en el mismo
+    /// orden en el que se aplican. Si no hay AOF ni replicas no se adquiere
+    /// 'state'.
+    fn propagate(&self, index: usize, frame: impl FnOnce() -> Frame) {
+        if !self.propagating.load(Ordering::Acquire) {
+            return;
+        }
+
+        self.state.lock().unwrap().propagate(index, frame);
+    }
+
+    /// Registra una muestra del evento de latencia 'event', si alcanza
+    /// 'Config::latency_monitor_threshold'. No se puede llamar con 'state'
+    /// adquirido.
+    fn latency_sample(&self, event: &'static str, latency: Duration) {
+        if reaches_threshold(&self.config, latency) {
+            self.state.lock().unwrap().latency.record(event, latency);
+        }
+    }
+
+    /// Publica una notificacion de 'keyspace', si 'Config::notify_keyspace_events'
+    /// tiene activada la clase 'class'. Ver 'State::notify_keyspace_event'.
+    fn notify_keyspace_event(&self, index: usize, class: KeyspaceEvents, event: &str, key: &str) {
+        let events = self.config.notify_keyspace_events;
+
+        // Es el caso habitual, no se adquiere 'state'.
+        if !events.contains(class) {
+            return;
+        }
+
+        self.state
+            .lock()
+            .unwrap()
+            .notify_keyspace_event(index, events, class, event, key);
+    }
+
+    /// Purga una tanda de claves que han expirado y retorna el `Instant` de
+    /// la que sera la siguiente expiracion.
+    ///
+    /// Cada tanda elimina como mucho 'EXPIRE_BATCH' claves, y se detiene
+    /// antes si supera 'EXPIRE_SLICE', para no bloquear al resto de
+    /// conexiones durante una expiracion masiva. Si quedan claves expiradas
+    /// por eliminar, el instante retornado ya ha pasado.
+    ///
+    /// 'cursor' es el 'shard' por el que empieza la tanda, y se actualiza con
+    /// el 'shard' por el que debe continuar la siguiente, para que un 'shard'
+    /// con muchas claves expiradas no retrase indefinidamente a los demas.
+    fn purge_expired_keys(&self, cursor: &mut usize) -> Option<Instant> {
+        if self.is_shutdown() {
+            // la base de datos se esta deteniendo.
+            // Todos los handlers del estado compartido seran borrados.
+            // La tarea en background se detendra.
+            return None;
+        }
+
+        // Se buscaran todas las claves que han expirado ya.
+        let now = Instant::now();
+
+        // La proxima expiracion de entre todos los 'shards'.
+        let mut next = None;
+
+        let shards = self.shards.len();
+        let mut purged = 0;
+
+        for i in 0..shards {
+            let shard_index = (*cursor + i) % shards;
+
+            // Cada 'shard' se bloquea por separado, el resto de 'shards'
+            // siguen disponibles mientras tanto.
+            let mut shard = self.shards[shard_index].lock().unwrap();
+
+            for (index, keyspace) in shard.dbs.iter_mut().enumerate() {
+                // Hay que tener en cuenta que el siguiente iterador entregara las entradas
+                // del hash ordenadas por su clave.
+                // Esto quiere decir que cuando la caducidad de la entrada sea posterior
+                // a la establecida, todas las restantes entradas seran posteriores y ya
+                // no es necesario continuiar avanzando la entrada.
+                while let Some((&(when, _), key)) = keyspace.expirations.iter().next() {
+                    if when > now {
+                        // se ha terminado la purga de esta base de datos, la entrada
+                        // actual ya es posterior al instante definido como limite y
+                        // tambien es por tanto su proxima entrada que caducara.
+                        next = Some(next.map_or(when, |next: Instant| next.min(when)));
+                        break;
+                    }
+
+                    // Se ha agotado la tanda. La siguiente continuara por este
+                    // mismo 'shard'. Consultar el reloj tiene un coste, asi
+                    // que solo se hace cada cierto numero de claves.
+                    if purged == EXPIRE_BATCH
+                        || (purged % 32 == 31 && now.elapsed() >= EXPIRE_SLICE)
+                    {
+                        *cursor = shard_index;
+                        return Some(now);
+                    }
+
+                    // La clave ha expirado, se borra.
+                    let key = key.clone();
+                    self.expire(keyspace, index, &key);
+                    purged += 1;
+                }
+            }
+        }
+
+        // La tarea esperara hasta la proxima expiracion, si la hay.
+        next
     }
 
     /// Retorna `true` si la base de datos esta parando.
@@ -396,32 +1997,465 @@ impl Shared {
     }
 }
 
-impl State {
-    /// Desde el mapa 'expiratons' (de tipo BTreeMap<(Instant, u64), String>) se
-    /// obtiene un iterador que estara ordenado de la clave.
+impl MonitorReceiver {
+    /// Espera al siguiente comando ejecutado.
+    pub(crate) async fn recv(&mut self) -> Result<String, broadcast::error::RecvError> {
+        self.rx.recv().await
+    }
+}
+
+impl Drop for MonitorReceiver {
+    fn drop(&mut self) {
+        self.shared.monitors.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl ReplicaOffset {
+    /// Registra que la replica ha recibido el flujo hasta 'offset'.
+    pub(crate) fn sent(&self, offset: u64) {
+        self.shared.replicas.send_modify(|replicas| {
+            replicas.insert(self.id, offset);
+        });
+    }
+}
+
+impl SaveGuard {
+    /// Marca el final del guardado.
+    ///
+    /// 'changes' es el numero de modificaciones pendientes de guardar que
+    /// retorno 'records'. Si el guardado fue correcto, esas modificaciones
+    /// ya estan en disco y se descuentan.
+    pub(crate) fn finish(self, ok: bool, changes: u64) {
+        if ok {
+            let mut state = self.shared.state.lock().unwrap();
+            let _ = self.shared.dirty.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |dirty| {
+                Some(dirty.saturating_sub(changes))
+            });
+            state.last_save = Some(SystemTime::now());
+        }
+    }
+}
+
+impl Drop for SaveGuard {
+    fn drop(&mut self) {
+        self.shared.saving.store(false, Ordering::Release);
+        self.shared.save_done.notify_waiters();
+    }
+}
+
+impl Drop for ReplicaOffset {
+    fn drop(&mut self) {
+        self.shared.replicas.send_modify(|replicas| {
+            replicas.remove(&self.id);
+        });
+    }
+}
+
+impl Shard {
+    /// Desde el mapa 'expiratons' (de tipo BTreeMap<(Instant, u64), String>) de
+    /// cada base de datos se obtiene un iterador que estara ordenado de la clave.
     /// Se hace avanzar el iterador a la primera posicion para obtener la primera clave
     /// (que sera la clave con el instante mas bajo).
-    /// De esta clave que esta formada por una tupla extrae el primer campo que es 
-    /// el Instant.
-    /// En realidad retornara un Option<Instant> ya que el caso de que el iterador 
-    /// de las claves este vacio la expresion funcional retornara un 'Option.None'.
+    /// De esta clave que esta formada por una tupla extrae el primer campo que es
+    /// el Instant, y se retorna el menor de todas las bases de datos.
+    /// En realidad retornara un Option<Instant> ya que el caso de que no haya
+    /// ninguna expiracion la expresion funcional retornara un 'Option.None'.
     fn next_expiration(&self) -> Option<Instant> {
-        self.expirations
-            .keys()
-            .next()
+        self.dbs
+            .iter()
+            .filter_map(|keyspace| keyspace.expirations.keys().next())
             .map(|expiration| expiration.0)
+            .min()
+    }
+
+    /// Memoria aproximada utilizada por las entradas del 'shard', en bytes.
+    fn used_memory(&self) -> usize {
+        self.dbs.iter().map(|keyspace| keyspace.used_memory).sum()
+    }
+}
+
+impl Keyspace {
+    /// Añade una entrada, registrando su expiracion si la tiene. Si ya
+    /// habia una entrada para la clave, se sustituye y se retorna.
+    fn insert(&mut self, key: String, mut entry: Entry) -> Option<Entry> {
+        let prev = self.remove(&key);
+
+        if let Some(when) = entry.expires_at {
+            self.expirations.insert((when, entry.id), key.clone());
+            entry.volatile_slot = Some(self.volatile.len());
+            self.volatile.push(key.clone());
+        }
+
+        entry.slot = self.keys.len();
+        self.keys.push(key.clone());
+
+        self.used_memory += entry_size(&key, &entry.data);
+        self.entries.insert(key, entry);
+
+        prev
+    }
+
+    /// Elimina la entrada de la clave, junto con su expiracion.
+    fn remove(&mut self, key: &str) -> Option<Entry> {
+        let entry = self.entries.remove(key)?;
+
+        if let Some(when) = entry.expires_at {
+            self.expirations.remove(&(when, entry.id));
+        }
+
+        // La ultima clave de cada vector ocupa el hueco que deja la
+        // eliminada, y su entrada debe reflejarlo.
+        if let Some(slot) = entry.volatile_slot {
+            self.volatile.swap_remove(slot);
+
+            if let Some(moved) = self.volatile.get(slot) {
+                self.entries.get_mut(moved).unwrap().volatile_slot = Some(slot);
+            }
+        }
+
+        self.keys.swap_remove(entry.slot);
+
+        if let Some(moved) = self.keys.get(entry.slot) {
+            self.entries.get_mut(moved).unwrap().slot = entry.slot;
+        }
+
+        self.used_memory -= entry_size(key, &entry.data);
+
+        Some(entry)
+    }
+
+    /// Claves entre las que elegir al liberar memoria: todas, o unicamente
+    /// las que tienen una expiracion.
+    fn candidates(&self, volatile: bool) -> &[String] {
+        if volatile {
+            &self.volatile
+        } else {
+            &self.keys
+        }
+    }
+}
+
+impl Entry {
+    /// Crea una entrada que todavia no se ha añadido a ningun 'Keyspace'.
+    fn new(id: u64, data: Bytes, expires_at: Option<Instant>) -> Entry {
+        Entry {
+            id,
+            data,
+            expires_at,
+            slot: 0,
+            volatile_slot: None,
+            accessed_at: Instant::now(),
+            freq: LFU_INIT_VAL,
+        }
+    }
+
+    /// Registra un acceso a la entrada.
+    ///
+    /// El contador LFU se incrementa con una probabilidad que disminuye a
+    /// medida que crece, de modo que 8 bits bastan para distinguir entre
+    /// claves con pocos y con millones de accesos.
+    fn touch(&mut self) {
+        let now = Instant::now();
+        let mut freq = self.decayed_freq(now);
+
+        if freq < u8::MAX {
+            let base = freq.saturating_sub(LFU_INIT_VAL) as f64;
+            let p = 1.0 / (base * LFU_LOG_FACTOR + 1.0);
+
+            if rand::thread_rng().gen::<f64>() < p {
+                freq += 1;
+            }
+        }
+
+        self.freq = freq;
+        self.accessed_at = now;
+    }
+
+    /// Contador LFU en el instante 'now', descontando el tiempo sin accesos.
+    fn decayed_freq(&self, now: Instant) -> u8 {
+        let periods = now.saturating_duration_since(self.accessed_at).as_secs()
+            / LFU_DECAY_TIME.as_secs();
+
+        self.freq.saturating_sub(periods.min(u8::MAX as u64) as u8)
+    }
+}
+
+impl State {
+    /// Cuenta una clave expirada en la ventana actual. Al cerrar la ventana
+    /// se publica el numero de claves expiradas por segundo como evento de
+    /// 'tracing'.
+    ///
+    /// No hay ningun temporizador que cierre las ventanas, se cierran con la
+    /// siguiente expiracion. Una ventana que ha durado mucho mas de lo
+    /// previsto, porque no expiraban claves, se descarta en lugar de
+    /// publicar una media que no es representativa.
+    fn count_expired(&mut self, expired_keys: u64) {
+        let now = Instant::now();
+        let (start, count) = &mut self.expire_window;
+        let elapsed = now - *start;
+
+        *count += 1;
+
+        if elapsed < EXPIRE_STATS_WINDOW {
+            return;
+        }
+
+        if elapsed < 2 * EXPIRE_STATS_WINDOW {
+            let per_sec = *count as f64 / elapsed.as_secs_f64();
+            debug!(expired_keys, expired_keys_per_sec = per_sec, "expired keys");
+        }
+
+        self.expire_window = (now, 0);
+    }
+
+    /// Registra una escritura en el AOF y la envia a las replicas.
+    ///
+    /// 'frame' construye el comando que reproduce la escritura sobre la base
+    /// de datos 'index'. Unicamente se llama si el AOF esta activado o hay
+    /// replicas, de modo que en otro caso no cuesta nada. Cada destino
+    /// antepone un 'SELECT' cuando la base de datos cambia.
+    fn propagate(&mut self, index: usize, frame: impl FnOnce() -> Frame) {
+        if self.aof.is_none() && self.backlog.is_none() {
+            return;
+        }
+
+        let frame = frame();
+
+        let mut buf = Vec::new();
+        frame.encode(&mut buf);
+
+        if let Some(backlog) = &mut self.backlog {
+            backlog.push(index, frame, buf.len());
+        }
+
+        if let Some(aof) = &mut self.aof {
+            aof.append(index, buf);
+        }
+    }
+
+    /// Publica un mensaje en el canal y retorna el numero de subscriptores
+    /// que hay en el momento del envio. Es la implementacion de
+    /// 'Db::publish', separada para poder publicar desde otras operaciones
+    /// que ya tienen el bloqueo adquirido.
+    fn publish(&mut self, key: &str, value: Bytes) -> usize {
+        // Se buscan el 'tokio::sync::broadcast::Sender' para el canal.
+        let sent = self
+            .pub_sub
+            .get(key)
+            // Si se encuentra utilizamos el closure del '.map' para
+            // enviar el mensaje con el 'Sender' recuperado.
+            // El envio falla unicamente cuando no queda ningun 'Receiver'.
+            .map(|tx| tx.send(value));
+
+        match sent {
+            Some(Ok(num_subscribers)) => num_subscribers,
+            Some(Err(_)) => {
+                // El canal ya no tiene subscriptores, se aprovecha para
+                // eliminar su 'Sender' del mapa.
+                self.pub_sub.remove(key);
+                0
+            }
+            // Si no existia en el mapa el canal, se retornaran 0 subscriptores
+            None => 0,
+        }
     }
+
+    /// Publica una notificacion de 'keyspace' para el evento 'event' de la
+    /// clase 'class' sobre la clave 'key', si 'events' lo tiene activado.
+    ///
+    /// Se publica en dos canales, segun este activado cada uno, donde 'index'
+    /// es la base de datos de la clave:
+    ///  - `__keyspace@<index>__:<key>` con el nombre del evento como mensaje.
+    ///  - `__keyevent@<index>__:<event>` con el nombre de la clave como mensaje.
+    fn notify_keyspace_event(
+        &mut self,
+        index: usize,
+        events: KeyspaceEvents,
+        class: KeyspaceEvents,
+        event: &str,
+        key: &str,
+    ) {
+        // Es el caso habitual, se evita cualquier reserva de memoria.
+        if !events.contains(class) {
+            return;
+        }
+
+        if events.contains(KeyspaceEvents::KEYSPACE) {
+            let channel = format!("__keyspace@{}__:{}", index, key);
+            self.publish(&channel, Bytes::copy_from_slice(event.as_bytes()));
+        }
+
+        if events.contains(KeyspaceEvents::KEYEVENT) {
+            let channel = format!("__keyevent@{}__:{}", index, event);
+            self.publish(&channel, Bytes::copy_from_slice(key.as_bytes()));
+        }
+    }
+}
+
+/// Memoria aproximada utilizada por las entradas de todos los 'shards', en
+/// bytes.
+fn used_memory(shards: &[MutexGuard<'_, Shard>]) -> usize {
+    shards.iter().map(|shard| shard.used_memory()).sum()
+}
+
+/// Elige la proxima clave a desalojar segun 'policy', retornando su 'shard',
+/// su base de datos y su nombre. Retorna 'None' si no hay ninguna candidata.
+///
+/// Las politicas LRU y LFU se aproximan eligiendo la mejor de 'samples'
+/// claves al azar, como hace Redis, en lugar de mantener las claves
+/// ordenadas en cada acceso.
+fn eviction_candidate(
+    shards: &[MutexGuard<'_, Shard>],
+    policy: MaxmemoryPolicy,
+    samples: usize,
+) -> Option<(usize, usize, String)> {
+    use MaxmemoryPolicy::*;
+
+    let volatile = policy.is_volatile();
+    let now = Instant::now();
+    let entry = |(shard, index, key): &(usize, usize, &String)| &shards[*shard].dbs[*index].entries[*key];
+
+    let (shard, index, key) = match policy {
+        NoEviction => return None,
+        // La expiracion mas proxima se conoce sin necesidad de muestrear.
+        VolatileTtl => keyspaces(shards)
+            .filter_map(|(shard, index, keyspace)| {
+                keyspace
+                    .expirations
+                    .iter()
+                    .next()
+                    .map(|((when, _), key)| (when, shard, index, key))
+            })
+            .min_by_key(|(when, ..)| *when)
+            .map(|(_, shard, index, key)| (shard, index, key))?,
+        AllKeysRandom | VolatileRandom => sample(shards, volatile, 1).pop()?,
+        AllKeysLru | VolatileLru => sample(shards, volatile, samples)
+            .into_iter()
+            .min_by_key(|candidate| entry(candidate).accessed_at)?,
+        AllKeysLfu | VolatileLfu => sample(shards, volatile, samples)
+            .into_iter()
+            .min_by_key(|candidate| entry(candidate).decayed_freq(now))?,
+    };
+
+    Some((shard, index, key.clone()))
+}
+
+/// Elige 'n' claves al azar de entre todas las bases de datos, o de entre
+/// las que tienen una expiracion si 'volatile' es 'true'. Una clave puede
+/// aparecer varias veces.
+fn sample<'a>(shards: &'a [MutexGuard<'_, Shard>], volatile: bool, n: usize) -> Vec<(usize, usize, &'a String)> {
+    let total: usize = keyspaces(shards)
+        .map(|(_, _, keyspace)| keyspace.candidates(volatile).len())
+        .sum();
+
+    if total == 0 {
+        return vec![];
+    }
+
+    let mut rng = rand::thread_rng();
+
+    (0..n)
+        .map(|_| {
+            // Se localiza la base de datos de la posicion elegida, de modo
+            // que todas las claves tienen la misma probabilidad.
+            let mut pos = rng.gen_range(0..total);
+
+            for (shard, index, keyspace) in keyspaces(shards) {
+                let keys = keyspace.candidates(volatile);
+
+                if pos < keys.len() {
+                    return (shard, index, &keys[pos]);
+                }
+
+                pos -= keys.len();
+            }
+
+            unreachable!("sampled position out of range")
+        })
+        .collect()
+}
+
+/// Recorre las bases de datos de todos los 'shards', junto con el indice del
+/// 'shard' y el de la base de datos.
+fn keyspaces<'a>(shards: &'a [MutexGuard<'_, Shard>]) -> impl Iterator<Item = (usize, usize, &'a Keyspace)> {
+    shards.iter().enumerate().flat_map(|(shard, guard)| {
+        guard
+            .dbs
+            .iter()
+            .enumerate()
+            .map(move |(index, keyspace)| (shard, index, keyspace))
+    })
+}
+
+/// Retorna una copia de todas las entradas de todos los 'shards'.
+///
+/// Las expiraciones se guardan como un instante absoluto del reloj del
+/// sistema, ya que un 'Instant' no tiene sentido fuera de este proceso.
+fn records(shards: &[MutexGuard<'_, Shard>]) -> Vec<Record> {
+    keyspaces(shards)
+        .flat_map(|(_, db, keyspace)| {
+            keyspace.entries.iter().map(move |(key, entry)| Record {
+                db,
+                key: key.clone(),
+                value: entry.data.clone(),
+                expires_at: entry.expires_at.map(system_time),
+            })
+        })
+        .collect()
+}
+
+/// Retorna 'true' si 'latency' alcanza 'Config::latency_monitor_threshold'.
+fn reaches_threshold(config: &Config, latency: Duration) -> bool {
+    config
+        .latency_monitor_threshold
+        .is_some_and(|threshold| latency >= threshold)
+}
+
+/// Memoria aproximada que ocupa una entrada, en bytes.
+fn entry_size(key: &str, data: &Bytes) -> usize {
+    key.len() + data.len() + ENTRY_OVERHEAD
+}
+
+/// Libera las claves eliminadas por 'FLUSHDB' o 'FLUSHALL'. En modo 'Async'
+/// se liberan en un hilo aparte, para no retrasar al resto de comandos.
+fn release(dbs: Vec<Keyspace>, mode: FlushMode) {
+    match mode {
+        FlushMode::Sync => drop(dbs),
+        FlushMode::Async => {
+            tokio::task::spawn_blocking(move || drop(dbs));
+        }
+    }
+}
+
+/// Convierte un 'Instant' en el instante equivalente del reloj del sistema.
+fn system_time(when: Instant) -> SystemTime {
+    SystemTime::now() + when.saturating_duration_since(Instant::now())
 }
 
 /// Tarea ejecutada en segundo plano.
 ///
 /// La terea estara dormida esperando alguna notificacion.
 async fn purge_expired_tasks(shared: Arc<Shared>) {
+    // 'Shard' por el que continuara la proxima tanda de expiraciones.
+    let mut cursor = 0;
+
     // La tarea permanecera en un blucle hasta que se le notifique la parada
     while !shared.is_shutdown() {
         // Se borran las entradas expiradas y el resultado nos indicara para
         // cuando es la siguiente caducidad.
-        if let Some(when) = shared.purge_expired_keys() {
+        let start = Instant::now();
+        let next = shared.purge_expired_keys(&mut cursor);
+        shared.latency_sample("expire-cycle", start.elapsed());
+
+        // Quedan claves expiradas por eliminar. Se cede el turno al resto de
+        // tareas antes de la siguiente tanda, para que las conexiones puedan
+        // adquirir el bloqueo entre tandas.
+        if next.is_some_and(|when| when <= Instant::now()) {
+            tokio::task::yield_now().await;
+            continue;
+        }
+
+        if let Some(when) = next {
             // Hay que esperar los siguientes eventos:
             //  1) Ha transcurrido el tiempo hasta la siguienet expiracion
             //  2) Hemos recibido una notificacion general.
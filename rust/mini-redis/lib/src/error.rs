@@ -0,0 +1,171 @@
+//! The error returned by mini-redis.
+//!
+//! Callers of the client can tell apart the errors the server replied with,
+//! such as `WRONGTYPE`, from failures of the connection itself, like an I/O
+//! error or the server sending something that is not a valid frame.
+
+use crate::{frame, ParseError};
+
+use std::string::FromUtf8Error;
+use std::{fmt, io};
+use tokio::task::JoinError;
+use tokio::time::error::Elapsed;
+use tokio_rustls::rustls;
+use tokio_rustls::rustls::pki_types::InvalidDnsNameError;
+use tokio_rustls::rustls::server::VerifierBuilderError;
+
+/// Error returned by most functions.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// Reading from or writing to the socket, or to a file, failed.
+    Io(io::Error),
+
+    /// The peer sent something that is not valid in the Redis protocol, or a
+    /// frame that was not expected in response to the command.
+    Protocol(String),
+
+    /// The server replied with an error, such as
+    /// `-WRONGTYPE Operation against a key holding the wrong kind of value`.
+    Server {
+        /// The first word of the error, by convention its kind, such as
+        /// `ERR`, `WRONGTYPE` or `NOAUTH`.
+        kind: String,
+
+        /// The rest of the error.
+        message: String,
+    },
+
+    /// The connection was closed while a response was expected.
+    ConnectionClosed,
+
+    /// The operation did not complete in time, from a `tokio::time::timeout`
+    /// around it.
+    Timeout,
+
+    /// Any other error, such as an invalid configuration.
+    Other(Box<dyn std::error::Error + Send + Sync>),
+}
+
+impl Error {
+    /// Builds the error of an error reply from the server, `msg` being the
+    /// contents of the `Frame::Error`.
+    pub(crate) fn server(msg: impl Into<String>) -> Error {
+        let msg = msg.into();
+
+        match msg.split_once(' ') {
+            Some((kind, message)) => Error::Server {
+                kind: kind.to_string(),
+                message: message.to_string(),
+            },
+            None => Error::Server {
+                kind: msg,
+                message: String::new(),
+            },
+        }
+    }
+
+    /// Returns the kind of the error reply from the server, such as `ERR` or
+    /// `WRONGTYPE`, if the server replied with an error.
+    pub fn server_kind(&self) -> Option<&str> {
+        match self {
+            Error::Server { kind, .. } => Some(kind),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(err) => err.fmt(f),
+            Error::Protocol(msg) => msg.fmt(f),
+            // Displayed as the server sent it.
+            Error::Server { kind, message } if message.is_empty() => kind.fmt(f),
+            Error::Server { kind, message } => write!(f, "{} {}", kind, message),
+            Error::ConnectionClosed => "connection closed by peer".fmt(f),
+            Error::Timeout => "operation timed out".fmt(f),
+            Error::Other(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            Error::Other(err) => Some(&**err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(src: io::Error) -> Error {
+        Error::Io(src)
+    }
+}
+
+impl From<frame::Error> for Error {
+    fn from(src: frame::Error) -> Error {
+        match src {
+            frame::Error::Incomplete => Error::Protocol(src.to_string()),
+            frame::Error::Other(err) => err,
+        }
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(src: ParseError) -> Error {
+        Error::Protocol(src.to_string())
+    }
+}
+
+/// Strings received from the peer must be UTF-8.
+impl From<FromUtf8Error> for Error {
+    fn from(_src: FromUtf8Error) -> Error {
+        Error::Protocol("protocol error; invalid string".to_string())
+    }
+}
+
+impl From<rustls::Error> for Error {
+    fn from(src: rustls::Error) -> Error {
+        Error::Other(src.into())
+    }
+}
+
+impl From<VerifierBuilderError> for Error {
+    fn from(src: VerifierBuilderError) -> Error {
+        Error::Other(src.into())
+    }
+}
+
+impl From<InvalidDnsNameError> for Error {
+    fn from(src: InvalidDnsNameError) -> Error {
+        Error::Other(src.into())
+    }
+}
+
+impl From<JoinError> for Error {
+    fn from(src: JoinError) -> Error {
+        Error::Other(src.into())
+    }
+}
+
+impl From<Elapsed> for Error {
+    fn from(_src: Elapsed) -> Error {
+        Error::Timeout
+    }
+}
+
+impl From<String> for Error {
+    fn from(src: String) -> Error {
+        Error::Other(src.into())
+    }
+}
+
+impl From<&str> for Error {
+    fn from(src: &str) -> Error {
+        Error::Other(src.into())
+    }
+}
@@ -2,8 +2,10 @@
 //! parsing frames from a byte array.
 
 use bytes::{Buf, Bytes};
+use std::collections::HashMap;
 use std::convert::TryInto;
 use std::fmt;
+use std::hash::Hash;
 use std::io::Cursor;
 use std::num::TryFromIntError;
 use std::string::FromUtf8Error;
@@ -62,6 +64,48 @@ impl Frame {
         }
     }
 
+    /// Appends the encoded frame to `dst`.
+    ///
+    /// This is the only encoder: `Connection::write_frame` writes these bytes
+    /// to the socket, and they are also used where frames are written
+    /// somewhere else, such as the append only file.
+    pub(crate) fn encode(&self, dst: &mut Vec<u8>) {
+        match self {
+            Frame::Simple(val) => {
+                dst.push(b'+');
+                dst.extend_from_slice(val.as_bytes());
+                dst.extend_from_slice(b"\r\n");
+            }
+            Frame::Error(val) => {
+                dst.push(b'-');
+                dst.extend_from_slice(val.as_bytes());
+                dst.extend_from_slice(b"\r\n");
+            }
+            Frame::Integer(val) => {
+                dst.push(b':');
+                dst.extend_from_slice(val.to_string().as_bytes());
+                dst.extend_from_slice(b"\r\n");
+            }
+            Frame::Null => dst.extend_from_slice(b"$-1\r\n"),
+            Frame::Bulk(val) => {
+                dst.push(b'$');
+                dst.extend_from_slice(val.len().to_string().as_bytes());
+                dst.extend_from_slice(b"\r\n");
+                dst.extend_from_slice(val);
+                dst.extend_from_slice(b"\r\n");
+            }
+            Frame::Array(val) => {
+                dst.push(b'*');
+                dst.extend_from_slice(val.len().to_string().as_bytes());
+                dst.extend_from_slice(b"\r\n");
+
+                for entry in val {
+                    entry.encode(dst);
+                }
+            }
+        }
+    }
+
     /// Checks if an entire message can be decoded from `src`
     pub fn check(src: &mut Cursor<&[u8]>) -> Result<(), Error> {
         match get_u8(src)? {
@@ -169,7 +213,7 @@ impl Frame {
 
     /// Converts the frame to an "unexpected frame" error
     pub(crate) fn to_error(&self) -> crate::Error {
-        format!("unexpected frame: {}", self).into()
+        crate::Error::Protocol(format!("unexpected frame: {}", self))
     }
 }
 
@@ -210,6 +254,149 @@ impl fmt::Display for Frame {
     }
 }
 
+/// Decodes a response frame into a Rust value, as `Cmd::query` does.
+///
+/// It is implemented for:
+///
+/// * `Frame`, returning the frame itself, and `()`, ignoring it.
+/// * `String` and `Bytes`, from simple and bulk strings.
+/// * `i64` and `u64`, from integers, and from strings holding a number.
+/// * `Option<T>`, `None` being decoded from a null.
+/// * `Vec<T>` from an array, and `HashMap<K, V>` from an array of
+///   alternating keys and values, such as the response to `PUBSUB NUMSUB`.
+/// * Tuples of up to 4 elements, from an array of that length.
+pub trait FromFrame: Sized {
+    /// Decodes `frame`, failing if it does not hold a `Self`.
+    fn from_frame(frame: Frame) -> crate::Result<Self>;
+}
+
+/// Returns the error for a frame that cannot be decoded as the requested
+/// type.
+fn unexpected(frame: Frame) -> crate::Error {
+    match frame {
+        // An error nested in an array, such as the reply to one of the
+        // commands of a transaction.
+        Frame::Error(msg) => crate::Error::server(msg),
+        frame => frame.to_error(),
+    }
+}
+
+impl FromFrame for Frame {
+    fn from_frame(frame: Frame) -> crate::Result<Frame> {
+        Ok(frame)
+    }
+}
+
+impl FromFrame for () {
+    fn from_frame(frame: Frame) -> crate::Result<()> {
+        match frame {
+            Frame::Error(_) => Err(unexpected(frame)),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl FromFrame for String {
+    fn from_frame(frame: Frame) -> crate::Result<String> {
+        match frame {
+            Frame::Simple(value) => Ok(value),
+            Frame::Bulk(value) => Ok(String::from_utf8(value.to_vec())?),
+            frame => Err(unexpected(frame)),
+        }
+    }
+}
+
+impl FromFrame for Bytes {
+    fn from_frame(frame: Frame) -> crate::Result<Bytes> {
+        match frame {
+            Frame::Simple(value) => Ok(value.into()),
+            Frame::Bulk(value) => Ok(value),
+            frame => Err(unexpected(frame)),
+        }
+    }
+}
+
+macro_rules! from_frame_int {
+    ($($ty:ty),*) => {$(
+        impl FromFrame for $ty {
+            fn from_frame(frame: Frame) -> crate::Result<$ty> {
+                let value = match &frame {
+                    Frame::Integer(value) => (*value).try_into().ok(),
+                    Frame::Simple(value) => value.parse().ok(),
+                    Frame::Bulk(value) => std::str::from_utf8(value)
+                        .ok()
+                        .and_then(|value| value.parse().ok()),
+                    _ => None,
+                };
+
+                value.ok_or_else(|| unexpected(frame))
+            }
+        }
+    )*};
+}
+
+from_frame_int!(i64, u64);
+
+impl<T: FromFrame> FromFrame for Option<T> {
+    fn from_frame(frame: Frame) -> crate::Result<Option<T>> {
+        match frame {
+            Frame::Null => Ok(None),
+            frame => T::from_frame(frame).map(Some),
+        }
+    }
+}
+
+impl<T: FromFrame> FromFrame for Vec<T> {
+    fn from_frame(frame: Frame) -> crate::Result<Vec<T>> {
+        match frame {
+            Frame::Array(entries) => entries.into_iter().map(T::from_frame).collect(),
+            frame => Err(unexpected(frame)),
+        }
+    }
+}
+
+impl<K: FromFrame + Eq + Hash, V: FromFrame> FromFrame for HashMap<K, V> {
+    fn from_frame(frame: Frame) -> crate::Result<HashMap<K, V>> {
+        match frame {
+            Frame::Array(entries) if entries.len() % 2 == 0 => {
+                let mut map = HashMap::with_capacity(entries.len() / 2);
+                let mut entries = entries.into_iter();
+
+                while let (Some(key), Some(value)) = (entries.next(), entries.next()) {
+                    map.insert(K::from_frame(key)?, V::from_frame(value)?);
+                }
+
+                Ok(map)
+            }
+            frame => Err(unexpected(frame)),
+        }
+    }
+}
+
+macro_rules! from_frame_tuple {
+    ($len:expr; $($name:ident),+) => {
+        impl<$($name: FromFrame),+> FromFrame for ($($name,)+) {
+            fn from_frame(frame: Frame) -> crate::Result<($($name,)+)> {
+                match frame {
+                    Frame::Array(entries) if entries.len() == $len => {
+                        let mut entries = entries.into_iter();
+
+                        // The length was checked, so there is an entry for
+                        // each element.
+                        Ok(($($name::from_frame(entries.next().unwrap())?,)+))
+                    }
+                    frame => Err(unexpected(frame)),
+                }
+            }
+        }
+    };
+}
+
+from_frame_tuple!(1; A);
+from_frame_tuple!(2; A, B);
+from_frame_tuple!(3; A, B, C);
+from_frame_tuple!(4; A, B, C, D);
+
 fn peek_u8(src: &mut Cursor<&[u8]>) -> Result<u8, Error> {
     if !src.has_remaining() {
         return Err(Error::Incomplete);
@@ -266,7 +453,7 @@ fn get_line<'a>(src: &mut Cursor<&'a [u8]>) -> Result<&'a [u8], Error> {
 
 impl From<String> for Error {
     fn from(src: String) -> Error {
-        Error::Other(src.into())
+        Error::Other(crate::Error::Protocol(src))
     }
 }
 
@@ -0,0 +1,234 @@
+//! Latency monitoring: the slow log and latency events.
+//!
+//! The slow log keeps the most recent commands that took longer than
+//! `Config::slowlog_log_slower_than` to execute, like Redis' `SLOWLOG`. Only
+//! the execution is timed, not the time spent reading the request or waiting
+//! for the client to read the response.
+//!
+//! Latency events are operations of the server, such as running a command or
+//! a cycle of the expiration task, that took at least
+//! `Config::latency_monitor_threshold`. For each event the latest samples
+//! are kept, one per second, for `LATENCY LATEST` and `LATENCY HISTORY`.
+
+use crate::Frame;
+
+use bytes::Bytes;
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Maximum number of arguments of a command kept in a slow log entry.
+/// Longer commands keep the first ones, followed by a note with the number
+/// of arguments left out, like Redis does.
+const SLOWLOG_MAX_ARGS: usize = 32;
+
+/// Maximum length of an argument kept in a slow log entry, in bytes.
+const SLOWLOG_MAX_ARG_LEN: usize = 128;
+
+/// Number of samples kept for each latency event.
+const LATENCY_HISTORY_LEN: usize = 160;
+
+/// A command of the slow log.
+#[derive(Debug, Clone)]
+pub(crate) struct SlowlogEntry {
+    /// Unique identifier, increasing with each entry logged.
+    pub(crate) id: u64,
+
+    /// When the command was logged.
+    pub(crate) time: SystemTime,
+
+    /// Time spent executing the command.
+    pub(crate) duration: Duration,
+
+    /// The command and its arguments, possibly shortened.
+    pub(crate) args: Vec<Bytes>,
+
+    /// Address of the client that sent the command.
+    pub(crate) addr: SocketAddr,
+
+    /// Name of the client that sent the command, empty if it has none.
+    pub(crate) name: String,
+}
+
+/// The most recent slow commands, newest first.
+#[derive(Debug)]
+pub(crate) struct Slowlog {
+    entries: VecDeque<SlowlogEntry>,
+
+    /// Identifier of the next entry.
+    next_id: u64,
+
+    /// Maximum number of entries kept.
+    max_len: usize,
+}
+
+/// A sample of a latency event.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct LatencySample {
+    /// Second, since the Unix epoch, the sample was taken in.
+    pub(crate) time: u64,
+
+    /// Highest latency observed in that second, in milliseconds.
+    pub(crate) latency: u64,
+}
+
+/// The samples of a latency event.
+#[derive(Debug, Default)]
+struct LatencyEvent {
+    /// Latest samples, oldest first.
+    history: VecDeque<LatencySample>,
+
+    /// Highest latency ever observed, in milliseconds.
+    max: u64,
+}
+
+/// Latency events observed by the server, by name.
+#[derive(Debug, Default)]
+pub(crate) struct LatencyMonitor {
+    events: HashMap<&'static str, LatencyEvent>,
+}
+
+impl Slowlog {
+    /// Creates an empty slow log that keeps up to `max_len` entries.
+    pub(crate) fn new(max_len: usize) -> Slowlog {
+        Slowlog {
+            entries: VecDeque::new(),
+            next_id: 0,
+            max_len,
+        }
+    }
+
+    /// Logs the command in `frame`, which ran for `duration`. The oldest
+    /// entry is dropped if the log is full.
+    pub(crate) fn push(&mut self, frame: &Frame, duration: Duration, addr: SocketAddr, name: &str) {
+        if self.max_len == 0 {
+            return;
+        }
+
+        let entry = SlowlogEntry {
+            id: self.next_id,
+            time: SystemTime::now(),
+            duration,
+            args: slowlog_args(frame),
+            addr,
+            name: name.to_string(),
+        };
+
+        self.next_id += 1;
+
+        if self.entries.len() == self.max_len {
+            self.entries.pop_back();
+        }
+
+        self.entries.push_front(entry);
+    }
+
+    /// Returns up to `count` entries, newest first. All of them if `None`.
+    pub(crate) fn get(&self, count: Option<usize>) -> Vec<SlowlogEntry> {
+        let count = count.unwrap_or(self.entries.len());
+        self.entries.iter().take(count).cloned().collect()
+    }
+
+    /// Number of entries in the log.
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Removes every entry. Identifiers keep increasing.
+    pub(crate) fn reset(&mut self) {
+        self.entries.clear();
+    }
+}
+
+impl LatencyMonitor {
+    /// Records a sample of `event`. Samples taken in the same second are
+    /// merged, keeping the highest latency.
+    pub(crate) fn record(&mut self, event: &'static str, latency: Duration) {
+        let sample = LatencySample {
+            time: unix_time(SystemTime::now()),
+            latency: latency.as_millis() as u64,
+        };
+
+        let event = self.events.entry(event).or_default();
+        event.max = event.max.max(sample.latency);
+
+        match event.history.back_mut() {
+            Some(last) if last.time == sample.time => {
+                last.latency = last.latency.max(sample.latency);
+            }
+            _ => {
+                if event.history.len() == LATENCY_HISTORY_LEN {
+                    event.history.pop_front();
+                }
+
+                event.history.push_back(sample);
+            }
+        }
+    }
+
+    /// Returns the latest sample and the highest latency of every event
+    /// observed, ordered by name.
+    pub(crate) fn latest(&self) -> Vec<(&'static str, LatencySample, u64)> {
+        let mut latest: Vec<_> = self
+            .events
+            .iter()
+            .filter_map(|(name, event)| Some((*name, *event.history.back()?, event.max)))
+            .collect();
+
+        latest.sort_unstable_by_key(|(name, _, _)| *name);
+        latest
+    }
+
+    /// Returns the samples of `event`, oldest first.
+    pub(crate) fn history(&self, event: &str) -> Vec<LatencySample> {
+        self.events
+            .get(event)
+            .map(|event| event.history.iter().copied().collect())
+            .unwrap_or_default()
+    }
+}
+
+/// The arguments of the command in `frame` to keep in a slow log entry,
+/// shortened to `SLOWLOG_MAX_ARGS` arguments of `SLOWLOG_MAX_ARG_LEN` bytes.
+fn slowlog_args(frame: &Frame) -> Vec<Bytes> {
+    let frames = match frame {
+        Frame::Array(frames) => &frames[..],
+        frame => std::slice::from_ref(frame),
+    };
+
+    let mut args = vec![];
+
+    for (i, frame) in frames.iter().enumerate() {
+        // The last argument kept notes how many were left out.
+        if i == SLOWLOG_MAX_ARGS - 1 && frames.len() > SLOWLOG_MAX_ARGS {
+            let more = frames.len() - i;
+            args.push(Bytes::from(format!("... ({} more arguments)", more)));
+            break;
+        }
+
+        let arg = match frame {
+            Frame::Simple(s) | Frame::Error(s) => Bytes::from(s.clone()),
+            Frame::Bulk(data) => data.clone(),
+            Frame::Integer(n) => Bytes::from(n.to_string()),
+            Frame::Null | Frame::Array(_) => Bytes::new(),
+        };
+
+        if arg.len() > SLOWLOG_MAX_ARG_LEN {
+            let more = arg.len() - SLOWLOG_MAX_ARG_LEN;
+            let mut shortened = arg[..SLOWLOG_MAX_ARG_LEN].to_vec();
+            shortened.extend_from_slice(format!("... ({} more bytes)", more).as_bytes());
+            args.push(Bytes::from(shortened));
+        } else {
+            args.push(arg);
+        }
+    }
+
+    args
+}
+
+/// Seconds since the Unix epoch.
+pub(crate) fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_secs()
+}
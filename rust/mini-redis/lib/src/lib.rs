@@ -21,18 +21,23 @@ pub mod client;
 pub mod cmd;
 pub use cmd::Command;
 
+pub mod config;
+
 mod connection;
 pub use connection::Connection;
 
 pub mod frame;
-pub use frame::Frame;
+pub use frame::{Frame, FromFrame};
 
 mod db;
 use db::Db;
 use db::DbDropGuard;
 
 mod parse;
-use parse::{Parse, ParseError};
+use parse::Parse;
+pub use parse::ParseError;
+
+mod pattern;
 
 pub mod server;
 
@@ -42,27 +47,28 @@ pub use buffer::{buffer, Buffer};
 mod shutdown;
 use shutdown::Shutdown;
 
+mod snapshot;
+
+mod aof;
+
+mod replication;
+
+mod metrics;
+
+mod latency;
+
+mod registry;
+
+mod acl;
+
+mod tls;
+
+mod error;
+pub use error::Error;
+
 /// Puerto por defecto por el que el servidor redis escuchara (se utilizara
 /// si no se especifica ninguno)
 pub const DEFAULT_PORT: u16 = 6379;
 
-/// Error returnado por la mayoria de funciones.
-/// 
-/// Al escribir una aplicación real, es posible que desee considerar una crate
-/// de manejo de errores especializado o definir un tipo de error como 
-/// una "enumeración" de causas.
-/// 
-/// Sin embargo, para nuestro ejemplo, usar un Boxed `std::error::Error` 
-/// es suficiente.
-/// 
-/// Por motivos de rendimiento, se evita el boxing en cualquier ruta activa.
-/// Por ejemplo, en `parse`, se define un error personalizado `enum`.
-/// Esto se debe a que el error se detecta y maneja durante la ejecución
-/// normal cuando se recibe un marco parcial en un socket.
-/// 
-/// `std::error::Error` se implementa para `parse::Error`, 
-/// lo que permite convertirlo en `Box<dyn std::error::Error>`.
-pub type Error = Box<dyn std::error::Error + Send + Sync>;
-
 /// Un tipo 'Result' especializado para las operaciones Redis.
 pub type Result<T> = std::result::Result<T, Error>;
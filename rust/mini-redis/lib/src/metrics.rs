@@ -0,0 +1,263 @@
+//! Metrics in the Prometheus text format.
+//!
+//! The server optionally listens for HTTP connections on a second address,
+//! `Config::metrics_addr`, and answers `GET /metrics` with the figures also
+//! reported by `INFO`, plus per-command latency histograms. Only what a
+//! Prometheus scraper needs of HTTP/1.1 is implemented: one request per
+//! connection, answered with `Connection: close`.
+
+use crate::db::{Stats, LATENCY_BUCKETS};
+use crate::{Db, Shutdown};
+
+use std::fmt::Write;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{debug, error, info};
+
+/// Content type of the Prometheus text exposition format.
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// Longest request line or header accepted, in bytes.
+const MAX_LINE: usize = 8 * 1024;
+
+/// Serves the metrics of `db` on `listener` until the server shuts down.
+pub(crate) async fn run(listener: TcpListener, db: Db, mut shutdown: Shutdown) {
+    info!(addr = ?listener.local_addr().ok(), "serving metrics");
+
+    loop {
+        let socket = tokio::select! {
+            res = listener.accept() => match res {
+                Ok((socket, _)) => socket,
+                Err(err) => {
+                    error!(cause = %err, "failed to accept a metrics connection");
+                    continue;
+                }
+            },
+            _ = shutdown.recv() => return,
+        };
+
+        let db = db.clone();
+
+        tokio::spawn(async move {
+            if let Err(err) = serve(socket, &db).await {
+                debug!(cause = %err, "metrics connection error");
+            }
+        });
+    }
+}
+
+/// Reads one request from `socket` and writes its response.
+async fn serve(socket: TcpStream, db: &Db) -> crate::Result<()> {
+    let mut socket = BufReader::new(socket);
+
+    let request = match read_line(&mut socket).await? {
+        Some(request) => request,
+        None => return Ok(()),
+    };
+
+    // The headers are not needed, but must be read before responding.
+    while let Some(header) = read_line(&mut socket).await? {
+        if header.is_empty() {
+            break;
+        }
+    }
+
+    let mut parts = request.split_whitespace();
+    let method = parts.next().unwrap_or_default();
+    let path = parts.next().unwrap_or_default();
+
+    // The query string, if any, is ignored.
+    let path = path.split('?').next().unwrap_or_default();
+
+    let (status, body) = match (method, path) {
+        ("GET", "/metrics") => ("200 OK", render(&db.stats())),
+        (_, "/metrics") => ("405 Method Not Allowed", String::new()),
+        _ => ("404 Not Found", String::new()),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        CONTENT_TYPE,
+        body.len(),
+        body
+    );
+
+    let socket = socket.get_mut();
+    socket.write_all(response.as_bytes()).await?;
+    socket.shutdown().await?;
+
+    Ok(())
+}
+
+/// Reads a line without its `\r\n` terminator. Returns `None` if the peer
+/// closed the connection first.
+async fn read_line(socket: &mut BufReader<TcpStream>) -> crate::Result<Option<String>> {
+    let mut line = String::new();
+
+    if 0 == (&mut *socket).take(MAX_LINE as u64).read_line(&mut line).await? {
+        return Ok(None);
+    }
+
+    if !line.ends_with('\n') {
+        return Err("request line too long".into());
+    }
+
+    Ok(Some(line.trim_end_matches(&['\r', '\n'][..]).to_string()))
+}
+
+/// Formats `stats` in the Prometheus text format.
+fn render(stats: &Stats) -> String {
+    let mut out = String::new();
+
+    // Writing to a `String` never fails.
+    let _ = write_metrics(&mut out, stats);
+
+    out
+}
+
+fn write_metrics(out: &mut String, stats: &Stats) -> std::fmt::Result {
+    header(out, "mini_redis_commands_total", "counter", "Commands processed, by command.")?;
+    for command in &stats.commands {
+        writeln!(
+            out,
+            "mini_redis_commands_total{{command=\"{}\"}} {}",
+            command.name, command.calls
+        )?;
+    }
+
+    header(
+        out,
+        "mini_redis_command_duration_seconds",
+        "histogram",
+        "Time spent executing commands, by command.",
+    )?;
+    for command in &stats.commands {
+        let mut count = 0;
+
+        for (limit, observed) in LATENCY_BUCKETS.iter().zip(&command.durations) {
+            count += observed;
+            writeln!(
+                out,
+                "mini_redis_command_duration_seconds_bucket{{command=\"{}\",le=\"{}\"}} {}",
+                command.name,
+                *limit as f64 / 1_000_000.0,
+                count
+            )?;
+        }
+
+        // The last interval has no limit.
+        count += command.durations[LATENCY_BUCKETS.len()];
+        writeln!(
+            out,
+            "mini_redis_command_duration_seconds_bucket{{command=\"{}\",le=\"+Inf\"}} {}",
+            command.name, count
+        )?;
+        writeln!(
+            out,
+            "mini_redis_command_duration_seconds_sum{{command=\"{}\"}} {}",
+            command.name,
+            command.usec as f64 / 1_000_000.0
+        )?;
+        writeln!(
+            out,
+            "mini_redis_command_duration_seconds_count{{command=\"{}\"}} {}",
+            command.name, count
+        )?;
+    }
+
+    metric(
+        out,
+        "mini_redis_connected_clients",
+        "gauge",
+        "Connections open.",
+        stats.connected_clients,
+    )?;
+    metric(
+        out,
+        "mini_redis_connections_received_total",
+        "counter",
+        "Connections accepted.",
+        stats.total_connections,
+    )?;
+    metric(
+        out,
+        "mini_redis_rejected_connections_total",
+        "counter",
+        "Connections rejected because the connection limit was reached.",
+        stats.rejected_connections,
+    )?;
+    metric(
+        out,
+        "mini_redis_net_input_bytes_total",
+        "counter",
+        "Bytes read from connections.",
+        stats.net_input_bytes,
+    )?;
+    metric(
+        out,
+        "mini_redis_net_output_bytes_total",
+        "counter",
+        "Bytes written to connections.",
+        stats.net_output_bytes,
+    )?;
+    metric(
+        out,
+        "mini_redis_pubsub_messages_published_total",
+        "counter",
+        "Messages published with PUBLISH.",
+        stats.published_messages,
+    )?;
+    metric(
+        out,
+        "mini_redis_pubsub_messages_dropped_total",
+        "counter",
+        "Messages dropped because a subscriber fell behind.",
+        stats.dropped_messages,
+    )?;
+    metric(
+        out,
+        "mini_redis_expired_keys_total",
+        "counter",
+        "Keys removed because they expired.",
+        stats.expired_keys,
+    )?;
+    metric(
+        out,
+        "mini_redis_evicted_keys_total",
+        "counter",
+        "Keys evicted to free memory.",
+        stats.evicted_keys,
+    )?;
+    metric(
+        out,
+        "mini_redis_used_memory_bytes",
+        "gauge",
+        "Approximate memory used by keys and values.",
+        stats.used_memory,
+    )?;
+
+    header(out, "mini_redis_keys", "gauge", "Keys, by database.")?;
+    for (index, (keys, _)) in stats.keyspaces.iter().enumerate() {
+        writeln!(out, "mini_redis_keys{{db=\"{}\"}} {}", index, keys)?;
+    }
+
+    Ok(())
+}
+
+/// Writes the `HELP` and `TYPE` lines of a metric.
+fn header(out: &mut String, name: &str, kind: &str, help: &str) -> std::fmt::Result {
+    writeln!(out, "# HELP {} {}\n# TYPE {} {}", name, help, name, kind)
+}
+
+/// Writes a metric without labels.
+fn metric(
+    out: &mut String,
+    name: &str,
+    kind: &str,
+    help: &str,
+    value: impl std::fmt::Display,
+) -> std::fmt::Result {
+    header(out, name, kind, help)?;
+    writeln!(out, "{} {}", name, value)
+}
@@ -15,18 +15,24 @@ pub(crate) struct Parse {
     parts: vec::IntoIter<Frame>,
 }
 
-/// Error encountered while parsing a frame.
+/// Error encountered while parsing a command from a frame.
 ///
-/// Only `EndOfStream` errors are handled at runtime. All other errors result in
-/// the connection being terminated.
+/// `EndOfStream` is handled by the commands taking optional arguments. When it
+/// reaches the server, the command had too few arguments. Like `Syntax`
+/// errors, it is answered with an error and the connection stays open. Only
+/// `Protocol` errors, frames that are not commands at all, close the
+/// connection, as what the client sends next can not be trusted.
 #[derive(Debug)]
-pub(crate) enum ParseError {
+pub enum ParseError {
     /// Attempting to extract a value failed due to the frame being fully
     /// consumed.
     EndOfStream,
 
-    /// All other errors
-    Other(crate::Error),
+    /// The command has an invalid argument, or the wrong number of them.
+    Syntax(String),
+
+    /// The frame is not an array of strings.
+    Protocol(String),
 }
 
 impl Parse {
@@ -36,7 +42,12 @@ impl Parse {
     pub(crate) fn new(frame: Frame) -> Result<Parse, ParseError> {
         let array = match frame {
             Frame::Array(array) => array,
-            frame => return Err(format!("protocol error; expected array, got {:?}", frame).into()),
+            frame => {
+                return Err(ParseError::Protocol(format!(
+                    "protocol error; expected array, got {:?}",
+                    frame
+                )))
+            }
         };
 
         Ok(Parse {
@@ -63,12 +74,11 @@ impl Parse {
             Frame::Simple(s) => Ok(s),
             Frame::Bulk(data) => str::from_utf8(&data[..])
                 .map(|s| s.to_string())
-                .map_err(|_| "protocol error; invalid string".into()),
-            frame => Err(format!(
+                .map_err(|_| "value is not a valid UTF-8 string".into()),
+            frame => Err(ParseError::Protocol(format!(
                 "protocol error; expected simple frame or bulk frame, got {:?}",
                 frame
-            )
-            .into()),
+            ))),
         }
     }
 
@@ -84,11 +94,10 @@ impl Parse {
             // raw bytes, they are considered separate types.
             Frame::Simple(s) => Ok(Bytes::from(s.into_bytes())),
             Frame::Bulk(data) => Ok(data),
-            frame => Err(format!(
+            frame => Err(ParseError::Protocol(format!(
                 "protocol error; expected simple frame or bulk frame, got {:?}",
                 frame
-            )
-            .into()),
+            ))),
         }
     }
 
@@ -102,7 +111,7 @@ impl Parse {
     pub(crate) fn next_int(&mut self) -> Result<u64, ParseError> {
         use atoi::atoi;
 
-        const MSG: &str = "protocol error; invalid number";
+        const MSG: &str = "value is not an integer or out of range";
 
         match self.next()? {
             // An integer frame type is already stored as an integer.
@@ -111,7 +120,10 @@ impl Parse {
             // fails, an error is returned.
             Frame::Simple(data) => atoi::<u64>(data.as_bytes()).ok_or_else(|| MSG.into()),
             Frame::Bulk(data) => atoi::<u64>(&data).ok_or_else(|| MSG.into()),
-            frame => Err(format!("protocol error; expected int frame but got {:?}", frame).into()),
+            frame => Err(ParseError::Protocol(format!(
+                "protocol error; expected int frame but got {:?}",
+                frame
+            ))),
         }
     }
 
@@ -120,14 +132,28 @@ impl Parse {
         if self.parts.next().is_none() {
             Ok(())
         } else {
-            Err("protocol error; expected end of frame, but there was more".into())
+            Err("syntax error".into())
         }
     }
 }
 
+impl ParseError {
+    /// Returns `true` if the connection can keep going after the error is
+    /// answered.
+    pub fn is_recoverable(&self) -> bool {
+        !matches!(self, ParseError::Protocol(_))
+    }
+
+    /// Converts the error into the error frame answering the command.
+    pub(crate) fn into_frame(self) -> Frame {
+        Frame::Error(format!("ERR {}", self))
+    }
+}
+
+/// Errors built from a message are invalid arguments, found by the commands.
 impl From<String> for ParseError {
     fn from(src: String) -> ParseError {
-        ParseError::Other(src.into())
+        ParseError::Syntax(src)
     }
 }
 
@@ -140,8 +166,8 @@ impl From<&str> for ParseError {
 impl fmt::Display for ParseError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            ParseError::EndOfStream => "protocol error; unexpected end of stream".fmt(f),
-            ParseError::Other(err) => err.fmt(f),
+            ParseError::EndOfStream => "wrong number of arguments".fmt(f),
+            ParseError::Syntax(msg) | ParseError::Protocol(msg) => msg.fmt(f),
         }
     }
 }
@@ -0,0 +1,127 @@
+//! Glob-style pattern matching, as used by Redis commands accepting a
+//! `pattern` argument (`PUBSUB CHANNELS`, `KEYS`, ...).
+
+/// Returns `true` if `string` matches the glob-style `pattern`.
+///
+/// The supported syntax is the same one Redis supports:
+///
+/// * `?` matches exactly one byte.
+/// * `*` matches any number of bytes, including none.
+/// * `[abc]` matches one of the listed bytes, `[^abc]` any byte not listed and
+///   `[a-z]` any byte in the range.
+/// * `\x` matches the byte `x` literally.
+///
+/// Matching works on raw bytes, so channel and key names do not need to be
+/// valid UTF-8.
+pub(crate) fn matches(pattern: &[u8], string: &[u8]) -> bool {
+    // Position in `pattern` and `string` of the last `*` seen, used to
+    // backtrack when the remainder of the pattern fails to match. Only the
+    // most recent star needs to be remembered: extending an earlier star can
+    // never produce a match that extending the latest one cannot.
+    let mut star: Option<(usize, usize)> = None;
+    let (mut p, mut s) = (0, 0);
+
+    while s < string.len() {
+        if p < pattern.len() {
+            match pattern[p] {
+                b'*' => {
+                    // Collapse consecutive stars, they are equivalent to one.
+                    while p < pattern.len() && pattern[p] == b'*' {
+                        p += 1;
+                    }
+
+                    // Start by matching the empty string, remembering where
+                    // to resume if that turns out to be too short.
+                    star = Some((p, s));
+                    continue;
+                }
+                b'?' => {
+                    p += 1;
+                    s += 1;
+                    continue;
+                }
+                b'[' => {
+                    if let Some(next) = match_class(pattern, p, string[s]) {
+                        p = next;
+                        s += 1;
+                        continue;
+                    }
+                }
+                b'\\' if p + 1 < pattern.len() => {
+                    if pattern[p + 1] == string[s] {
+                        p += 2;
+                        s += 1;
+                        continue;
+                    }
+                }
+                byte => {
+                    if byte == string[s] {
+                        p += 1;
+                        s += 1;
+                        continue;
+                    }
+                }
+            }
+        }
+
+        // Mismatch. If a star was seen, let it swallow one more byte and try
+        // again, otherwise the string does not match.
+        match star {
+            Some((star_p, star_s)) => {
+                star = Some((star_p, star_s + 1));
+                p = star_p;
+                s = star_s + 1;
+            }
+            None => return false,
+        }
+    }
+
+    // The string is exhausted. Any trailing stars match the empty string.
+    pattern[p..].iter().all(|&byte| byte == b'*')
+}
+
+/// Matches `byte` against the character class starting at `pattern[start]`
+/// (which must be `[`).
+///
+/// Returns the position right after the closing `]` if the byte is part of
+/// the class, `None` otherwise. An unterminated class extends to the end of the
+/// pattern, like it does in Redis.
+fn match_class(pattern: &[u8], start: usize, byte: u8) -> Option<usize> {
+    let mut p = start + 1;
+
+    let negate = pattern.get(p) == Some(&b'^');
+    if negate {
+        p += 1;
+    }
+
+    let mut matched = false;
+
+    while p < pattern.len() && pattern[p] != b']' {
+        if pattern[p] == b'\\' && p + 1 < pattern.len() {
+            // Escaped byte, compared literally.
+            p += 1;
+            matched |= pattern[p] == byte;
+        } else if p + 2 < pattern.len() && pattern[p + 1] == b'-' && pattern[p + 2] != b']' {
+            // A range. Redis accepts reversed ranges such as `[z-a]`.
+            let (lo, hi) = if pattern[p] <= pattern[p + 2] {
+                (pattern[p], pattern[p + 2])
+            } else {
+                (pattern[p + 2], pattern[p])
+            };
+
+            matched |= lo <= byte && byte <= hi;
+            p += 2;
+        } else {
+            matched |= pattern[p] == byte;
+        }
+
+        p += 1;
+    }
+
+    if matched != negate {
+        // Skip the closing `]`, if any.
+        Some((p + 1).min(pattern.len()))
+    } else {
+        None
+    }
+}
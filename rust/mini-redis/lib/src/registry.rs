@@ -0,0 +1,277 @@
+//! Registry of the connected clients.
+//!
+//! Every connection registers itself when it is accepted and is removed when
+//! it terminates. The registry backs the `CLIENT` command: listing the
+//! clients, killing them and pausing them.
+
+use crate::cmd::PauseMode;
+
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{watch, Notify};
+use tokio::time::{self, Duration, Instant};
+
+/// The connected clients, by id.
+#[derive(Debug)]
+pub(crate) struct Registry {
+    /// Id of the next client registered. Ids are never reused.
+    next_id: AtomicU64,
+
+    clients: Mutex<BTreeMap<u64, Arc<ClientEntry>>>,
+
+    /// Current `CLIENT PAUSE`, if any. It may have expired already.
+    pause: watch::Sender<Option<Pause>>,
+}
+
+/// A connected client.
+#[derive(Debug)]
+pub(crate) struct ClientEntry {
+    /// Unique id, as returned by `CLIENT ID`.
+    pub(crate) id: u64,
+
+    /// Address of the peer.
+    pub(crate) addr: SocketAddr,
+
+    /// When the connection was accepted.
+    created: Instant,
+
+    /// Fields updated by the connection as it runs commands.
+    state: Mutex<ClientState>,
+
+    /// Notified by `CLIENT KILL`. The connection listens for it alongside the
+    /// server shutdown.
+    kill: Arc<Notify>,
+}
+
+#[derive(Debug)]
+struct ClientState {
+    /// Name set with `CLIENT SETNAME`, empty if none.
+    name: String,
+
+    /// User the client is authenticated as, `None` until it runs `AUTH` if
+    /// the `default` user needs a password.
+    user: Option<String>,
+
+    /// Database selected.
+    db: usize,
+
+    /// Number of channels subscribed to.
+    subscriptions: usize,
+
+    /// Name of the last command run, `None` before the first one.
+    last_command: Option<&'static str>,
+
+    /// When the last command was received.
+    last_interaction: Instant,
+}
+
+/// A `CLIENT PAUSE`.
+#[derive(Debug, Clone, Copy)]
+struct Pause {
+    until: Instant,
+    mode: PauseMode,
+}
+
+/// Which clients `CLIENT KILL` terminates. A client must match every filter
+/// given.
+#[derive(Debug, Default)]
+pub(crate) struct KillFilter {
+    pub(crate) id: Option<u64>,
+    pub(crate) addr: Option<String>,
+    pub(crate) user: Option<String>,
+
+    /// Id of a client that is never terminated, the one issuing the command
+    /// unless it asked otherwise.
+    pub(crate) skip: Option<u64>,
+}
+
+impl Registry {
+    pub(crate) fn new() -> Registry {
+        Registry {
+            next_id: AtomicU64::new(1),
+            clients: Mutex::new(BTreeMap::new()),
+            pause: watch::channel(None).0,
+        }
+    }
+
+    /// Registers a client connected from `addr` on database 0.
+    pub(crate) fn register(&self, addr: SocketAddr) -> Arc<ClientEntry> {
+        let now = Instant::now();
+
+        let client = Arc::new(ClientEntry {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            addr,
+            created: now,
+            state: Mutex::new(ClientState {
+                name: String::new(),
+                user: None,
+                db: 0,
+                subscriptions: 0,
+                last_command: None,
+                last_interaction: now,
+            }),
+            kill: Arc::new(Notify::new()),
+        });
+
+        self.clients.lock().unwrap().insert(client.id, client.clone());
+        client
+    }
+
+    /// Removes the client `id`. Does nothing if it was already removed.
+    pub(crate) fn unregister(&self, id: u64) {
+        self.clients.lock().unwrap().remove(&id);
+    }
+
+    /// Returns the connected clients, by increasing id.
+    pub(crate) fn list(&self) -> Vec<Arc<ClientEntry>> {
+        self.clients.lock().unwrap().values().cloned().collect()
+    }
+
+    /// Terminates the clients matching `filter`, returning how many there
+    /// were. They are removed from the registry right away, and the
+    /// connections terminate as soon as they notice.
+    pub(crate) fn kill(&self, filter: &KillFilter) -> usize {
+        let mut clients = self.clients.lock().unwrap();
+
+        let ids: Vec<u64> = clients
+            .values()
+            .filter(|client| filter.id.is_none_or(|id| client.id == id))
+            .filter(|client| {
+                filter
+                    .addr
+                    .as_ref()
+                    .is_none_or(|addr| client.addr.to_string() == *addr)
+            })
+            .filter(|client| {
+                filter
+                    .user
+                    .as_ref()
+                    .is_none_or(|user| client.state.lock().unwrap().user.as_ref() == Some(user))
+            })
+            .filter(|client| filter.skip != Some(client.id))
+            .map(|client| client.id)
+            .collect();
+
+        for id in &ids {
+            if let Some(client) = clients.remove(id) {
+                client.kill.notify_one();
+            }
+        }
+
+        ids.len()
+    }
+
+    /// Pauses the clients for `timeout`, replacing any previous pause.
+    pub(crate) fn pause(&self, timeout: Duration, mode: PauseMode) {
+        let until = Instant::now() + timeout;
+        self.pause.send_replace(Some(Pause { until, mode }));
+    }
+
+    /// Ends the current pause, if any.
+    pub(crate) fn unpause(&self) {
+        self.pause.send_replace(None);
+    }
+
+    /// Waits until the clients are no longer paused in a mode for which
+    /// `paused` returns `true`.
+    pub(crate) async fn wait_unpaused(&self, paused: impl Fn(PauseMode) -> bool) {
+        // The usual case, there is no pause.
+        if self.pause.borrow().is_none() {
+            return;
+        }
+
+        let mut changes = self.pause.subscribe();
+
+        loop {
+            let pause = *changes.borrow_and_update();
+
+            match pause {
+                Some(pause) if pause.until > Instant::now() && paused(pause.mode) => {
+                    tokio::select! {
+                        _ = time::sleep_until(pause.until) => {}
+                        res = changes.changed() => {
+                            // The registry is gone, and the server with it.
+                            if res.is_err() {
+                                return;
+                            }
+                        }
+                    }
+                }
+                _ => return,
+            }
+        }
+    }
+}
+
+impl ClientEntry {
+    /// Signal notified when the client is killed.
+    pub(crate) fn kill_signal(&self) -> Arc<Notify> {
+        self.kill.clone()
+    }
+
+    /// Name set with `CLIENT SETNAME`, empty if none.
+    pub(crate) fn name(&self) -> String {
+        self.state.lock().unwrap().name.clone()
+    }
+
+    pub(crate) fn set_name(&self, name: String) {
+        self.state.lock().unwrap().name = name;
+    }
+
+    /// User the client is authenticated as, if any.
+    pub(crate) fn user(&self) -> Option<String> {
+        self.state.lock().unwrap().user.clone()
+    }
+
+    pub(crate) fn set_user(&self, user: &str) {
+        self.state.lock().unwrap().user = Some(user.to_string());
+    }
+
+    /// Leaves the client unauthenticated.
+    pub(crate) fn clear_user(&self) {
+        self.state.lock().unwrap().user = None;
+    }
+
+    /// Records that the client selected database `db`.
+    pub(crate) fn set_db(&self, db: usize) {
+        self.state.lock().unwrap().db = db;
+    }
+
+    /// Records the number of channels the client is subscribed to.
+    pub(crate) fn set_subscriptions(&self, subscriptions: usize) {
+        self.state.lock().unwrap().subscriptions = subscriptions;
+    }
+
+    /// Records that the client sent a command, named `name` if it is known.
+    pub(crate) fn touch(&self, name: Option<&'static str>) {
+        let mut state = self.state.lock().unwrap();
+        state.last_interaction = Instant::now();
+
+        if name.is_some() {
+            state.last_command = name;
+        }
+    }
+
+    /// Describes the client in the format of `CLIENT LIST`, without the
+    /// trailing newline.
+    pub(crate) fn describe(&self) -> String {
+        let state = self.state.lock().unwrap();
+        let flags = if state.subscriptions > 0 { "P" } else { "N" };
+
+        format!(
+            "id={} addr={} name={} age={} idle={} flags={} db={} sub={} psub=0 cmd={} user={}",
+            self.id,
+            self.addr,
+            state.name,
+            self.created.elapsed().as_secs(),
+            state.last_interaction.elapsed().as_secs(),
+            flags,
+            state.db,
+            state.subscriptions,
+            state.last_command.unwrap_or("NULL"),
+            state.user.as_deref().unwrap_or(""),
+        )
+    }
+}
@@ -0,0 +1,275 @@
+//! Primary / replica replication.
+//!
+//! A replica connects to its primary with a regular [`Client`] and sends
+//! `PSYNC <replid> <offset>`, where `replid` identifies the replication stream
+//! of the primary and `offset` is the number of bytes of that stream the
+//! replica has already applied. The first time, the replica does not know
+//! either and sends `PSYNC ? -1`.
+//!
+//! The primary answers in one of two ways:
+//!
+//! * `+FULLRESYNC <replid> <offset>`, followed by a bulk string holding a
+//!   snapshot of the dataset at `offset`.
+//! * `+CONTINUE <replid>` if the replica is following this primary and the
+//!   writes since `offset` are still in the replication backlog.
+//!
+//! Either way, the primary then streams every write from the backlog to the
+//! replica, as the commands that reproduce it. The backlog keeps the most
+//! recent writes, up to `Config::repl_backlog_size` bytes, so replicas that
+//! reconnect after a short disconnection only receive what they missed.
+//!
+//! [`Client`]: crate::client::Client
+
+use crate::client::{self, Client};
+use crate::cmd::Psync;
+use crate::snapshot::{self, Record};
+use crate::{aof, Db, Frame, Shutdown};
+
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use std::collections::VecDeque;
+use tokio::sync::{mpsc, watch};
+use tokio::time::{self, Duration};
+use tracing::{info, warn};
+
+/// Time the replica waits before reconnecting to the primary.
+const RECONNECT_DELAY: Duration = Duration::from_millis(100);
+
+/// The most recent writes of a primary, with their offsets in the replication
+/// stream.
+///
+/// The backlog is created when the first replica connects. Until then, writes
+/// are not recorded and the offset does not advance.
+#[derive(Debug)]
+pub(crate) struct Backlog {
+    /// Buffered writes, with the offset each one starts at.
+    frames: VecDeque<(u64, Frame)>,
+
+    /// Offset following the last write.
+    offset: u64,
+
+    /// Encoded size of the buffered writes.
+    size: usize,
+
+    /// Maximum encoded size of the buffered writes.
+    capacity: usize,
+
+    /// Database selected by the last `SELECT` in the stream, `None` if the
+    /// next write must select it again.
+    selected: Option<usize>,
+
+    /// Publishes `offset` when it advances, waking up the connections
+    /// streaming writes to replicas.
+    changes: watch::Sender<u64>,
+}
+
+/// How a replication stream starts, as decided by the primary.
+pub(crate) enum Resync {
+    /// The replica must load `records` and follow the stream from `offset`.
+    Full {
+        replid: String,
+        offset: u64,
+        records: Vec<Record>,
+        changes: watch::Receiver<u64>,
+    },
+
+    /// The replica can follow the stream from the offset it asked for.
+    Partial {
+        replid: String,
+        changes: watch::Receiver<u64>,
+    },
+}
+
+impl Backlog {
+    /// Creates an empty backlog holding up to `capacity` bytes of writes.
+    pub(crate) fn new(capacity: usize) -> Backlog {
+        let (changes, _) = watch::channel(0);
+
+        Backlog {
+            frames: VecDeque::new(),
+            offset: 0,
+            size: 0,
+            capacity,
+            selected: None,
+            changes,
+        }
+    }
+
+    /// Offset following the last write.
+    pub(crate) fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Returns a receiver notified when writes are added.
+    pub(crate) fn subscribe(&self) -> watch::Receiver<u64> {
+        self.changes.subscribe()
+    }
+
+    /// Makes the next write select its database, for replicas that start
+    /// following the stream without knowing which one is selected.
+    pub(crate) fn reselect(&mut self) {
+        self.selected = None;
+    }
+
+    /// Adds a write to database `index`, of `len` bytes once encoded,
+    /// preceded by a `SELECT` if the database changes.
+    pub(crate) fn push(&mut self, index: usize, frame: Frame, len: usize) {
+        if self.selected != Some(index) {
+            let select = aof::select_frame(index);
+
+            let mut buf = Vec::new();
+            select.encode(&mut buf);
+
+            self.selected = Some(index);
+            self.append(select, buf.len());
+        }
+
+        self.append(frame, len);
+        self.changes.send_replace(self.offset);
+    }
+
+    /// Buffers a frame, of `len` bytes once encoded, dropping the oldest
+    /// writes to stay under the capacity.
+    fn append(&mut self, frame: Frame, len: usize) {
+        self.frames.push_back((self.offset, frame));
+        self.offset += len as u64;
+        self.size += len;
+
+        // The last write is always kept, even if it is larger than the whole
+        // backlog, so the offset of the stream can be resolved.
+        while self.size > self.capacity && self.frames.len() > 1 {
+            let (start, _) = self.frames.pop_front().unwrap();
+            let next = self.frames.front().map_or(self.offset, |(next, _)| *next);
+            self.size -= (next - start) as usize;
+        }
+    }
+
+    /// Returns the writes from `offset` on, or `None` if they are no longer
+    /// buffered.
+    pub(crate) fn since(&self, offset: u64) -> Option<Vec<Frame>> {
+        if offset == self.offset {
+            return Some(vec![]);
+        }
+
+        // Offsets are sorted, and `offset` must be the start of a write.
+        let idx = self.frames.partition_point(|(start, _)| *start < offset);
+
+        match self.frames.get(idx) {
+            Some((start, _)) if *start == offset => {
+                Some(self.frames.range(idx..).map(|(_, frame)| frame.clone()).collect())
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Generates a new replication id: 40 random alphanumeric characters, as in
+/// Redis.
+pub(crate) fn new_replid() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(40)
+        .map(char::from)
+        .collect()
+}
+
+/// Replicates `primary` into `db` until the server shuts down.
+///
+/// The connection to the primary is re-established whenever it is lost,
+/// resuming the replication stream where it was left when possible. The
+/// database selected by the stream is kept across connections, as a resumed
+/// stream does not select it again.
+pub(crate) async fn run_replica(
+    mut db: Db,
+    primary: String,
+    mut shutdown: Shutdown,
+    _shutdown_complete: mpsc::Sender<()>,
+) {
+    // Replication id and offset of the stream followed, once known.
+    let mut link = None;
+
+    loop {
+        tokio::select! {
+            res = replicate(&mut db, &primary, &mut link) => {
+                if let Err(err) = res {
+                    warn!(%primary, cause = %err, "replication link lost");
+                }
+            }
+            _ = shutdown.recv() => return,
+        }
+
+        tokio::select! {
+            _ = time::sleep(RECONNECT_DELAY) => {}
+            _ = shutdown.recv() => return,
+        }
+    }
+}
+
+/// Connects to `primary` and applies its replication stream to `db`, until
+/// the connection is lost.
+///
+/// `link` holds the replication id and offset of the stream, and is kept up
+/// to date as writes are applied.
+async fn replicate(
+    db: &mut Db,
+    primary: &str,
+    link: &mut Option<(String, u64)>,
+) -> crate::Result<()> {
+    let mut client = client::connect(primary).await?;
+
+    let psync = match link {
+        Some((replid, offset)) => Psync::new(Some(replid.clone()), Some(*offset)),
+        None => Psync::new(None, None),
+    };
+
+    let reply = client.psync(psync).await?;
+
+    match reply.split(' ').collect::<Vec<_>>()[..] {
+        ["FULLRESYNC", replid, offset] => {
+            let offset = offset.parse().map_err(|_| {
+                crate::Error::Protocol(format!("protocol error; invalid offset '{}'", offset))
+            })?;
+            full_resync(db, &mut client).await?;
+
+            info!(%primary, %replid, offset, "full resync with primary");
+            *link = Some((replid.to_string(), offset));
+        }
+        ["CONTINUE", _] if link.is_some() => {
+            info!(%primary, "partial resync with primary");
+        }
+        _ => return Err(format!("unexpected PSYNC reply `{}`", reply).into()),
+    }
+
+    // `link` is always set past this point.
+    let (_, offset) = link.as_mut().unwrap();
+
+    loop {
+        let frame = client.read_replicated().await?;
+
+        // The offset advances by the size of the write as the primary
+        // encoded it, which is the size it is encoded to again.
+        let mut buf = Vec::new();
+        frame.encode(&mut buf);
+
+        aof::apply(db, frame)?;
+        *offset += buf.len() as u64;
+    }
+}
+
+/// Replaces the dataset of `db` with the snapshot sent by the primary.
+async fn full_resync(db: &Db, client: &mut Client) -> crate::Result<()> {
+    let data = match client.read_replicated().await? {
+        Frame::Bulk(data) => data,
+        frame => return Err(frame.to_error()),
+    };
+
+    let records = snapshot::decode(&data)?;
+
+    db.replace(records)?;
+
+    // The replaced dataset was not logged to the AOF. Rewriting it brings the
+    // AOF in line with the new dataset. It fails if the AOF is disabled.
+    let _ = aof::bgrewrite(db);
+
+    Ok(())
+}
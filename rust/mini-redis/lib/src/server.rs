@@ -3,14 +3,23 @@
 //! Provides an async `run` function that listens for inbound connections,
 //! spawning a task per connection.
 
-use crate::{Command, Connection, Db, DbDropGuard, Shutdown};
-
+use crate::cmd::SaveMode;
+use crate::config::Config;
+use crate::{
+    aof, metrics, replication, snapshot, tls, Command, Connection, Db, DbDropGuard, Frame,
+    Shutdown,
+};
+
+use socket2::{SockRef, TcpKeepalive};
 use std::future::Future;
+use std::net::SocketAddr;
 use std::sync::Arc;
 use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::{broadcast, mpsc, Semaphore};
-use tokio::time::{self, Duration};
-use tracing::{debug, error, info, instrument};
+use tokio::time::{self, Duration, Instant};
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::TlsAcceptor;
+use tracing::{debug, error, info, instrument, warn};
 
 /// Server listener state. Created in the `run` call. It includes a `run` method
 /// which performs the TCP listening and initialization of per-connection state.
@@ -28,11 +37,16 @@ struct Listener {
     /// TCP listener supplied by the `run` caller.
     listener: TcpListener,
 
+    /// Listener of the TLS connections, with their configuration, if
+    /// `Config::tls_addr` is set.
+    tls: Option<(TcpListener, Arc<ServerConfig>)>,
+
     /// Limit the max number of connections.
     ///
-    /// A `Semaphore` is used to limit the max number of connections. Before
-    /// attempting to accept a new connection, a permit is acquired from the
-    /// semaphore. If none are available, the listener waits for one.
+    /// A `Semaphore` is used to limit the max number of connections, set by
+    /// `Config::maxclients`. Once a connection is accepted, a permit is
+    /// acquired from the semaphore. If none are available, the connection is
+    /// told so and closed.
     ///
     /// When handlers complete processing a connection, the permit is returned
     /// to the semaphore.
@@ -48,6 +62,11 @@ struct Listener {
     /// safe terminal state, and completes the task.
     notify_shutdown: broadcast::Sender<()>,
 
+    /// Closes the connections still open once the shutdown timeout elapses,
+    /// by being dropped. A connection stuck writing to a client that does
+    /// not read would otherwise hold the shutdown forever.
+    notify_force_close: broadcast::Sender<()>,
+
     /// Used as part of the graceful shutdown process to wait for client
     /// connections to complete processing.
     ///
@@ -84,6 +103,9 @@ struct Handler {
     /// the byte level protocol parsing details encapsulated in `Connection`.
     connection: Connection,
 
+    /// Address of the peer, recorded in the slow log.
+    addr: SocketAddr,
+
     /// Listen for shutdown notifications.
     ///
     /// A wrapper around the `broadcast::Receiver` paired with the sender in
@@ -98,44 +120,127 @@ struct Handler {
     _shutdown_complete: mpsc::Sender<()>,
 }
 
-/// Maximum number of concurrent connections the redis server will accept.
-///
-/// When this limit is reached, the server will stop accepting connections until
-/// an active connection terminates.
-///
-/// A real application will want to make this value configurable, but for this
-/// example, it is hard coded.
-///
-/// This is also set to a pretty low value to discourage using this in
-/// production (you'd think that all the disclaimers would make it obvious that
-/// this is not a serious project... but I thought that about mini-http as
-/// well).
-const MAX_CONNECTIONS: usize = 250;
+/// Longest a TLS handshake may take. A peer that stalls it would otherwise
+/// hold its connection slot until the server shuts down.
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
 
 /// Run the mini-redis server.
 ///
 /// Accepts connections from the supplied listener. For each inbound connection,
 /// a task is spawned to handle that connection. The server runs until the
-/// `shutdown` future completes, at which point the server shuts down
-/// gracefully.
+/// `shutdown` future completes or a client sends `SHUTDOWN`, at which point
+/// the server shuts down gracefully.
 ///
 /// `tokio::signal::ctrl_c()` can be used as the `shutdown` argument. This will
 /// listen for a SIGINT signal.
+///
+/// The server uses the default [`Config`]. See [`run_with_config`] to change
+/// it.
 pub async fn run(listener: TcpListener, shutdown: impl Future) {
+    run_with_config(listener, Config::default(), shutdown).await
+}
+
+/// Run the mini-redis server with the given `config`.
+///
+/// If `config` enables persistence, the dataset is loaded before accepting
+/// connections: from the AOF if it is enabled, from the snapshot otherwise.
+/// The server does not start if the dataset cannot be loaded. When it shuts
+/// down, connections still open after `Config::shutdown_timeout` are closed,
+/// and a final snapshot is saved if snapshots are enabled. Behaves like
+/// [`run`] otherwise.
+pub async fn run_with_config(listener: TcpListener, config: Config, shutdown: impl Future) {
     // When the provided `shutdown` future completes, we must send a shutdown
     // message to all active connections. We use a broadcast channel for this
     // purpose. The call below ignores the receiver of the broadcast pair, and when
     // a receiver is needed, the subscribe() method on the sender is used to create
     // one.
     let (notify_shutdown, _) = broadcast::channel(1);
+    let (notify_force_close, _) = broadcast::channel(1);
     let (shutdown_complete_tx, shutdown_complete_rx) = mpsc::channel(1);
 
+    let replicaof = config.replicaof.clone();
+    let metrics_addr = config.metrics_addr.clone();
+    let maxclients = config.maxclients;
+    let shutdown_timeout = config.shutdown_timeout;
+
+    // Certificates are loaded once, a bad one keeps the server from starting.
+    let tls = match &config.tls_addr {
+        Some(addr) => {
+            let server_config = match tls::server_config(&config) {
+                Ok(server_config) => server_config,
+                Err(err) => {
+                    error!(cause = %err, "failed to configure TLS");
+                    return;
+                }
+            };
+
+            match TcpListener::bind(addr).await {
+                Ok(tls_listener) => Some((tls_listener, server_config)),
+                Err(err) => {
+                    error!(cause = %err, %addr, "failed to bind the TLS listener");
+                    return;
+                }
+            }
+        }
+        None => None,
+    };
+
+    let db_holder = DbDropGuard::new(config);
+
+    let limit_connections = Arc::new(Semaphore::new(maxclients));
+    db_holder.db().attach_clients(limit_connections.clone(), maxclients);
+
+    // Restore the dataset before the first client can observe it. Starting
+    // with an empty dataset instead would lose the snapshot on the next save.
+    let db = db_holder.db();
+    let loaded = match db.aof_path() {
+        Some(_) => aof::open(&db).await,
+        None => snapshot::load(&db).await.map(drop),
+    };
+
+    if let Err(err) = loaded {
+        error!(cause = %err, "failed to load the dataset");
+        return;
+    }
+
+    // Kept to wait for `SHUTDOWN` and save the final snapshot.
+    let final_db = db.clone();
+
+    // Metrics are served until the server shuts down.
+    if let Some(addr) = metrics_addr {
+        let metrics_listener = match TcpListener::bind(&addr).await {
+            Ok(metrics_listener) => metrics_listener,
+            Err(err) => {
+                error!(cause = %err, %addr, "failed to bind the metrics listener");
+                return;
+            }
+        };
+
+        tokio::spawn(metrics::run(
+            metrics_listener,
+            db.clone(),
+            Shutdown::new(notify_shutdown.subscribe()),
+        ));
+    }
+
+    // A replica keeps following its primary until the server shuts down.
+    if let Some(primary) = replicaof {
+        tokio::spawn(replication::run_replica(
+            db,
+            primary,
+            Shutdown::new(notify_shutdown.subscribe()),
+            shutdown_complete_tx.clone(),
+        ));
+    }
+
     // Initialize the listener state
     let mut server = Listener {
         listener,
-        db_holder: DbDropGuard::new(),
-        limit_connections: Arc::new(Semaphore::new(MAX_CONNECTIONS)),
+        tls,
+        db_holder,
+        limit_connections,
         notify_shutdown,
+        notify_force_close,
         shutdown_complete_tx,
         shutdown_complete_rx,
     };
@@ -159,7 +264,7 @@ pub async fn run(listener: TcpListener, shutdown: impl Future) {
     // asynchronous Rust. See the API docs for more details:
     //
     // https://docs.rs/tokio/*/tokio/macro.select.html
-    tokio::select! {
+    let save = tokio::select! {
         res = server.run() => {
             // If an error is received here, accepting connections from the TCP
             // listener failed multiple times and the server is giving up and
@@ -170,12 +275,18 @@ pub async fn run(listener: TcpListener, shutdown: impl Future) {
             if let Err(err) = res {
                 error!(cause = %err, "failed to accept");
             }
+            SaveMode::Default
         }
         _ = shutdown => {
             // The shutdown signal has been received.
             info!("shutting down");
+            SaveMode::Default
         }
-    }
+        save = final_db.shutdown_requested() => {
+            info!("shutting down on SHUTDOWN");
+            save
+        }
+    };
 
     // Extract the `shutdown_complete` receiver and transmitter
     // explicitly drop `shutdown_transmitter`. This is important, as the
@@ -184,6 +295,7 @@ pub async fn run(listener: TcpListener, shutdown: impl Future) {
         mut shutdown_complete_rx,
         shutdown_complete_tx,
         notify_shutdown,
+        notify_force_close,
         ..
     } = server;
 
@@ -197,7 +309,33 @@ pub async fn run(listener: TcpListener, shutdown: impl Future) {
     // handle held by the listener has been dropped above, the only remaining
     // `Sender` instances are held by connection handler tasks. When those drop,
     // the `mpsc` channel will close and `recv()` will return `None`.
-    let _ = shutdown_complete_rx.recv().await;
+    //
+    // Connections that are still open after the shutdown timeout are closed,
+    // dropping their in-flight work.
+    if time::timeout(shutdown_timeout, shutdown_complete_rx.recv()).await.is_err() {
+        warn!("closing the connections still open after the shutdown timeout");
+        drop(notify_force_close);
+        let _ = shutdown_complete_rx.recv().await;
+    }
+
+    // No client can write anymore, the snapshot has the whole dataset.
+    let save = match save {
+        SaveMode::Default => final_db.snapshot_path().is_some(),
+        SaveMode::Save => true,
+        SaveMode::NoSave => false,
+    };
+
+    if save {
+        // A `SAVE` or `BGSAVE` may still be writing, even if the connection
+        // that started it was closed. Its snapshot may predate the last
+        // writes, so another one is taken once it is done.
+        final_db.wait_save().await;
+
+        match snapshot::save(&final_db).await {
+            Ok(()) => info!("final snapshot saved"),
+            Err(err) => error!(cause = %err, "failed to save the final snapshot"),
+        }
+    }
 }
 
 impl Listener {
@@ -220,50 +358,147 @@ impl Listener {
         info!("accepting inbound connections");
 
         loop {
-            // Wait for a permit to become available
-            //
-            // `acquire_owned` returns a permit that is bound to the semaphore.
-            // When the permit value is dropped, it is automatically returned
-            // to the semaphore.
-            //
-            // `acquire_owned()` returns `Err` when the semaphore has been
-            // closed. We don't ever close the semaphore, so `unwrap()` is safe.
-            let permit = self
-                .limit_connections
-                .clone()
-                .acquire_owned()
-                .await
-                .unwrap();
-
             // Accept a new socket. This will attempt to perform error handling.
             // The `accept` method internally attempts to recover errors, so an
             // error here is non-recoverable.
-            let socket = self.accept().await?;
+            let (socket, addr, tls) = self.accept().await?;
+
+            // Responses are already coalesced into as few writes as possible,
+            // so, like Redis, Nagle's algorithm is disabled. Otherwise the
+            // last write of a pipeline that arrived in several reads waits
+            // for the client to acknowledge the previous ones.
+            if let Err(err) = socket.set_nodelay(true) {
+                debug!(cause = %err, %addr, "failed to disable Nagle's algorithm");
+            }
+
+            // Keepalive detects peers that vanished without closing the
+            // connection, such as a host that lost power. The connection
+            // works without it, so a failure is only logged.
+            if let Some(time) = self.db_holder.db().tcp_keepalive() {
+                if let Err(err) = set_keepalive(&socket, time) {
+                    debug!(cause = %err, %addr, "failed to enable TCP keepalive");
+                }
+            }
 
-            // Create the necessary per-connection handler state.
-            let mut handler = Handler {
-                // Get a handle to the shared database.
-                db: self.db_holder.db(),
+            // Take a permit for the connection, if one is available.
+            //
+            // The permit is only acquired once a connection has been accepted,
+            // so the permits taken from the semaphore are the connections
+            // open, which `INFO` reports.
+            //
+            // `try_acquire_owned` returns a permit that is bound to the
+            // semaphore. When the permit value is dropped, it is automatically
+            // returned to the semaphore.
+            //
+            // Waiting for a permit would leave the client hanging without any
+            // feedback. Instead, like Redis, the connection is answered with
+            // an error and closed, which is counted for the metrics.
+            let permit = self.limit_connections.clone().try_acquire_owned().ok();
 
-                // Initialize the connection state. This allocates read/write
-                // buffers to perform redis protocol frame parsing.
-                connection: Connection::new(socket),
+            if permit.is_none() {
+                self.db_holder.db().connection_rejected();
+            }
 
-                // Receive shutdown notifications.
-                shutdown: Shutdown::new(self.notify_shutdown.subscribe()),
+            // Listen for shutdown notifications from now on, so that a
+            // shutdown sent while the task starts is not missed.
+            let shutdown = Shutdown::new(self.notify_shutdown.subscribe());
+            let mut force_close = self.notify_force_close.subscribe();
 
-                // Notifies the receiver half once all clones are
-                // dropped.
-                _shutdown_complete: self.shutdown_complete_tx.clone(),
+            let tls = match &self.tls {
+                Some((_, server_config)) if tls => Some(TlsAcceptor::from(server_config.clone())),
+                _ => None,
             };
 
+            let mut db = self.db_holder.db();
+
+            // Notifies the receiver half once all clones are dropped.
+            let shutdown_complete = self.shutdown_complete_tx.clone();
+
             // Spawn a new task to process the connections. Tokio tasks are like
             // asynchronous green threads and are executed concurrently.
             tokio::spawn(async move {
+                // Initialize the connection state. This allocates read/write
+                // buffers to perform redis protocol frame parsing. A TLS
+                // connection is only handled once the handshake succeeds. The
+                // handshake is abandoned on shutdown, or if the peer stalls it
+                // for longer than the idle timeout or `TLS_HANDSHAKE_TIMEOUT`.
+                let (connection, mut shutdown) = match tls {
+                    Some(acceptor) => {
+                        let mut shutdown = shutdown;
+                        let timeout = match db.timeout() {
+                            Some(timeout) => timeout.min(TLS_HANDSHAKE_TIMEOUT),
+                            None => TLS_HANDSHAKE_TIMEOUT,
+                        };
+
+                        let res = tokio::select! {
+                            res = time::timeout(timeout, acceptor.accept(socket)) => res,
+                            _ = shutdown.recv() => return,
+                        };
+
+                        match res {
+                            Ok(Ok(stream)) => (Connection::new_tls(stream.into()), shutdown),
+                            Ok(Err(err)) => {
+                                debug!(cause = %err, %addr, "TLS handshake failed");
+                                return;
+                            }
+                            Err(_) => {
+                                debug!(%addr, "TLS handshake timed out");
+                                return;
+                            }
+                        }
+                    }
+                    None => (Connection::new(socket), shutdown),
+                };
+
+                // The error is sent once the TLS handshake succeeded, so the
+                // client can read it.
+                let permit = match permit {
+                    Some(permit) => permit,
+                    None => {
+                        let mut connection = connection;
+                        let response =
+                            Frame::Error("ERR max number of clients reached".to_string());
+
+                        if let Err(err) = connection.write_frame(&response).await {
+                            debug!(cause = %err, %addr, "failed to reject connection");
+                        }
+                        return;
+                    }
+                };
+
+                // The client is listed by `CLIENT LIST` until the task ends,
+                // and `CLIENT KILL` terminates the connection as a shutdown
+                // would.
+                let kill = db.register_client(addr);
+                shutdown = shutdown.with_kill(kill);
+
+                // Create the necessary per-connection handler state.
+                let mut handler = Handler {
+                    db,
+                    connection,
+                    addr,
+                    shutdown,
+                    _shutdown_complete: shutdown_complete,
+                };
+
+                // Counted for `INFO`, along with the bytes read and written.
+                handler.db.connection_received();
+                handler.connection.count_traffic(handler.db.traffic());
+
                 // Process the connection. If an error is encountered, log it.
-                if let Err(err) = handler.run().await {
+                let res = tokio::select! {
+                    res = handler.run() => res,
+                    _ = force_close.recv() => {
+                        debug!(%addr, "connection closed by the shutdown timeout");
+                        Ok(())
+                    }
+                };
+
+                if let Err(err) = res {
                     error!(cause = ?err, "connection error");
                 }
+                handler.db.unregister_client();
+
                 // Move the permit into the task and drop it after completion.
                 // This returns the permit back to the semaphore.
                 drop(permit);
@@ -278,15 +513,26 @@ impl Listener {
     /// After the second failure, the task waits for 2 seconds. Each subsequent
     /// failure doubles the wait time. If accepting fails on the 6th try after
     /// waiting for 64 seconds, then this function returns with an error.
-    async fn accept(&mut self) -> crate::Result<TcpStream> {
+    ///
+    /// Connections are accepted on both listeners, the returned flag is
+    /// `true` for those of the TLS listener.
+    async fn accept(&mut self) -> crate::Result<(TcpStream, SocketAddr, bool)> {
         let mut backoff = 1;
 
         // Try to accept a few times
         loop {
+            let res = match &self.tls {
+                Some((tls_listener, _)) => tokio::select! {
+                    res = self.listener.accept() => res.map(|(socket, addr)| (socket, addr, false)),
+                    res = tls_listener.accept() => res.map(|(socket, addr)| (socket, addr, true)),
+                },
+                None => self.listener.accept().await.map(|(socket, addr)| (socket, addr, false)),
+            };
+
             // Perform the accept operation. If a socket is successfully
             // accepted, return it. Otherwise, save the error.
-            match self.listener.accept().await {
-                Ok((socket, _)) => return Ok(socket),
+            match res {
+                Ok(accepted) => return Ok(accepted),
                 Err(err) => {
                     if backoff > 64 {
                         // Accept has failed too many times. Return the error.
@@ -310,27 +556,52 @@ impl Handler {
     /// Request frames are read from the socket and processed. Responses are
     /// written back to the socket.
     ///
-    /// Currently, pipelining is not implemented. Pipelining is the ability to
-    /// process more than one request concurrently per connection without
-    /// interleaving frames. See for more details:
+    /// Pipelining, sending several requests without waiting for the
+    /// responses, is supported: the requests are processed one after the
+    /// other, and the responses to the requests read at once are written to
+    /// the socket at once. See for more details:
     /// https://redis.io/topics/pipelining
     ///
     /// When the shutdown signal is received, the connection is processed until
     /// it reaches a safe state, at which point it is terminated.
     #[instrument(skip(self))]
     async fn run(&mut self) -> crate::Result<()> {
+        let res = self.process_frames().await;
+
+        // Responses held back for the rest of a pipeline are still sent if
+        // the connection stops in the middle of it.
+        let flushed = self.connection.flush().await;
+
+        res?;
+        Ok(flushed?)
+    }
+
+    /// Processes the request frames until the connection stops, without
+    /// flushing the responses held back for a pipeline on the way out.
+    async fn process_frames(&mut self) -> crate::Result<()> {
         // As long as the shutdown signal has not been received, try to read a
         // new request frame.
         while !self.shutdown.is_shutdown() {
             // While reading a request frame, also listen for the shutdown
             // signal.
+            //
+            // The branches are checked in order, so a command received just
+            // as the connection times out is still served.
             let maybe_frame = tokio::select! {
-                res = self.connection.read_frame() => res?,
+                biased;
+
                 _ = self.shutdown.recv() => {
                     // If a shutdown signal is received, return from `run`.
                     // This will result in the task terminating.
                     return Ok(());
                 }
+                res = self.connection.read_frame() => res?,
+                _ = idle(self.db.timeout()) => {
+                    // The client sent nothing for too long. Closing the
+                    // connection returns its permit to the semaphore.
+                    debug!(addr = %self.addr, "closing idle connection");
+                    return Ok(());
+                }
             };
 
             // If `None` is returned from `read_frame()` then the peer closed
@@ -341,10 +612,31 @@ impl Handler {
                 None => return Ok(()),
             };
 
+            // The request is kept for the monitors and the slow log. Cloning
+            // the frame only clones the handles to the received bytes.
+            let request = frame.clone();
+
+            // Further requests already buffered mean the client pipelined
+            // them.
+            let pipelined = self.connection.has_frame();
+
             // Convert the redis frame into a command struct. This returns an
-            // error if the frame is not a valid redis command or it is an
-            // unsupported command.
-            let cmd = Command::from_frame(frame)?;
+            // error if the frame is not a valid redis command.
+            //
+            // A command with invalid arguments is answered with the error,
+            // like Redis, and the connection keeps going. A frame that is not
+            // a command at all closes the connection.
+            let cmd = match Command::from_frame(frame) {
+                Ok(cmd) => cmd,
+                Err(err) if err.is_recoverable() => {
+                    let response = err.into_frame();
+                    debug!(?response);
+                    self.connection.defer_flush(pipelined);
+                    self.connection.write_frame(&response).await?;
+                    continue;
+                }
+                Err(err) => return Err(err.into()),
+            };
 
             // Logs the `cmd` object. The syntax here is a shorthand provided by
             // the `tracing` crate. It can be thought of as similar to:
@@ -357,6 +649,22 @@ impl Handler {
             // as key-value pairs.
             debug!(?cmd);
 
+            // When the client pipelined more requests, the response is left
+            // in the write buffer, and written along with the response to the
+            // last request of the batch. Streaming commands write as messages
+            // arrive, so they always flush.
+            self.connection.defer_flush(pipelined && !cmd.is_streaming());
+
+            // The client must be authenticated, and its user allowed to run
+            // the command, before anything else happens. Rejected commands
+            // are only answered with the error.
+            if let Err(err) = self.db.authorize(&cmd) {
+                let response = Frame::Error(err);
+                debug!(?response);
+                self.connection.write_frame(&response).await?;
+                continue;
+            }
+
             // Perform the work needed to apply the command. This may mutate the
             // database state as a result.
             //
@@ -364,10 +672,99 @@ impl Handler {
             // command to write response frames directly to the connection. In
             // the case of pub/sub, multiple frames may be send back to the
             // peer.
-            cmd.apply(&self.db, &mut self.connection, &mut self.shutdown)
+            //
+            // The command is counted before it runs, so `INFO` counts itself,
+            // and its duration once it completes.
+            let name = self.db.command_processed(cmd.get_name());
+
+            // Clients in `MONITOR` see the command before it runs, on the
+            // database selected when it was received. Commands that may
+            // carry passwords are neither monitored nor logged as slow.
+            let secret = cmd.has_secrets();
+
+            if !secret {
+                self.db.feed_monitors(&request, self.addr);
+            }
+
+            // While the clients are paused with `CLIENT PAUSE`, the command
+            // waits before running. The server shutdown or `CLIENT KILL`
+            // still terminate the connection.
+            tokio::select! {
+                _ = self.db.wait_unpaused(|mode| cmd.is_paused_by(mode)) => {}
+                _ = self.shutdown.recv() => break,
+            }
+
+            let streaming = cmd.is_streaming();
+            let start = Instant::now();
+
+            // Like in Redis, the response to a write is held back until the
+            // write is in the AOF, and synced with `appendfsync always`.
+            let wait_aof = cmd.is_write() && self.db.aof_path().is_some();
+
+            if wait_aof {
+                self.connection.defer_flush(true);
+            }
+
+            cmd.apply(&mut self.db, &mut self.connection, &mut self.shutdown)
                 .await?;
+
+            let elapsed = start.elapsed();
+
+            if wait_aof {
+                self.db.wait_aof().await;
+
+                if !pipelined {
+                    self.connection.flush().await?;
+                }
+            }
+
+            if let Some(name) = name {
+                self.db.command_completed(name, elapsed);
+            }
+
+            // Commands streaming to the client last as long as the client
+            // wants, so they are not slow.
+            if !streaming && !secret {
+                let name = self.db.client_name();
+                self.db.command_executed(&request, elapsed, self.addr, &name);
+            }
         }
 
         Ok(())
     }
 }
+
+/// Enables TCP keepalive on `socket`, probing after `time` of silence.
+///
+/// Like Redis, probes are then sent every third of `time`.
+fn set_keepalive(socket: &TcpStream, time: Duration) -> std::io::Result<()> {
+    let keepalive = TcpKeepalive::new().with_time(time).with_interval(time / 3);
+
+    // The sockets of the tokio version in use do not implement `AsFd`, which
+    // `SockRef` needs, so the descriptor is borrowed from the raw one.
+    #[cfg(unix)]
+    let fd = {
+        use std::os::unix::io::{AsRawFd, BorrowedFd};
+
+        // SAFETY: the descriptor belongs to `socket`, which outlives `fd`.
+        unsafe { BorrowedFd::borrow_raw(socket.as_raw_fd()) }
+    };
+
+    #[cfg(windows)]
+    let fd = {
+        use std::os::windows::io::{AsRawSocket, BorrowedSocket};
+
+        // SAFETY: the socket belongs to `socket`, which outlives `fd`.
+        unsafe { BorrowedSocket::borrow_raw(socket.as_raw_socket()) }
+    };
+
+    SockRef::from(&fd).set_tcp_keepalive(&keepalive)
+}
+
+/// Completes once a connection has been idle for `timeout`, never if `None`.
+async fn idle(timeout: Option<Duration>) {
+    match timeout {
+        Some(timeout) => time::sleep(timeout).await,
+        None => std::future::pending().await,
+    }
+}
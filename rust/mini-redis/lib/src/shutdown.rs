@@ -1,4 +1,5 @@
-use tokio::sync::broadcast;
+use std::sync::Arc;
+use tokio::sync::{broadcast, Notify};
 
 /// Listens for the server shutdown signal.
 ///
@@ -9,6 +10,10 @@ use tokio::sync::broadcast;
 /// The `Shutdown` struct listens for the signal and tracks that the signal has
 /// been received. Callers may query for whether the shutdown signal has been
 /// received or not.
+///
+/// Each connection owns its `Shutdown`, which can also be triggered locally to
+/// terminate just that connection, for example when the client sends `QUIT`,
+/// or by another client with `CLIENT KILL`.
 #[derive(Debug)]
 pub(crate) struct Shutdown {
     /// `true` if the shutdown signal has been received
@@ -16,6 +21,10 @@ pub(crate) struct Shutdown {
 
     /// The receive half of the channel used to listen for shutdown.
     notify: broadcast::Receiver<()>,
+
+    /// Notified when another client terminates this connection with
+    /// `CLIENT KILL`, if it is a client connection.
+    kill: Option<Arc<Notify>>,
 }
 
 impl Shutdown {
@@ -24,14 +33,29 @@ impl Shutdown {
         Shutdown {
             shutdown: false,
             notify,
+            kill: None,
         }
     }
 
+    /// Also shut down when `kill` is notified.
+    pub(crate) fn with_kill(mut self, kill: Arc<Notify>) -> Shutdown {
+        self.kill = Some(kill);
+        self
+    }
+
     /// Returns `true` if the shutdown signal has been received.
     pub(crate) fn is_shutdown(&self) -> bool {
         self.shutdown
     }
 
+    /// Marks the shutdown as received without waiting for the server signal.
+    ///
+    /// The connection owning this `Shutdown` terminates once the current
+    /// command completes. Other connections are not affected.
+    pub(crate) fn trigger(&mut self) {
+        self.shutdown = true;
+    }
+
     /// Receive the shutdown notice, waiting if necessary.
     pub(crate) async fn recv(&mut self) {
         // If the shutdown signal has already been received, then return
@@ -40,8 +64,22 @@ impl Shutdown {
             return;
         }
 
-        // Cannot receive a "lag error" as only one value is ever sent.
-        let _ = self.notify.recv().await;
+        match &self.kill {
+            Some(kill) => {
+                tokio::select! {
+                    // Cannot receive a "lag error" as only one value is ever
+                    // sent.
+                    _ = self.notify.recv() => {}
+                    // `notify_one` stores a permit, so the kill is not missed
+                    // if it happens while the connection is not waiting.
+                    _ = kill.notified() => {}
+                }
+            }
+            None => {
+                // Cannot receive a "lag error" as only one value is ever sent.
+                let _ = self.notify.recv().await;
+            }
+        }
 
         // Remember that the signal has been received.
         self.shutdown = true;
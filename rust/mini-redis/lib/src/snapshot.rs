@@ -0,0 +1,361 @@
+//! Snapshot persistence.
+//!
+//! A snapshot is a binary dump of every key in the `Db`, with its database,
+//! value and absolute expiration time. It is written by the `SAVE` and `BGSAVE` commands
+//! and loaded by the server before it starts accepting connections.
+//!
+//! # Format
+//!
+//! All integers are big endian.
+//!
+//! ```text
+//! "MINIRDB" 0x00        magic
+//! u32                   format version
+//! record*               one per key
+//! 0xFF                  end of records
+//! u64                   FNV-1a checksum of everything before it
+//! ```
+//!
+//! Each record is an opcode followed by its fields:
+//!
+//! ```text
+//! 0x00 key value           key without expiration
+//! 0x01 u64 key value       key expiring at the given Unix time, in ms
+//! 0xFE u32                 the records that follow belong to this database
+//! ```
+//!
+//! where `key` and `value` are a `u32` length followed by that many bytes.
+//! Records belong to database 0 until the first `0xFE`. Version 1 snapshots,
+//! written before there were several databases, never contain it.
+//!
+//! Records are not grouped by database. They are written shard by shard, each
+//! with its own databases, so the same database may be selected several times.
+//!
+//! The snapshot is first written to a temporary file next to the destination,
+//! which is then renamed over it. A crash while saving therefore never leaves a
+//! truncated snapshot behind.
+
+use crate::Db;
+
+use bytes::{Buf, BufMut, Bytes};
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{error, info};
+
+/// A key-value pair as stored in a snapshot.
+#[derive(Debug)]
+pub(crate) struct Record {
+    /// Index of the database holding the key.
+    pub(crate) db: usize,
+
+    pub(crate) key: String,
+    pub(crate) value: Bytes,
+
+    /// Absolute expiration time. Wall clock time is used, instead of the
+    /// `Instant` kept by the `Db`, as the snapshot may be loaded by another
+    /// process.
+    pub(crate) expires_at: Option<SystemTime>,
+}
+
+/// Identifies a snapshot file.
+const MAGIC: &[u8; 8] = b"MINIRDB\0";
+
+/// Current version of the format.
+const VERSION: u32 = 2;
+
+/// Record opcodes.
+const OP_ENTRY: u8 = 0x00;
+const OP_ENTRY_EXPIRES: u8 = 0x01;
+const OP_SELECTDB: u8 = 0xFE;
+const OP_EOF: u8 = 0xFF;
+
+/// Saves a snapshot of `db` to the configured path, completing once the file
+/// is written.
+///
+/// Used by `SAVE`. Only copying the records holds the `Db` lock, encoding and
+/// writing the file happen on a blocking thread.
+pub(crate) async fn save(db: &Db) -> crate::Result<()> {
+    let path = match db.snapshot_path() {
+        Some(path) => path.to_path_buf(),
+        None => return Err("snapshot persistence is disabled".into()),
+    };
+
+    let guard = match db.begin_save() {
+        Some(guard) => guard,
+        None => return Err("background save already in progress".into()),
+    };
+
+    let (records, changes) = db.records();
+
+    // The blocking task owns the guard, so the save runs to completion and
+    // releases it even if the `SAVE` waiting here is cancelled, as happens
+    // when its connection is closed at shutdown.
+    let res = tokio::task::spawn_blocking(move || {
+        let res = write_file(&path, &encode(&records));
+        guard.finish(res.is_ok(), changes);
+        res
+    })
+    .await;
+
+    // The task only fails if it panicked, which is reported as an error too.
+    // Dropping the guard while unwinding has already ended the save.
+    match res {
+        Ok(res) => res.map_err(Into::into),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Starts saving a snapshot of `db` in the background.
+///
+/// Used by `BGSAVE`. Returns once the records have been copied, the outcome
+/// of writing the file is only logged.
+pub(crate) fn bgsave(db: &Db) -> crate::Result<()> {
+    let path = match db.snapshot_path() {
+        Some(path) => path.to_path_buf(),
+        None => return Err("snapshot persistence is disabled".into()),
+    };
+
+    let guard = match db.begin_save() {
+        Some(guard) => guard,
+        None => return Err("background save already in progress".into()),
+    };
+
+    // The copy is taken now, so the snapshot reflects the state at the time
+    // `BGSAVE` was received, whatever happens while it is being written.
+    let (records, changes) = db.records();
+
+    tokio::spawn(async move {
+        let res = tokio::task::spawn_blocking(move || write_file(&path, &encode(&records))).await;
+
+        let ok = match res {
+            Ok(Ok(())) => {
+                info!("background save completed");
+                true
+            }
+            Ok(Err(err)) => {
+                error!(cause = %err, "background save failed");
+                false
+            }
+            Err(err) => {
+                error!(cause = %err, "background save panicked");
+                false
+            }
+        };
+
+        guard.finish(ok, changes);
+    });
+
+    Ok(())
+}
+
+/// Loads the snapshot at the configured path into `db`.
+///
+/// Returns the number of keys loaded. A missing snapshot is not an error, the
+/// `Db` is left empty.
+pub(crate) async fn load(db: &Db) -> crate::Result<usize> {
+    let path = match db.snapshot_path() {
+        Some(path) => path.to_path_buf(),
+        None => return Ok(0),
+    };
+
+    let data = match tokio::fs::read(&path).await {
+        Ok(data) => data,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(err) => return Err(err.into()),
+    };
+
+    let records = decode(&data)?;
+    let num_keys = records.len();
+
+    db.restore(records)?;
+
+    info!(path = %path.display(), num_keys, "snapshot loaded");
+    Ok(num_keys)
+}
+
+/// Encodes `records` in the snapshot format.
+pub(crate) fn encode(records: &[Record]) -> Vec<u8> {
+    let mut dst = Vec::new();
+
+    dst.put_slice(MAGIC);
+    dst.put_u32(VERSION);
+
+    let mut selected = 0;
+
+    for record in records {
+        if record.db != selected {
+            dst.put_u8(OP_SELECTDB);
+            dst.put_u32(record.db as u32);
+            selected = record.db;
+        }
+
+        match record.expires_at {
+            Some(expires_at) => {
+                dst.put_u8(OP_ENTRY_EXPIRES);
+                dst.put_u64(unix_millis(expires_at));
+            }
+            None => dst.put_u8(OP_ENTRY),
+        }
+
+        put_bytes(&mut dst, record.key.as_bytes());
+        put_bytes(&mut dst, &record.value);
+    }
+
+    dst.put_u8(OP_EOF);
+
+    let checksum = fnv1a(&dst);
+    dst.put_u64(checksum);
+
+    dst
+}
+
+/// Decodes a snapshot, validating its checksum.
+pub(crate) fn decode(data: &[u8]) -> crate::Result<Vec<Record>> {
+    // The checksum is verified first, so the rest of the decoding only has to
+    // deal with well formed data written by `encode`, or a format bug.
+    if data.len() < MAGIC.len() + 4 + 1 + 8 {
+        return Err("invalid snapshot; file too short".into());
+    }
+
+    let (body, checksum) = data.split_at(data.len() - 8);
+
+    if fnv1a(body) != (&checksum[..]).get_u64() {
+        return Err("invalid snapshot; checksum mismatch".into());
+    }
+
+    let mut src = body;
+
+    if &src[..MAGIC.len()] != MAGIC {
+        return Err("invalid snapshot; bad magic".into());
+    }
+    src.advance(MAGIC.len());
+
+    let version = src.get_u32();
+    if version != 1 && version != VERSION {
+        return Err(format!("unsupported snapshot version {}", version).into());
+    }
+
+    let mut records = vec![];
+    let mut db = 0;
+
+    loop {
+        let expires_at = match get_u8(&mut src)? {
+            OP_EOF => break,
+            OP_SELECTDB if version > 1 => {
+                db = get_u32(&mut src)? as usize;
+                continue;
+            }
+            OP_ENTRY => None,
+            OP_ENTRY_EXPIRES => {
+                let ms = get_u64(&mut src)?;
+                Some(UNIX_EPOCH + Duration::from_millis(ms))
+            }
+            op => return Err(format!("invalid snapshot; unknown opcode {:#x}", op).into()),
+        };
+
+        let key = String::from_utf8(get_bytes(&mut src)?.to_vec())
+            .map_err(|_| "invalid snapshot; key is not valid UTF-8")?;
+        let value = Bytes::copy_from_slice(get_bytes(&mut src)?);
+
+        records.push(Record {
+            db,
+            key,
+            value,
+            expires_at,
+        });
+    }
+
+    if src.has_remaining() {
+        return Err("invalid snapshot; trailing data".into());
+    }
+
+    Ok(records)
+}
+
+/// Atomically replaces the file at `path` with `data`.
+///
+/// The data is written to a temporary file in the same directory and synced
+/// to disk before being renamed over `path`. Rename is atomic within a file
+/// system, so readers either see the previous snapshot or the new one.
+fn write_file(path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(format!(".tmp-{}", std::process::id()));
+
+    let res = (|| {
+        let mut file = fs::File::create(&tmp)?;
+        file.write_all(data)?;
+        file.sync_all()?;
+        fs::rename(&tmp, path)
+    })();
+
+    if res.is_err() {
+        // Do not leave a partial temporary file behind.
+        let _ = fs::remove_file(&tmp);
+    }
+
+    res
+}
+
+fn put_bytes(dst: &mut Vec<u8>, bytes: &[u8]) {
+    dst.put_u32(bytes.len() as u32);
+    dst.put_slice(bytes);
+}
+
+fn get_u8(src: &mut &[u8]) -> crate::Result<u8> {
+    if src.remaining() < 1 {
+        return Err("invalid snapshot; unexpected end of data".into());
+    }
+
+    Ok(src.get_u8())
+}
+
+fn get_u32(src: &mut &[u8]) -> crate::Result<u32> {
+    if src.remaining() < 4 {
+        return Err("invalid snapshot; unexpected end of data".into());
+    }
+
+    Ok(src.get_u32())
+}
+
+fn get_u64(src: &mut &[u8]) -> crate::Result<u64> {
+    if src.remaining() < 8 {
+        return Err("invalid snapshot; unexpected end of data".into());
+    }
+
+    Ok(src.get_u64())
+}
+
+fn get_bytes<'a>(src: &mut &'a [u8]) -> crate::Result<&'a [u8]> {
+    if src.remaining() < 4 {
+        return Err("invalid snapshot; unexpected end of data".into());
+    }
+
+    let len = src.get_u32() as usize;
+
+    if src.remaining() < len {
+        return Err("invalid snapshot; unexpected end of data".into());
+    }
+
+    let (bytes, rest) = src.split_at(len);
+    *src = rest;
+
+    Ok(bytes)
+}
+
+/// Converts `time` to milliseconds since the Unix epoch. Times before the
+/// epoch are clamped to it.
+pub(crate) fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// 64-bit FNV-1a hash. It is not a cryptographic hash, but it is simple and
+/// good enough to detect a corrupted file.
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
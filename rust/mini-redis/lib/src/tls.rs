@@ -0,0 +1,105 @@
+//! TLS between the clients and the server.
+//!
+//! The server accepts TLS connections on a second address,
+//! `Config::tls_addr`, alongside the plain one. Certificates and keys are
+//! read from PEM files. If `Config::tls_ca_cert_file` is set, the server also
+//! requires clients to present a certificate signed by that CA, which is
+//! known as mutual TLS.
+//!
+//! Clients connect with [`client::connect_tls`], verifying the certificate of
+//! the server against the CA of [`client::TlsOptions`].
+//!
+//! [`client::connect_tls`]: crate::client::connect_tls
+//! [`client::TlsOptions`]: crate::client::TlsOptions
+
+use crate::client::TlsOptions;
+use crate::config::Config;
+
+use std::path::Path;
+use std::sync::Arc;
+use tokio_rustls::rustls::crypto::{ring, CryptoProvider};
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::rustls::{ClientConfig, RootCertStore, ServerConfig};
+use tokio_rustls::TlsConnector;
+
+/// Builds the configuration of the TLS connections to the server, from the
+/// certificate, key and CA files of `config`.
+pub(crate) fn server_config(config: &Config) -> crate::Result<Arc<ServerConfig>> {
+    let (cert_file, key_file) = match (&config.tls_cert_file, &config.tls_key_file) {
+        (Some(cert_file), Some(key_file)) => (cert_file, key_file),
+        _ => return Err("TLS requires both a certificate and a key file".into()),
+    };
+
+    let builder =
+        ServerConfig::builder_with_provider(provider()).with_safe_default_protocol_versions()?;
+
+    let builder = match &config.tls_ca_cert_file {
+        Some(ca_cert_file) => {
+            let roots = Arc::new(load_roots(ca_cert_file)?);
+            let verifier = WebPkiClientVerifier::builder_with_provider(roots, provider()).build()?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    let server_config = builder.with_single_cert(load_certs(cert_file)?, load_key(key_file)?)?;
+    Ok(Arc::new(server_config))
+}
+
+/// Builds the connector of a client connecting with `options`.
+pub(crate) fn connector(options: &TlsOptions) -> crate::Result<TlsConnector> {
+    let roots = load_roots(&options.ca_cert_file)?;
+
+    let builder = ClientConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()?
+        .with_root_certificates(roots);
+
+    let client_config = match (&options.cert_file, &options.key_file) {
+        (Some(cert_file), Some(key_file)) => {
+            builder.with_client_auth_cert(load_certs(cert_file)?, load_key(key_file)?)?
+        }
+        (None, None) => builder.with_no_client_auth(),
+        _ => return Err("a client certificate requires both a certificate and a key file".into()),
+    };
+
+    Ok(TlsConnector::from(Arc::new(client_config)))
+}
+
+/// The cryptography used, from `ring`, whatever provider the process may
+/// have installed as default.
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(ring::default_provider())
+}
+
+/// Reads the certificate chain in the PEM file at `path`, leaf first.
+fn load_certs(path: &Path) -> crate::Result<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|err| format!("failed to read certificates from '{}': {}", path.display(), err))?;
+
+    if certs.is_empty() {
+        return Err(format!("no certificate found in '{}'", path.display()).into());
+    }
+
+    Ok(certs)
+}
+
+/// Reads the first private key in the PEM file at `path`.
+fn load_key(path: &Path) -> crate::Result<PrivateKeyDer<'static>> {
+    PrivateKeyDer::from_pem_file(path).map_err(|err| {
+        format!("failed to read a private key from '{}': {}", path.display(), err).into()
+    })
+}
+
+/// Reads the CA certificates in the PEM file at `path`.
+fn load_roots(path: &Path) -> crate::Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+
+    for cert in load_certs(path)? {
+        roots.add(cert)?;
+    }
+
+    Ok(roots)
+}
@@ -1,6 +1,18 @@
-use mini_redis::{client, server};
+use bytes::Bytes;
+use mini_redis::cmd::{FlushMode, PauseMode, SaveMode};
+use mini_redis::config::{AppendFsync, Config, KeyspaceEvents, MaxmemoryPolicy, SlowSubscriberPolicy};
+use mini_redis::client::TlsOptions;
+use mini_redis::{client, server, Error, Frame};
+use rcgen::{
+    BasicConstraints, CertificateParams, ExtendedKeyUsagePurpose, IsCa, KeyPair, KeyUsagePurpose,
+};
 use std::net::SocketAddr;
-use tokio::net::TcpListener;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
 use tokio::task::JoinHandle;
 
 /// A PING PONG test without message provided.
@@ -104,6 +116,1970 @@ async fn unsubscribes_from_channels() {
     assert_eq!(subscriber.get_subscribed().len(), 0);
 }
 
+/// a subscriber can ping the server without losing messages, and reset the
+/// connection to issue regular commands again
+#[tokio::test]
+async fn subscriber_ping_and_reset() {
+    let (addr, _) = start_server().await;
+
+    let client = client::connect(addr).await.unwrap();
+    let mut subscriber = client.subscribe(vec!["hello".into()]).await.unwrap();
+
+    let mut publisher = client::connect(addr).await.unwrap();
+    publisher.publish("hello", "world".into()).await.unwrap();
+
+    // The message is received before the `pong` and must be kept
+    let pong = subscriber.ping(None).await.unwrap();
+    assert_eq!(b"PONG", &pong[..]);
+
+    let pong = subscriber.ping(Some("hi".into())).await.unwrap();
+    assert_eq!(b"hi", &pong[..]);
+
+    let message = subscriber.next_message().await.unwrap().unwrap();
+    assert_eq!("hello", &message.channel);
+    assert_eq!(b"world", &message.content[..]);
+
+    let mut client = subscriber.reset().await.unwrap();
+    client.set("hello", "world".into()).await.unwrap();
+    assert_eq!(publisher.publish("hello", "world".into()).await.unwrap(), 0);
+}
+
+/// test the PUBSUB introspection subcommands, and that channels are released
+/// once their last subscriber leaves
+#[tokio::test]
+async fn pubsub_introspection() {
+    let (addr, _) = start_server().await;
+
+    let mut client = client::connect(addr).await.unwrap();
+    assert!(client.pubsub_channels(None).await.unwrap().is_empty());
+    assert_eq!(client.pubsub_numpat().await.unwrap(), 0);
+
+    let subscriber = client::connect(addr).await.unwrap();
+    let mut subscriber = subscriber
+        .subscribe(vec!["news.tech".into(), "news.sport".into()])
+        .await
+        .unwrap();
+
+    let other = client::connect(addr).await.unwrap();
+    let other = other.subscribe(vec!["news.tech".into(), "weather".into()]).await.unwrap();
+
+    let mut channels = client.pubsub_channels(None).await.unwrap();
+    channels.sort();
+    assert_eq!(channels, ["news.sport", "news.tech", "weather"]);
+
+    let mut channels = client.pubsub_channels(Some("news.*")).await.unwrap();
+    channels.sort();
+    assert_eq!(channels, ["news.sport", "news.tech"]);
+
+    let numsub = client
+        .pubsub_numsub(&["news.tech".into(), "weather".into(), "missing".into()])
+        .await
+        .unwrap();
+    assert_eq!(
+        numsub,
+        [
+            ("news.tech".to_string(), 2),
+            ("weather".to_string(), 1),
+            ("missing".to_string(), 0)
+        ]
+    );
+
+    // Unsubscribing releases `news.sport`, `news.tech` is still in use.
+    subscriber.unsubscribe(&[]).await.unwrap();
+
+    let mut channels = client.pubsub_channels(None).await.unwrap();
+    channels.sort();
+    assert_eq!(channels, ["news.tech", "weather"]);
+
+    // Disconnecting releases the remaining channels.
+    drop(other);
+
+    // The server notices the disconnect asynchronously.
+    while !client.pubsub_channels(None).await.unwrap().is_empty() {
+        tokio::task::yield_now().await;
+    }
+}
+
+/// keyspace notifications are published when keys are set, deleted and
+/// expired
+#[tokio::test]
+async fn keyspace_notifications() {
+    let (addr, _) = start_server_with_config(Config {
+        notify_keyspace_events: "KEA".parse().unwrap(),
+        ..Config::default()
+    })
+    .await;
+
+    let subscriber = client::connect(addr).await.unwrap();
+    let mut subscriber = subscriber
+        .subscribe(vec![
+            "__keyspace@0__:foo".into(),
+            "__keyevent@0__:del".into(),
+            "__keyevent@0__:expired".into(),
+        ])
+        .await
+        .unwrap();
+
+    let mut client = client::connect(addr).await.unwrap();
+
+    client.set("foo", "bar".into()).await.unwrap();
+
+    let message = subscriber.next_message().await.unwrap().unwrap();
+    assert_eq!("__keyspace@0__:foo", message.channel);
+    assert_eq!(b"set", &message.content[..]);
+
+    // Deleting a key that does not exist does not notify anything
+    assert_eq!(client.del(&["foo".into(), "baz".into()]).await.unwrap(), 1);
+
+    // The notifications on both channels may be received in any order
+    let mut messages = vec![];
+    for _ in 0..2 {
+        let message = subscriber.next_message().await.unwrap().unwrap();
+        messages.push((message.channel, message.content));
+    }
+    messages.sort();
+
+    assert_eq!(
+        messages,
+        [
+            ("__keyevent@0__:del".to_string(), "foo".into()),
+            ("__keyspace@0__:foo".to_string(), "del".into()),
+        ]
+    );
+
+    client
+        .set_expires("bar", "baz".into(), Duration::from_millis(50))
+        .await
+        .unwrap();
+
+    let message = subscriber.next_message().await.unwrap().unwrap();
+    assert_eq!("__keyevent@0__:expired", message.channel);
+    assert_eq!(b"bar", &message.content[..]);
+}
+
+/// only the enabled event classes are published
+#[tokio::test]
+async fn keyspace_notifications_by_class() {
+    let (addr, _) = start_server_with_config(Config {
+        notify_keyspace_events: KeyspaceEvents::KEYEVENT | KeyspaceEvents::EXPIRED,
+        ..Config::default()
+    })
+    .await;
+
+    let subscriber = client::connect(addr).await.unwrap();
+    let mut subscriber = subscriber
+        .subscribe(vec!["__keyevent@0__:set".into(), "__keyevent@0__:expired".into()])
+        .await
+        .unwrap();
+
+    let mut client = client::connect(addr).await.unwrap();
+
+    // No `set` event is published, the first message is the expiration
+    client
+        .set_expires("foo", "bar".into(), Duration::from_millis(50))
+        .await
+        .unwrap();
+
+    let message = subscriber.next_message().await.unwrap().unwrap();
+    assert_eq!("__keyevent@0__:expired", message.channel);
+    assert_eq!(b"foo", &message.content[..]);
+}
+
+/// Number and size of the messages published to slow subscribers. Large
+/// enough to fill the socket buffers, so the server can no longer write to the
+/// subscriber and messages pile up in its channel.
+const SLOW_MESSAGES: usize = 200;
+const SLOW_MESSAGE_SIZE: usize = 64 * 1024;
+
+/// Subscribes to `channel` without reading, then publishes `SLOW_MESSAGES`
+/// messages on it. Returns the subscriber, which has not received anything
+/// yet.
+async fn publish_to_slow_subscriber(addr: SocketAddr, channel: &str) -> client::Subscriber {
+    let client = client::connect(addr).await.unwrap();
+    let subscriber = client.subscribe(vec![channel.into()]).await.unwrap();
+
+    let mut publisher = client::connect(addr).await.unwrap();
+
+    for i in 0..SLOW_MESSAGES {
+        let mut message = format!("{:08}", i).into_bytes();
+        message.resize(SLOW_MESSAGE_SIZE, b'.');
+        publisher.publish(channel, message.into()).await.unwrap();
+    }
+
+    subscriber
+}
+
+/// With the default policy a slow subscriber loses messages but is told how
+/// many.
+#[tokio::test]
+async fn slow_subscriber_drop_and_notify() {
+    let (addr, _) = start_server_with_config(Config {
+        pubsub_capacity: 2,
+        ..Config::default()
+    })
+    .await;
+
+    let mut subscriber = publish_to_slow_subscriber(addr, "hello").await;
+
+    // Read until the last message published, which is never dropped.
+    let mut received = 0;
+    loop {
+        let message = subscriber.next_message().await.unwrap().unwrap();
+        received += 1;
+
+        if message.content.starts_with(format!("{:08}", SLOW_MESSAGES - 1).as_bytes()) {
+            break;
+        }
+    }
+
+    assert!(subscriber.lagged_messages() > 0);
+    assert_eq!(received + subscriber.lagged_messages() as usize, SLOW_MESSAGES);
+}
+
+/// With the `Disconnect` policy a slow subscriber is disconnected.
+#[tokio::test]
+async fn slow_subscriber_disconnect() {
+    let (addr, _) = start_server_with_config(Config {
+        pubsub_capacity: 2,
+        slow_subscriber: SlowSubscriberPolicy::Disconnect,
+        ..Config::default()
+    })
+    .await;
+
+    let mut subscriber = publish_to_slow_subscriber(addr, "hello").await;
+
+    // The messages already written to the socket are received, then the
+    // connection is closed.
+    let mut received = 0;
+    while let Ok(Some(_)) = subscriber.next_message().await {
+        received += 1;
+    }
+
+    assert!(received < SLOW_MESSAGES);
+    assert_eq!(subscriber.lagged_messages(), 0);
+}
+
+/// With the `Grow` policy a slow subscriber receives every message as long as
+/// they fit in the byte limit...
+#[tokio::test]
+async fn slow_subscriber_grow() {
+    let (addr, _) = start_server_with_config(Config {
+        pubsub_capacity: 2,
+        slow_subscriber: SlowSubscriberPolicy::Grow {
+            max_bytes: 2 * SLOW_MESSAGES * SLOW_MESSAGE_SIZE,
+        },
+        ..Config::default()
+    })
+    .await;
+
+    let mut subscriber = publish_to_slow_subscriber(addr, "hello").await;
+
+    for i in 0..SLOW_MESSAGES {
+        let message = subscriber.next_message().await.unwrap().unwrap();
+        assert!(message.content.starts_with(format!("{:08}", i).as_bytes()));
+    }
+
+    assert_eq!(subscriber.lagged_messages(), 0);
+}
+
+/// ...and is disconnected once they do not.
+#[tokio::test]
+async fn slow_subscriber_grow_limit() {
+    let (addr, _) = start_server_with_config(Config {
+        pubsub_capacity: 2,
+        slow_subscriber: SlowSubscriberPolicy::Grow {
+            max_bytes: 8 * SLOW_MESSAGE_SIZE,
+        },
+        ..Config::default()
+    })
+    .await;
+
+    let mut subscriber = publish_to_slow_subscriber(addr, "hello").await;
+
+    let mut received = 0;
+    while let Ok(Some(_)) = subscriber.next_message().await {
+        received += 1;
+    }
+
+    assert!(received < SLOW_MESSAGES);
+}
+
+#[tokio::test]
+async fn snapshot_save_and_load() {
+    let path = snapshot_path("save");
+    let config = Config {
+        snapshot_path: Some(path.clone()),
+        ..Config::default()
+    };
+
+    let (addr, _) = start_server_with_config(config.clone()).await;
+    let mut client = client::connect(addr).await.unwrap();
+
+    client.set("hello", "world".into()).await.unwrap();
+    client
+        .set_expires("short", "lived".into(), Duration::from_millis(200))
+        .await
+        .unwrap();
+    client
+        .set_expires("long", "lived".into(), Duration::from_secs(60))
+        .await
+        .unwrap();
+    client.save().await.unwrap();
+
+    // A second server using the same file starts with the saved dataset.
+    let (addr, _) = start_server_with_config(config.clone()).await;
+    let mut restored = client::connect(addr).await.unwrap();
+
+    assert_eq!(b"world", &restored.get("hello").await.unwrap().unwrap()[..]);
+    assert_eq!(b"lived", &restored.get("long").await.unwrap().unwrap()[..]);
+
+    // Keys that expired before the snapshot is loaded are not restored.
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    let (addr, _) = start_server_with_config(config).await;
+    let mut client = client::connect(addr).await.unwrap();
+
+    assert!(client.get("short").await.unwrap().is_none());
+    assert!(client.get("hello").await.unwrap().is_some());
+
+    // Expirations are restored as well.
+    tokio::time::pause();
+    tokio::time::advance(Duration::from_secs(61)).await;
+    assert!(restored.get("long").await.unwrap().is_none());
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn snapshot_bgsave() {
+    let path = snapshot_path("bgsave");
+    let config = Config {
+        snapshot_path: Some(path.clone()),
+        ..Config::default()
+    };
+
+    let (addr, _) = start_server_with_config(config.clone()).await;
+    let mut client = client::connect(addr).await.unwrap();
+
+    client.set("hello", "world".into()).await.unwrap();
+    client.bgsave().await.unwrap();
+
+    // The save completes in the background.
+    while !path.exists() {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    let (addr, _) = start_server_with_config(config).await;
+    let mut client = client::connect(addr).await.unwrap();
+
+    assert_eq!(b"world", &client.get("hello").await.unwrap().unwrap()[..]);
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn snapshot_disabled() {
+    let (addr, _) = start_server().await;
+    let mut client = client::connect(addr).await.unwrap();
+
+    assert!(client.save().await.is_err());
+    assert!(client.bgsave().await.is_err());
+
+    // The error does not close the connection.
+    client.set("hello", "world".into()).await.unwrap();
+}
+
+#[tokio::test]
+async fn aof_replay() {
+    let path = snapshot_path("aof-replay");
+    let config = Config {
+        aof_path: Some(path.clone()),
+        appendfsync: AppendFsync::Always,
+        ..Config::default()
+    };
+
+    let (addr, _) = start_server_with_config(config.clone()).await;
+    let mut client = client::connect(addr).await.unwrap();
+
+    client.set("hello", "world".into()).await.unwrap();
+    client.set("removed", "soon".into()).await.unwrap();
+    client.del(&["removed".into()]).await.unwrap();
+    client
+        .set_expires("long", "lived".into(), Duration::from_secs(60))
+        .await
+        .unwrap();
+
+    // The expiration is logged as an absolute time, so replaying it does not
+    // restart it.
+    let aof = std::fs::read(&path).unwrap();
+    assert!(aof.windows(4).any(|window| window == b"pxat"));
+
+    // Replaying the AOF on a second server rebuilds the dataset.
+    let (addr, _) = start_server_with_config(config).await;
+    let mut client = client::connect(addr).await.unwrap();
+
+    assert_eq!(b"world", &client.get("hello").await.unwrap().unwrap()[..]);
+    assert!(client.get("removed").await.unwrap().is_none());
+    assert_eq!(b"lived", &client.get("long").await.unwrap().unwrap()[..]);
+
+    // The expiration is replayed as well.
+    tokio::time::pause();
+    tokio::time::advance(Duration::from_secs(61)).await;
+    assert!(client.get("long").await.unwrap().is_none());
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn aof_truncated_tail() {
+    let path = snapshot_path("aof-truncated");
+    let config = Config {
+        aof_path: Some(path.clone()),
+        ..Config::default()
+    };
+
+    // The server stopped while appending the second command.
+    std::fs::write(
+        &path,
+        b"*3\r\n$3\r\nset\r\n$5\r\nhello\r\n$5\r\nworld\r\n*3\r\n$3\r\nset\r\n$3\r\nfo",
+    )
+    .unwrap();
+
+    let (addr, _) = start_server_with_config(config.clone()).await;
+    let mut client = client::connect(addr).await.unwrap();
+
+    assert_eq!(b"world", &client.get("hello").await.unwrap().unwrap()[..]);
+    client.set("foo", "bar".into()).await.unwrap();
+
+    // The truncated command was discarded, so the write that followed it can
+    // be replayed.
+    let (addr, _) = start_server_with_config(config).await;
+    let mut client = client::connect(addr).await.unwrap();
+
+    assert_eq!(b"world", &client.get("hello").await.unwrap().unwrap()[..]);
+    assert_eq!(b"bar", &client.get("foo").await.unwrap().unwrap()[..]);
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn aof_rewrite() {
+    let path = snapshot_path("aof-rewrite");
+    let config = Config {
+        aof_path: Some(path.clone()),
+        ..Config::default()
+    };
+
+    let (addr, _) = start_server_with_config(config.clone()).await;
+    let mut client = client::connect(addr).await.unwrap();
+
+    for i in 0..100 {
+        client.set("counter", i.to_string().into()).await.unwrap();
+    }
+
+    let len = std::fs::metadata(&path).unwrap().len();
+    client.bgrewriteaof().await.unwrap();
+
+    // Once rewritten, the AOF only holds the last `SET`.
+    while std::fs::metadata(&path).unwrap().len() >= len {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    client.set("hello", "world".into()).await.unwrap();
+
+    let (addr, _) = start_server_with_config(config).await;
+    let mut client = client::connect(addr).await.unwrap();
+
+    assert_eq!(b"99", &client.get("counter").await.unwrap().unwrap()[..]);
+    assert_eq!(b"world", &client.get("hello").await.unwrap().unwrap()[..]);
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn replica_follows_primary() {
+    let (primary_addr, _) = start_server().await;
+    let mut primary = client::connect(primary_addr).await.unwrap();
+
+    // Written before the replica connects, received with the full resync.
+    primary.set("hello", "world".into()).await.unwrap();
+    primary.set("removed", "soon".into()).await.unwrap();
+
+    let (replica_addr, _) = start_server_with_config(Config {
+        replicaof: Some(primary_addr.to_string()),
+        ..Config::default()
+    })
+    .await;
+    let mut replica = client::connect(replica_addr).await.unwrap();
+
+    assert_eq!(b"world", &wait_for(&mut replica, "hello").await[..]);
+
+    // Written afterwards, received through the replication stream.
+    primary.set("foo", "bar".into()).await.unwrap();
+    primary.del(&["removed".into()]).await.unwrap();
+
+    assert_eq!(b"bar", &wait_for(&mut replica, "foo").await[..]);
+    assert!(replica.get("removed").await.unwrap().is_none());
+
+    // Replicas are read only.
+    let err = replica.set("hello", "replica".into()).await.unwrap_err();
+    assert!(err.to_string().starts_with("READONLY"));
+    assert_eq!(b"world", &replica.get("hello").await.unwrap().unwrap()[..]);
+}
+
+#[tokio::test]
+async fn replica_partial_resync() {
+    let (primary_addr, _) = start_server().await;
+    let mut primary = client::connect(primary_addr).await.unwrap();
+
+    // The replica connects through a proxy, which records how the primary
+    // starts each replication stream and can cut the link.
+    let proxy = Proxy::start(primary_addr).await;
+
+    let (replica_addr, _) = start_server_with_config(Config {
+        replicaof: Some(proxy.addr.to_string()),
+        ..Config::default()
+    })
+    .await;
+    let mut replica = client::connect(replica_addr).await.unwrap();
+
+    primary.set("hello", "world".into()).await.unwrap();
+    assert_eq!(b"world", &wait_for(&mut replica, "hello").await[..]);
+
+    // Writes applied while the link is down are sent once the replica
+    // reconnects, without a full resync.
+    proxy.disconnect();
+    primary.set("foo", "bar".into()).await.unwrap();
+
+    assert_eq!(b"bar", &wait_for(&mut replica, "foo").await[..]);
+
+    let replies = proxy.replies.lock().unwrap();
+    assert!(replies[0].starts_with(b"+FULLRESYNC "));
+    assert!(replies[1].starts_with(b"+CONTINUE "));
+}
+
+#[tokio::test]
+async fn select_isolates_databases() {
+    let (addr, _) = start_server().await;
+
+    let mut client = client::connect(addr).await.unwrap();
+    client.set("foo", "zero".into()).await.unwrap();
+
+    client.select(1).await.unwrap();
+    assert!(client.get("foo").await.unwrap().is_none());
+    client.set("foo", "one".into()).await.unwrap();
+
+    // Other connections still start on database 0.
+    let mut other = client::connect(addr).await.unwrap();
+    assert_eq!(b"zero", &other.get("foo").await.unwrap().unwrap()[..]);
+
+    client.select(0).await.unwrap();
+    assert_eq!(b"zero", &client.get("foo").await.unwrap().unwrap()[..]);
+
+    let err = client.select(16).await.unwrap_err();
+    assert_eq!("ERR DB index is out of range", err.to_string());
+}
+
+/// `RESET` switches the connection back to database 0, also when issued in
+/// the subscribed state.
+#[tokio::test]
+async fn reset_selects_database_zero() {
+    let (addr, _) = start_server().await;
+
+    let mut client = client::connect(addr).await.unwrap();
+    client.set("foo", "zero".into()).await.unwrap();
+
+    client.select(3).await.unwrap();
+    client.set("foo", "three".into()).await.unwrap();
+
+    let frame = client.cmd("RESET").send().await.unwrap();
+    assert!(matches!(frame, Frame::Simple(reset) if reset == "RESET"));
+    assert_eq!(b"zero", &client.get("foo").await.unwrap().unwrap()[..]);
+
+    client.select(3).await.unwrap();
+    let subscriber = client.subscribe(vec!["hello".into()]).await.unwrap();
+    let mut client = subscriber.reset().await.unwrap();
+    assert_eq!(b"zero", &client.get("foo").await.unwrap().unwrap()[..]);
+}
+
+/// Errors replied by the server are told apart from failures of the
+/// connection.
+#[tokio::test]
+async fn error_kinds() {
+    let (addr, _) = start_server().await;
+    let mut client = client::connect(addr).await.unwrap();
+
+    let err = client.select(16).await.unwrap_err();
+    assert_eq!(Some("ERR"), err.server_kind());
+    match err {
+        Error::Server { kind, message } => {
+            assert_eq!("ERR", kind);
+            assert_eq!("DB index is out of range", message);
+        }
+        err => panic!("unexpected error: {:?}", err),
+    }
+
+    // A fake server that answers the first command with a frame `GET` does
+    // not expect, then closes the connection after the second one.
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let fake_addr = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut request = [0; b"*2\r\n$3\r\nGET\r\n$3\r\nfoo\r\n".len()];
+
+        socket.read_exact(&mut request).await.unwrap();
+        socket.write_all(b":1\r\n").await.unwrap();
+        socket.read_exact(&mut request).await.unwrap();
+    });
+
+    let mut client = client::connect(fake_addr).await.unwrap();
+
+    let err = client.get("foo").await.unwrap_err();
+    assert!(matches!(err, Error::Protocol(_)), "{:?}", err);
+    assert_eq!(None, err.server_kind());
+
+    let err = client.get("foo").await.unwrap_err();
+    assert!(matches!(err, Error::ConnectionClosed), "{:?}", err);
+}
+
+/// Commands without a method of their own are sent with `Client::cmd`, and
+/// the responses decoded with `FromFrame`.
+#[tokio::test]
+async fn generic_commands() {
+    let (addr, _) = start_server().await;
+    let mut client = client::connect(addr).await.unwrap();
+
+    let frame = client.cmd("SET").arg("foo").arg("bar").send().await.unwrap();
+    assert_eq!(frame, "OK");
+
+    let value: Option<String> = client.cmd("GET").arg("foo").query().await.unwrap();
+    assert_eq!(Some("bar".to_string()), value);
+    let value: Option<Bytes> = client.cmd("GET").arg("missing").query().await.unwrap();
+    assert_eq!(None, value);
+
+    client.cmd("SET").arg("counter").arg(42.to_string()).query::<()>().await.unwrap();
+    let counter: i64 = client.cmd("GET").arg("counter").query().await.unwrap();
+    assert_eq!(42, counter);
+
+    let deleted: u64 = client.cmd("DEL").arg("foo").arg("counter").query().await.unwrap();
+    assert_eq!(2, deleted);
+
+    let categories: Vec<String> = client.cmd("ACL").arg("CAT").query().await.unwrap();
+    assert!(categories.contains(&"pubsub".to_string()));
+
+    let _subscriber = client::connect(addr)
+        .await
+        .unwrap()
+        .subscribe(vec!["news".into()])
+        .await
+        .unwrap();
+
+    let numsub: (String, i64) = client
+        .cmd("PUBSUB")
+        .arg("NUMSUB")
+        .arg("news")
+        .query()
+        .await
+        .unwrap();
+    assert_eq!(("news".to_string(), 1), numsub);
+
+    let numsub: HashMap<String, i64> = client
+        .cmd("PUBSUB")
+        .arg("NUMSUB")
+        .arg("news")
+        .arg("weather")
+        .query()
+        .await
+        .unwrap();
+    assert_eq!(HashMap::from([("news".into(), 1), ("weather".into(), 0)]), numsub);
+
+    // The frame does not hold the requested type.
+    let err = client.cmd("PING").query::<i64>().await.unwrap_err();
+    assert!(matches!(err, Error::Protocol(_)), "{:?}", err);
+
+    let err = client
+        .cmd("HSET")
+        .arg("hash")
+        .arg("field")
+        .arg("value")
+        .send()
+        .await
+        .unwrap_err();
+    assert_eq!(Some("ERR"), err.server_kind());
+
+    // The connection is still usable after the errors.
+    let frame = client.cmd("PING").send().await.unwrap();
+    assert!(matches!(frame, Frame::Simple(pong) if pong == "PONG"));
+}
+
+#[tokio::test]
+async fn move_key_between_databases() {
+    let (addr, _) = start_server().await;
+    let mut client = client::connect(addr).await.unwrap();
+
+    client
+        .set_expires("foo", "bar".into(), Duration::from_secs(60))
+        .await
+        .unwrap();
+    client.set("taken", "zero".into()).await.unwrap();
+
+    assert!(client.move_key("foo", 2).await.unwrap());
+    assert!(client.get("foo").await.unwrap().is_none());
+
+    // Missing keys, and keys already in the destination, are not moved.
+    assert!(!client.move_key("foo", 2).await.unwrap());
+
+    client.select(2).await.unwrap();
+    client.set("taken", "two".into()).await.unwrap();
+    assert_eq!(b"bar", &client.get("foo").await.unwrap().unwrap()[..]);
+
+    client.select(0).await.unwrap();
+    assert!(!client.move_key("taken", 2).await.unwrap());
+    assert_eq!(b"zero", &client.get("taken").await.unwrap().unwrap()[..]);
+
+    let err = client.move_key("taken", 0).await.unwrap_err();
+    assert_eq!("ERR source and destination objects are the same", err.to_string());
+
+    // The expiration moves with the key.
+    client.select(2).await.unwrap();
+    tokio::time::pause();
+    tokio::time::advance(Duration::from_secs(61)).await;
+    assert!(client.get("foo").await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn swapdb_swaps_contents() {
+    let (addr, _) = start_server().await;
+
+    let mut client = client::connect(addr).await.unwrap();
+    client.set("foo", "zero".into()).await.unwrap();
+
+    let mut other = client::connect(addr).await.unwrap();
+    other.select(1).await.unwrap();
+    other.set("bar", "one".into()).await.unwrap();
+
+    client.swapdb(0, 1).await.unwrap();
+
+    // Connections see the swapped contents right away.
+    assert!(client.get("foo").await.unwrap().is_none());
+    assert_eq!(b"one", &client.get("bar").await.unwrap().unwrap()[..]);
+    assert_eq!(b"zero", &other.get("foo").await.unwrap().unwrap()[..]);
+
+    let err = client.swapdb(0, 16).await.unwrap_err();
+    assert_eq!("ERR invalid second DB index", err.to_string());
+}
+
+#[tokio::test]
+async fn flushdb_and_flushall() {
+    let (addr, _) = start_server().await;
+    let mut client = client::connect(addr).await.unwrap();
+
+    for db in 0..3 {
+        client.select(db).await.unwrap();
+        client.set("foo", "bar".into()).await.unwrap();
+    }
+
+    // Database 2 is still selected.
+    client.flushdb(FlushMode::Sync).await.unwrap();
+    assert!(client.get("foo").await.unwrap().is_none());
+
+    client.select(1).await.unwrap();
+    assert!(client.get("foo").await.unwrap().is_some());
+
+    client.flushall(FlushMode::Async).await.unwrap();
+
+    for db in 0..3 {
+        client.select(db).await.unwrap();
+        assert!(client.get("foo").await.unwrap().is_none());
+    }
+}
+
+/// notifications name the database of the key
+#[tokio::test]
+async fn keyspace_notifications_per_database() {
+    let (addr, _) = start_server_with_config(Config {
+        notify_keyspace_events: "KEA".parse().unwrap(),
+        ..Config::default()
+    })
+    .await;
+
+    let subscriber = client::connect(addr).await.unwrap();
+    let mut subscriber = subscriber
+        .subscribe(vec![
+            "__keyspace@1__:foo".into(),
+            "__keyspace@3__:foo".into(),
+        ])
+        .await
+        .unwrap();
+
+    let mut client = client::connect(addr).await.unwrap();
+    client.set("foo", "zero".into()).await.unwrap();
+    client.select(1).await.unwrap();
+    client.set("foo", "one".into()).await.unwrap();
+
+    let message = subscriber.next_message().await.unwrap().unwrap();
+    assert_eq!("__keyspace@1__:foo", message.channel);
+    assert_eq!(b"set", &message.content[..]);
+
+    client.move_key("foo", 3).await.unwrap();
+
+    // The notifications on both channels may be received in any order
+    let mut messages = vec![];
+    for _ in 0..2 {
+        let message = subscriber.next_message().await.unwrap().unwrap();
+        messages.push((message.channel, message.content));
+    }
+    messages.sort();
+
+    assert_eq!(
+        messages,
+        [
+            ("__keyspace@1__:foo".to_string(), "move_from".into()),
+            ("__keyspace@3__:foo".to_string(), "move_to".into()),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn databases_are_persisted() {
+    let rdb = snapshot_path("databases");
+    let aof = snapshot_path("databases-aof");
+
+    let (addr, _) = start_server_with_config(Config {
+        snapshot_path: Some(rdb.clone()),
+        aof_path: Some(aof.clone()),
+        appendfsync: AppendFsync::Always,
+        ..Config::default()
+    })
+    .await;
+    let mut client = client::connect(addr).await.unwrap();
+
+    client.set("foo", "zero".into()).await.unwrap();
+    client.select(5).await.unwrap();
+    client.set("foo", "five".into()).await.unwrap();
+    client.set("bar", "five".into()).await.unwrap();
+    client.move_key("bar", 7).await.unwrap();
+    client.swapdb(5, 6).await.unwrap();
+    client.save().await.unwrap();
+
+    // Both the snapshot and the AOF restore every database.
+    for config in [
+        Config {
+            snapshot_path: Some(rdb.clone()),
+            ..Config::default()
+        },
+        Config {
+            aof_path: Some(aof.clone()),
+            ..Config::default()
+        },
+    ] {
+        let (addr, _) = start_server_with_config(config).await;
+        let mut client = client::connect(addr).await.unwrap();
+
+        assert_eq!(b"zero", &client.get("foo").await.unwrap().unwrap()[..]);
+
+        client.select(5).await.unwrap();
+        assert!(client.get("foo").await.unwrap().is_none());
+
+        client.select(6).await.unwrap();
+        assert_eq!(b"five", &client.get("foo").await.unwrap().unwrap()[..]);
+
+        client.select(7).await.unwrap();
+        assert_eq!(b"five", &client.get("bar").await.unwrap().unwrap()[..]);
+    }
+
+    std::fs::remove_file(rdb).unwrap();
+    std::fs::remove_file(aof).unwrap();
+}
+
+#[tokio::test]
+async fn replica_follows_selected_database() {
+    let (primary_addr, _) = start_server().await;
+    let mut primary = client::connect(primary_addr).await.unwrap();
+
+    primary.select(4).await.unwrap();
+    primary.set("before", "sync".into()).await.unwrap();
+
+    let (replica_addr, _) = start_server_with_config(Config {
+        replicaof: Some(primary_addr.to_string()),
+        ..Config::default()
+    })
+    .await;
+    let mut replica = client::connect(replica_addr).await.unwrap();
+    replica.select(4).await.unwrap();
+
+    assert_eq!(b"sync", &wait_for(&mut replica, "before").await[..]);
+
+    primary.set("after", "sync".into()).await.unwrap();
+    assert_eq!(b"sync", &wait_for(&mut replica, "after").await[..]);
+
+    replica.select(0).await.unwrap();
+    assert!(replica.get("after").await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn maxmemory_noeviction() {
+    let (addr, _) = start_server_with_config(Config {
+        maxmemory: 2000,
+        ..Config::default()
+    })
+    .await;
+    let mut client = client::connect(addr).await.unwrap();
+
+    let value = Bytes::from(vec![b'x'; 100]);
+    let mut written = 0;
+
+    let err = loop {
+        match client.set(&format!("key-{}", written), value.clone()).await {
+            Ok(()) => written += 1,
+            Err(err) => break err,
+        }
+    };
+
+    assert_eq!(
+        "OOM command not allowed when used memory > 'maxmemory'.",
+        err.to_string()
+    );
+    assert!(written > 0);
+
+    // Reads and deletes are still accepted, and free memory for writes.
+    assert!(client.get("key-0").await.unwrap().is_some());
+    client.del(&["key-0".into()]).await.unwrap();
+    client.set("key-0", value).await.unwrap();
+}
+
+#[tokio::test]
+async fn maxmemory_allkeys_lru() {
+    let (addr, _) = start_server_with_config(Config {
+        maxmemory: 2000,
+        maxmemory_policy: MaxmemoryPolicy::AllKeysLru,
+        ..Config::default()
+    })
+    .await;
+    let mut client = client::connect(addr).await.unwrap();
+
+    let value = Bytes::from(vec![b'x'; 100]);
+    client.set("hot", value.clone()).await.unwrap();
+
+    for i in 0..100 {
+        client.get("hot").await.unwrap();
+        client.set(&format!("key-{}", i), value.clone()).await.unwrap();
+    }
+
+    // The recently used key survives, while most of the others are evicted.
+    assert!(client.get("hot").await.unwrap().is_some());
+    assert!(client.get("key-99").await.unwrap().is_some());
+
+    let mut remaining = 0;
+    for i in 0..100 {
+        if client.get(&format!("key-{}", i)).await.unwrap().is_some() {
+            remaining += 1;
+        }
+    }
+    assert!(remaining < 20);
+}
+
+#[tokio::test]
+async fn maxmemory_volatile_ttl() {
+    let (addr, _) = start_server_with_config(Config {
+        maxmemory: 2000,
+        maxmemory_policy: MaxmemoryPolicy::VolatileTtl,
+        notify_keyspace_events: "Ee".parse().unwrap(),
+        ..Config::default()
+    })
+    .await;
+
+    let subscriber = client::connect(addr).await.unwrap();
+    let mut subscriber = subscriber
+        .subscribe(vec!["__keyevent@0__:evicted".into()])
+        .await
+        .unwrap();
+
+    let mut client = client::connect(addr).await.unwrap();
+    let value = Bytes::from(vec![b'x'; 100]);
+
+    client.set("persistent", value.clone()).await.unwrap();
+    client
+        .set_expires("soon", value.clone(), Duration::from_secs(60))
+        .await
+        .unwrap();
+
+    for i in 0..20 {
+        client
+            .set_expires(&format!("later-{}", i), value.clone(), Duration::from_secs(3600))
+            .await
+            .unwrap();
+    }
+
+    // The key closest to expiring is evicted first, keys without an
+    // expiration are never evicted.
+    let message = subscriber.next_message().await.unwrap().unwrap();
+    assert_eq!(b"soon", &message.content[..]);
+
+    assert!(client.get("soon").await.unwrap().is_none());
+    assert!(client.get("persistent").await.unwrap().is_some());
+}
+
+#[tokio::test]
+async fn object_idletime_and_freq() {
+    let (addr, _) = start_server().await;
+    let mut client = client::connect(addr).await.unwrap();
+
+    client.set("foo", "bar".into()).await.unwrap();
+
+    assert_eq!(Some(0), client.object_idletime("foo").await.unwrap());
+
+    // New keys start with a non-zero counter, which only grows with reads.
+    let freq = client.object_freq("foo").await.unwrap().unwrap();
+    assert_eq!(5, freq);
+
+    for _ in 0..100 {
+        client.get("foo").await.unwrap();
+    }
+    assert!(client.object_freq("foo").await.unwrap().unwrap() > freq);
+
+    assert!(client.object_idletime("missing").await.unwrap().is_none());
+    assert!(client.object_freq("missing").await.unwrap().is_none());
+}
+
+/// a mass expiry is purged over several batches without losing any key
+#[tokio::test]
+async fn mass_expiry() {
+    const KEYS: usize = 2000;
+
+    let (addr, _) = start_server_with_config(Config {
+        notify_keyspace_events: "Ex".parse().unwrap(),
+        pubsub_capacity: 2 * KEYS,
+        ..Config::default()
+    })
+    .await;
+
+    let subscriber = client::connect(addr).await.unwrap();
+    let mut subscriber = subscriber
+        .subscribe(vec!["__keyevent@0__:expired".into()])
+        .await
+        .unwrap();
+
+    let mut client = client::connect(addr).await.unwrap();
+
+    for i in 0..KEYS {
+        client
+            .set_expires(&format!("key-{}", i), "value".into(), Duration::from_millis(500))
+            .await
+            .unwrap();
+    }
+
+    // Every key is expired by the background task, as none is read.
+    for _ in 0..KEYS {
+        subscriber.next_message().await.unwrap().unwrap();
+    }
+
+    // The server kept serving other commands meanwhile.
+    client.set("foo", "bar".into()).await.unwrap();
+    assert!(client.get("key-0").await.unwrap().is_none());
+}
+
+/// concurrent clients writing to a sharded keyspace, and a `DEL` spanning
+/// every shard
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn sharded_keyspace() {
+    const CLIENTS: usize = 8;
+    const KEYS: usize = 50;
+
+    let (addr, _) = start_server_with_config(Config {
+        shards: 4,
+        ..Config::default()
+    })
+    .await;
+
+    let writers: Vec<_> = (0..CLIENTS)
+        .map(|c| {
+            tokio::spawn(async move {
+                let mut client = client::connect(addr).await.unwrap();
+
+                for k in 0..KEYS {
+                    let key = format!("key-{}-{}", c, k);
+                    client.set(&key, key.clone().into()).await.unwrap();
+                }
+            })
+        })
+        .collect();
+
+    for writer in writers {
+        writer.await.unwrap();
+    }
+
+    let mut client = client::connect(addr).await.unwrap();
+
+    let value = client.get("key-7-49").await.unwrap().unwrap();
+    assert_eq!(b"key-7-49", &value[..]);
+
+    // Keys that are not set, or given twice, are not counted.
+    let mut keys: Vec<String> = (0..CLIENTS)
+        .flat_map(|c| (0..KEYS).map(move |k| format!("key-{}-{}", c, k)))
+        .collect();
+    keys.push("key-0-0".into());
+    keys.push("missing".into());
+
+    assert_eq!((CLIENTS * KEYS) as u64, client.del(&keys).await.unwrap());
+    assert!(client.get("key-0-0").await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn info_sections() {
+    let (addr, _) = start_server().await;
+    let mut client = client::connect(addr).await.unwrap();
+
+    client.set("foo", "bar".into()).await.unwrap();
+    client
+        .set_expires("baz", "qux".into(), Duration::from_secs(60))
+        .await
+        .unwrap();
+    client.get("foo").await.unwrap();
+    client.select(2).await.unwrap();
+    client.set("foo", "bar".into()).await.unwrap();
+
+    let report = client.info(None).await.unwrap();
+
+    for header in ["# Server", "# Clients", "# Memory", "# Persistence", "# Stats", "# Keyspace"] {
+        assert!(report.contains(header), "missing {:?} in {:?}", header, report);
+    }
+    assert!(!report.contains("# Commandstats"));
+
+    assert_eq!(Some("1"), info_field(&report, "connected_clients"));
+    assert_eq!(Some("db0:keys=2,expires=1"), report.lines().find(|line| line.starts_with("db0:")));
+    assert_eq!(Some("db2:keys=1,expires=0"), report.lines().find(|line| line.starts_with("db2:")));
+    assert_eq!(Some("noeviction"), info_field(&report, "maxmemory_policy"));
+    assert_eq!(Some("3"), info_field(&report, "rdb_changes_since_last_save"));
+
+    // The `INFO` being answered is already counted.
+    assert_eq!(Some("6"), info_field(&report, "total_commands_processed"));
+
+    let report = client.info(Some("CommandStats")).await.unwrap();
+    assert!(report.starts_with("# Commandstats\r\n"));
+    assert!(info_field(&report, "cmdstat_set").unwrap().starts_with("calls=3,usec="));
+    assert!(info_field(&report, "cmdstat_info").unwrap().starts_with("calls=2,usec="));
+    assert_eq!(None, info_field(&report, "cmdstat_ping"));
+
+    assert_eq!("", client.info(Some("nonexistent")).await.unwrap());
+}
+
+#[tokio::test]
+async fn metrics_endpoint() {
+    // Reserve a free port for the metrics listener.
+    let metrics_addr = TcpListener::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .local_addr()
+        .unwrap();
+
+    let (addr, _) = start_server_with_config(Config {
+        metrics_addr: Some(metrics_addr.to_string()),
+        ..Config::default()
+    })
+    .await;
+
+    let mut client = client::connect(addr).await.unwrap();
+    client.set("foo", "bar".into()).await.unwrap();
+    client.set("baz", "qux".into()).await.unwrap();
+    client.get("foo").await.unwrap();
+    client.publish("channel", "message".into()).await.unwrap();
+
+    let response = http_get(metrics_addr, "/metrics").await;
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+
+    assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{:?}", head);
+    assert!(head.contains("Content-Type: text/plain; version=0.0.4\r\n"));
+
+    let sample = |name: &str| {
+        body.lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(' '))
+            .unwrap_or_else(|| panic!("missing {:?} in {:?}", name, body))
+            .to_string()
+    };
+
+    assert_eq!("2", sample("mini_redis_commands_total{command=\"set\"}"));
+    assert_eq!("1", sample("mini_redis_commands_total{command=\"get\"}"));
+    assert_eq!(
+        "2",
+        sample("mini_redis_command_duration_seconds_bucket{command=\"set\",le=\"+Inf\"}")
+    );
+    assert_eq!("2", sample("mini_redis_command_duration_seconds_count{command=\"set\"}"));
+    assert_eq!("1", sample("mini_redis_connected_clients"));
+    assert_eq!("1", sample("mini_redis_connections_received_total"));
+    assert_eq!("0", sample("mini_redis_rejected_connections_total"));
+    assert_eq!("1", sample("mini_redis_pubsub_messages_published_total"));
+    assert_eq!("2", sample("mini_redis_keys{db=\"0\"}"));
+    assert_ne!("0", sample("mini_redis_net_input_bytes_total"));
+    assert_ne!("0", sample("mini_redis_net_output_bytes_total"));
+
+    assert!(http_get(metrics_addr, "/other").await.starts_with("HTTP/1.1 404 "));
+}
+
+#[tokio::test]
+async fn slowlog_records_commands() {
+    let (addr, _) = start_server_with_config(Config {
+        slowlog_log_slower_than: Some(Duration::ZERO),
+        slowlog_max_len: 3,
+        ..Config::default()
+    })
+    .await;
+    let mut client = client::connect(addr).await.unwrap();
+
+    client.set("foo", vec![b'x'; 200].into()).await.unwrap();
+    client.get("foo").await.unwrap();
+
+    // Newest first. The `SLOWLOG GET` being answered is not logged yet.
+    let entries = client.slowlog_get(None).await.unwrap();
+    assert_eq!(2, entries.len());
+    assert_eq!(1, entries[0].id);
+    assert_eq!(vec![Bytes::from("get"), Bytes::from("foo")], entries[0].args);
+    assert_eq!("", entries[0].name);
+    assert!(entries[0].addr.starts_with("127.0.0.1:"));
+
+    // Long arguments are shortened.
+    let value = &entries[1].args[2];
+    assert_eq!(&value[..128], &[b'x'; 128][..]);
+    assert_eq!(b"... (72 more bytes)", &value[128..]);
+
+    // The oldest entry is dropped once the log is full.
+    assert_eq!(3, client.slowlog_len().await.unwrap());
+    let ids: Vec<u64> = client
+        .slowlog_get(None)
+        .await
+        .unwrap()
+        .iter()
+        .map(|entry| entry.id)
+        .collect();
+    assert_eq!(vec![3, 2, 1], ids);
+    assert_eq!(1, client.slowlog_get(Some(1)).await.unwrap().len());
+
+    client.slowlog_reset().await.unwrap();
+    assert_eq!(1, client.slowlog_len().await.unwrap());
+}
+
+#[tokio::test]
+async fn latency_events() {
+    let path = snapshot_path("latency-events");
+
+    let (addr, _) = start_server_with_config(Config {
+        snapshot_path: Some(path.clone()),
+        latency_monitor_threshold: Some(Duration::ZERO),
+        ..Config::default()
+    })
+    .await;
+    let mut client = client::connect(addr).await.unwrap();
+
+    client.set("foo", "bar".into()).await.unwrap();
+    client.save().await.unwrap();
+
+    let events = client.latency_latest().await.unwrap();
+    let names: Vec<&str> = events.iter().map(|event| &event.name[..]).collect();
+    assert!(names.contains(&"command"), "{:?}", names);
+    assert!(names.contains(&"snapshot-copy"), "{:?}", names);
+
+    // Samples taken in the same second are merged.
+    let history = client.latency_history("command").await.unwrap();
+    assert!(!history.is_empty() && history.len() <= 2, "{:?}", history);
+    assert!(client.latency_history("nonexistent").await.unwrap().is_empty());
+
+    // The default threshold of the slow log is not reached by these commands.
+    assert_eq!(0, client.slowlog_len().await.unwrap());
+
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test]
+async fn monitor_streams_commands() {
+    let (addr, _) = start_server().await;
+
+    let mut monitor = client::connect(addr).await.unwrap().monitor().await.unwrap();
+
+    let mut client = client::connect(addr).await.unwrap();
+    client.select(1).await.unwrap();
+    client.set("foo", "a \"b\"\n".into()).await.unwrap();
+    client.get("foo").await.unwrap();
+
+    let line = monitor.next_line().await.unwrap().unwrap();
+    let (time, rest) = line.split_once(' ').unwrap();
+    assert!(time.parse::<f64>().is_ok(), "{:?}", line);
+    assert!(rest.starts_with("[0 127.0.0.1:"), "{:?}", line);
+    assert!(rest.ends_with("] \"select\" \"1\""), "{:?}", line);
+
+    // The database is the one selected when the command is executed.
+    let line = monitor.next_line().await.unwrap().unwrap();
+    assert!(line.contains(" [1 127.0.0.1:"), "{:?}", line);
+    assert!(line.ends_with(r#"] "set" "foo" "a \"b\"\n""#), "{:?}", line);
+
+    let line = monitor.next_line().await.unwrap().unwrap();
+    assert!(line.ends_with(r#"] "get" "foo""#), "{:?}", line);
+
+    // Leaving the monitor state, the connection is usable again.
+    let mut client = monitor.reset().await.unwrap();
+    assert_eq!(b"PONG", &client.ping(None).await.unwrap()[..]);
+}
+
+#[tokio::test]
+async fn client_list_and_names() {
+    let (addr, _) = start_server().await;
+
+    let mut admin = client::connect(addr).await.unwrap();
+    let mut worker = client::connect(addr).await.unwrap();
+    let subscriber = client::connect(addr).await.unwrap();
+
+    let admin_id = admin.client_id().await.unwrap();
+    let worker_id = worker.client_id().await.unwrap();
+    assert_ne!(admin_id, worker_id);
+
+    assert_eq!(None, worker.client_getname().await.unwrap());
+    worker.client_setname("worker").await.unwrap();
+    assert_eq!(Some("worker".to_string()), worker.client_getname().await.unwrap());
+    assert!(worker.client_setname("bad name").await.is_err());
+
+    worker.select(1).await.unwrap();
+    worker.set("foo", "bar".into()).await.unwrap();
+
+    let _subscriber = subscriber.subscribe(vec!["hello".into()]).await.unwrap();
+
+    let list = admin.client_list().await.unwrap();
+    assert_eq!(3, list.lines().count(), "{:?}", list);
+
+    let line = list
+        .lines()
+        .find(|line| line.starts_with(&format!("id={} ", worker_id)))
+        .unwrap();
+    assert!(line.contains(" name=worker "), "{:?}", line);
+    assert!(line.contains(" flags=N db=1 sub=0 "), "{:?}", line);
+    assert!(line.ends_with(" cmd=set user=default"), "{:?}", line);
+
+    let line = list.lines().find(|line| line.contains(" flags=P ")).unwrap();
+    assert!(line.contains(" sub=1 "), "{:?}", line);
+    assert!(line.contains(" cmd=subscribe "), "{:?}", line);
+}
+
+/// `RESET` clears the name set with `CLIENT SETNAME`.
+#[tokio::test]
+async fn reset_clears_client_name() {
+    let (addr, _) = start_server().await;
+    let mut client = client::connect(addr).await.unwrap();
+
+    client.client_setname("worker").await.unwrap();
+    client.cmd("RESET").send().await.unwrap();
+    assert_eq!(None, client.client_getname().await.unwrap());
+}
+
+#[tokio::test]
+async fn client_kill() {
+    let (addr, _) = start_server().await;
+
+    let mut admin = client::connect(addr).await.unwrap();
+    let mut by_id = client::connect(addr).await.unwrap();
+    let mut by_addr = client::connect(addr).await.unwrap();
+
+    let id = by_id.client_id().await.unwrap();
+    assert!(admin.client_kill_id(id).await.unwrap());
+    assert!(by_id.ping(None).await.is_err());
+
+    // Killed clients are no longer listed.
+    assert!(!admin.client_kill_id(id).await.unwrap());
+
+    by_addr.client_setname("target").await.unwrap();
+    let list = admin.client_list().await.unwrap();
+    let target = list
+        .lines()
+        .find(|line| line.contains(" name=target "))
+        .and_then(|line| line.split(' ').find_map(|field| field.strip_prefix("addr=")))
+        .unwrap();
+
+    admin.client_kill_addr(target).await.unwrap();
+    assert!(by_addr.ping(None).await.is_err());
+    assert!(admin.client_kill_addr(target).await.is_err());
+
+    assert_eq!(1, admin.client_list().await.unwrap().lines().count());
+}
+
+#[tokio::test]
+async fn client_pause_holds_writes() {
+    let (addr, _) = start_server().await;
+
+    let mut admin = client::connect(addr).await.unwrap();
+    let mut reader = client::connect(addr).await.unwrap();
+    let mut writer = client::connect(addr).await.unwrap();
+
+    admin.client_pause(Duration::from_secs(60), PauseMode::Write).await.unwrap();
+
+    let mut write = tokio::spawn(async move {
+        writer.set("foo", "bar".into()).await.unwrap();
+    });
+
+    // Reads still run while writes wait.
+    assert_eq!(None, reader.get("foo").await.unwrap());
+    assert!(tokio::time::timeout(Duration::from_millis(100), &mut write)
+        .await
+        .is_err());
+
+    admin.client_unpause().await.unwrap();
+    write.await.unwrap();
+
+    assert_eq!(b"bar", &reader.get("foo").await.unwrap().unwrap()[..]);
+}
+
+/// Idle connections are closed once the timeout elapses, returning their
+/// permits, while subscribers stay connected.
+///
+/// Time is paused once the connections are set up. From then on, the runtime
+/// jumps to the next timer whenever the test waits, even for a response, so
+/// only connections meant to time out have timers running.
+#[tokio::test]
+async fn idle_timeout() {
+    let (addr, _) = start_server_with_config(Config {
+        timeout: Some(Duration::from_secs(10)),
+        ..Config::default()
+    })
+    .await;
+
+    let mut idle = TcpStream::connect(addr).await.unwrap();
+    let _subscriber = client::connect(addr)
+        .await
+        .unwrap()
+        .subscribe(vec!["news".into()])
+        .await
+        .unwrap();
+
+    let mut client = client::connect(addr).await.unwrap();
+    let report = client.info(Some("clients")).await.unwrap();
+    assert_eq!(Some("3"), info_field(&report, "connected_clients"));
+
+    // Both timeouts elapse at once. Letting the runtime jump to the first
+    // one only could serve the `PING` before the timeout of `client`.
+    tokio::time::pause();
+    tokio::time::advance(Duration::from_secs(11)).await;
+
+    let mut buf = [0; 1];
+    assert_eq!(0, idle.read(&mut buf).await.unwrap());
+    assert!(client.ping(None).await.is_err());
+
+    // The command is sent along with the connection, so it is received
+    // before the new connection times out.
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream
+        .write_all(b"*2\r\n$4\r\nINFO\r\n$7\r\nclients\r\n")
+        .await
+        .unwrap();
+
+    let mut response = vec![];
+    stream.read_to_end(&mut response).await.unwrap();
+
+    // The subscriber and the new connection.
+    let report = String::from_utf8(response).unwrap();
+    assert_eq!(Some("2"), info_field(&report, "connected_clients"));
+}
+
+#[tokio::test]
+async fn maxclients() {
+    let (addr, _) = start_server_with_config(Config {
+        maxclients: 2,
+        ..Config::default()
+    })
+    .await;
+
+    let mut client = client::connect(addr).await.unwrap();
+    let mut other = client::connect(addr).await.unwrap();
+    other.ping(None).await.unwrap();
+
+    // The third client is told why it is disconnected, instead of hanging.
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let mut response = vec![];
+    stream.read_to_end(&mut response).await.unwrap();
+    assert_eq!(&b"-ERR max number of clients reached\r\n"[..], &response[..]);
+
+    let mut rejected = client::connect(addr).await.unwrap();
+    let err = rejected.ping(None).await.unwrap_err();
+    assert_eq!("ERR max number of clients reached", err.to_string());
+
+    let report = client.info(Some("stats")).await.unwrap();
+    assert_eq!(Some("2"), info_field(&report, "rejected_connections"));
+
+    // Once a client leaves, there is room for another.
+    drop(other);
+
+    let mut connected = false;
+    for _ in 0..500 {
+        let mut client = client::connect(addr).await.unwrap();
+
+        if client.ping(None).await.is_ok() {
+            connected = true;
+            break;
+        }
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert!(connected);
+}
+
+#[tokio::test]
+async fn shutdown_saves_snapshot() {
+    let path = snapshot_path("shutdown");
+    let config = Config {
+        snapshot_path: Some(path.clone()),
+        ..Config::default()
+    };
+
+    let (addr, handle) = start_server_with_config(config.clone()).await;
+    let mut client = client::connect(addr).await.unwrap();
+
+    let err = client.shutdown_abort().await.unwrap_err();
+    assert_eq!("ERR No shutdown in progress.", err.to_string());
+
+    client.set("hello", "world".into()).await.unwrap();
+    client.shutdown(SaveMode::Default, false).await.unwrap();
+
+    tokio::time::timeout(Duration::from_secs(5), handle)
+        .await
+        .unwrap()
+        .unwrap();
+
+    // The dataset was saved on the way out.
+    let (addr, _) = start_server_with_config(config).await;
+    let mut client = client::connect(addr).await.unwrap();
+    assert_eq!(b"world", &client.get("hello").await.unwrap().unwrap()[..]);
+
+    std::fs::remove_file(path).unwrap();
+
+    // Without snapshots there is nothing to save to.
+    let (addr, _) = start_server().await;
+    let mut client = client::connect(addr).await.unwrap();
+
+    let err = client.shutdown(SaveMode::Save, false).await.unwrap_err();
+    assert_eq!("ERR snapshot persistence is disabled", err.to_string());
+}
+
+/// A subscriber that stopped reading holds its connection in the middle of a
+/// write, which the shutdown timeout closes.
+#[tokio::test]
+async fn shutdown_timeout_closes_stuck_connections() {
+    let (addr, handle) = start_server_with_config(Config {
+        shutdown_timeout: Duration::from_secs(1),
+        ..Config::default()
+    })
+    .await;
+
+    let _subscriber = publish_to_slow_subscriber(addr, "news").await;
+
+    let mut client = client::connect(addr).await.unwrap();
+    client.shutdown(SaveMode::NoSave, true).await.unwrap();
+
+    tokio::time::timeout(Duration::from_secs(5), handle)
+        .await
+        .unwrap()
+        .unwrap();
+}
+
+/// A `SAVE` whose connection is closed by the shutdown timeout still finishes,
+/// and the final snapshot is taken after it.
+#[cfg(unix)]
+#[tokio::test]
+async fn shutdown_timeout_during_save() {
+    let path = snapshot_path("shutdown-save");
+    let config = Config {
+        snapshot_path: Some(path.clone()),
+        shutdown_timeout: Duration::from_millis(200),
+        ..Config::default()
+    };
+
+    let (addr, handle) = start_server_with_config(config.clone()).await;
+    let mut client = client::connect(addr).await.unwrap();
+    client.set("hello", "world".into()).await.unwrap();
+
+    // The save writes to a temporary file next to the snapshot. Making it a
+    // FIFO holds the save until the test reads from it.
+    let mut tmp = path.clone().into_os_string();
+    tmp.push(format!(".tmp-{}", std::process::id()));
+    let status = std::process::Command::new("mkfifo").arg(&tmp).status().unwrap();
+    assert!(status.success());
+
+    let mut saver = client::connect(addr).await.unwrap();
+    let save = tokio::spawn(async move { saver.save().await });
+
+    while !client
+        .info(Some("persistence"))
+        .await
+        .unwrap()
+        .contains("rdb_bgsave_in_progress:1")
+    {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    tokio::spawn(async move { client.shutdown(SaveMode::Default, false).await });
+
+    // The shutdown timeout closes the connection in the middle of the `SAVE`.
+    assert!(save.await.unwrap().is_err());
+
+    tokio::task::spawn_blocking(move || {
+        use std::io::Read;
+
+        let mut data = vec![];
+        std::fs::File::open(tmp).unwrap().read_to_end(&mut data).unwrap();
+    })
+    .await
+    .unwrap();
+
+    tokio::time::timeout(Duration::from_secs(5), handle)
+        .await
+        .unwrap()
+        .unwrap();
+
+    let (addr, _) = start_server_with_config(config).await;
+    let mut client = client::connect(addr).await.unwrap();
+    assert_eq!(b"world", &client.get("hello").await.unwrap().unwrap()[..]);
+
+    std::fs::remove_file(path).unwrap();
+}
+
+/// `SHUTDOWN` waits for a replica that does not read the replication stream,
+/// until another client aborts it.
+#[tokio::test]
+async fn shutdown_abort() {
+    let (addr, handle) = start_server_with_config(Config {
+        repl_backlog_size: 64 * 1024 * 1024,
+        shutdown_timeout: Duration::from_secs(30),
+        ..Config::default()
+    })
+    .await;
+
+    // A replica that stops reading once the stream has started.
+    let mut replica = TcpStream::connect(addr).await.unwrap();
+    replica
+        .write_all(b"*3\r\n$5\r\nPSYNC\r\n$1\r\n?\r\n$2\r\n-1\r\n")
+        .await
+        .unwrap();
+
+    let mut buf = [0; 1];
+    replica.read_exact(&mut buf).await.unwrap();
+    assert_eq!(b"+", &buf);
+
+    // More than the socket buffers hold, so the replica stays behind.
+    let mut client = client::connect(addr).await.unwrap();
+    for i in 0..100 {
+        let value = Bytes::from(vec![b'.'; 256 * 1024]);
+        client.set(&format!("key{}", i), value).await.unwrap();
+    }
+
+    let shutdown = tokio::spawn(async move { client.shutdown(SaveMode::NoSave, false).await });
+
+    let mut other = client::connect(addr).await.unwrap();
+    while other.shutdown_abort().await.is_err() {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    let err = shutdown.await.unwrap().unwrap_err();
+    assert_eq!("ERR Errors trying to SHUTDOWN. Check logs.", err.to_string());
+
+    // The server keeps running, with writes no longer paused.
+    other.set("foo", "bar".into()).await.unwrap();
+    assert!(!handle.is_finished());
+}
+
+#[tokio::test]
+async fn requirepass() {
+    let (addr, _) = start_server_with_config(Config {
+        requirepass: Some("secret".to_string()),
+        ..Config::default()
+    })
+    .await;
+
+    let mut client = client::connect(addr).await.unwrap();
+    let err = client.get("foo").await.unwrap_err();
+    assert!(err.to_string().starts_with("NOAUTH "), "{}", err);
+
+    let err = client.auth(None, "wrong").await.unwrap_err();
+    assert!(err.to_string().starts_with("WRONGPASS "), "{}", err);
+    assert!(client.get("foo").await.is_err());
+
+    client.auth(None, "secret").await.unwrap();
+    assert_eq!(None, client.get("foo").await.unwrap());
+
+    let mut client = client::connect_with_auth(addr, Some("default"), "secret")
+        .await
+        .unwrap();
+    assert_eq!("default", client.acl_whoami().await.unwrap());
+
+    assert!(client::connect_with_auth(addr, None, "wrong").await.is_err());
+}
+
+/// `RESET` drops the authenticated user. The client is left unauthenticated
+/// if the `default` user needs a password, and is logged in as `default`
+/// otherwise.
+#[tokio::test]
+async fn reset_drops_authenticated_user() {
+    let (addr, _) = start_server_with_config(Config {
+        requirepass: Some("secret".to_string()),
+        ..Config::default()
+    })
+    .await;
+
+    let mut client = client::connect_with_auth(addr, None, "secret").await.unwrap();
+    client.cmd("RESET").send().await.unwrap();
+    let err = client.get("foo").await.unwrap_err();
+    assert!(err.to_string().starts_with("NOAUTH "), "{}", err);
+
+    let (addr, _) = start_server().await;
+
+    let mut client = client::connect(addr).await.unwrap();
+    client.acl_setuser("admin", &["on", ">secret", "+@all"]).await.unwrap();
+    client.auth(Some("admin"), "secret").await.unwrap();
+    assert_eq!("admin", client.acl_whoami().await.unwrap());
+
+    client.cmd("RESET").send().await.unwrap();
+    assert_eq!("default", client.acl_whoami().await.unwrap());
+}
+
+#[tokio::test]
+async fn acl_users() {
+    let (addr, _) = start_server().await;
+
+    let mut admin = client::connect(addr).await.unwrap();
+    assert_eq!("default", admin.acl_whoami().await.unwrap());
+
+    // Without `requirepass`, the default user needs no password.
+    assert!(admin.auth(None, "secret").await.is_err());
+
+    admin
+        .acl_setuser(
+            "cache",
+            &["on", ">secret", "~cache:*", "&news.*", "+@read", "+set", "+publish"],
+        )
+        .await
+        .unwrap();
+
+    let user = admin.acl_getuser("cache").await.unwrap().unwrap();
+    assert_eq!(vec!["on"], user.flags);
+    assert_eq!(
+        vec!["2bb80d537b1da3e38bd30361aa855686bde0eacd7162fef6a25fe97bf527a25b"],
+        user.passwords
+    );
+    assert_eq!("-@all +@read +set +publish", user.commands);
+    assert_eq!("~cache:*", user.keys);
+    assert_eq!("&news.*", user.channels);
+    assert!(admin.acl_getuser("nobody").await.unwrap().is_none());
+
+    // Invalid rules are rejected, and none is applied.
+    assert!(admin.acl_setuser("cache", &["off", "+nosuchcommand"]).await.is_err());
+    let list = admin.acl_list().await.unwrap();
+    assert_eq!(
+        vec![
+            "user cache on #2bb80d537b1da3e38bd30361aa855686bde0eacd7162fef6a25fe97bf527a25b \
+             ~cache:* &news.* -@all +@read +set +publish",
+            "user default on nopass ~* &* +@all",
+        ],
+        list
+    );
+
+    let mut cache = client::connect_with_auth(addr, Some("cache"), "secret")
+        .await
+        .unwrap();
+    cache.set("cache:a", "1".into()).await.unwrap();
+    assert_eq!(b"1", &cache.get("cache:a").await.unwrap().unwrap()[..]);
+    assert_eq!(0, cache.publish("news.today", "hi".into()).await.unwrap());
+
+    let err = cache.set("other", "1".into()).await.unwrap_err();
+    assert_eq!("NOPERM No permissions to access a key", err.to_string());
+    let err = cache.publish("other", "hi".into()).await.unwrap_err();
+    assert_eq!("NOPERM No permissions to access a channel", err.to_string());
+    let err = cache.del(&["cache:a".into()]).await.unwrap_err();
+    assert_eq!(
+        "NOPERM User cache has no permissions to run the 'del' command",
+        err.to_string()
+    );
+
+    assert!(admin.acl_cat(None).await.unwrap().contains(&"read".to_string()));
+    assert_eq!(vec!["get", "object"], admin.acl_cat(Some("read")).await.unwrap());
+    assert!(admin.acl_cat(Some("nosuchcategory")).await.is_err());
+
+    // Removing the user terminates its clients.
+    assert!(admin.acl_deluser(&["default"]).await.is_err());
+    assert_eq!(1, admin.acl_deluser(&["cache", "nobody"]).await.unwrap());
+    assert!(cache.ping(None).await.is_err());
+    assert!(client::connect_with_auth(addr, Some("cache"), "secret").await.is_err());
+}
+
+/// Sends a `GET` request for `path` and returns the whole response.
+async fn http_get(addr: SocketAddr, path: &str) -> String {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+
+    let request = format!("GET {} HTTP/1.1\r\nHost: {}\r\n\r\n", path, addr);
+    stream.write_all(request.as_bytes()).await.unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    response
+}
+
+/// Returns the value of `field` in an `INFO` report.
+fn info_field<'a>(report: &'a str, field: &str) -> Option<&'a str> {
+    report
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| *name == field)
+        .map(|(_, value)| value)
+}
+
+/// Waits until `key` is set on the server, returning its value.
+async fn wait_for(client: &mut client::Client, key: &str) -> Bytes {
+    for _ in 0..500 {
+        if let Some(value) = client.get(key).await.unwrap() {
+            return value;
+        }
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    panic!("`{}` was never set", key);
+}
+
+/// A TCP proxy to a server.
+struct Proxy {
+    addr: SocketAddr,
+
+    /// Start of the first reply of the server on each connection.
+    replies: Arc<Mutex<Vec<Vec<u8>>>>,
+
+    /// Tasks forwarding the traffic of the open connections.
+    forwarding: Arc<Mutex<Vec<JoinHandle<()>>>>,
+}
+
+impl Proxy {
+    async fn start(server: SocketAddr) -> Proxy {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let replies = Arc::new(Mutex::new(vec![]));
+        let forwarding = Arc::new(Mutex::new(vec![]));
+
+        let proxy = Proxy {
+            addr,
+            replies: replies.clone(),
+            forwarding: forwarding.clone(),
+        };
+
+        tokio::spawn(async move {
+            loop {
+                let (mut inbound, _) = listener.accept().await.unwrap();
+                let mut outbound = TcpStream::connect(server).await.unwrap();
+                let replies = replies.clone();
+
+                let task = tokio::spawn(async move {
+                    let (mut client_rd, mut client_wr) = inbound.split();
+                    let (mut server_rd, mut server_wr) = outbound.split();
+
+                    let to_server = tokio::io::copy(&mut client_rd, &mut server_wr);
+                    let to_client = async {
+                        let mut buf = [0; 64];
+                        let n = server_rd.read(&mut buf).await.unwrap();
+                        replies.lock().unwrap().push(buf[..n].to_vec());
+                        client_wr.write_all(&buf[..n]).await.unwrap();
+
+                        tokio::io::copy(&mut server_rd, &mut client_wr).await
+                    };
+
+                    let _ = tokio::join!(to_server, to_client);
+                });
+
+                forwarding.lock().unwrap().push(task);
+            }
+        });
+
+        proxy
+    }
+
+    /// Closes the open connections.
+    fn disconnect(&self) {
+        for task in self.forwarding.lock().unwrap().drain(..) {
+            task.abort();
+        }
+    }
+}
+
+#[tokio::test]
+async fn tls_connections() {
+    let certs = TlsCerts::generate("tls-connections");
+    let (addr, tls_addr) = start_tls_server(&certs, false).await;
+
+    let options = TlsOptions {
+        ca_cert_file: certs.ca_cert.clone(),
+        cert_file: None,
+        key_file: None,
+    };
+
+    let mut client = client::connect_tls(tls_addr, "localhost", &options).await.unwrap();
+    client.set("hello", "world".into()).await.unwrap();
+    assert_eq!(Some("world".into()), client.get("hello").await.unwrap());
+
+    // Plain connections share the dataset.
+    let mut plain = client::connect(addr).await.unwrap();
+    assert_eq!(Some("world".into()), plain.get("hello").await.unwrap());
+
+    // The certificate of the server is only valid for `localhost`.
+    assert!(client::connect_tls(tls_addr, "example.com", &options).await.is_err());
+
+    // A certificate not signed by the CA is refused.
+    let other = TlsCerts::generate("tls-connections-other");
+    let options = TlsOptions {
+        ca_cert_file: other.ca_cert.clone(),
+        ..options
+    };
+    assert!(client::connect_tls(tls_addr, "localhost", &options).await.is_err());
+}
+
+#[tokio::test]
+async fn mutual_tls() {
+    let certs = TlsCerts::generate("mutual-tls");
+    let (_, tls_addr) = start_tls_server(&certs, true).await;
+
+    let options = TlsOptions {
+        ca_cert_file: certs.ca_cert.clone(),
+        cert_file: Some(certs.client_cert.clone()),
+        key_file: Some(certs.client_key.clone()),
+    };
+
+    let mut client = client::connect_tls(tls_addr, "localhost", &options).await.unwrap();
+    let pong = client.ping(None).await.unwrap();
+    assert_eq!(b"PONG", &pong[..]);
+
+    // The client is registered once the handshake succeeded.
+    assert_eq!(1, client.client_list().await.unwrap().lines().count());
+
+    // With TLS 1.3, the server checks the certificate of the client after the
+    // client completed the handshake, so the refusal may only show up on the
+    // first command.
+    let options = TlsOptions {
+        cert_file: None,
+        key_file: None,
+        ..options
+    };
+
+    if let Ok(mut client) = client::connect_tls(tls_addr, "localhost", &options).await {
+        assert!(client.ping(None).await.is_err());
+    }
+}
+
+/// A peer that never completes the TLS handshake is disconnected.
+#[tokio::test]
+async fn tls_handshake_timeout() {
+    let certs = TlsCerts::generate("tls-handshake-timeout");
+    let (_, tls_addr) = start_tls_server(&certs, false).await;
+
+    let mut stalled = TcpStream::connect(tls_addr).await.unwrap();
+
+    // The handshake times out long before this does.
+    tokio::time::pause();
+
+    let mut buf = [0; 1];
+    let res = tokio::time::timeout(Duration::from_secs(60), stalled.read(&mut buf)).await;
+    assert_eq!(0, res.unwrap().unwrap());
+}
+
 async fn start_server() -> (SocketAddr, JoinHandle<()>) {
     let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
     let addr = listener.local_addr().unwrap();
@@ -112,3 +2088,111 @@ async fn start_server() -> (SocketAddr, JoinHandle<()>) {
 
     (addr, handle)
 }
+
+async fn start_server_with_config(config: Config) -> (SocketAddr, JoinHandle<()>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let handle = tokio::spawn(async move {
+        server::run_with_config(listener, config, tokio::signal::ctrl_c()).await
+    });
+
+    (addr, handle)
+}
+
+/// Returns a snapshot path in the temporary directory that is unique to the
+/// test and this process, removing any previous file.
+fn snapshot_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "mini-redis-{}-{}.rdb",
+        name,
+        std::process::id()
+    ));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+/// PEM files of a CA, and of a server certificate for `localhost` and a
+/// client certificate it signed, in the temporary directory.
+struct TlsCerts {
+    ca_cert: PathBuf,
+    server_cert: PathBuf,
+    server_key: PathBuf,
+    client_cert: PathBuf,
+    client_key: PathBuf,
+}
+
+impl TlsCerts {
+    /// Generates the certificates, in files unique to the test `name` and
+    /// this process.
+    fn generate(name: &str) -> TlsCerts {
+        let path = |file: &str| {
+            std::env::temp_dir().join(format!(
+                "mini-redis-{}-{}-{}.pem",
+                name,
+                std::process::id(),
+                file
+            ))
+        };
+
+        let mut ca_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        ca_params.key_usages = vec![KeyUsagePurpose::KeyCertSign, KeyUsagePurpose::DigitalSignature];
+        let ca_key = KeyPair::generate().unwrap();
+        let ca = ca_params.self_signed(&ca_key).unwrap();
+
+        let sign = |purpose| {
+            let mut params = CertificateParams::new(vec!["localhost".to_string()]).unwrap();
+            params.extended_key_usages = vec![purpose];
+            let key = KeyPair::generate().unwrap();
+            let cert = params.signed_by(&key, &ca, &ca_key).unwrap();
+            (cert.pem(), key.serialize_pem())
+        };
+
+        let certs = TlsCerts {
+            ca_cert: path("ca"),
+            server_cert: path("server"),
+            server_key: path("server-key"),
+            client_cert: path("client"),
+            client_key: path("client-key"),
+        };
+
+        let (server_cert, server_key) = sign(ExtendedKeyUsagePurpose::ServerAuth);
+        let (client_cert, client_key) = sign(ExtendedKeyUsagePurpose::ClientAuth);
+
+        std::fs::write(&certs.ca_cert, ca.pem()).unwrap();
+        std::fs::write(&certs.server_cert, server_cert).unwrap();
+        std::fs::write(&certs.server_key, server_key).unwrap();
+        std::fs::write(&certs.client_cert, client_cert).unwrap();
+        std::fs::write(&certs.client_key, client_key).unwrap();
+
+        certs
+    }
+}
+
+/// Starts a server accepting TLS connections with `certs`, requiring client
+/// certificates if `mutual`. Returns the plain and the TLS addresses.
+async fn start_tls_server(certs: &TlsCerts, mutual: bool) -> (SocketAddr, SocketAddr) {
+    // The server binds the TLS address itself, pick a free port for it.
+    let tls_addr = TcpListener::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .local_addr()
+        .unwrap();
+
+    let (addr, _) = start_server_with_config(Config {
+        tls_addr: Some(tls_addr.to_string()),
+        tls_cert_file: Some(certs.server_cert.clone()),
+        tls_key_file: Some(certs.server_key.clone()),
+        tls_ca_cert_file: mutual.then(|| certs.ca_cert.clone()),
+        ..Config::default()
+    })
+    .await;
+
+    // Wait for the server to listen on the TLS address.
+    while TcpStream::connect(tls_addr).await.is_err() {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    (addr, tls_addr)
+}
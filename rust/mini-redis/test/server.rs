@@ -73,11 +73,14 @@ async fn key_value_timeout() {
     // Establish a connection to the server
     let mut stream = TcpStream::connect(addr).await.unwrap();
 
-    // Set a key
+    // Set a key and get it back, data is present. Both commands are sent
+    // together: with the clock paused, the runtime jumps to the expiration
+    // as soon as it is idle, and the key would expire before the `GET`.
     stream
         .write_all(
             b"*5\r\n$3\r\nSET\r\n$5\r\nhello\r\n$5\r\nworld\r\n\
-                     +EX\r\n:1\r\n",
+                     +EX\r\n:1\r\n\
+              *2\r\n$3\r\nGET\r\n$5\r\nhello\r\n",
         )
         .await
         .unwrap();
@@ -89,12 +92,6 @@ async fn key_value_timeout() {
 
     assert_eq!(b"+OK\r\n", &response);
 
-    // Get the key, data is present
-    stream
-        .write_all(b"*2\r\n$3\r\nGET\r\n$5\r\nhello\r\n")
-        .await
-        .unwrap();
-
     // Read "world" response
     let mut response = [0; 11];
 
@@ -353,6 +350,94 @@ async fn send_error_unknown_command() {
     assert_eq!(b"-ERR unknown command \'foo\'\r\n", &response);
 }
 
+/// Commands pipelined in a single write are answered in order, even though
+/// the responses are flushed together once the last one is processed.
+#[tokio::test]
+async fn pipelined_commands() {
+    let addr = start_server().await;
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+
+    stream
+        .write_all(
+            b"*2\r\n$3\r\nGET\r\n$5\r\nhello\r\n\
+              *3\r\n$3\r\nSET\r\n$5\r\nhello\r\n$5\r\nworld\r\n\
+              *2\r\n$3\r\nFOO\r\n$5\r\nhello\r\n\
+              *2\r\n$3\r\nGET\r\n$5\r\nhello\r\n",
+        )
+        .await
+        .unwrap();
+
+    let expected = b"$-1\r\n+OK\r\n-ERR unknown command 'foo'\r\n$5\r\nworld\r\n";
+    let mut response = [0; 49];
+    stream.read_exact(&mut response).await.unwrap();
+    assert_eq!(&expected[..], &response[..]);
+
+    // A frame that is not a command closes the connection, but the responses
+    // to the commands pipelined before it are still sent.
+    stream
+        .write_all(b"*3\r\n$3\r\nSET\r\n$3\r\nfoo\r\n$3\r\nbar\r\n*1\r\n:1\r\n")
+        .await
+        .unwrap();
+
+    let mut response = vec![];
+    stream.read_to_end(&mut response).await.unwrap();
+    assert_eq!(b"+OK\r\n", &response[..]);
+}
+
+/// Commands with invalid arguments are answered with an error, and the
+/// connection keeps going.
+#[tokio::test]
+async fn send_error_invalid_arguments() {
+    let addr = start_server().await;
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+
+    // A missing argument
+    stream.write_all(b"*1\r\n$3\r\nGET\r\n").await.unwrap();
+
+    let expected = b"-ERR wrong number of arguments for 'get' command\r\n";
+    let mut response = [0; 50];
+    stream.read_exact(&mut response).await.unwrap();
+    assert_eq!(expected, &response);
+
+    // An unknown option
+    stream
+        .write_all(b"*4\r\n$3\r\nSET\r\n$5\r\nhello\r\n$5\r\nworld\r\n$3\r\nFOO\r\n")
+        .await
+        .unwrap();
+
+    let expected = b"-ERR currently `SET` only supports the expiration option\r\n";
+    let mut response = [0; 58];
+    stream.read_exact(&mut response).await.unwrap();
+    assert_eq!(expected, &response);
+
+    // An argument that is not a number
+    stream
+        .write_all(b"*5\r\n$3\r\nSET\r\n$5\r\nhello\r\n$5\r\nworld\r\n$2\r\nEX\r\n$3\r\nabc\r\n")
+        .await
+        .unwrap();
+
+    let expected = b"-ERR value is not an integer or out of range\r\n";
+    let mut response = [0; 46];
+    stream.read_exact(&mut response).await.unwrap();
+    assert_eq!(expected, &response);
+
+    // The connection is still usable, and the invalid command did nothing.
+    stream
+        .write_all(b"*2\r\n$3\r\nGET\r\n$5\r\nhello\r\n")
+        .await
+        .unwrap();
+
+    let mut response = [0; 5];
+    stream.read_exact(&mut response).await.unwrap();
+    assert_eq!(b"$-1\r\n", &response);
+
+    // A frame that is not a command closes the connection.
+    stream.write_all(b"*1\r\n:1\r\n").await.unwrap();
+    assert_eq!(0, stream.read(&mut response).await.unwrap());
+}
+
 // In this case we test that server Responds with an Error message if a client
 // sends an GET or SET command after a SUBSCRIBE
 #[tokio::test]
@@ -397,6 +482,78 @@ async fn send_error_get_set_after_subscribe() {
     assert_eq!(b"-ERR unknown command \'get\'\r\n", &response);
 }
 
+// PING, RESET and QUIT are accepted in the subscribed state
+#[tokio::test]
+async fn ping_reset_quit_after_subscribe() {
+    let addr = start_server().await;
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+
+    stream
+        .write_all(b"*2\r\n$9\r\nsubscribe\r\n$5\r\nhello\r\n")
+        .await
+        .unwrap();
+
+    let mut response = [0; 34];
+    stream.read_exact(&mut response).await.unwrap();
+    assert_eq!(
+        &b"*3\r\n$9\r\nsubscribe\r\n$5\r\nhello\r\n:1\r\n"[..],
+        &response[..]
+    );
+
+    // PING without a message echoes an empty one
+    stream.write_all(b"*1\r\n$4\r\nPING\r\n").await.unwrap();
+
+    let mut response = [0; 20];
+    stream.read_exact(&mut response).await.unwrap();
+    assert_eq!(&b"*2\r\n$4\r\npong\r\n$0\r\n\r\n"[..], &response[..]);
+
+    // PING with a message
+    stream
+        .write_all(b"*2\r\n$4\r\nPING\r\n$2\r\nhi\r\n")
+        .await
+        .unwrap();
+
+    let mut response = [0; 22];
+    stream.read_exact(&mut response).await.unwrap();
+    assert_eq!(&b"*2\r\n$4\r\npong\r\n$2\r\nhi\r\n"[..], &response[..]);
+
+    // RESET leaves the subscribed state
+    stream.write_all(b"*1\r\n$5\r\nRESET\r\n").await.unwrap();
+
+    let mut response = [0; 8];
+    stream.read_exact(&mut response).await.unwrap();
+    assert_eq!(b"+RESET\r\n", &response);
+
+    // Regular commands work again
+    stream
+        .write_all(b"*2\r\n$3\r\nGET\r\n$5\r\nhello\r\n")
+        .await
+        .unwrap();
+
+    let mut response = [0; 5];
+    stream.read_exact(&mut response).await.unwrap();
+    assert_eq!(b"$-1\r\n", &response);
+
+    // Subscribe again, then QUIT closes the connection
+    stream
+        .write_all(b"*2\r\n$9\r\nsubscribe\r\n$5\r\nhello\r\n")
+        .await
+        .unwrap();
+
+    let mut response = [0; 34];
+    stream.read_exact(&mut response).await.unwrap();
+
+    stream.write_all(b"*1\r\n$4\r\nQUIT\r\n").await.unwrap();
+
+    let mut response = [0; 5];
+    stream.read_exact(&mut response).await.unwrap();
+    assert_eq!(b"+OK\r\n", &response);
+
+    // Receive `None`
+    assert_eq!(0, stream.read(&mut response).await.unwrap());
+}
+
 async fn start_server() -> SocketAddr {
     let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
     let addr = listener.local_addr().unwrap();